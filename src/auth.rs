@@ -14,8 +14,11 @@ bitflags! {
         const DROP   = 1 << 5;
         const CREATE_USER = 1 << 6;
         const GRANT_OPTION = 1 << 7;
+        // Global only: reading and writing files on the server (LOAD DATA, INTO OUTFILE).
+        const FILE = 1 << 8;
         const ALL = Self::SELECT.bits() | Self::INSERT.bits() | Self::UPDATE.bits() | Self::DELETE.bits()
-                  | Self::CREATE.bits() | Self::DROP.bits() | Self::CREATE_USER.bits() | Self::GRANT_OPTION.bits();
+                  | Self::CREATE.bits() | Self::DROP.bits() | Self::CREATE_USER.bits() | Self::GRANT_OPTION.bits()
+                  | Self::FILE.bits();
     }
 }
 
@@ -93,6 +96,8 @@ pub fn parse_priv_list(input: &str) -> Result<Priv, MiniError> {
             Priv::CREATE_USER
         } else if p.eq_ignore_ascii_case("GRANT OPTION") || p.eq_ignore_ascii_case("GRANT_OPTION") {
             Priv::GRANT_OPTION
+        } else if p.eq_ignore_ascii_case("FILE") {
+            Priv::FILE
         } else {
             return Err(MiniError::Parse(format!("unknown privilege: {p}")));
         };
@@ -1,6 +1,6 @@
 use crate::auth::verify_mysql_native_password;
 use crate::error::MiniError;
-use crate::sql::{execute, SessionState, SERVER_VERSION};
+use crate::sql::{close_session, execute, SessionState, SERVER_VERSION};
 use crate::store::Store;
 use async_trait::async_trait;
 use opensrv_mysql::{
@@ -62,11 +62,60 @@ impl Backend {
             MiniError::NotSupported(_) => ErrorKind::ER_NOT_SUPPORTED_YET,
             MiniError::Invalid(_) => ErrorKind::ER_WRONG_VALUE_COUNT_ON_ROW,
             MiniError::LockWaitTimeout(_) => ErrorKind::ER_LOCK_WAIT_TIMEOUT,
+            // ER_LOCK_NOWAIT (3572) is past the error codes opensrv knows (they end at 1885),
+            // so NOWAIT fails with the code MariaDB uses for it. Only the message tells it apart
+            // from a lock wait timeout.
+            MiniError::LockNowait => ErrorKind::ER_LOCK_WAIT_TIMEOUT,
+            MiniError::Deadlock(_) => ErrorKind::ER_LOCK_DEADLOCK,
+            MiniError::TxInProgress => ErrorKind::ER_CANT_CHANGE_TX_ISOLATION,
             MiniError::UnknownSystemVariable(_) => ErrorKind::ER_UNKNOWN_SYSTEM_VARIABLE,
+            MiniError::GlobalVariable(_) => ErrorKind::ER_GLOBAL_VARIABLE,
+            MiniError::WrongValueForVar(..) => ErrorKind::ER_WRONG_VALUE_FOR_VAR,
+            MiniError::NoDefault(_) => ErrorKind::ER_NO_DEFAULT_FOR_FIELD,
+            MiniError::DuplicateEntry(..) => ErrorKind::ER_DUP_ENTRY,
+            MiniError::DuplicateColumn(_) => ErrorKind::ER_DUP_FIELDNAME,
+            MiniError::TableNotLocked(_) => ErrorKind::ER_TABLE_NOT_LOCKED,
+            MiniError::TableNotLockedForWrite(_) => ErrorKind::ER_TABLE_NOT_LOCKED_FOR_WRITE,
+            MiniError::ReadLockConflict => ErrorKind::ER_CANT_UPDATE_WITH_READLOCK,
+            MiniError::LockedTables => ErrorKind::ER_LOCK_OR_ACTIVE_TRANSACTION,
+            MiniError::FileNotFound(_) => ErrorKind::ER_FILE_NOT_FOUND,
+            MiniError::FileExists(_) => ErrorKind::ER_FILE_EXISTS_ERROR,
+            MiniError::SecureFilePriv => ErrorKind::ER_OPTION_PREVENTS_STATEMENT,
+            MiniError::TooManyRows => ErrorKind::ER_TOO_MANY_ROWS,
             _ => ErrorKind::ER_UNKNOWN_ERROR,
         }
     }
 
+    /// opensrv writes the OK packet's info text as is, since it never negotiates session
+    /// tracking, but clients read it as a length-encoded string. Prefix the length here.
+    fn ok_info(info: String) -> String {
+        if info.is_empty() {
+            return info;
+        }
+        let len = info.len() as u64;
+        let mut bytes = Vec::with_capacity(info.len() + 9);
+        match len {
+            0..=250 => bytes.push(len as u8),
+            251..=0xFFFF => {
+                bytes.push(0xFC);
+                bytes.extend_from_slice(&len.to_le_bytes()[..2]);
+            }
+            0x1_0000..=0xFF_FFFF => {
+                bytes.push(0xFD);
+                bytes.extend_from_slice(&len.to_le_bytes()[..3]);
+            }
+            _ => {
+                bytes.push(0xFE);
+                bytes.extend_from_slice(&len.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(info.as_bytes());
+        // SAFETY: prefixes of 128 bytes or more are not UTF-8, but the string is only handed to
+        // opensrv's `completed`, which copies `info.as_bytes()` into the packet and never reads
+        // it as text.
+        unsafe { String::from_utf8_unchecked(bytes) }
+    }
+
     fn err_msg(err: &MiniError) -> String {
         err.to_string()
     }
@@ -221,13 +270,15 @@ where
             .get_user(&username)?
             .ok_or_else(|| MiniError::AccessDenied("unknown user".into()))?;
 
-        let (out, autocommit, in_trans) = {
+        // Statements may block on row locks (up to innodb_lock_wait_timeout), so run them
+        // without stalling the other connections scheduled on this runtime worker.
+        let (out, autocommit, in_trans, warnings) = tokio::task::block_in_place(|| {
             let mut sess = self.session.lock();
             let out = execute(query, &self.store, &mut sess, &user);
             let autocommit = sess.autocommit;
             let in_trans = sess.in_transaction();
-            (out, autocommit, in_trans)
-        };
+            (out, autocommit, in_trans, sess.warning_count())
+        });
 
         match out {
             Ok(crate::sql::ExecOutput::Ok {
@@ -239,14 +290,26 @@ where
                     affected_rows,
                     last_insert_id,
                     status_flags: Self::status_flags(autocommit, in_trans),
-                    info,
+                    warnings,
+                    info: Self::ok_info(info),
                     ..Default::default()
                 };
                 results.completed(ok).await?;
             }
             Ok(crate::sql::ExecOutput::ResultSet { columns, rows }) => {
+                // Rows are produced as they are written, so a scan error can surface after the
+                // header went out; it then ends the result set with an error packet.
                 let mut rw = results.start(&columns).await?;
                 for row in rows {
+                    let row = match row {
+                        Ok(row) => row,
+                        Err(err) => {
+                            let kind = Self::err_to_kind(&err);
+                            let msg = Self::err_msg(&err);
+                            rw.finish_error(kind, &msg.into_bytes()).await?;
+                            return Ok(());
+                        }
+                    };
                     for (i, cell) in row.into_iter().enumerate() {
                         let coltype = columns
                             .get(i)
@@ -300,7 +363,8 @@ where
 
 impl Drop for Backend {
     fn drop(&mut self) {
-        self.store.unlock_all(self.conn_id);
+        // Rolling back the open transaction releases the connection's row locks too.
+        close_session(&self.store, self.session.get_mut());
     }
 }
 
@@ -366,3 +430,47 @@ fn mysql_value_to_sql(value: opensrv_mysql::Value<'_>) -> Result<String, MiniErr
 fn escape_sql_string(input: &str) -> String {
     input.replace('\'', "''")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read back a length-encoded string the way clients do.
+    fn read_lenenc(bytes: &[u8]) -> &[u8] {
+        let (len, rest) = match bytes[0] {
+            0xFC => (
+                u16::from_le_bytes([bytes[1], bytes[2]]) as usize,
+                &bytes[3..],
+            ),
+            0xFD => (
+                u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]) as usize,
+                &bytes[4..],
+            ),
+            0xFE => (
+                u64::from_le_bytes(bytes[1..9].try_into().unwrap()) as usize,
+                &bytes[9..],
+            ),
+            n => (n as usize, &bytes[1..]),
+        };
+        assert_eq!(rest.len(), len);
+        rest
+    }
+
+    #[test]
+    fn test_ok_info_is_length_encoded() {
+        assert_eq!(Backend::ok_info(String::new()), "");
+        for len in [1, 127, 128, 250, 251, 300, 0xFFFF, 0x1_0000, 70_000] {
+            let info: String = "Records: 3  Duplicates: 0  Warnings: 0 "
+                .chars()
+                .cycle()
+                .take(len)
+                .collect();
+            let encoded = Backend::ok_info(info.clone());
+            assert_eq!(
+                read_lenenc(encoded.as_bytes()),
+                info.as_bytes(),
+                "length {len}"
+            );
+        }
+    }
+}
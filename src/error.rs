@@ -29,6 +29,56 @@ pub enum MiniError {
     #[error("Lock wait timeout: {0}")]
     LockWaitTimeout(String),
 
+    #[error(
+        "Statement aborted because lock(s) could not be acquired immediately and NOWAIT is set."
+    )]
+    LockNowait,
+
+    #[error("Deadlock found when trying to get lock; try restarting transaction: {0}")]
+    Deadlock(String),
+
+    #[error("Table '{0}' was not locked with LOCK TABLES")]
+    TableNotLocked(String),
+
+    #[error("Table '{0}' was locked with a READ lock and can't be updated")]
+    TableNotLockedForWrite(String),
+
+    #[error("Can't execute the query because you have a conflicting read lock")]
+    ReadLockConflict,
+
+    #[error("Can't execute the given command because you have active locked tables or an active transaction")]
+    LockedTables,
+
+    #[error("Transaction characteristics can't be changed while a transaction is in progress")]
+    TxInProgress,
+
     #[error("Unknown system variable '{0}'")]
     UnknownSystemVariable(String),
+
+    #[error("Variable '{0}' is a GLOBAL variable and should be set with SET GLOBAL")]
+    GlobalVariable(String),
+
+    #[error("Variable '{0}' can't be set to the value of '{1}'")]
+    WrongValueForVar(String, String),
+
+    #[error("Field '{0}' doesn't have a default value")]
+    NoDefault(String),
+
+    #[error("Duplicate entry '{0}' for key '{1}'")]
+    DuplicateEntry(String, String),
+
+    #[error("Duplicate column name '{0}'")]
+    DuplicateColumn(String),
+
+    #[error("Can't find file: '{0}' (errno: 2 - No such file or directory)")]
+    FileNotFound(String),
+
+    #[error("File '{0}' already exists")]
+    FileExists(String),
+
+    #[error("The MySQL server is running with the --secure-file-priv option so it cannot execute this statement")]
+    SecureFilePriv,
+
+    #[error("Result consisted of more than one row")]
+    TooManyRows,
 }
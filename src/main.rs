@@ -13,7 +13,7 @@ use std::sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
 };
-use store::Store;
+use store::{FlushPolicy, Store};
 use tokio::net::TcpListener;
 
 #[derive(Debug, Parser)]
@@ -31,6 +31,16 @@ struct Args {
     /// Root password (root@%) used on first boot; ignored if root already exists
     #[arg(long, default_value = "root")]
     root_password: String,
+
+    /// Commit durability: 1 = flush on every commit (group commit), 0 = flush once per second,
+    /// 2 = leave flushing to the storage engine
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=2))]
+    innodb_flush_log_at_trx_commit: i64,
+
+    /// Directory LOAD DATA and SELECT ... INTO OUTFILE may use; an empty value allows any,
+    /// and without it both are disabled
+    #[arg(long)]
+    secure_file_priv: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -39,6 +49,18 @@ async fn main() -> anyhow::Result<()> {
 
     let store = Store::open(&args.data)?;
     store.ensure_root_user(&args.root_password)?;
+    if let Some(policy) = FlushPolicy::from_level(args.innodb_flush_log_at_trx_commit) {
+        store.set_flush_policy(policy);
+    }
+    if let Some(dir) = args.secure_file_priv {
+        let dir = if dir.as_os_str().is_empty() {
+            dir
+        } else {
+            dir.canonicalize()
+                .map_err(|e| anyhow::anyhow!("secure-file-priv {}: {e}", dir.display()))?
+        };
+        store.set_secure_file_priv(Some(dir));
+    }
 
     let listener = TcpListener::bind(&args.listen).await?;
     let local_addr = listener.local_addr()?;
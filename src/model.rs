@@ -16,13 +16,45 @@ pub struct ColumnDef {
     pub name: String,
     pub ty: SqlType,
     pub nullable: bool,
+    /// Value for rows that do not set the column; `None` is NULL, or no default when NOT NULL.
+    #[serde(default)]
+    pub default: Option<ColumnDefault>,
+    /// `ON UPDATE CURRENT_TIMESTAMP`: updates that change the row and do not assign the column
+    /// set it to the statement's time.
+    #[serde(default)]
+    pub on_update_current_timestamp: bool,
+}
+
+impl ColumnDef {
+    /// A column with no `DEFAULT` clause and no `ON UPDATE`.
+    pub fn new(name: impl Into<String>, ty: SqlType, nullable: bool) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            nullable,
+            default: None,
+            on_update_current_timestamp: false,
+        }
+    }
+}
+
+/// A column's `DEFAULT` clause.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ColumnDefault {
+    /// A constant, already coerced to the column's type.
+    Literal(Cell),
+    /// `CURRENT_TIMESTAMP` (or `NOW()`), taken when the row is written.
+    CurrentTimestamp,
+    /// A parenthesized expression such as `(UUID())`, kept as SQL and evaluated for each row.
+    Expr(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexDef {
     pub name: String,
     pub columns: Vec<String>,
-    // Potentially Unique flag in future
+    #[serde(default)]
+    pub unique: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -127,12 +159,88 @@ impl Cell {
     }
 }
 
+/// The value as MySQL prints it in text results and error messages.
+impl std::fmt::Display for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use chrono::TimeZone;
+        match self {
+            Cell::Int(i) => write!(f, "{i}"),
+            Cell::Float(x) => write!(f, "{x}"),
+            Cell::Text(s) => f.write_str(s),
+            Cell::Date(days) => {
+                let secs = days.saturating_mul(86_400);
+                match chrono::Utc.timestamp_opt(secs, 0).single() {
+                    Some(dt) => write!(f, "{}", dt.format("%Y-%m-%d")),
+                    None => write!(f, "{secs}"),
+                }
+            }
+            Cell::DateTime(millis) => match chrono::Utc.timestamp_millis_opt(*millis).single() {
+                Some(dt) => write!(f, "{}", dt.format("%Y-%m-%d %H:%M:%S")),
+                None => write!(f, "{millis}"),
+            },
+            Cell::Null => f.write_str("NULL"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Row {
     /// Values aligned to TableDef.columns.
     pub values: Vec<Cell>,
 }
 
+/// Approximate size of a table, maintained incrementally by every commit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableStats {
+    /// Rows whose latest version is not a delete.
+    pub rows: u64,
+    /// Serialized bytes of those latest versions.
+    pub data_length: u64,
+    /// Bytes of their secondary index entries.
+    pub index_length: u64,
+    /// Millis since epoch of the last commit that changed the table.
+    pub update_time: Option<i64>,
+}
+
+impl TableStats {
+    pub fn avg_row_length(&self) -> u64 {
+        self.data_length.checked_div(self.rows).unwrap_or(0)
+    }
+}
+
+/// What `ANALYZE TABLE` last computed for a table. Kept in the catalog.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableAnalysis {
+    /// Millis since epoch of the last `ANALYZE TABLE`.
+    pub analyzed_at: Option<i64>,
+    /// Distinct values per index, keyed by index name (`PRIMARY` included).
+    pub cardinality: BTreeMap<String, u64>,
+    /// Column histograms, keyed by column name.
+    pub histograms: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Histogram {
+    /// Equi-height when there are more distinct values than buckets, singleton otherwise.
+    pub equi_height: bool,
+    pub buckets: Vec<HistogramBucket>,
+    /// Fraction of rows where the column is NULL.
+    pub null_fraction: f64,
+    pub buckets_specified: usize,
+    /// Millis since epoch.
+    pub last_updated: i64,
+}
+
+/// Values in `lower..=upper`. Frequencies are fractions of all rows, NULLs included, so the last
+/// bucket ends at `1 - null_fraction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub lower: Cell,
+    pub upper: Cell,
+    pub cumulative_frequency: f64,
+    pub distinct: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserRecord {
     pub username: String,
@@ -1,15 +1,26 @@
 use crate::auth::{has_priv, Priv};
 use crate::error::MiniError;
-use crate::model::{Cell, ColumnDef, IndexDef, Row, SqlType, TableDef, TransactionId, UserRecord};
-use crate::store::{ReadView, Store};
+use crate::model::{
+    Cell, ColumnDef, ColumnDefault, Histogram, HistogramBucket, IndexDef, Row, SqlType,
+    TableAnalysis, TableDef, TableStats, TransactionId, UserRecord,
+};
+use crate::store::{
+    FlushPolicy, Gap, LockGrant, LockMode, LockWait, ReadView, Store, TableLockMode,
+};
 use opensrv_mysql::{Column, ColumnFlags, ColumnType};
 use regex::Regex;
 
 use sqlparser::ast::{self, Ident, ObjectName, ObjectNamePart, SetExpr, Statement, TableFactor};
 use sqlparser::dialect::MySqlDialect;
 use sqlparser::parser::Parser;
-use std::collections::{BTreeMap, HashSet};
+use sqlparser::tokenizer::Location;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::RangeInclusive;
 use std::sync::OnceLock;
+use std::time::Duration;
+
+mod file_io;
+mod plan;
 
 #[derive(Debug, Clone)]
 pub struct SessionState {
@@ -25,9 +36,38 @@ pub struct SessionState {
     pub character_set_connection: String,
     pub character_set_results: String,
     pub collation_connection: String,
+    pub innodb_lock_wait_timeout: u64,
+    pub lock_wait_timeout: u64,
+    // MariaDB syntax that MySQL rejects, such as `RETURNING`, is accepted only when set.
+    pub mariadb_compat: bool,
+    // Tables held with `LOCK TABLES`; while any are held, only they can be used.
+    locked_tables: Vec<LockedTable>,
+    // Whether the session holds the global read lock (`FLUSH TABLES WITH READ LOCK`).
+    global_read_lock: bool,
+    // `SET TRANSACTION ...` without a scope: characteristics for the next transaction only.
+    next_txn_isolation: Option<String>,
+    next_txn_read_only: Option<bool>,
+    // Where the statement being executed writes `SELECT STRAIGHT_JOIN`, by the position of the
+    // SELECT keyword; see `normalize_straight_join`.
+    straight_join_selects: Vec<Location>,
+    // When the statement being executed started, in millis since epoch truncated to seconds:
+    // `NOW()` and `CURRENT_TIMESTAMP` defaults read it, so one statement sees one time.
+    statement_time: i64,
+    // Warnings raised by the last statement, listed by `SHOW WARNINGS`.
+    warnings: Vec<SqlWarning>,
+    // Temporary tables the session created, by database and name; see `table_schema`.
+    temp_tables: BTreeSet<(String, String)>,
     txn: TransactionState,
 }
 
+/// A row of `SHOW WARNINGS`.
+#[derive(Debug, Clone)]
+struct SqlWarning {
+    level: &'static str,
+    code: u16,
+    message: String,
+}
+
 impl SessionState {
     pub fn new(conn_id: u32) -> Self {
         Self {
@@ -43,22 +83,101 @@ impl SessionState {
             character_set_connection: "utf8".into(),
             character_set_results: "utf8".into(),
             collation_connection: "utf8_general_ci".into(),
+            innodb_lock_wait_timeout: 50,
+            lock_wait_timeout: 31_536_000,
+            mariadb_compat: false,
+            locked_tables: Vec::new(),
+            global_read_lock: false,
+            next_txn_isolation: None,
+            next_txn_read_only: None,
+            straight_join_selects: Vec::new(),
+            statement_time: 0,
+            warnings: Vec::new(),
+            temp_tables: BTreeSet::new(),
             txn: TransactionState::default(),
         }
     }
 
+    /// A copy of the fields expressions read (connection, user, current database, temporary
+    /// tables, statement time), for result streams that evaluate rows after the statement has
+    /// returned.
+    fn detached(&self) -> SessionState {
+        let mut out = SessionState::new(self.conn_id);
+        out.username = self.username.clone();
+        out.current_db = self.current_db.clone();
+        out.temp_tables = self.temp_tables.clone();
+        out.statement_time = self.statement_time;
+        out
+    }
+
     pub fn in_transaction(&self) -> bool {
         self.txn.in_txn
     }
+
+    /// The number of warnings the last statement raised, for the OK packet.
+    pub fn warning_count(&self) -> u16 {
+        self.warnings.len().min(u16::MAX as usize) as u16
+    }
+
+    fn warn(&mut self, code: u16, message: String) {
+        self.warnings.push(SqlWarning {
+            level: "Warning",
+            code,
+            message,
+        });
+    }
+}
+
+/// A table locked with `LOCK TABLES`, under the name (or alias) statements must use.
+#[derive(Debug, Clone)]
+struct LockedTable {
+    db: String,
+    table: String,
+    alias: Option<String>,
+    write: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum IsolationLevel {
+    ReadUncommitted,
+    ReadCommitted,
+    #[default]
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Parse a `transaction_isolation` value (`READ-COMMITTED`, ...).
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "READ-UNCOMMITTED" => Some(Self::ReadUncommitted),
+            "READ-COMMITTED" => Some(Self::ReadCommitted),
+            "REPEATABLE-READ" => Some(Self::RepeatableRead),
+            "SERIALIZABLE" => Some(Self::Serializable),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 struct TransactionState {
     in_txn: bool,
     tx_id: Option<TransactionId>,
+    // Fixed when the transaction starts.
+    isolation: IsolationLevel,
     read_view: Option<ReadView>,
     pending_rows: BTreeMap<RowKey, Option<Row>>,
     savepoints: Vec<(String, BTreeMap<RowKey, Option<Row>>)>,
+    // Tables this transaction read or wrote. Their metadata locks are held until it ends, so
+    // their definitions cannot change underneath it.
+    metadata_locks: BTreeSet<(String, String)>,
+    // Rows read with FOR UPDATE/FOR SHARE, or locked by UPDATE/DELETE, and still locked. Reads
+    // of them see the latest committed version, and commit validation trusts the lock instead
+    // of the snapshot.
+    locked_reads: BTreeSet<RowKey>,
+    // Rows the running statement locked to write and added to `locked_reads`. A failed
+    // statement releases their locks, so they are taken out again.
+    statement_reads: Vec<RowKey>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -71,29 +190,64 @@ struct RowKey {
 struct RowLockGuard<'a> {
     store: &'a Store,
     owner: u32,
+    wait_timeout: Duration,
     acquired: Vec<RowKey>,
+    acquired_gaps: Vec<(String, String, Gap)>,
     keep: bool,
 }
 
 impl<'a> RowLockGuard<'a> {
-    fn new(store: &'a Store, owner: u32) -> Self {
+    fn new(store: &'a Store, session: &SessionState) -> Self {
         Self {
             store,
-            owner,
+            owner: session.conn_id,
+            wait_timeout: Duration::from_secs(session.innodb_lock_wait_timeout),
             acquired: Vec::new(),
+            acquired_gaps: Vec::new(),
             keep: false,
         }
     }
 
     fn lock_row(&mut self, db: &str, table: &str, pk: i64) -> Result<(), MiniError> {
-        let newly_acquired = self.store.lock_row(self.owner, db, table, pk)?;
-        if newly_acquired {
+        let wait = LockWait::Block(self.wait_timeout);
+        self.lock_row_with(db, table, pk, LockMode::Exclusive, wait)?;
+        Ok(())
+    }
+
+    fn lock_row_with(
+        &mut self,
+        db: &str,
+        table: &str,
+        pk: i64,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<LockGrant, MiniError> {
+        let grant = self.store.lock_row(self.owner, db, table, pk, mode, wait)?;
+        if grant == LockGrant::Acquired {
             self.acquired.push(RowKey {
                 db: db.to_string(),
                 table: table.to_string(),
                 pk,
             });
         }
+        Ok(grant)
+    }
+
+    fn lock_gap(
+        &mut self,
+        db: &str,
+        table: &str,
+        gap: Gap,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<(), MiniError> {
+        let grant = self
+            .store
+            .lock_gap(self.owner, db, table, &gap, mode, wait)?;
+        if grant == LockGrant::Acquired {
+            self.acquired_gaps
+                .push((db.to_string(), table.to_string(), gap));
+        }
         Ok(())
     }
 
@@ -111,6 +265,9 @@ impl Drop for RowLockGuard<'_> {
             self.store
                 .unlock_row(self.owner, &key.db, &key.table, key.pk);
         }
+        for (db, table, gap) in &self.acquired_gaps {
+            self.store.unlock_gap(self.owner, db, table, gap);
+        }
     }
 }
 
@@ -123,10 +280,46 @@ pub enum ExecOutput {
     },
     ResultSet {
         columns: Vec<Column>,
-        rows: Vec<Vec<Cell>>,
+        rows: RowStream,
     },
 }
 
+/// Result rows, pulled one at a time while the backend writes them to the client. Streamed
+/// results own everything they read (store handle, pinned read view, table locks), so they
+/// outlive the statement that built them.
+pub struct RowStream(Box<dyn Iterator<Item = Result<Vec<Cell>, MiniError>> + Send>);
+
+impl RowStream {
+    pub fn new(rows: impl Iterator<Item = Result<Vec<Cell>, MiniError>> + Send + 'static) -> Self {
+        Self(Box::new(rows))
+    }
+
+    #[cfg(test)]
+    pub fn collect_rows(self) -> Result<Vec<Vec<Cell>>, MiniError> {
+        self.collect()
+    }
+}
+
+impl From<Vec<Vec<Cell>>> for RowStream {
+    fn from(rows: Vec<Vec<Cell>>) -> Self {
+        Self::new(rows.into_iter().map(Ok))
+    }
+}
+
+impl Iterator for RowStream {
+    type Item = Result<Vec<Cell>, MiniError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl std::fmt::Debug for RowStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RowStream")
+    }
+}
+
 pub const SERVER_VERSION: &str = "8.0.0-rusty-mini-mysql";
 pub const VERSION_COMMENT: &str = "rusty-mini-mysql";
 
@@ -243,6 +436,8 @@ fn parse_db_table_token(token: &str) -> Result<(Option<String>, String), MiniErr
 }
 
 fn require_priv(user: &UserRecord, db: Option<&str>, needed: Priv) -> Result<(), MiniError> {
+    // Temporary tables take the privileges of the database they were created in.
+    let db = db.map(|db| Store::temp_schema_db(db).unwrap_or(db));
     if has_priv(user, db, needed) {
         Ok(())
     } else {
@@ -268,11 +463,23 @@ const SYSTEM_VARIABLES: &[&str] = &[
     "lower_case_table_names",
     "max_allowed_packet",
     "socket",
+    "innodb_lock_wait_timeout",
+    "innodb_deadlock_detect",
+    "innodb_flush_log_at_trx_commit",
+    "lock_wait_timeout",
+    "mariadb_compat",
+    "secure_file_priv",
 ];
 
 const SYSTEM_SCHEMAS: &[&str] = &["information_schema", "mysql", "performance_schema", "sys"];
 
-const INFORMATION_SCHEMA_TABLES: &[&str] = &["SCHEMATA", "TABLES", "COLUMNS", "STATISTICS"];
+const INFORMATION_SCHEMA_TABLES: &[&str] = &[
+    "SCHEMATA",
+    "TABLES",
+    "COLUMNS",
+    "STATISTICS",
+    "COLUMN_STATISTICS",
+];
 
 fn is_system_schema(name: &str) -> bool {
     SYSTEM_SCHEMAS
@@ -304,17 +511,23 @@ fn information_schema_table_names() -> Vec<String> {
     out
 }
 
-fn sysvar_value(session: &SessionState, name: &str) -> Option<Cell> {
+fn sysvar_value(store: &Store, session: &SessionState, name: &str) -> Option<Cell> {
     let name = name.trim().to_ascii_lowercase();
     match name.as_str() {
         "autocommit" => Some(Cell::Int(if session.autocommit { 1 } else { 0 })),
         "version" => Some(Cell::Text(SERVER_VERSION.to_string())),
         "version_comment" => Some(Cell::Text(VERSION_COMMENT.to_string())),
-        "transaction_isolation" | "tx_isolation" => {
-            Some(Cell::Text(session.transaction_isolation.clone()))
-        }
+        "transaction_isolation" | "tx_isolation" => Some(Cell::Text(
+            session
+                .next_txn_isolation
+                .clone()
+                .unwrap_or_else(|| session.transaction_isolation.clone()),
+        )),
         "transaction_read_only" => {
-            Some(Cell::Int(if session.transaction_read_only { 1 } else { 0 }))
+            let read_only = session
+                .next_txn_read_only
+                .unwrap_or(session.transaction_read_only);
+            Some(Cell::Int(if read_only { 1 } else { 0 }))
         }
         "sql_mode" => Some(Cell::Text(session.sql_mode.clone())),
         "time_zone" => Some(Cell::Text(session.time_zone.clone())),
@@ -325,23 +538,40 @@ fn sysvar_value(session: &SessionState, name: &str) -> Option<Cell> {
         "lower_case_table_names" => Some(Cell::Int(0)),
         "max_allowed_packet" => Some(Cell::Int(64 * 1024 * 1024)),
         "socket" => Some(Cell::Text("".into())),
+        "innodb_lock_wait_timeout" => Some(Cell::Int(session.innodb_lock_wait_timeout as i64)),
+        "innodb_deadlock_detect" => Some(Cell::Int(if store.deadlock_detect() { 1 } else { 0 })),
+        "innodb_flush_log_at_trx_commit" => Some(Cell::Int(store.flush_policy().level() as i64)),
+        "lock_wait_timeout" => Some(Cell::Int(session.lock_wait_timeout as i64)),
+        "warning_count" => Some(Cell::Int(session.warnings.len() as i64)),
+        "mariadb_compat" => Some(Cell::Int(if session.mariadb_compat { 1 } else { 0 })),
+        "secure_file_priv" => Some(match store.secure_file_priv() {
+            Some(dir) => Cell::Text(dir.to_string_lossy().into_owned()),
+            None => Cell::Null,
+        }),
         _ => None,
     }
 }
 
-fn sysvar_show_value(session: &SessionState, name: &str) -> Option<String> {
+fn sysvar_show_value(store: &Store, session: &SessionState, name: &str) -> Option<String> {
     let name = name.trim().to_ascii_lowercase();
     match name.as_str() {
         "autocommit" => Some(if session.autocommit { "ON" } else { "OFF" }.to_string()),
         "transaction_read_only" => Some(
-            if session.transaction_read_only {
+            if session
+                .next_txn_read_only
+                .unwrap_or(session.transaction_read_only)
+            {
                 "ON"
             } else {
                 "OFF"
             }
             .to_string(),
         ),
-        _ => sysvar_value(session, &name).map(|c| cell_to_string(&c)),
+        "innodb_deadlock_detect" => {
+            Some(if store.deadlock_detect() { "ON" } else { "OFF" }.to_string())
+        }
+        "mariadb_compat" => Some(if session.mariadb_compat { "ON" } else { "OFF" }.to_string()),
+        _ => sysvar_value(store, session, &name).map(|c| cell_to_string(&c)),
     }
 }
 
@@ -363,6 +593,7 @@ fn like_matches(pattern: &str, value: &str) -> bool {
 
 fn try_handle_select_sysvar(
     query: &str,
+    store: &Store,
     session: &SessionState,
 ) -> Option<Result<ExecOutput, MiniError>> {
     static RE: OnceLock<Regex> = OnceLock::new();
@@ -394,7 +625,7 @@ fn try_handle_select_sysvar(
         format!("@@{var_name}")
     };
 
-    let Some(value) = sysvar_value(session, var_name) else {
+    let Some(value) = sysvar_value(store, session, var_name) else {
         return Some(Err(MiniError::UnknownSystemVariable(var_name.to_string())));
     };
 
@@ -410,7 +641,140 @@ fn try_handle_select_sysvar(
             coltype,
             colflags: ColumnFlags::empty(),
         }],
-        rows: vec![vec![value]],
+        rows: vec![vec![value]].into(),
+    }))
+}
+
+/// sqlparser has no `FORMAT=TREE` or `FORMAT=TRADITIONAL` for EXPLAIN: spell TREE as TEXT,
+/// which `handle_explain` reads as the tree format, and drop TRADITIONAL, the default.
+fn normalize_explain_format(query: &str) -> std::borrow::Cow<'_, str> {
+    let tokens = split_sql_tokens(query);
+    let is_explain = tokens.first().is_some_and(|t| {
+        ["explain", "describe", "desc"]
+            .iter()
+            .any(|k| t.eq_ignore_ascii_case(k))
+    });
+    if !is_explain {
+        return query.into();
+    }
+    let skip = if tokens
+        .get(1)
+        .is_some_and(|t| t.eq_ignore_ascii_case("analyze"))
+    {
+        2
+    } else {
+        1
+    };
+    let offset = |tok: &str| tok.as_ptr() as usize - query.as_ptr() as usize;
+    let Some(start) = tokens.get(skip).map(|t| offset(t)) else {
+        return query.into();
+    };
+    // `FORMAT=TREE`, possibly with spaces around `=`.
+    let mut clause = String::new();
+    for tok in tokens.iter().skip(skip).take(3) {
+        clause.push_str(tok);
+        let Some((key, value)) = clause.split_once('=') else {
+            continue;
+        };
+        if !key.eq_ignore_ascii_case("format") || value.is_empty() {
+            continue;
+        }
+        let replacement = if value.eq_ignore_ascii_case("tree") {
+            "FORMAT=TEXT"
+        } else if value.eq_ignore_ascii_case("traditional") {
+            ""
+        } else {
+            break;
+        };
+        let end = offset(tok) + tok.len();
+        return format!("{}{replacement}{}", &query[..start], &query[end..]).into();
+    }
+    query.into()
+}
+
+/// sqlparser has no `SELECT STRAIGHT_JOIN`: blank the modifier out, keeping every other token
+/// where it was, and return where the SELECTs that had it start, as sqlparser reports them.
+fn normalize_straight_join(query: &str) -> (std::borrow::Cow<'_, str>, Vec<Location>) {
+    let tokens = split_sql_tokens(query);
+    let mut blanked = Vec::new();
+    for pair in tokens.windows(2) {
+        let [select, modifier] = pair else {
+            continue;
+        };
+        let select = select.trim_start_matches('(');
+        if select.eq_ignore_ascii_case("select") && modifier.eq_ignore_ascii_case("straight_join") {
+            let offset = |tok: &str| tok.as_ptr() as usize - query.as_ptr() as usize;
+            blanked.push((offset(select), offset(modifier), modifier.len()));
+        }
+    }
+    if blanked.is_empty() {
+        return (query.into(), Vec::new());
+    }
+    let mut out = query.to_string();
+    let mut locations = Vec::new();
+    for (select, modifier, len) in blanked {
+        out.replace_range(modifier..modifier + len, &" ".repeat(len));
+        // Lines and columns count from 1, columns in characters.
+        let before = &query[..select];
+        let line = before.matches('\n').count() as u64 + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() as u64 + 1;
+        locations.push(Location::new(line, column));
+    }
+    (out.into(), locations)
+}
+
+/// `SHOW WARNINGS [LIMIT n]` and `SHOW COUNT(*) WARNINGS`.
+fn try_handle_show_warnings(
+    query: &str,
+    session: &SessionState,
+) -> Option<Result<ExecOutput, MiniError>> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r#"(?is)^\s*show\s+(count\s*\(\s*\*\s*\)\s+)?warnings(?:\s+limit\s+(\d+))?\s*$"#)
+            .expect("valid show warnings regex")
+    });
+    let caps = re.captures(query)?;
+    if caps.get(1).is_some() {
+        return Some(Ok(ExecOutput::ResultSet {
+            columns: vec![Column {
+                table: "".into(),
+                column: "@@session.warning_count".into(),
+                coltype: ColumnType::MYSQL_TYPE_LONGLONG,
+                colflags: ColumnFlags::empty(),
+            }],
+            rows: vec![vec![Cell::Int(session.warnings.len() as i64)]].into(),
+        }));
+    }
+    let limit = caps
+        .get(2)
+        .and_then(|m| m.as_str().parse().ok())
+        .unwrap_or(usize::MAX);
+    let rows: Vec<Vec<Cell>> = session
+        .warnings
+        .iter()
+        .take(limit)
+        .map(|w| {
+            vec![
+                Cell::Text(w.level.into()),
+                Cell::Int(w.code as i64),
+                Cell::Text(w.message.clone()),
+            ]
+        })
+        .collect();
+    let column = |name: &str, coltype| Column {
+        table: "".into(),
+        column: name.into(),
+        coltype,
+        colflags: ColumnFlags::empty(),
+    };
+    Some(Ok(ExecOutput::ResultSet {
+        columns: vec![
+            column("Level", ColumnType::MYSQL_TYPE_VAR_STRING),
+            column("Code", ColumnType::MYSQL_TYPE_LONG),
+            column("Message", ColumnType::MYSQL_TYPE_VAR_STRING),
+        ],
+        rows: rows.into(),
     }))
 }
 
@@ -474,6 +838,7 @@ fn try_handle_show_index(
     if let Err(e) = require_priv(user, Some(&db), Priv::SELECT) {
         return Some(Err(e));
     }
+    let db = table_schema(session, db, &table);
 
     let def = match store.get_table(&db, &table) {
         Ok(def) => def,
@@ -486,8 +851,11 @@ fn try_handle_show_index(
         .find(|c| c.name.eq_ignore_ascii_case(&pk_name))
         .map(|c| c.nullable)
         .unwrap_or(false);
-    let cardinality = match store.count_rows(&db, &table) {
-        Ok(n) => n.min(i64::MAX as u64) as i64,
+    let (stats, analysis) = match store
+        .table_stats(&db, &table)
+        .and_then(|stats| Ok((stats, store.table_analysis(&db, &table)?)))
+    {
+        Ok(v) => v,
         Err(e) => return Some(Err(e)),
     };
 
@@ -501,7 +869,7 @@ fn try_handle_show_index(
         Cell::Int(1),
         Cell::Text(pk_name.clone()),
         Cell::Text("A".into()),
-        Cell::Int(cardinality),
+        index_cardinality(analysis.as_ref(), &stats, "PRIMARY"),
         Cell::Null,
         Cell::Null,
         Cell::Text(if pk_nullable { "YES" } else { "NO" }.into()),
@@ -517,12 +885,12 @@ fn try_handle_show_index(
         for (seq, col) in idx.columns.iter().enumerate() {
             rows.push(vec![
                 Cell::Text(def.name.clone()),
-                Cell::Int(1), // Non_unique
+                Cell::Int(if idx.unique { 0 } else { 1 }), // Non_unique
                 Cell::Text(idx.name.clone()),
                 Cell::Int((seq + 1) as i64),
                 Cell::Text(col.clone()),
                 Cell::Text("A".into()),
-                Cell::Null,
+                index_cardinality(analysis.as_ref(), &stats, &idx.name),
                 Cell::Null,
                 Cell::Null,
                 Cell::Text("YES".into()),
@@ -628,7 +996,7 @@ fn try_handle_show_index(
                 colflags: ColumnFlags::empty(),
             },
         ],
-        rows,
+        rows: rows.into(),
     }))
 }
 fn try_handle_show_table_status(
@@ -709,8 +1077,11 @@ fn try_handle_show_table_status(
                 continue;
             }
         }
-        let row_count = match store.count_rows(&db, &table) {
-            Ok(n) => n.min(i64::MAX as u64) as i64,
+        let (stats, analysis) = match store
+            .table_stats(&db, &table)
+            .and_then(|stats| Ok((stats, store.table_analysis(&db, &table)?)))
+        {
+            Ok(v) => v,
             Err(e) => return Some(Err(e)),
         };
         let auto_inc = match store.get_table(&db, &table) {
@@ -727,16 +1098,16 @@ fn try_handle_show_table_status(
             Cell::Text("InnoDB".into()),
             Cell::Int(10),
             Cell::Text("Dynamic".into()),
-            Cell::Int(row_count),
-            Cell::Int(0),
-            Cell::Int(0),
-            Cell::Int(0),
+            stat_cell(stats.rows),
+            stat_cell(stats.avg_row_length()),
+            stat_cell(stats.data_length),
             Cell::Int(0),
+            stat_cell(stats.index_length),
             Cell::Int(0),
             auto_inc,
             Cell::Null,
-            Cell::Null,
-            Cell::Null,
+            datetime_text(stats.update_time),
+            datetime_text(analysis.and_then(|a| a.analyzed_at)),
             Cell::Text(session.collation_connection.clone()),
             Cell::Null,
             Cell::Text("".into()),
@@ -855,10 +1226,373 @@ fn try_handle_show_table_status(
                 colflags: ColumnFlags::empty(),
             },
         ],
-        rows,
+        rows: rows.into(),
     }))
 }
 
+/// `ANALYZE [NO_WRITE_TO_BINLOG | LOCAL] TABLE t [, t ...]`, plus
+/// `ANALYZE TABLE t UPDATE HISTOGRAM ON col [, col ...] [WITH n BUCKETS]` and
+/// `ANALYZE TABLE t DROP HISTOGRAM ON col [, col ...]`.
+fn try_handle_analyze_table(
+    query: &str,
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Option<Result<ExecOutput, MiniError>> {
+    let tokens = split_sql_tokens(query);
+    if tokens.is_empty() || !tokens[0].eq_ignore_ascii_case("analyze") {
+        return None;
+    }
+    Some(handle_analyze_table(&tokens, store, session, user))
+}
+
+fn handle_analyze_table(
+    tokens: &[&str],
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Result<ExecOutput, MiniError> {
+    let mut idx = 1;
+    if tokens.get(idx).is_some_and(|t| {
+        t.eq_ignore_ascii_case("no_write_to_binlog") || t.eq_ignore_ascii_case("local")
+    }) {
+        idx += 1;
+    }
+    if !tokens
+        .get(idx)
+        .is_some_and(|t| t.eq_ignore_ascii_case("table"))
+    {
+        return Err(MiniError::Parse("ANALYZE requires TABLE <table>".into()));
+    }
+    idx += 1;
+    // Names may be separated by "," or ", ": rejoin the tokens and split on commas.
+    let name_list = |tokens: &[&str]| -> Vec<String> {
+        tokens
+            .join(" ")
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+    let end = tokens[idx..]
+        .iter()
+        .position(|t| t.eq_ignore_ascii_case("update") || t.eq_ignore_ascii_case("drop"))
+        .map_or(tokens.len(), |p| idx + p);
+    let tables = name_list(&tokens[idx..end]);
+    if tables.is_empty() {
+        return Err(MiniError::Parse(
+            "ANALYZE TABLE requires a table name".into(),
+        ));
+    }
+
+    // UPDATE/DROP HISTOGRAM ON ...: Some((update, columns, buckets)).
+    let histogram = if end < tokens.len() {
+        let update = tokens[end].eq_ignore_ascii_case("update");
+        if !tokens
+            .get(end + 1)
+            .is_some_and(|t| t.eq_ignore_ascii_case("histogram"))
+            || !tokens
+                .get(end + 2)
+                .is_some_and(|t| t.eq_ignore_ascii_case("on"))
+        {
+            return Err(MiniError::Parse(format!(
+                "expected HISTOGRAM ON after {}",
+                tokens[end]
+            )));
+        }
+        let rest = &tokens[end + 3..];
+        let with = rest
+            .iter()
+            .position(|t| t.eq_ignore_ascii_case("with"))
+            .unwrap_or(rest.len());
+        let columns: Vec<String> = name_list(&rest[..with])
+            .iter()
+            .map(|c| unquote_identifier(c))
+            .collect();
+        let buckets = match &rest[with..] {
+            [] => 100,
+            [_, n, kw] if update && kw.eq_ignore_ascii_case("buckets") => {
+                match n.parse::<usize>() {
+                    Ok(n) if (1..=1024).contains(&n) => n,
+                    _ => {
+                        return Err(MiniError::Invalid(
+                            "the number of histogram buckets must be between 1 and 1024".into(),
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(MiniError::Parse(format!(
+                    "unexpected input in ANALYZE TABLE: {}",
+                    rest[with..].join(" ")
+                )))
+            }
+        };
+        if columns.is_empty() {
+            return Err(MiniError::Parse("HISTOGRAM ON requires a column".into()));
+        }
+        if tables.len() > 1 {
+            return Err(MiniError::NotSupported(
+                "only one table can be specified while modifying histogram statistics".into(),
+            ));
+        }
+        Some((update, columns, buckets))
+    } else {
+        None
+    };
+
+    let mut targets = Vec::new();
+    for table in &tables {
+        let (db, table) = parse_db_table_token(table)?;
+        let db = db
+            .or_else(|| session.current_db.clone())
+            .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+        require_priv(user, Some(&db), Priv::SELECT)?;
+        require_priv(user, Some(&db), Priv::INSERT)?;
+        targets.push((table_schema(session, db, &table), table));
+    }
+    // Like other statements that write the data dictionary, ANALYZE commits implicitly.
+    txn_commit(store, session)?;
+
+    let op = if histogram.is_some() {
+        "histogram"
+    } else {
+        "analyze"
+    };
+    let mut rows = Vec::new();
+    for (db, table) in targets {
+        let name = format!("{db}.{table}");
+        let msg = |msg_type: &str, text: String| {
+            vec![
+                Cell::Text(name.clone()),
+                Cell::Text(op.into()),
+                Cell::Text(msg_type.into()),
+                Cell::Text(text),
+            ]
+        };
+        let def = match store.get_table(&db, &table) {
+            Ok(def) => def,
+            Err(MiniError::NotFound(_)) => {
+                rows.push(msg("Error", format!("Table '{name}' doesn't exist")));
+                rows.push(msg("status", "Operation failed".into()));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let Some((update, columns, buckets)) = &histogram else {
+            store.analyze_table(&db, &table)?;
+            rows.push(msg("status", "OK".into()));
+            continue;
+        };
+
+        let existing = store
+            .table_analysis(&db, &table)?
+            .map(|a| a.histograms)
+            .unwrap_or_default();
+        let mut changes = Vec::new();
+        let mut table_rows = None;
+        for column in columns {
+            let Some(pos) = def
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(column))
+            else {
+                rows.push(msg(
+                    "Error",
+                    format!("The column '{column}' does not exist."),
+                ));
+                continue;
+            };
+            let column = def.columns[pos].name.clone();
+            if !update {
+                if existing.contains_key(&column) {
+                    rows.push(msg(
+                        "status",
+                        format!("Histogram statistics removed for column '{column}'."),
+                    ));
+                    changes.push((column, None));
+                } else {
+                    rows.push(msg(
+                        "Error",
+                        format!("No histogram statistics found for column '{column}'."),
+                    ));
+                }
+                continue;
+            }
+            if column.eq_ignore_ascii_case(&def.primary_key) {
+                rows.push(msg(
+                    "Error",
+                    format!("The column '{column}' is covered by a single-part unique index."),
+                ));
+                continue;
+            }
+            if table_rows.is_none() {
+                table_rows = Some(store.scan_rows(&db, &table)?);
+            }
+            let values = table_rows
+                .iter()
+                .flatten()
+                .map(|(_, row)| row.values.get(pos).cloned().unwrap_or(Cell::Null))
+                .collect();
+            changes.push((column.clone(), Some(build_histogram(values, *buckets))));
+            rows.push(msg(
+                "status",
+                format!("Histogram statistics created for column '{column}'."),
+            ));
+        }
+        if !changes.is_empty() {
+            store.set_histograms(&db, &table, changes)?;
+        }
+    }
+
+    let column = |name: &str| Column {
+        table: "".into(),
+        column: name.into(),
+        coltype: ColumnType::MYSQL_TYPE_VAR_STRING,
+        colflags: ColumnFlags::empty(),
+    };
+    Ok(ExecOutput::ResultSet {
+        columns: vec![
+            column("Table"),
+            column("Op"),
+            column("Msg_type"),
+            column("Msg_text"),
+        ],
+        rows: rows.into(),
+    })
+}
+
+/// Build a histogram over one column's values (NULLs included). With at most `buckets` distinct
+/// values every value gets its own bucket (singleton); otherwise values are grouped into about
+/// `buckets` buckets of equal row counts (equi-height), never splitting a value across two.
+fn build_histogram(mut values: Vec<Cell>, buckets: usize) -> Histogram {
+    let total = values.len();
+    values.retain(|v| !matches!(v, Cell::Null));
+    let non_null = values.len();
+    values.sort_by(compare_cell_for_order);
+
+    let mut groups: Vec<(Cell, usize)> = Vec::new();
+    for v in values {
+        match groups.last_mut() {
+            Some((last, n)) if compare_cell_for_order(last, &v).is_eq() => *n += 1,
+            _ => groups.push((v, 1)),
+        }
+    }
+    let freq = |n: usize| n as f64 / total as f64;
+    let equi_height = groups.len() > buckets;
+    let mut out: Vec<HistogramBucket> = Vec::new();
+    let mut current: Option<HistogramBucket> = None;
+    let mut cumulative = 0;
+    for (v, n) in groups {
+        cumulative += n;
+        let bucket = current.get_or_insert_with(|| HistogramBucket {
+            lower: v.clone(),
+            upper: v.clone(),
+            cumulative_frequency: 0.0,
+            distinct: 0,
+        });
+        bucket.upper = v;
+        bucket.distinct += 1;
+        bucket.cumulative_frequency = freq(cumulative);
+        // Close the bucket once it reaches its share of the non-NULL rows.
+        if !equi_height || cumulative * buckets >= non_null * (out.len() + 1) {
+            out.extend(current.take());
+        }
+    }
+    out.extend(current);
+
+    Histogram {
+        equi_height,
+        buckets: out,
+        null_fraction: if total == 0 {
+            0.0
+        } else {
+            freq(total - non_null)
+        },
+        buckets_specified: buckets,
+        last_updated: chrono::Utc::now().timestamp_millis(),
+    }
+}
+
+/// A histogram as the JSON document MySQL shows in `information_schema.COLUMN_STATISTICS`.
+fn histogram_json(histogram: &Histogram, ty: &SqlType) -> String {
+    let value = |c: &Cell| match c {
+        Cell::Int(_) | Cell::Float(_) => cell_to_string(c),
+        _ => json_string(&cell_to_string(c)),
+    };
+    let buckets: Vec<String> = histogram
+        .buckets
+        .iter()
+        .map(|b| {
+            if histogram.equi_height {
+                format!(
+                    "[{}, {}, {:?}, {}]",
+                    value(&b.lower),
+                    value(&b.upper),
+                    b.cumulative_frequency,
+                    b.distinct
+                )
+            } else {
+                format!("[{}, {:?}]", value(&b.lower), b.cumulative_frequency)
+            }
+        })
+        .collect();
+    let data_type = match ty {
+        SqlType::Int => "int",
+        SqlType::Float => "double",
+        SqlType::Text => "string",
+        SqlType::Date => "date",
+        SqlType::DateTime => "datetime",
+    };
+    format!(
+        "{{\"buckets\": [{}], \"data-type\": \"{data_type}\", \"null-values\": {:?}, \"collation-id\": 8, \"last-updated\": \"{}.000000\", \"sampling-rate\": 1.0, \"histogram-type\": \"{}\", \"number-of-buckets-specified\": {}}}",
+        buckets.join(", "),
+        histogram.null_fraction,
+        cell_to_string(&Cell::DateTime(histogram.last_updated)),
+        if histogram.equi_height {
+            "equi-height"
+        } else {
+            "singleton"
+        },
+        histogram.buckets_specified
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `Cardinality` as shown by SHOW INDEX and `information_schema.STATISTICS`: the figure from the
+/// last `ANALYZE TABLE`, or for the primary key the live row count.
+fn index_cardinality(analysis: Option<&TableAnalysis>, stats: &TableStats, index: &str) -> Cell {
+    match analysis.and_then(|a| a.cardinality.get(index)) {
+        Some(n) => stat_cell(*n),
+        None if index == "PRIMARY" => stat_cell(stats.rows),
+        None => Cell::Null,
+    }
+}
+
+fn stat_cell(n: u64) -> Cell {
+    Cell::Int(n.min(i64::MAX as u64) as i64)
+}
+
+fn datetime_text(millis: Option<i64>) -> Cell {
+    match millis {
+        Some(ms) => Cell::Text(cell_to_string(&Cell::DateTime(ms))),
+        None => Cell::Null,
+    }
+}
+
 pub fn execute(
     raw_query: &str,
     store: &Store,
@@ -866,6 +1600,8 @@ pub fn execute(
     user: &UserRecord,
 ) -> Result<ExecOutput, MiniError> {
     let q = strip_trailing_semicolon(strip_leading_comments(raw_query));
+    let now = chrono::Utc::now().timestamp_millis();
+    session.statement_time = now - now.rem_euclid(1000);
     if q.is_empty() {
         return Ok(ExecOutput::Ok {
             affected_rows: 0,
@@ -874,18 +1610,35 @@ pub fn execute(
         });
     }
 
-    if let Some(out) = try_handle_select_sysvar(q, session) {
+    // These read the previous statement's warnings; every other statement starts afresh.
+    if let Some(out) = try_handle_show_warnings(q, session) {
         return out;
     }
+    if let Some(out) = try_handle_select_sysvar(q, store, session) {
+        return out;
+    }
+    session.warnings.clear();
     if let Some(out) = try_handle_show_index(q, store, session, user) {
         return out;
     }
     if let Some(out) = try_handle_show_table_status(q, store, session, user) {
         return out;
     }
+    if let Some(out) = try_handle_analyze_table(q, store, session, user) {
+        return out;
+    }
+    if let Some(out) = file_io::try_handle_select_into_outfile(q, store, session, user) {
+        return out;
+    }
+    if let Some(out) = file_io::try_handle_load_data(q, store, session, user) {
+        return out;
+    }
 
     let dialect = MySqlDialect {};
-    let ast = match Parser::parse_sql(&dialect, q) {
+    let q_explain = normalize_explain_format(q);
+    let (q_parsed, straight_join_selects) = normalize_straight_join(&q_explain);
+    session.straight_join_selects = straight_join_selects;
+    let ast = match Parser::parse_sql(&dialect, &q_parsed) {
         Ok(ast) => ast,
         Err(e) => {
             return Err(MiniError::Parse(e.to_string()));
@@ -907,6 +1660,8 @@ pub fn execute(
             if session.txn.tx_id.is_some() {
                 txn_commit(store, session)?;
             }
+            // Starting a transaction also ends LOCK TABLES.
+            release_locked_tables(store, session);
             ensure_txn_active(store, session);
             session.txn.in_txn = true;
             Ok(ExecOutput::Ok {
@@ -938,11 +1693,22 @@ pub fn execute(
                 info: "".into(),
             })
         }
+        Statement::Set(ast::Set::SetTransaction { modes, .. }) => {
+            handle_set_transaction(session, modes, set_transaction_scope(q))
+        }
+        Statement::LockTables { tables } => handle_lock_tables(store, session, user, tables),
+        Statement::UnlockTables => handle_unlock_tables(store, session),
+        Statement::Flush {
+            object_type: ast::FlushType::Tables,
+            read_lock: true,
+            tables,
+            ..
+        } => handle_flush_tables_with_read_lock(store, session, user, tables),
         Statement::Savepoint { name } => handle_savepoint(session, name),
         Statement::ReleaseSavepoint { name } => handle_release_savepoint(session, name),
         Statement::ShowColumns { .. } | Statement::ShowCreate { .. } => {
             // These use internal helpers that don't scan rows usually, or use store.get_table which is catalog.
-            // Catalog is not MVCC yet.
+            // Catalog is not MVCC; metadata locks keep tables a transaction used from changing.
             // But let's ensure we are in a txn just in case.
             ensure_txn_active(store, session);
             match stmt {
@@ -952,83 +1718,125 @@ pub fn execute(
             }
         }
         // Catch-all for other statements that need implicit txn
-        _ => {
-            ensure_txn_active(store, session);
-            let res = match stmt {
-                Statement::Set(set) => handle_set(store, session, set),
-                Statement::CreateDatabase {
-                    db_name,
-                    if_not_exists,
-                    ..
-                } => handle_create_database(store, session, user, db_name, *if_not_exists),
-                Statement::Drop {
-                    object_type: ast::ObjectType::Schema | ast::ObjectType::Database,
-                    names,
-                    if_exists,
-                    ..
-                } => {
-                    if names.is_empty() {
-                        return Err(MiniError::Parse("No database name".into()));
-                    }
-                    handle_drop_database(store, session, user, &names[0], *if_exists)
-                }
-                Statement::CreateTable(c) => handle_create_table(
-                    store,
-                    session,
-                    user,
-                    &c.name,
-                    &c.columns,
-                    &c.constraints,
-                    c.if_not_exists,
-                ),
-                Statement::AlterTable(alter) => handle_alter_table(store, session, user, alter),
-                Statement::Drop {
-                    object_type: ast::ObjectType::Table,
-                    names,
-                    if_exists,
-                    ..
-                } => {
-                    if names.is_empty() {
-                        return Err(MiniError::Parse("No table name".into()));
-                    }
-                    handle_drop_table(store, session, user, &names[0], *if_exists)
-                }
-                Statement::Use(use_stmt) => handle_use(store, session, use_stmt),
-                Statement::ShowDatabases { show_options, .. } => {
-                    handle_show_databases(store, session, user, show_options)
-                }
-                Statement::ShowTables { .. } => handle_show_tables(store, session, user, stmt),
-                Statement::CreateIndex(create_index) => {
-                    handle_create_index(store, session, user, create_index)
-                }
-                Statement::ExplainTable { table_name, .. } => {
-                    handle_describe_table(store, session, user, table_name)
-                }
-                Statement::Query(q) => handle_query(store, session, user, q),
-                Statement::Insert(insert) => handle_insert(store, session, user, insert),
-                Statement::Update(update) => handle_update(store, session, user, update),
-                Statement::Delete(delete) => handle_delete(store, session, user, delete),
-                Statement::ShowVariables {
-                    filter,
-                    global,
-                    session: session_scope,
-                } => handle_show_variables(session, filter.as_ref(), *global, *session_scope),
-                _ => Err(MiniError::NotSupported(format!(
-                    "Statement not implemented: {:?}",
-                    stmt
-                ))),
-            };
+        _ => run_statement(
+            store,
+            session,
+            // SET and USE never read rows, so they must not use up a `SET TRANSACTION`.
+            !matches!(stmt, Statement::Set(_) | Statement::Use(_)),
+            |store, session| lock_statement_tables(store, session, stmt),
+            |store, session| dispatch_statement(stmt, store, session, user),
+        ),
+    }
+}
 
-            // Implicit Commit if needed
-            if !session.txn.in_txn {
-                if res.is_ok() {
-                    txn_commit(store, session)?;
-                } else {
-                    txn_rollback(store, session);
-                }
+/// Run a statement in the session's transaction: `lock` takes its table locks, held until it
+/// has committed, and `run` executes it. Outside a transaction the statement commits on its own.
+fn run_statement(
+    store: &Store,
+    session: &mut SessionState,
+    reads_rows: bool,
+    lock: impl FnOnce(&Store, &mut SessionState) -> Result<TableLockGuard, MiniError>,
+    run: impl FnOnce(&Store, &mut SessionState) -> Result<ExecOutput, MiniError>,
+) -> Result<ExecOutput, MiniError> {
+    if reads_rows {
+        ensure_txn_active(store, session);
+        refresh_statement_view(store, session);
+    }
+    session.txn.statement_reads.clear();
+    let (_table_locks, res) = match lock(store, session) {
+        Ok(guard) => (Some(guard), run(store, session)),
+        Err(e) => (None, Err(e)),
+    };
+    if res.is_err() {
+        for key in std::mem::take(&mut session.txn.statement_reads) {
+            session.txn.locked_reads.remove(&key);
+        }
+    }
+
+    // Implicit Commit if needed
+    if !session.txn.in_txn {
+        if res.is_ok() {
+            txn_commit(store, session)?;
+        } else {
+            txn_rollback(store, session);
+        }
+    } else if matches!(res, Err(MiniError::Deadlock(_))) {
+        // A deadlock victim loses its whole transaction, not just the statement.
+        txn_rollback(store, session);
+        session.txn.in_txn = false;
+    }
+    res
+}
+
+fn dispatch_statement(
+    stmt: &Statement,
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Result<ExecOutput, MiniError> {
+    match stmt {
+        Statement::Set(set) => handle_set(store, session, set),
+        Statement::CreateDatabase {
+            db_name,
+            if_not_exists,
+            ..
+        } => handle_create_database(store, session, user, db_name, *if_not_exists),
+        Statement::Drop {
+            object_type: ast::ObjectType::Schema | ast::ObjectType::Database,
+            names,
+            if_exists,
+            ..
+        } => {
+            if names.is_empty() {
+                return Err(MiniError::Parse("No database name".into()));
             }
-            res
+            handle_drop_database(store, session, user, &names[0], *if_exists)
+        }
+        Statement::CreateTable(c) => handle_create_table(store, session, user, c),
+        Statement::AlterTable(alter) => handle_alter_table(store, session, user, alter),
+        Statement::Drop {
+            object_type: ast::ObjectType::Table,
+            names,
+            if_exists,
+            temporary,
+            ..
+        } => {
+            if names.is_empty() {
+                return Err(MiniError::Parse("No table name".into()));
+            }
+            handle_drop_table(store, session, user, &names[0], *if_exists, *temporary)
+        }
+        Statement::Truncate(truncate) => handle_truncate(store, session, user, truncate),
+        Statement::Use(use_stmt) => handle_use(store, session, use_stmt),
+        Statement::ShowDatabases { show_options, .. } => {
+            handle_show_databases(store, session, user, show_options)
         }
+        Statement::ShowTables { .. } => handle_show_tables(store, session, user, stmt),
+        Statement::CreateIndex(create_index) => {
+            handle_create_index(store, session, user, create_index)
+        }
+        Statement::ExplainTable { table_name, .. } => {
+            handle_describe_table(store, session, user, table_name)
+        }
+        Statement::Explain {
+            analyze,
+            statement,
+            format,
+            ..
+        } => handle_explain(store, session, user, statement, *analyze, format.as_ref()),
+        Statement::Query(q) => handle_query(store, session, user, q),
+        Statement::Insert(insert) => handle_insert(store, session, user, insert),
+        Statement::Update(update) => handle_update(store, session, user, update),
+        Statement::Delete(delete) => handle_delete(store, session, user, delete),
+        Statement::ShowVariables {
+            filter,
+            global,
+            session: session_scope,
+        } => handle_show_variables(store, session, filter.as_ref(), *global, *session_scope),
+        _ => Err(MiniError::NotSupported(format!(
+            "Statement not implemented: {:?}",
+            stmt
+        ))),
     }
 }
 
@@ -1152,16 +1960,8 @@ fn show_columns_result(
             SqlType::DateTime => "datetime",
         };
         let null = if col.nullable { "YES" } else { "NO" };
-        let key = if col.name.eq_ignore_ascii_case(&def.primary_key) {
-            "PRI"
-        } else {
-            ""
-        };
-        let extra = if def.auto_increment && col.name.eq_ignore_ascii_case(&def.primary_key) {
-            "auto_increment"
-        } else {
-            ""
-        };
+        let key = column_key(def, col);
+        let extra = column_extra(def, col);
 
         if full {
             let collation = match col.ty {
@@ -1174,8 +1974,8 @@ fn show_columns_result(
                 collation,
                 Cell::Text(null.to_string()),
                 Cell::Text(key.to_string()),
-                Cell::Null,
-                Cell::Text(extra.into()),
+                column_default_text(col),
+                Cell::Text(extra),
                 Cell::Text("select,insert,update,references".into()),
                 Cell::Text("".into()),
             ]);
@@ -1185,13 +1985,70 @@ fn show_columns_result(
                 Cell::Text(ty.to_string()),
                 Cell::Text(null.to_string()),
                 Cell::Text(key.to_string()),
-                Cell::Null,
-                Cell::Text(extra.into()),
+                column_default_text(col),
+                Cell::Text(extra),
             ]);
         }
     }
 
-    ExecOutput::ResultSet { columns, rows }
+    ExecOutput::ResultSet {
+        columns,
+        rows: rows.into(),
+    }
+}
+
+/// A column's default as `COLUMN_DEFAULT` and SHOW COLUMNS report it: the value as text,
+/// `CURRENT_TIMESTAMP`, or the expression.
+fn column_default_text(col: &ColumnDef) -> Cell {
+    match &col.default {
+        None => Cell::Null,
+        Some(ColumnDefault::Literal(value)) => Cell::Text(cell_to_string(value)),
+        Some(ColumnDefault::CurrentTimestamp) => Cell::Text("CURRENT_TIMESTAMP".into()),
+        Some(ColumnDefault::Expr(sql)) => Cell::Text(sql.clone()),
+    }
+}
+
+/// A column's `Key`/`COLUMN_KEY`: `PRI` for the primary key, `UNI` or `MUL` for the first
+/// column of a unique or non-unique index.
+fn column_key(def: &TableDef, col: &ColumnDef) -> &'static str {
+    if col.name.eq_ignore_ascii_case(&def.primary_key) {
+        return "PRI";
+    }
+    let leading = |idx: &&IndexDef| {
+        idx.columns
+            .first()
+            .is_some_and(|c| c.eq_ignore_ascii_case(&col.name))
+    };
+    match def
+        .indexes
+        .iter()
+        .filter(leading)
+        .map(|idx| idx.unique)
+        .max()
+    {
+        Some(true) => "UNI",
+        Some(false) => "MUL",
+        None => "",
+    }
+}
+
+/// A column's `EXTRA`: auto increment, a default computed when rows are written, and
+/// `ON UPDATE CURRENT_TIMESTAMP`.
+fn column_extra(def: &TableDef, col: &ColumnDef) -> String {
+    let mut extra = Vec::new();
+    if def.auto_increment && col.name.eq_ignore_ascii_case(&def.primary_key) {
+        extra.push("auto_increment");
+    }
+    if matches!(
+        col.default,
+        Some(ColumnDefault::CurrentTimestamp | ColumnDefault::Expr(_))
+    ) {
+        extra.push("DEFAULT_GENERATED");
+    }
+    if col.on_update_current_timestamp {
+        extra.push("on update CURRENT_TIMESTAMP");
+    }
+    extra.join(" ")
 }
 
 fn handle_show_databases(
@@ -1230,10 +2087,10 @@ fn handle_show_databases(
         .into_iter()
         .filter(|db| like_pattern.is_none_or(|pat| like_matches(pat, db)))
         .map(|d| vec![Cell::Text(d)])
-        .collect();
+        .collect::<Vec<_>>();
     Ok(ExecOutput::ResultSet {
         columns: cols,
-        rows,
+        rows: rows.into(),
     })
 }
 
@@ -1302,7 +2159,7 @@ fn handle_show_tables(
         });
     }
 
-    let rows = tables
+    let rows: Vec<_> = tables
         .into_iter()
         .filter(|t| like_pattern.is_none_or(|pat| like_matches(pat, t)))
         .map(|t| {
@@ -1315,7 +2172,7 @@ fn handle_show_tables(
         .collect();
     Ok(ExecOutput::ResultSet {
         columns: cols,
-        rows,
+        rows: rows.into(),
     })
 }
 
@@ -1351,6 +2208,7 @@ fn handle_show_columns(
     let db = db_opt
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table);
     let def = store.get_table(&db, &table)?;
 
     let filter = show_options.filter_position.as_ref().map(|pos| match pos {
@@ -1383,7 +2241,7 @@ fn handle_describe_table(
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
     require_priv(user, Some(&db), Priv::SELECT)?;
-    let def = store.get_table(&db, &table)?;
+    let def = store.get_table(&table_schema(session, db, &table), &table)?;
     Ok(show_columns_result(session, &def, None, false))
 }
 
@@ -1409,7 +2267,7 @@ fn handle_show_create(
     let db = db_opt
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
-    let def = store.get_table(&db, &table)?;
+    let def = store.get_table(&table_schema(session, db, &table), &table)?;
 
     let mut parts = Vec::new();
     for col in &def.columns {
@@ -1424,13 +2282,47 @@ fn handle_show_create(
         if !col.nullable {
             line.push_str(" NOT NULL");
         }
+        match &col.default {
+            Some(ColumnDefault::Literal(value)) => {
+                let value = cell_to_string(value).replace('\'', "''");
+                line.push_str(&format!(" DEFAULT '{value}'"));
+            }
+            Some(ColumnDefault::CurrentTimestamp) => line.push_str(" DEFAULT CURRENT_TIMESTAMP"),
+            Some(ColumnDefault::Expr(sql)) => line.push_str(&format!(" DEFAULT ({sql})")),
+            // MySQL spells out the implicit default, except for keys and TEXT, which cannot
+            // have one.
+            None if col.nullable
+                && col.ty != SqlType::Text
+                && !col.name.eq_ignore_ascii_case(&def.primary_key) =>
+            {
+                line.push_str(" DEFAULT NULL")
+            }
+            None => {}
+        }
+        if col.on_update_current_timestamp {
+            line.push_str(" ON UPDATE CURRENT_TIMESTAMP");
+        }
         if def.auto_increment && col.name.eq_ignore_ascii_case(&def.primary_key) {
             line.push_str(" AUTO_INCREMENT");
         }
         parts.push(line);
     }
     parts.push(format!("PRIMARY KEY (`{}`)", def.primary_key));
-    let create = format!("CREATE TABLE `{}` ({})", def.name, parts.join(", "));
+    for idx in &def.indexes {
+        let columns: Vec<String> = idx.columns.iter().map(|c| format!("`{c}`")).collect();
+        let kind = if idx.unique { "UNIQUE KEY" } else { "KEY" };
+        parts.push(format!("{kind} `{}` ({})", idx.name, columns.join(",")));
+    }
+    let temporary = if Store::temp_schema_db(&def.db).is_some() {
+        "TEMPORARY "
+    } else {
+        ""
+    };
+    let create = format!(
+        "CREATE {temporary}TABLE `{}` ({})",
+        def.name,
+        parts.join(", ")
+    );
 
     Ok(ExecOutput::ResultSet {
         columns: vec![
@@ -1447,11 +2339,12 @@ fn handle_show_create(
                 colflags: ColumnFlags::empty(),
             },
         ],
-        rows: vec![vec![Cell::Text(def.name), Cell::Text(create)]],
+        rows: vec![vec![Cell::Text(def.name), Cell::Text(create)]].into(),
     })
 }
 
 fn handle_show_variables(
+    store: &Store,
     session: &SessionState,
     filter: Option<&ast::ShowStatementFilter>,
     _global: bool,
@@ -1488,7 +2381,7 @@ fn handle_show_variables(
         if !matches {
             continue;
         }
-        let Some(val) = sysvar_show_value(session, name) else {
+        let Some(val) = sysvar_show_value(store, session, name) else {
             continue;
         };
         rows.push(vec![Cell::Text(name.to_string()), Cell::Text(val)]);
@@ -1496,7 +2389,7 @@ fn handle_show_variables(
 
     Ok(ExecOutput::ResultSet {
         columns: cols,
-        rows,
+        rows: rows.into(),
     })
 }
 
@@ -1566,13 +2459,42 @@ fn handle_set(
                          name: &ObjectName,
                          value: &ast::Expr|
      -> Result<(), MiniError> {
-        if matches!(scope, Some(ast::ContextModifier::Global)) {
+        let var = get_ident_name(name.0.last().unwrap());
+        let global = matches!(scope, Some(ast::ContextModifier::Global));
+        if var.eq_ignore_ascii_case("innodb_deadlock_detect") {
+            if !global {
+                return Err(MiniError::GlobalVariable("innodb_deadlock_detect".into()));
+            }
+            store.set_deadlock_detect(parse_bool(value)?);
+            return Ok(());
+        }
+        if var.eq_ignore_ascii_case("innodb_flush_log_at_trx_commit") {
+            if !global {
+                return Err(MiniError::GlobalVariable(
+                    "innodb_flush_log_at_trx_commit".into(),
+                ));
+            }
+            let level = eval_expr(value)?;
+            let policy = match &level {
+                Cell::Int(n) => FlushPolicy::from_level(*n),
+                Cell::Text(t) => t.trim().parse().ok().and_then(FlushPolicy::from_level),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                MiniError::WrongValueForVar(
+                    "innodb_flush_log_at_trx_commit".into(),
+                    cell_to_string(&level),
+                )
+            })?;
+            store.set_flush_policy(policy);
+            return Ok(());
+        }
+        if global {
             return Err(MiniError::NotSupported(
                 "SET GLOBAL is not supported".into(),
             ));
         }
 
-        let var = get_ident_name(name.0.last().unwrap());
         match var.to_ascii_lowercase().as_str() {
             "autocommit" => {
                 let new_autocommit = parse_bool(value)?;
@@ -1600,6 +2522,29 @@ fn handle_set(
             "transaction_read_only" => {
                 session.transaction_read_only = parse_bool(value)?;
             }
+            "mariadb_compat" => {
+                session.mariadb_compat = parse_bool(value)?;
+            }
+            "innodb_lock_wait_timeout" => {
+                let secs = eval_expr(value)?
+                    .as_i64()
+                    .filter(|n| *n >= 1)
+                    .ok_or_else(|| {
+                        MiniError::Invalid(
+                            "innodb_lock_wait_timeout must be a positive integer".into(),
+                        )
+                    })?;
+                session.innodb_lock_wait_timeout = secs.min(1_073_741_824) as u64;
+            }
+            "lock_wait_timeout" => {
+                let secs = eval_expr(value)?
+                    .as_i64()
+                    .filter(|n| *n >= 1)
+                    .ok_or_else(|| {
+                        MiniError::Invalid("lock_wait_timeout must be a positive integer".into())
+                    })?;
+                session.lock_wait_timeout = secs.min(31_536_000) as u64;
+            }
             other => {
                 return Err(MiniError::NotSupported(format!(
                     "SET {other} is not supported"
@@ -1644,32 +2589,6 @@ fn handle_set(
             session.character_set_results = "utf8".into();
             session.collation_connection = "utf8_general_ci".into();
         }
-        ast::Set::SetTransaction { modes, .. } => {
-            for mode in modes {
-                match mode {
-                    ast::TransactionMode::AccessMode(ast::TransactionAccessMode::ReadOnly) => {
-                        session.transaction_read_only = true;
-                    }
-                    ast::TransactionMode::AccessMode(ast::TransactionAccessMode::ReadWrite) => {
-                        session.transaction_read_only = false;
-                    }
-                    ast::TransactionMode::IsolationLevel(level) => {
-                        let iso = match level {
-                            ast::TransactionIsolationLevel::ReadUncommitted => "READ-UNCOMMITTED",
-                            ast::TransactionIsolationLevel::ReadCommitted => "READ-COMMITTED",
-                            ast::TransactionIsolationLevel::RepeatableRead => "REPEATABLE-READ",
-                            ast::TransactionIsolationLevel::Serializable => "SERIALIZABLE",
-                            ast::TransactionIsolationLevel::Snapshot => {
-                                return Err(MiniError::NotSupported(
-                                    "SNAPSHOT isolation is not supported".into(),
-                                ))
-                            }
-                        };
-                        session.transaction_isolation = iso.to_string();
-                    }
-                }
-            }
-        }
         _ => return Err(MiniError::NotSupported("Unsupported SET statement".into())),
     }
 
@@ -1683,16 +2602,83 @@ fn handle_set(
     })
 }
 
-fn handle_savepoint(session: &mut SessionState, name: &Ident) -> Result<ExecOutput, MiniError> {
-    if !session.txn.in_txn {
-        return Err(MiniError::Invalid(
-            "SAVEPOINT requires an active transaction".into(),
-        ));
+/// The scope keyword of a `SET [GLOBAL | SESSION | LOCAL] TRANSACTION` statement. The parser
+/// accepts it but does not keep it in the AST, so it is read from the token stream, which
+/// skips comments.
+fn set_transaction_scope(query: &str) -> Option<ast::ContextModifier> {
+    use sqlparser::keywords::Keyword;
+    let dialect = MySqlDialect {};
+    let mut p = Parser::new(&dialect).try_with_sql(query).ok()?;
+    if !p.parse_keyword(Keyword::SET) {
+        return None;
+    }
+    match p.parse_one_of_keywords(&[Keyword::GLOBAL, Keyword::SESSION, Keyword::LOCAL])? {
+        Keyword::GLOBAL => Some(ast::ContextModifier::Global),
+        Keyword::SESSION => Some(ast::ContextModifier::Session),
+        _ => Some(ast::ContextModifier::Local),
+    }
+}
+
+/// `SET TRANSACTION` without a scope sets the characteristics of the next transaction only;
+/// `SET SESSION TRANSACTION` changes the session defaults.
+fn handle_set_transaction(
+    session: &mut SessionState,
+    modes: &[ast::TransactionMode],
+    scope: Option<ast::ContextModifier>,
+) -> Result<ExecOutput, MiniError> {
+    let mut isolation = None;
+    let mut read_only = None;
+    for mode in modes {
+        match mode {
+            ast::TransactionMode::AccessMode(ast::TransactionAccessMode::ReadOnly) => {
+                read_only = Some(true);
+            }
+            ast::TransactionMode::AccessMode(ast::TransactionAccessMode::ReadWrite) => {
+                read_only = Some(false);
+            }
+            ast::TransactionMode::IsolationLevel(level) => {
+                let iso = match level {
+                    ast::TransactionIsolationLevel::ReadUncommitted => "READ-UNCOMMITTED",
+                    ast::TransactionIsolationLevel::ReadCommitted => "READ-COMMITTED",
+                    ast::TransactionIsolationLevel::RepeatableRead => "REPEATABLE-READ",
+                    ast::TransactionIsolationLevel::Serializable => "SERIALIZABLE",
+                    ast::TransactionIsolationLevel::Snapshot => {
+                        return Err(MiniError::NotSupported(
+                            "SNAPSHOT isolation is not supported".into(),
+                        ))
+                    }
+                };
+                isolation = Some(iso.to_string());
+            }
+        }
+    }
+
+    match scope {
+        Some(ast::ContextModifier::Global) => {
+            return Err(MiniError::NotSupported(
+                "SET GLOBAL is not supported".into(),
+            ))
+        }
+        Some(ast::ContextModifier::Session | ast::ContextModifier::Local) => {
+            if let Some(iso) = isolation {
+                session.transaction_isolation = iso;
+            }
+            if let Some(read_only) = read_only {
+                session.transaction_read_only = read_only;
+            }
+        }
+        None => {
+            if session.txn.in_txn {
+                return Err(MiniError::TxInProgress);
+            }
+            if isolation.is_some() {
+                session.next_txn_isolation = isolation;
+            }
+            if read_only.is_some() {
+                session.next_txn_read_only = read_only;
+            }
+        }
     }
-    session
-        .txn
-        .savepoints
-        .push((name.value.clone(), session.txn.pending_rows.clone()));
     Ok(ExecOutput::Ok {
         affected_rows: 0,
         last_insert_id: 0,
@@ -1700,25 +2686,71 @@ fn handle_savepoint(session: &mut SessionState, name: &Ident) -> Result<ExecOutp
     })
 }
 
-fn handle_rollback_to_savepoint(
+/// `LOCK TABLES`: commit, drop the session's earlier table locks, then lock every listed table,
+/// waiting up to `lock_wait_timeout`. Until `UNLOCK TABLES` the session may only use these
+/// tables, under the names it locked them as.
+fn handle_lock_tables(
+    store: &Store,
     session: &mut SessionState,
-    name: &Ident,
+    user: &UserRecord,
+    tables: &[ast::LockTable],
 ) -> Result<ExecOutput, MiniError> {
-    if !session.txn.in_txn {
-        return Err(MiniError::Invalid(
-            "ROLLBACK TO SAVEPOINT requires an active transaction".into(),
-        ));
+    let db = session
+        .current_db
+        .clone()
+        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    require_priv(user, Some(&db), Priv::SELECT)?;
+    let mut locked = Vec::new();
+    for t in tables {
+        // Temporary tables are the session's own and need no locks; MySQL accepts and ignores
+        // them here.
+        if table_schema(session, db.clone(), &t.table.value) != db {
+            continue;
+        }
+        store.get_table(&db, &t.table.value)?;
+        locked.push(LockedTable {
+            db: db.clone(),
+            table: t.table.value.clone(),
+            alias: t.alias.as_ref().map(|a| a.value.clone()),
+            write: matches!(t.lock_type, ast::LockTableType::Write { .. }),
+        });
+    }
+    let write = locked.iter().any(|t| t.write);
+    if write && session.global_read_lock {
+        return Err(MiniError::ReadLockConflict);
     }
-    let pos = session
-        .txn
-        .savepoints
-        .iter()
-        .rposition(|(n, _)| n.eq_ignore_ascii_case(&name.value))
-        .ok_or_else(|| MiniError::NotFound(format!("unknown savepoint: {}", name.value)))?;
-
-    session.txn.pending_rows = session.txn.savepoints[pos].1.clone();
-    session.txn.savepoints.truncate(pos + 1);
 
+    txn_commit(store, session)?;
+    session.txn.in_txn = false;
+    release_locked_tables(store, session);
+
+    let owner = session.conn_id;
+    let timeout = Duration::from_secs(session.lock_wait_timeout);
+    let acquire = || -> Result<(), MiniError> {
+        // Like a writing statement, a WRITE lock keeps the global read lock out.
+        if write {
+            store.lock_global(owner, TableLockMode::Write, timeout)?;
+        }
+        for t in &locked {
+            let mode = if t.write {
+                TableLockMode::NoReadWrite
+            } else {
+                TableLockMode::ReadOnly
+            };
+            store.lock_table(owner, &t.db, &t.table, mode, timeout)?;
+        }
+        Ok(())
+    };
+    if let Err(e) = acquire() {
+        for t in &locked {
+            store.unlock_table(owner, &t.db, &t.table);
+        }
+        if write {
+            store.unlock_global(owner);
+        }
+        return Err(e);
+    }
+    session.locked_tables = locked;
     Ok(ExecOutput::Ok {
         affected_rows: 0,
         last_insert_id: 0,
@@ -1726,22 +2758,20 @@ fn handle_rollback_to_savepoint(
     })
 }
 
-fn handle_release_savepoint(
+/// `UNLOCK TABLES`: end `LOCK TABLES` (committing, as MySQL does) and release the global read lock.
+fn handle_unlock_tables(
+    store: &Store,
     session: &mut SessionState,
-    name: &Ident,
 ) -> Result<ExecOutput, MiniError> {
-    if !session.txn.in_txn {
-        return Err(MiniError::Invalid(
-            "RELEASE SAVEPOINT requires an active transaction".into(),
-        ));
+    if !session.locked_tables.is_empty() {
+        txn_commit(store, session)?;
+        session.txn.in_txn = false;
+        release_locked_tables(store, session);
+    }
+    if session.global_read_lock {
+        store.unlock_global(session.conn_id);
+        session.global_read_lock = false;
     }
-    let pos = session
-        .txn
-        .savepoints
-        .iter()
-        .rposition(|(n, _)| n.eq_ignore_ascii_case(&name.value))
-        .ok_or_else(|| MiniError::NotFound(format!("unknown savepoint: {}", name.value)))?;
-    session.txn.savepoints.truncate(pos);
     Ok(ExecOutput::Ok {
         affected_rows: 0,
         last_insert_id: 0,
@@ -1749,295 +2779,1948 @@ fn handle_release_savepoint(
     })
 }
 
-fn handle_insert(
+/// `FLUSH TABLES WITH READ LOCK`: commit, then wait for running writes and commits to finish
+/// and block new ones (in every session, this one included) until `UNLOCK TABLES`.
+fn handle_flush_tables_with_read_lock(
     store: &Store,
     session: &mut SessionState,
     user: &UserRecord,
-    insert: &ast::Insert,
+    tables: &[ObjectName],
 ) -> Result<ExecOutput, MiniError> {
-    require_priv(user, session.current_db.as_deref(), Priv::INSERT)?;
-
-    let Some(src) = &insert.source else {
-        return Err(MiniError::Parse("INSERT missing source".into()));
-    };
+    if !tables.is_empty() {
+        return Err(MiniError::NotSupported(
+            "FLUSH TABLES tbl_name ... WITH READ LOCK is not supported".into(),
+        ));
+    }
+    // MySQL requires RELOAD, which is not a separate privilege here.
+    require_priv(user, None, Priv::ALL)?;
+    if !session.locked_tables.is_empty() {
+        return Err(MiniError::LockedTables);
+    }
+    txn_commit(store, session)?;
+    session.txn.in_txn = false;
+    if !session.global_read_lock {
+        let timeout = Duration::from_secs(session.lock_wait_timeout);
+        store.lock_global(session.conn_id, TableLockMode::ReadOnly, timeout)?;
+        session.global_read_lock = true;
+    }
+    Ok(ExecOutput::Ok {
+        affected_rows: 0,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
 
-    let table_name = match &insert.table {
-        ast::TableObject::TableName(name) => name,
-        _ => {
-            return Err(MiniError::NotSupported(
-                "Complex table insert not supported".into(),
-            ));
-        }
-    };
+fn release_metadata_locks(store: &Store, session: &mut SessionState) {
+    for (db, table) in std::mem::take(&mut session.txn.metadata_locks) {
+        store.unlock_table(session.conn_id, &db, &table);
+    }
+}
 
-    let (db_opt, table) = object_name_to_parts(table_name)?;
-    let db = db_opt
-        .or_else(|| session.current_db.clone())
-        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
-    let def = store.get_table(&db, &table)?;
+fn release_locked_tables(store: &Store, session: &mut SessionState) {
+    if session.locked_tables.iter().any(|t| t.write) {
+        store.unlock_global(session.conn_id);
+    }
+    for t in session.locked_tables.drain(..) {
+        store.unlock_table(session.conn_id, &t.db, &t.table);
+    }
+}
 
-    let cols: Vec<String> = if insert.columns.is_empty() {
-        def.columns.iter().map(|c| c.name.clone()).collect()
-    } else {
-        insert.columns.iter().map(|c| c.value.clone()).collect()
-    };
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum TableAccess {
+    Read,
+    Write,
+    Ddl,
+}
 
-    // Extract rows from source
-    let rows_exprs = match &src.body.as_ref() {
-        SetExpr::Values(values) => &values.rows,
-        _ => {
-            return Err(MiniError::NotSupported(
-                "INSERT only supports VALUES".into(),
-            ))
-        }
-    };
+/// A table a statement uses, under the name or alias it uses it by.
+#[derive(Debug)]
+struct TableRef {
+    db: String,
+    table: String,
+    alias: Option<String>,
+    access: TableAccess,
+}
 
-    let buffer_writes = should_buffer_writes(session);
-    let mut locks = RowLockGuard::new(store, session.conn_id);
-    let mut stmt_rows: BTreeMap<i64, Row> = BTreeMap::new();
-    let mut affected = 0u64;
-    let mut first_generated_id: Option<i64> = None;
-    let pk_index = def
-        .columns
-        .iter()
-        .position(|c| c.name.eq_ignore_ascii_case(&def.primary_key))
-        .ok_or_else(|| MiniError::Invalid("corrupt table: missing primary key column".into()))?;
-    let mut auto_inc_initialized = false;
+/// Table and global locks held for one statement (or a result stream), released on drop.
+struct TableLockGuard {
+    store: Store,
+    owner: u32,
+    tables: Vec<(String, String)>,
+    global: bool,
+}
 
-    for row_exprs in rows_exprs {
-        if row_exprs.len() != cols.len() {
-            return Err(MiniError::Invalid("column/value count mismatch".into()));
+impl Drop for TableLockGuard {
+    fn drop(&mut self) {
+        for (db, table) in &self.tables {
+            self.store.unlock_table(self.owner, db, table);
         }
-        let mut map: BTreeMap<String, Cell> = BTreeMap::new();
-        for (c, expr) in cols.iter().zip(row_exprs.iter()) {
-            map.insert(c.clone(), eval_expr(expr)?);
+        if self.global {
+            self.store.unlock_global(self.owner);
         }
+    }
+}
 
-        let mut row_vals = Vec::with_capacity(def.columns.len());
-        for coldef in &def.columns {
-            let v = map.get(&coldef.name).cloned().unwrap_or(Cell::Null);
-            let coerced = coerce_cell(v, &coldef.ty)?;
-            row_vals.push(coerced);
+/// Take the table (metadata) locks a statement needs. Under `LOCK TABLES` the session's own
+/// locks must already cover every table it uses (error 1100 / 1099 otherwise). Other sessions
+/// lock the tables they read or write until their transaction ends, so DDL, which locks its
+/// tables exclusively for the statement, waits for transactions that used them. Writes wait for
+/// the global read lock; a session holding it cannot write at all (error 1223).
+fn lock_statement_tables(
+    store: &Store,
+    session: &mut SessionState,
+    stmt: &Statement,
+) -> Result<TableLockGuard, MiniError> {
+    let (mut refs, writes) = statement_tables(session, stmt)?;
+    if let Statement::Drop {
+        object_type: ast::ObjectType::Schema | ast::ObjectType::Database,
+        names,
+        ..
+    } = stmt
+    {
+        if !session.locked_tables.is_empty() {
+            return Err(MiniError::LockedTables);
+        }
+        // Dropping a database drops its tables.
+        if let Some(name) = names.first() {
+            let (_, db) = object_name_to_parts(name)?;
+            for table in store.list_tables(&db).unwrap_or_default() {
+                refs.push(TableRef {
+                    db: db.clone(),
+                    table,
+                    alias: None,
+                    access: TableAccess::Ddl,
+                });
+            }
         }
+    }
+    lock_tables(store, session, refs, writes)
+}
 
-        let mut pk_cell = row_vals
-            .get(pk_index)
-            .cloned()
-            .ok_or_else(|| MiniError::Invalid("corrupt row".into()))?;
-        let mut generated = false;
-        let pk = match pk_cell.as_i64() {
-            Some(pk) => pk,
-            None if matches!(pk_cell, Cell::Null) && def.auto_increment => {
-                if !auto_inc_initialized {
-                    if store.auto_increment_next(&db, &table)?.is_none() {
-                        let mut max_pk = 0i64;
-                        for (pk, _row) in txn_scan_rows(store, session, &db, &table)? {
-                            max_pk = max_pk.max(pk);
-                        }
-                        store.bump_auto_increment_next(
-                            &db,
-                            &table,
-                            max_pk.saturating_add(1).max(1),
-                        )?;
+/// Take the metadata (or, under LOCK TABLES, check the table) locks for `refs`; `writes` says
+/// whether the statement changes data or schema.
+fn lock_tables(
+    store: &Store,
+    session: &mut SessionState,
+    refs: Vec<TableRef>,
+    writes: bool,
+) -> Result<TableLockGuard, MiniError> {
+    let mut guard = TableLockGuard {
+        store: store.clone(),
+        owner: session.conn_id,
+        tables: Vec::new(),
+        global: false,
+    };
+    if writes && session.global_read_lock {
+        return Err(MiniError::ReadLockConflict);
+    }
+    let timeout = Duration::from_secs(session.lock_wait_timeout);
+
+    if !session.locked_tables.is_empty() {
+        // The session's temporary tables need no locks.
+        for r in refs
+            .iter()
+            .filter(|r| Store::temp_schema_db(&r.db).is_none())
+        {
+            let mut held = session.locked_tables.iter().filter(|t| {
+                t.db == r.db
+                    && t.table == r.table
+                    && match (&t.alias, &r.alias) {
+                        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                        (None, None) => true,
+                        _ => false,
                     }
-                    auto_inc_initialized = true;
+            });
+            let name = r.alias.clone().unwrap_or_else(|| r.table.clone());
+            let Some(first) = held.next() else {
+                return Err(MiniError::TableNotLocked(name));
+            };
+            let write = first.write || held.any(|t| t.write);
+            if r.access != TableAccess::Read && !write {
+                return Err(MiniError::TableNotLockedForWrite(name));
+            }
+        }
+        // WRITE locks already hold the global lock; e.g. CREATE TABLE under READ locks does not.
+        if writes && !session.locked_tables.iter().any(|t| t.write) {
+            guard.global = store.lock_global(guard.owner, TableLockMode::Write, timeout)?
+                == LockGrant::Acquired;
+        }
+        return Ok(guard);
+    }
+
+    if refs
+        .iter()
+        .any(|r| r.access == TableAccess::Ddl && Store::temp_schema_db(&r.db).is_none())
+    {
+        // DDL commits implicitly, except on temporary tables. Do it before waiting, so this
+        // session's own metadata locks do not hold up others.
+        txn_commit(store, session)?;
+    }
+
+    if writes {
+        guard.global =
+            store.lock_global(guard.owner, TableLockMode::Write, timeout)? == LockGrant::Acquired;
+    }
+    let mut strongest: BTreeMap<(String, String), TableAccess> = BTreeMap::new();
+    for r in refs {
+        let access = strongest.entry((r.db, r.table)).or_insert(r.access);
+        *access = (*access).max(r.access);
+    }
+    for ((db, table), access) in strongest {
+        let mode = match access {
+            TableAccess::Read => TableLockMode::Read,
+            TableAccess::Write => TableLockMode::Write,
+            TableAccess::Ddl => TableLockMode::Exclusive,
+        };
+        let grant = store.lock_table(guard.owner, &db, &table, mode, timeout)?;
+        if access == TableAccess::Ddl {
+            if grant == LockGrant::Acquired {
+                guard.tables.push((db, table));
+            }
+        } else {
+            session.txn.metadata_locks.insert((db, table));
+        }
+    }
+    Ok(guard)
+}
+
+/// The tables a statement uses and whether it changes data or schema. System schemas are left
+/// out; names without a database are resolved against the current one (and skipped without it).
+fn statement_tables(
+    session: &SessionState,
+    stmt: &Statement,
+) -> Result<(Vec<TableRef>, bool), MiniError> {
+    let mut refs = Vec::new();
+    let mut add = |name: &ObjectName, alias: Option<&ast::TableAlias>, access| {
+        let (db, table) = object_name_to_parts(name)?;
+        let Some(db) = db.or_else(|| session.current_db.clone()) else {
+            return Ok::<_, MiniError>(());
+        };
+        if !is_system_schema(&db) {
+            refs.push(TableRef {
+                db: table_schema(session, db, &table),
+                table,
+                alias: alias.map(|a| a.name.value.clone()),
+                access,
+            });
+        }
+        Ok(())
+    };
+    let writes = match stmt {
+        Statement::Query(q) => {
+            query_tables(q, &mut add)?;
+            false
+        }
+        Statement::Explain { statement, .. } => {
+            if let Statement::Query(q) = statement.as_ref() {
+                query_tables(q, &mut add)?;
+            }
+            false
+        }
+        Statement::Insert(insert) => {
+            if let ast::TableObject::TableName(name) = &insert.table {
+                add(name, None, TableAccess::Write)?;
+            }
+            if let Some(src) = &insert.source {
+                query_tables(src, &mut add)?;
+            }
+            if let Some(ast::OnInsert::DuplicateKeyUpdate(assignments)) = &insert.on {
+                for a in assignments {
+                    expr_tables(&a.value, &mut add)?;
                 }
-                let pk = store.allocate_auto_increment(&db, &table)?;
-                pk_cell = Cell::Int(pk);
-                row_vals[pk_index] = pk_cell.clone();
-                generated = true;
-                pk
             }
-            _ => {
-                return Err(MiniError::Invalid(
-                    "PRIMARY KEY must be provided (INT)".into(),
+            true
+        }
+        Statement::Update(update) => {
+            // A multi-table UPDATE writes the tables its assignments name; an unqualified
+            // column may belong to any of them.
+            let qualifiers: Option<Vec<String>> = update
+                .assignments
+                .iter()
+                .map(|a| match &a.target {
+                    ast::AssignmentTarget::ColumnName(name) if name.0.len() >= 2 => {
+                        Some(get_ident_name(&name.0[name.0.len() - 2]))
+                    }
+                    _ => None,
+                })
+                .collect();
+            match qualifiers {
+                Some(qualifiers) if !update.table.joins.is_empty() => {
+                    let written = |name: &ObjectName, alias: Option<&ast::TableAlias>| {
+                        let used = alias.map_or_else(
+                            || {
+                                object_name_to_parts(name)
+                                    .map(|(_, t)| t)
+                                    .unwrap_or_default()
+                            },
+                            |a| a.name.value.clone(),
+                        );
+                        qualifiers.iter().any(|q| q.eq_ignore_ascii_case(&used))
+                    };
+                    dml_tables(&update.table, &written, &mut add)?;
+                }
+                _ if !update.table.joins.is_empty() => {
+                    dml_tables(&update.table, &|_, _| true, &mut add)?
+                }
+                _ => table_with_joins_tables(&update.table, TableAccess::Write, &mut add)?,
+            }
+            for a in &update.assignments {
+                expr_tables(&a.value, &mut add)?;
+            }
+            if let Some(selection) = &update.selection {
+                expr_tables(selection, &mut add)?;
+            }
+            true
+        }
+        Statement::Delete(delete) => {
+            let (ast::FromTable::WithFromKeyword(from) | ast::FromTable::WithoutKeyword(from)) =
+                &delete.from;
+            if let Some(using) = &delete.using {
+                // `DELETE FROM t1 USING t1 JOIN t2`: the tables before USING are written.
+                let targets: Vec<&ObjectName> = from
+                    .iter()
+                    .filter_map(|t| match &t.relation {
+                        TableFactor::Table { name, .. } => Some(name),
+                        _ => None,
+                    })
+                    .collect();
+                let written = |name: &ObjectName, alias: Option<&ast::TableAlias>| {
+                    alias.is_none() && targets.iter().any(|t| t.0 == name.0)
+                };
+                for t in using {
+                    dml_tables(t, &written, &mut add)?;
+                }
+            } else if !delete.tables.is_empty() {
+                // `DELETE t1 FROM t1 JOIN t2`: the named tables (or aliases) are written.
+                let written = |name: &ObjectName, alias: Option<&ast::TableAlias>| {
+                    let used = alias.map_or_else(
+                        || {
+                            object_name_to_parts(name)
+                                .map(|(_, t)| t)
+                                .unwrap_or_default()
+                        },
+                        |a| a.name.value.clone(),
+                    );
+                    delete.tables.iter().any(|t| {
+                        object_name_to_parts(t).is_ok_and(|(_, t)| t.eq_ignore_ascii_case(&used))
+                    })
+                };
+                for t in from {
+                    dml_tables(t, &written, &mut add)?;
+                }
+            } else {
+                for t in from {
+                    table_with_joins_tables(t, TableAccess::Write, &mut add)?;
+                }
+            }
+            if let Some(selection) = &delete.selection {
+                expr_tables(selection, &mut add)?;
+            }
+            true
+        }
+        Statement::AlterTable(alter) => {
+            add(&alter.name, None, TableAccess::Ddl)?;
+            true
+        }
+        Statement::CreateIndex(create_index) => {
+            add(&create_index.table_name, None, TableAccess::Ddl)?;
+            true
+        }
+        Statement::Truncate(truncate) => {
+            for target in &truncate.table_names {
+                add(&target.name, None, TableAccess::Ddl)?;
+            }
+            true
+        }
+        Statement::Drop {
+            object_type: ast::ObjectType::Table,
+            names,
+            temporary,
+            ..
+        } => {
+            // Temporary tables are the session's own and need no locks.
+            if !temporary {
+                for name in names {
+                    add(name, None, TableAccess::Ddl)?;
+                }
+            }
+            true
+        }
+        Statement::CreateTable(create) => {
+            if let Some(
+                ast::CreateTableLikeKind::Plain(like)
+                | ast::CreateTableLikeKind::Parenthesized(like),
+            ) = &create.like
+            {
+                add(&like.name, None, TableAccess::Read)?;
+            }
+            if let Some(query) = &create.query {
+                query_tables(query, &mut add)?;
+            }
+            true
+        }
+        Statement::CreateDatabase { .. }
+        | Statement::Drop {
+            object_type: ast::ObjectType::Schema | ast::ObjectType::Database,
+            ..
+        } => true,
+        _ => false,
+    };
+    Ok((refs, writes))
+}
+
+type AddTable<'f> =
+    dyn FnMut(&ObjectName, Option<&ast::TableAlias>, TableAccess) -> Result<(), MiniError> + 'f;
+
+/// Report the tables a query reads: in its FROM items and joins, derived tables, the queries
+/// of its WITH clause and the subqueries of its expressions. The names the WITH clause defines
+/// are not tables.
+fn query_tables(query: &ast::Query, add: &mut AddTable<'_>) -> Result<(), MiniError> {
+    fn set_expr_tables(body: &SetExpr, add: &mut AddTable<'_>) -> Result<(), MiniError> {
+        match body {
+            SetExpr::Select(select) => {
+                for t in &select.from {
+                    table_with_joins_tables(t, TableAccess::Read, add)?;
+                }
+                for item in &select.projection {
+                    if let ast::SelectItem::UnnamedExpr(expr)
+                    | ast::SelectItem::ExprWithAlias { expr, .. } = item
+                    {
+                        expr_tables(expr, add)?;
+                    }
+                }
+                if let ast::GroupByExpr::Expressions(exprs, _) = &select.group_by {
+                    for expr in exprs {
+                        expr_tables(expr, add)?;
+                    }
+                }
+                for expr in select.selection.iter().chain(&select.having) {
+                    expr_tables(expr, add)?;
+                }
+            }
+            SetExpr::Query(q) => query_tables(q, add)?,
+            SetExpr::SetOperation { left, right, .. } => {
+                set_expr_tables(left, add)?;
+                set_expr_tables(right, add)?;
+            }
+            SetExpr::Values(values) => {
+                for expr in values.rows.iter().flatten() {
+                    expr_tables(expr, add)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    let ctes: Vec<&str> = query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .map(|cte| cte.alias.name.value.as_str())
+        .collect();
+    let mut add_table =
+        |name: &ObjectName, alias: Option<&ast::TableAlias>, access| match object_name_to_parts(
+            name,
+        ) {
+            Ok((None, table)) if ctes.iter().any(|c| c.eq_ignore_ascii_case(&table)) => Ok(()),
+            _ => add(name, alias, access),
+        };
+    let add: &mut AddTable<'_> = &mut add_table;
+    for cte in query.with.iter().flat_map(|with| &with.cte_tables) {
+        query_tables(&cte.query, add)?;
+    }
+    set_expr_tables(&query.body, add)?;
+    if let Some(ast::OrderByKind::Expressions(exprs)) = query.order_by.as_ref().map(|o| &o.kind) {
+        for o in exprs {
+            expr_tables(&o.expr, add)?;
+        }
+    }
+    Ok(())
+}
+
+/// Report the tables the subqueries of an expression read.
+fn expr_tables(expr: &ast::Expr, add: &mut AddTable<'_>) -> Result<(), MiniError> {
+    match expr {
+        ast::Expr::Subquery(q) | ast::Expr::Exists { subquery: q, .. } => query_tables(q, add),
+        ast::Expr::InSubquery { expr, subquery, .. } => {
+            expr_tables(expr, add)?;
+            query_tables(subquery, add)
+        }
+        ast::Expr::Nested(e)
+        | ast::Expr::UnaryOp { expr: e, .. }
+        | ast::Expr::Cast { expr: e, .. }
+        | ast::Expr::Collate { expr: e, .. }
+        | ast::Expr::IsNull(e)
+        | ast::Expr::IsNotNull(e)
+        | ast::Expr::IsTrue(e)
+        | ast::Expr::IsNotTrue(e)
+        | ast::Expr::IsFalse(e)
+        | ast::Expr::IsNotFalse(e) => expr_tables(e, add),
+        ast::Expr::BinaryOp { left, right, .. }
+        | ast::Expr::AnyOp { left, right, .. }
+        | ast::Expr::AllOp { left, right, .. }
+        | ast::Expr::IsDistinctFrom(left, right)
+        | ast::Expr::IsNotDistinctFrom(left, right)
+        | ast::Expr::Like {
+            expr: left,
+            pattern: right,
+            ..
+        }
+        | ast::Expr::ILike {
+            expr: left,
+            pattern: right,
+            ..
+        } => {
+            expr_tables(left, add)?;
+            expr_tables(right, add)
+        }
+        ast::Expr::Between {
+            expr, low, high, ..
+        } => {
+            expr_tables(expr, add)?;
+            expr_tables(low, add)?;
+            expr_tables(high, add)
+        }
+        ast::Expr::InList { expr, list, .. } => {
+            expr_tables(expr, add)?;
+            list.iter().try_for_each(|e| expr_tables(e, add))
+        }
+        ast::Expr::Tuple(list) => list.iter().try_for_each(|e| expr_tables(e, add)),
+        ast::Expr::Case {
+            operand,
+            conditions,
+            else_result,
+            ..
+        } => {
+            for e in operand.iter().chain(else_result) {
+                expr_tables(e, add)?;
+            }
+            for when in conditions {
+                expr_tables(&when.condition, add)?;
+                expr_tables(&when.result, add)?;
+            }
+            Ok(())
+        }
+        ast::Expr::Function(f) => match &f.args {
+            ast::FunctionArguments::List(l) => l.args.iter().try_for_each(|arg| match arg {
+                ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e))
+                | ast::FunctionArg::Named {
+                    arg: ast::FunctionArgExpr::Expr(e),
+                    ..
+                }
+                | ast::FunctionArg::ExprNamed {
+                    arg: ast::FunctionArgExpr::Expr(e),
+                    ..
+                } => expr_tables(e, add),
+                _ => Ok(()),
+            }),
+            ast::FunctionArguments::Subquery(q) => query_tables(q, add),
+            ast::FunctionArguments::None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Report the tables of a FROM item: its first relation with `access`, joined ones as reads.
+fn table_with_joins_tables(
+    t: &ast::TableWithJoins,
+    access: TableAccess,
+    add: &mut AddTable<'_>,
+) -> Result<(), MiniError> {
+    fn factor_tables(
+        factor: &TableFactor,
+        access: TableAccess,
+        add: &mut AddTable<'_>,
+    ) -> Result<(), MiniError> {
+        match factor {
+            TableFactor::Table { name, alias, .. } => add(name, alias.as_ref(), access),
+            TableFactor::Derived { subquery, .. } => query_tables(subquery, add),
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => table_with_joins_tables(table_with_joins, access, add),
+            _ => Ok(()),
+        }
+    }
+    factor_tables(&t.relation, access, add)?;
+    for join in &t.joins {
+        factor_tables(&join.relation, TableAccess::Read, add)?;
+        join_on_tables(&join.join_operator, add)?;
+    }
+    Ok(())
+}
+
+/// Report the tables the subqueries of a join's ON condition read.
+fn join_on_tables(op: &ast::JoinOperator, add: &mut AddTable<'_>) -> Result<(), MiniError> {
+    match op {
+        ast::JoinOperator::Join(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::Inner(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::Left(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::LeftOuter(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::Right(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::RightOuter(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::FullOuter(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::CrossJoin(ast::JoinConstraint::On(on))
+        | ast::JoinOperator::StraightJoin(ast::JoinConstraint::On(on)) => expr_tables(on, add),
+        _ => Ok(()),
+    }
+}
+
+/// Report the tables of a multi-table UPDATE or DELETE's FROM item, as writes when `written`
+/// says the statement changes them and as reads otherwise.
+fn dml_tables(
+    t: &ast::TableWithJoins,
+    written: &dyn Fn(&ObjectName, Option<&ast::TableAlias>) -> bool,
+    add: &mut AddTable<'_>,
+) -> Result<(), MiniError> {
+    let relations = std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation));
+    // Other table factors are rejected when the statement runs.
+    for relation in relations {
+        if let TableFactor::Table { name, alias, .. } = relation {
+            let access = if written(name, alias.as_ref()) {
+                TableAccess::Write
+            } else {
+                TableAccess::Read
+            };
+            add(name, alias.as_ref(), access)?;
+        }
+    }
+    for join in &t.joins {
+        join_on_tables(&join.join_operator, add)?;
+    }
+    Ok(())
+}
+
+fn handle_savepoint(session: &mut SessionState, name: &Ident) -> Result<ExecOutput, MiniError> {
+    if !session.txn.in_txn {
+        return Err(MiniError::Invalid(
+            "SAVEPOINT requires an active transaction".into(),
+        ));
+    }
+    session
+        .txn
+        .savepoints
+        .push((name.value.clone(), session.txn.pending_rows.clone()));
+    Ok(ExecOutput::Ok {
+        affected_rows: 0,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
+fn handle_rollback_to_savepoint(
+    session: &mut SessionState,
+    name: &Ident,
+) -> Result<ExecOutput, MiniError> {
+    if !session.txn.in_txn {
+        return Err(MiniError::Invalid(
+            "ROLLBACK TO SAVEPOINT requires an active transaction".into(),
+        ));
+    }
+    let pos = session
+        .txn
+        .savepoints
+        .iter()
+        .rposition(|(n, _)| n.eq_ignore_ascii_case(&name.value))
+        .ok_or_else(|| MiniError::NotFound(format!("unknown savepoint: {}", name.value)))?;
+
+    session.txn.pending_rows = session.txn.savepoints[pos].1.clone();
+    session.txn.savepoints.truncate(pos + 1);
+
+    Ok(ExecOutput::Ok {
+        affected_rows: 0,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
+fn handle_release_savepoint(
+    session: &mut SessionState,
+    name: &Ident,
+) -> Result<ExecOutput, MiniError> {
+    if !session.txn.in_txn {
+        return Err(MiniError::Invalid(
+            "RELEASE SAVEPOINT requires an active transaction".into(),
+        ));
+    }
+    let pos = session
+        .txn
+        .savepoints
+        .iter()
+        .rposition(|(n, _)| n.eq_ignore_ascii_case(&name.value))
+        .ok_or_else(|| MiniError::NotFound(format!("unknown savepoint: {}", name.value)))?;
+    session.txn.savepoints.truncate(pos);
+    Ok(ExecOutput::Ok {
+        affected_rows: 0,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
+fn handle_insert(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    insert: &ast::Insert,
+) -> Result<ExecOutput, MiniError> {
+    require_priv(user, session.current_db.as_deref(), Priv::INSERT)?;
+    let returning = returning_items(session, user, insert.returning.as_ref())?;
+
+    let Some(src) = &insert.source else {
+        return Err(MiniError::Parse("INSERT missing source".into()));
+    };
+
+    let table_name = match &insert.table {
+        ast::TableObject::TableName(name) => name,
+        _ => {
+            return Err(MiniError::NotSupported(
+                "Complex table insert not supported".into(),
+            ));
+        }
+    };
+
+    let (db_opt, table) = object_name_to_parts(table_name)?;
+    let db = db_opt
+        .or_else(|| session.current_db.clone())
+        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table);
+    let def = store.get_table(&db, &table)?;
+
+    // Positions of the columns the VALUES rows set, in their order.
+    let targets: Vec<usize> = if insert.columns.is_empty() {
+        (0..def.columns.len()).collect()
+    } else {
+        let mut targets = Vec::with_capacity(insert.columns.len());
+        for c in &insert.columns {
+            let idx = def
+                .columns
+                .iter()
+                .position(|col| col.name.eq_ignore_ascii_case(&c.value))
+                .ok_or_else(|| MiniError::NotFound(format!("unknown column: {}", c.value)))?;
+            if targets.contains(&idx) {
+                return Err(MiniError::Invalid(format!(
+                    "Column '{}' specified twice",
+                    c.value
+                )));
+            }
+            targets.push(idx);
+        }
+        targets
+    };
+    let col_map = insert_row_col_map(&def, &targets, insert.insert_alias.as_ref())?;
+
+    // `ON DUPLICATE KEY UPDATE` reads the existing row by column name, and the row that was to
+    // be inserted, placed after it, through `VALUES(col)` and the row alias.
+    let upsert = match &insert.on {
+        None => None,
+        Some(ast::OnInsert::DuplicateKeyUpdate(assignments)) => {
+            let mut map = build_col_map(&[&def]);
+            for (key, &idx) in &col_map {
+                if !map.contains_key(key) {
+                    let idx = if idx == usize::MAX {
+                        idx
+                    } else {
+                        def.columns.len() + idx
+                    };
+                    map.insert(key.clone(), idx);
+                }
+            }
+            Some((upsert_assignments(&def, assignments)?, map))
+        }
+        Some(_) => {
+            return Err(MiniError::NotSupported(
+                "INSERT ... ON CONFLICT is not supported".into(),
+            ))
+        }
+    };
+
+    // Rows come from VALUES lists, evaluated one at a time, or from a SELECT. The SELECT's
+    // stream is kept until the rows are staged: an autocommit statement's stream holds the
+    // metadata locks of the tables it reads.
+    let mut selected = None;
+    let value_rows: &[Vec<ast::Expr>] = match src.body.as_ref() {
+        SetExpr::Values(values) => &values.rows,
+        _ => {
+            let ExecOutput::ResultSet { columns, rows } = handle_query(store, session, user, src)?
+            else {
+                return Err(MiniError::Invalid("INSERT source must be a query".into()));
+            };
+            if columns.len() != targets.len() {
+                return Err(MiniError::Invalid("column/value count mismatch".into()));
+            }
+            selected = Some(rows);
+            &[]
+        }
+    };
+    // Like MySQL, only a multi-row INSERT reports what it did in the OK packet's info.
+    let multi_row = value_rows.len() > 1 || selected.is_some();
+    let source_rows = value_rows
+        .iter()
+        .map(|exprs| Ok(InsertValues::Exprs(exprs)))
+        .chain(
+            selected
+                .iter_mut()
+                .flatten()
+                .map(|row| row.map(InsertValues::Cells)),
+        );
+
+    let mut locks = RowLockGuard::new(store, session);
+    let mut stmt_rows = StatementRows::new(&def);
+    let mut returned: Vec<Row> = Vec::new();
+    let mut affected = 0u64;
+    let (mut records, mut duplicates) = (0u64, 0u64);
+    let mut first_generated_id: Option<i64> = None;
+    let pk_index = primary_key_index(&def)?;
+    let mut auto_inc_initialized = false;
+
+    for values in source_rows {
+        records += 1;
+        let mut row_vals = insert_row_values(session, &def, &targets, &col_map, values?)?;
+        let (pk, generated) = insert_primary_key(
+            store,
+            session,
+            &def,
+            pk_index,
+            &mut row_vals,
+            &mut auto_inc_initialized,
+        )?;
+
+        let row = Row { values: row_vals };
+        lock_insert_intentions(&mut locks, &def, pk, &row, None)?;
+        locks.lock_row(&db, &table, pk)?;
+
+        // Affected rows follow MySQL: 1 per insert, 1 per row REPLACE deletes, and 2 for a row
+        // ON DUPLICATE KEY UPDATE changes (0 if it stays the same).
+        let mut conflicts = stmt_rows.conflicts(store, session, pk, &row, false)?;
+        if conflicts.is_empty() || insert.replace_into {
+            if !conflicts.is_empty() {
+                duplicates += 1;
+            }
+            for (other, _) in conflicts {
+                locks.lock_row(&db, &table, other)?;
+                stmt_rows.put(other, None);
+                affected += 1;
+            }
+            if returning.is_some() {
+                returned.push(row.clone());
+            }
+            stmt_rows.put(pk, Some(row));
+            affected += 1;
+            if generated && first_generated_id.is_none() {
+                first_generated_id = Some(pk);
+            }
+            continue;
+        }
+        let (other, duplicate) = conflicts.swap_remove(0);
+        duplicates += 1;
+        let Some((assignments, upsert_col_map)) = &upsert else {
+            if !insert.ignore {
+                return Err(duplicate);
+            }
+            session.warn(ER_DUP_ENTRY, duplicate.to_string());
+            continue;
+        };
+        lock_row_for_write(session, &mut locks, &db, &table, other)?;
+        let old_row = stmt_rows
+            .get(store, session, other)?
+            .ok_or_else(|| MiniError::Invalid("corrupt index entry".into()))?;
+        let new_row = upsert_row(session, &def, assignments, upsert_col_map, &old_row, &row)?;
+        if new_row.values == old_row.values {
+            continue;
+        }
+        // The updated row may collide with yet another one.
+        let update = update_row(
+            store,
+            session,
+            &mut locks,
+            &mut stmt_rows,
+            other,
+            &old_row,
+            new_row.clone(),
+        );
+        match update {
+            Ok(()) => {}
+            Err(duplicate @ MiniError::DuplicateEntry(..)) if insert.ignore => {
+                session.warn(ER_DUP_ENTRY, duplicate.to_string());
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        if returning.is_some() {
+            returned.push(new_row);
+        }
+        affected += 2;
+    }
+
+    // Evaluated before anything is staged, so a bad RETURNING list fails the whole statement.
+    let result = returning
+        .map(|items| returning_result(session, &def, items, &returned))
+        .transpose()?;
+    stage_row_changes(session, &mut locks, &db, &table, stmt_rows.rows);
+    if let Some(result) = result {
+        return Ok(result);
+    }
+
+    let info = if multi_row {
+        format!(
+            "Records: {records}  Duplicates: {duplicates}  Warnings: {}",
+            session.warnings.len()
+        )
+    } else {
+        String::new()
+    };
+    Ok(ExecOutput::Ok {
+        affected_rows: affected,
+        last_insert_id: first_generated_id.unwrap_or(0).max(0) as u64,
+        info,
+    })
+}
+
+fn primary_key_index(def: &TableDef) -> Result<usize, MiniError> {
+    def.columns
+        .iter()
+        .position(|c| c.name.eq_ignore_ascii_case(&def.primary_key))
+        .ok_or_else(|| MiniError::Invalid("corrupt table: missing primary key column".into()))
+}
+
+/// The primary key of a row about to be inserted, and whether it was generated: a NULL
+/// auto-increment key takes the next counter value, written into `row_vals`. A table without a
+/// counter gets one past its largest key the first time (`auto_inc_initialized`).
+fn insert_primary_key(
+    store: &Store,
+    session: &SessionState,
+    def: &TableDef,
+    pk_index: usize,
+    row_vals: &mut [Cell],
+    auto_inc_initialized: &mut bool,
+) -> Result<(i64, bool), MiniError> {
+    let (db, table) = (&def.db, &def.name);
+    let pk_cell = row_vals
+        .get(pk_index)
+        .ok_or_else(|| MiniError::Invalid("corrupt row".into()))?;
+    match pk_cell.as_i64() {
+        Some(pk) => {
+            if def.auto_increment {
+                store.bump_auto_increment_next(db, table, pk.saturating_add(1))?;
+            }
+            Ok((pk, false))
+        }
+        None if matches!(pk_cell, Cell::Null) && def.auto_increment => {
+            if !*auto_inc_initialized {
+                if store.auto_increment_next(db, table)?.is_none() {
+                    let mut max_pk = 0i64;
+                    for (pk, _row) in txn_scan_rows(store, session, db, table)? {
+                        max_pk = max_pk.max(pk);
+                    }
+                    store.bump_auto_increment_next(db, table, max_pk.saturating_add(1).max(1))?;
+                }
+                *auto_inc_initialized = true;
+            }
+            let pk = store.allocate_auto_increment(db, table)?;
+            row_vals[pk_index] = Cell::Int(pk);
+            Ok((pk, true))
+        }
+        _ => Err(MiniError::Invalid(
+            "PRIMARY KEY must be provided (INT)".into(),
+        )),
+    }
+}
+
+/// `ER_DUP_ENTRY`, the code of the warning `INSERT IGNORE` raises for a skipped row.
+const ER_DUP_ENTRY: u16 = 1062;
+
+/// The unique indexes of a table, with the position of the column each covers.
+fn unique_key_columns(def: &TableDef) -> Vec<(&IndexDef, usize)> {
+    def.indexes
+        .iter()
+        .filter(|idx| idx.unique)
+        .filter_map(|idx| {
+            let col_idx = def
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(&idx.columns[0]))?;
+            Some((idx, col_idx))
+        })
+        .collect()
+}
+
+/// The error for a row whose `key` (an index name, or `PRIMARY`) value is already taken.
+fn duplicate_entry(def: &TableDef, key: &str, val: &Cell) -> MiniError {
+    MiniError::DuplicateEntry(val.to_string(), format!("{}.{key}", def.name))
+}
+
+/// The rows an INSERT or UPDATE writes to one table, staged together once the statement
+/// succeeds. Rows are checked for key conflicts against these as well as against what the
+/// transaction sees.
+struct StatementRows<'a> {
+    def: &'a TableDef,
+    unique_keys: Vec<(&'a IndexDef, usize)>,
+    rows: BTreeMap<i64, Option<Row>>,
+    // The rows given each unique index value, by column position. Entries are not removed when
+    // a row changes again, so hits are rechecked against `rows`.
+    unique_values: HashMap<(usize, Cell), Vec<i64>>,
+}
+
+impl<'a> StatementRows<'a> {
+    fn new(def: &'a TableDef) -> Self {
+        Self {
+            def,
+            unique_keys: unique_key_columns(def),
+            rows: BTreeMap::new(),
+            unique_values: HashMap::new(),
+        }
+    }
+
+    /// Write (`Some`) or delete (`None`) the row under `pk`.
+    fn put(&mut self, pk: i64, row: Option<Row>) {
+        if let Some(row) = &row {
+            for &(_, col_idx) in &self.unique_keys {
+                let val = &row.values[col_idx];
+                if *val != Cell::Null {
+                    self.unique_values
+                        .entry((col_idx, val.clone()))
+                        .or_default()
+                        .push(pk);
+                }
+            }
+        }
+        self.rows.insert(pk, row);
+    }
+
+    /// The row under `pk` as the transaction sees it, with this statement's writes on top.
+    fn get(
+        &self,
+        store: &Store,
+        session: &SessionState,
+        pk: i64,
+    ) -> Result<Option<Row>, MiniError> {
+        match self.rows.get(&pk) {
+            Some(row) => Ok(row.clone()),
+            None => txn_get_row(store, session, &self.def.db, &self.def.name, pk),
+        }
+    }
+
+    /// The rows `row` would collide with if stored under `pk`: the one holding the same
+    /// primary key (unless `updating` that row), then those holding one of its non-NULL unique
+    /// index values, each with the error naming the key.
+    fn conflicts(
+        &self,
+        store: &Store,
+        session: &SessionState,
+        pk: i64,
+        row: &Row,
+        updating: bool,
+    ) -> Result<Vec<(i64, MiniError)>, MiniError> {
+        let mut out: Vec<(i64, MiniError)> = Vec::new();
+        if !updating && self.get(store, session, pk)?.is_some() {
+            out.push((pk, duplicate_entry(self.def, "PRIMARY", &Cell::Int(pk))));
+        }
+        for &(idx, col_idx) in &self.unique_keys {
+            let val = &row.values[col_idx];
+            if *val == Cell::Null {
+                continue;
+            }
+            let mut candidates: BTreeSet<i64> =
+                txn_index_lookup(store, session, self.def, col_idx, val)?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+            if let Some(pks) = self.unique_values.get(&(col_idx, val.clone())) {
+                candidates.extend(pks);
+            }
+            for other in candidates {
+                if other == pk || out.iter().any(|(p, _)| *p == other) {
+                    continue;
+                }
+                let current = self.get(store, session, other)?;
+                if current.is_some_and(|r| r.values.get(col_idx) == Some(val)) {
+                    out.push((other, duplicate_entry(self.def, &idx.name, val)));
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Resolve the assignments of `ON DUPLICATE KEY UPDATE` to column positions.
+fn upsert_assignments<'a>(
+    def: &TableDef,
+    assignments: &'a [ast::Assignment],
+) -> Result<Vec<(usize, &'a ast::Expr)>, MiniError> {
+    let mut out = Vec::with_capacity(assignments.len());
+    for a in assignments {
+        let col_name = match &a.target {
+            ast::AssignmentTarget::ColumnName(name) => get_ident_name(name.0.last().unwrap()),
+            ast::AssignmentTarget::Tuple(_) => {
+                return Err(MiniError::NotSupported(
+                    "Tuple assignment is not supported".into(),
+                ))
+            }
+        };
+        let idx = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&col_name))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {col_name}")))?;
+        out.push((idx, &a.value));
+    }
+    Ok(out)
+}
+
+/// Apply `ON DUPLICATE KEY UPDATE` to the row an insert collided with. Assignments run in
+/// order, each seeing the ones before it, over the existing row followed by `inserted`.
+fn upsert_row(
+    session: &SessionState,
+    def: &TableDef,
+    assignments: &[(usize, &ast::Expr)],
+    col_map: &HashMap<String, usize>,
+    existing: &Row,
+    inserted: &Row,
+) -> Result<Row, MiniError> {
+    let mut row = Row {
+        values: existing
+            .values
+            .iter()
+            .chain(&inserted.values)
+            .cloned()
+            .collect(),
+    };
+    for &(idx, expr) in assignments {
+        let col = &def.columns[idx];
+        let val = if is_default_keyword(expr) {
+            column_default_value(session, def, col)?
+        } else {
+            coerce_cell(eval_row_expr(session, expr, &row, col_map)?, &col.ty)?
+        };
+        if matches!(val, Cell::Null) && !col.nullable {
+            return Err(MiniError::Invalid(format!(
+                "column {} cannot be NULL",
+                col.name
+            )));
+        }
+        row.values[idx] = val;
+    }
+    row.values.truncate(def.columns.len());
+    if row.values != existing.values {
+        for (idx, col) in def.columns.iter().enumerate() {
+            if col.on_update_current_timestamp && assignments.iter().all(|(i, _)| *i != idx) {
+                row.values[idx] = Cell::DateTime(session.statement_time);
+            }
+        }
+    }
+    Ok(row)
+}
+
+/// One row's values for INSERT: the expressions of a VALUES list, or a row a SELECT returned.
+enum InsertValues<'a> {
+    Exprs(&'a [ast::Expr]),
+    Cells(Vec<Cell>),
+}
+
+/// Build the row INSERT stores from one source row, setting the `targets` columns in order.
+fn insert_row_values(
+    session: &SessionState,
+    def: &TableDef,
+    targets: &[usize],
+    col_map: &HashMap<String, usize>,
+    values: InsertValues<'_>,
+) -> Result<Vec<Cell>, MiniError> {
+    let count = match &values {
+        InsertValues::Exprs(exprs) => exprs.len(),
+        InsertValues::Cells(cells) => cells.len(),
+    };
+    if count != targets.len() {
+        return Err(MiniError::Invalid("column/value count mismatch".into()));
+    }
+
+    // Columns the row leaves out take their defaults first. The values are then evaluated in
+    // order, and, as in MySQL, may read columns set before them; a column set later still
+    // holds its default.
+    let mut row = Row {
+        values: Vec::with_capacity(def.columns.len()),
+    };
+    for (idx, coldef) in def.columns.iter().enumerate() {
+        let initial = if targets.contains(&idx) {
+            eval_column_default(session, coldef.default.as_ref(), &coldef.ty)?
+        } else {
+            column_default_value(session, def, coldef)?
+        };
+        row.values.push(initial);
+    }
+    match values {
+        InsertValues::Exprs(exprs) => {
+            for (&idx, expr) in targets.iter().zip(exprs) {
+                let coldef = &def.columns[idx];
+                let v = if is_default_keyword(expr) {
+                    column_default_value(session, def, coldef)?
+                } else {
+                    eval_row_expr(session, expr, &row, col_map)?
+                };
+                row.values[idx] = coerce_cell(v, &coldef.ty)?;
+            }
+        }
+        InsertValues::Cells(cells) => {
+            for (&idx, v) in targets.iter().zip(cells) {
+                row.values[idx] = coerce_cell(v, &def.columns[idx].ty)?;
+            }
+        }
+    }
+    Ok(row.values)
+}
+
+fn handle_update(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    update: &ast::Update,
+) -> Result<ExecOutput, MiniError> {
+    require_priv(user, session.current_db.as_deref(), Priv::UPDATE)?;
+    let returning = returning_items(session, user, update.returning.as_ref())?;
+
+    if update.from.is_some() || update.or.is_some() || update.limit.is_some() {
+        return Err(MiniError::NotSupported(
+            "UPDATE with FROM/OR/LIMIT is not supported".into(),
+        ));
+    }
+    if !update.table.joins.is_empty() {
+        if returning.is_some() {
+            return Err(MiniError::NotSupported(
+                "RETURNING is not supported for multi-table UPDATE".into(),
+            ));
+        }
+        return handle_multi_update(store, session, user, update);
+    }
+
+    let (db_opt, table_name) = match &update.table.relation {
+        TableFactor::Table { name, .. } => object_name_to_parts(name)?,
+        _ => {
+            return Err(MiniError::NotSupported(
+                "Only simple UPDATE supported".into(),
+            ))
+        }
+    };
+    let db = db_opt
+        .or_else(|| session.current_db.clone())
+        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table_name);
+    let def = store.get_table(&db, &table_name)?;
+
+    // Values are evaluated for each row, left to right, so an assignment sees the columns set
+    // before it. `None` assigns the column's default.
+    let mut assignments: Vec<(usize, Option<&ast::Expr>)> = Vec::new();
+    for a in &update.assignments {
+        let col_name = match &a.target {
+            ast::AssignmentTarget::ColumnName(name) => get_ident_name(name.0.last().unwrap()),
+            ast::AssignmentTarget::Tuple(_) => {
+                return Err(MiniError::NotSupported(
+                    "UPDATE tuple assignment is not supported".into(),
                 ))
             }
         };
+        let idx = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&col_name))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {col_name}")))?;
+        if is_default_keyword(&a.value) {
+            let col = &def.columns[idx];
+            if col.default.is_none() && !col.nullable {
+                return Err(MiniError::NoDefault(col.name.clone()));
+            }
+            assignments.push((idx, None));
+            continue;
+        }
+        assignments.push((idx, Some(&a.value)));
+    }
+    let col_map = build_col_map(&[&def]);
 
-        if def.auto_increment && !generated {
-            store.bump_auto_increment_next(&db, &table, pk.saturating_add(1))?;
+    // WHERE
+    let mut target_pks: Vec<i64> = Vec::new();
+    // The column and value of a non-key WHERE, rechecked on the rows once they are locked.
+    let mut where_idx = None;
+    let Some(selection) = &update.selection else {
+        // MySQL updates all rows without a WHERE clause; keep it explicit for now.
+        return Err(MiniError::NotSupported(
+            "UPDATE without WHERE is not supported".into(),
+        ));
+    };
+    let (where_col, where_val) = parse_eq_predicate(selection)?;
+    if where_col.eq_ignore_ascii_case(&def.primary_key) {
+        let pk = where_val
+            .as_i64()
+            .ok_or_else(|| MiniError::Invalid("PRIMARY KEY must be INT".into()))?;
+        target_pks.push(pk);
+    } else {
+        let idxw = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&where_col))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {where_col}")))?;
+        let stored = key_cell(where_val.clone(), &def.columns[idxw].ty);
+        where_idx = Some((idxw, stored.unwrap_or_else(|_| where_val.clone())));
+        if let Some(pks) = txn_index_lookup(store, session, &def, idxw, &where_val)? {
+            target_pks = pks;
+        } else {
+            for (pk, row) in txn_scan_rows(store, session, &db, &table_name)? {
+                if row.values.get(idxw) == Some(&where_val) {
+                    target_pks.push(pk);
+                }
+            }
         }
+    }
 
-        locks.lock_row(&db, &table, pk)?;
+    target_pks.sort_unstable();
+    target_pks.dedup();
 
-        if stmt_rows.contains_key(&pk) || txn_get_row(store, session, &db, &table, pk)?.is_some() {
-            return Err(MiniError::Invalid(format!(
-                "duplicate entry for primary key: {pk}"
-            )));
-        }
+    let mut locks = RowLockGuard::new(store, session);
+    let mut stmt_rows = StatementRows::new(&def);
+    let mut returned: Vec<Row> = Vec::new();
+    let mut affected = 0u64;
 
-        stmt_rows.insert(pk, Row { values: row_vals });
-        affected += 1;
-        if generated && first_generated_id.is_none() {
-            first_generated_id = Some(pk);
+    for pk in target_pks {
+        lock_row_for_write(session, &mut locks, &db, &table_name, pk)?;
+        let Some(old_row) = txn_get_row(store, session, &db, &table_name, pk)? else {
+            continue;
+        };
+        // The latest version may no longer match.
+        if where_idx
+            .as_ref()
+            .is_some_and(|(idx, val)| old_row.values.get(*idx) != Some(val))
+        {
+            continue;
+        }
+        let mut row = old_row.clone();
+        for &(idx, value) in &assignments {
+            if idx >= row.values.len() {
+                return Err(MiniError::Invalid("corrupt row".into()));
+            }
+            let col = &def.columns[idx];
+            row.values[idx] = match value {
+                Some(value) => {
+                    let v = eval_row_expr(session, value, &row, &col_map)?;
+                    if matches!(v, Cell::Null) && !col.nullable {
+                        return Err(MiniError::Invalid(format!(
+                            "column {} cannot be NULL",
+                            col.name
+                        )));
+                    }
+                    coerce_cell(v, &col.ty)?
+                }
+                None => eval_column_default(session, col.default.as_ref(), &col.ty)?,
+            };
+        }
+        if row.values != old_row.values {
+            for (idx, col) in def.columns.iter().enumerate() {
+                if col.on_update_current_timestamp && assignments.iter().all(|(i, _)| *i != idx) {
+                    row.values[idx] = Cell::DateTime(session.statement_time);
+                }
+            }
         }
+        if returning.is_some() {
+            returned.push(row.clone());
+        }
+        update_row(
+            store,
+            session,
+            &mut locks,
+            &mut stmt_rows,
+            pk,
+            &old_row,
+            row,
+        )?;
+        affected += 1;
     }
 
-    if buffer_writes {
-        session.txn.in_txn = true;
-        for (pk, row) in stmt_rows {
-            session.txn.pending_rows.insert(
-                RowKey {
-                    db: db.clone(),
-                    table: table.clone(),
-                    pk,
-                },
-                Some(row),
-            );
-        }
-        locks.keep_locks();
-    } else {
-        let changes = stmt_rows
-            .iter()
-            .map(|(pk, row)| (db.as_str(), table.as_str(), *pk, Some(row)));
-        store.apply_row_changes(changes)?;
+    let result = returning
+        .map(|items| returning_result(session, &def, items, &returned))
+        .transpose()?;
+    stage_row_changes(session, &mut locks, &db, &table_name, stmt_rows.rows);
+    if let Some(result) = result {
+        return Ok(result);
     }
 
     Ok(ExecOutput::Ok {
         affected_rows: affected,
-        last_insert_id: first_generated_id.unwrap_or(0).max(0) as u64,
+        last_insert_id: 0,
         info: "".into(),
     })
 }
 
-fn handle_update(
+fn handle_delete(
     store: &Store,
     session: &mut SessionState,
     user: &UserRecord,
-    update: &ast::Update,
+    delete: &ast::Delete,
 ) -> Result<ExecOutput, MiniError> {
-    require_priv(user, session.current_db.as_deref(), Priv::UPDATE)?;
+    require_priv(user, session.current_db.as_deref(), Priv::DELETE)?;
+    let returning = returning_items(session, user, delete.returning.as_ref())?;
 
-    if update.from.is_some()
-        || update.returning.is_some()
-        || update.or.is_some()
-        || update.limit.is_some()
+    let from_tables = match &delete.from {
+        ast::FromTable::WithFromKeyword(t) | ast::FromTable::WithoutKeyword(t) => t,
+    };
+    if delete.using.is_some()
+        || !delete.tables.is_empty()
+        || from_tables.len() != 1
+        || !from_tables[0].joins.is_empty()
     {
-        return Err(MiniError::NotSupported(
-            "UPDATE with FROM/RETURNING/OR/LIMIT is not supported".into(),
-        ));
+        if returning.is_some() {
+            return Err(MiniError::NotSupported(
+                "RETURNING is not supported for multi-table DELETE".into(),
+            ));
+        }
+        return handle_multi_delete(store, session, user, delete);
     }
-    if !update.table.joins.is_empty() {
+    if !delete.order_by.is_empty() || delete.limit.is_some() {
         return Err(MiniError::NotSupported(
-            "UPDATE with joins is not supported".into(),
+            "Only simple DELETE FROM <table> WHERE ... is supported".into(),
         ));
     }
 
-    let (db_opt, table_name) = match &update.table.relation {
+    let (db_opt, table_name) = match &from_tables[0].relation {
         TableFactor::Table { name, .. } => object_name_to_parts(name)?,
         _ => {
             return Err(MiniError::NotSupported(
-                "Only simple UPDATE supported".into(),
+                "Only simple DELETE FROM <table> is supported".into(),
             ))
         }
     };
     let db = db_opt
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table_name);
     let def = store.get_table(&db, &table_name)?;
 
-    let mut assignments: Vec<(usize, Cell)> = Vec::new();
-    for a in &update.assignments {
-        let col_name = match &a.target {
-            ast::AssignmentTarget::ColumnName(name) => get_ident_name(name.0.last().unwrap()),
-            ast::AssignmentTarget::Tuple(_) => {
-                return Err(MiniError::NotSupported(
-                    "UPDATE tuple assignment is not supported".into(),
-                ))
+    let selection = delete
+        .selection
+        .as_ref()
+        .ok_or_else(|| MiniError::NotSupported("DELETE without WHERE is not supported".into()))?;
+    let (where_col, where_val) = parse_eq_predicate(selection)?;
+
+    let mut target_pks: Vec<i64> = Vec::new();
+    // The column and value of a non-key WHERE, rechecked on the rows once they are locked.
+    let mut where_idx = None;
+    if where_col.eq_ignore_ascii_case(&def.primary_key) {
+        let pk = where_val
+            .as_i64()
+            .ok_or_else(|| MiniError::Invalid("PRIMARY KEY must be INT".into()))?;
+        target_pks.push(pk);
+    } else {
+        let idxw = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&where_col))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {where_col}")))?;
+        let stored = key_cell(where_val.clone(), &def.columns[idxw].ty);
+        where_idx = Some((idxw, stored.unwrap_or_else(|_| where_val.clone())));
+        if let Some(pks) = txn_index_lookup(store, session, &def, idxw, &where_val)? {
+            target_pks = pks;
+        } else {
+            for (pk, row) in txn_scan_rows(store, session, &db, &table_name)? {
+                if row.values.get(idxw) == Some(&where_val) {
+                    target_pks.push(pk);
+                }
             }
+        }
+    }
+
+    target_pks.sort_unstable();
+    target_pks.dedup();
+
+    let mut locks = RowLockGuard::new(store, session);
+    let mut stmt_deletes: Vec<i64> = Vec::new();
+    let mut returned: Vec<Row> = Vec::new();
+    let mut affected = 0u64;
+
+    for pk in target_pks {
+        lock_row_for_write(session, &mut locks, &db, &table_name, pk)?;
+        let Some(old_row) = txn_get_row(store, session, &db, &table_name, pk)? else {
+            continue;
+        };
+        // The latest version may no longer match.
+        if where_idx
+            .as_ref()
+            .is_some_and(|(idx, val)| old_row.values.get(*idx) != Some(val))
+        {
+            continue;
+        }
+        if returning.is_some() {
+            returned.push(old_row);
+        }
+        stmt_deletes.push(pk);
+        affected += 1;
+    }
+
+    let result = returning
+        .map(|items| returning_result(session, &def, items, &returned))
+        .transpose()?;
+    stage_row_changes(
+        session,
+        &mut locks,
+        &db,
+        &table_name,
+        stmt_deletes.into_iter().map(|pk| (pk, None)),
+    );
+    if let Some(result) = result {
+        return Ok(result);
+    }
+
+    Ok(ExecOutput::Ok {
+        affected_rows: affected,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
+/// A table of a multi-table UPDATE or DELETE, in FROM order.
+struct JoinedTable {
+    db: String,
+    /// The table as stored.
+    def: TableDef,
+    /// The name the statement uses for it: its alias, or else its table name.
+    name: String,
+    /// Position of its first column in a joined row.
+    offset: usize,
+    /// Position of its primary key column in its own row.
+    pk_idx: usize,
+}
+
+impl JoinedTable {
+    /// The primary key of this table's part of a joined row; `None` when an outer join found
+    /// no row.
+    fn pk_in(&self, joined: &Row) -> Option<i64> {
+        joined.values[self.offset + self.pk_idx].as_i64()
+    }
+
+    /// Whether `name` (`t` or `db.t`) refers to this table.
+    fn is_named(&self, name: &ObjectName) -> Result<bool, MiniError> {
+        let (db, table) = object_name_to_parts(name)?;
+        let own_db = Store::temp_schema_db(&self.db).unwrap_or(&self.db);
+        Ok(table.eq_ignore_ascii_case(&self.name)
+            && db.is_none_or(|db| db.eq_ignore_ascii_case(own_db)))
+    }
+
+    /// Rows can only be written in base tables.
+    fn check_writable(&self) -> Result<(), MiniError> {
+        if is_system_schema(&self.db) {
+            return Err(MiniError::NotSupported(format!(
+                "Writing system schema {} is not supported",
+                self.db
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The tables of a multi-table UPDATE or DELETE's FROM items, in the order their columns
+/// appear in the joined rows.
+fn joined_tables(
+    store: &Store,
+    session: &SessionState,
+    from: &[ast::TableWithJoins],
+) -> Result<Vec<JoinedTable>, MiniError> {
+    let mut tables: Vec<JoinedTable> = Vec::new();
+    let mut offset = 0;
+    let relations = from
+        .iter()
+        .flat_map(|t| std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation)));
+    for relation in relations {
+        let TableFactor::Table { name, alias, .. } = relation else {
+            return Err(MiniError::NotSupported(
+                "Only simple table joins supported".into(),
+            ));
+        };
+        let (db_opt, table) = object_name_to_parts(name)?;
+        let db = db_opt
+            .or_else(|| session.current_db.clone())
+            .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+        let db = table_schema(session, db, &table);
+        let def = if is_information_schema(&db) {
+            build_information_schema_table(store, session, &table)?.0
+        } else {
+            store.get_table(&db, &table)?
+        };
+        let pk_idx = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&def.primary_key))
+            .unwrap_or(0);
+        let name = alias
+            .as_ref()
+            .map_or_else(|| table.clone(), |a| a.name.value.clone());
+        let width = def.columns.len();
+        tables.push(JoinedTable {
+            db,
+            def,
+            name,
+            offset,
+            pk_idx,
+        });
+        offset += width;
+    }
+    Ok(tables)
+}
+
+/// Run the join of a multi-table UPDATE or DELETE through the SELECT planner. The rows are
+/// read from the statement's snapshot; the statement locks and rereads each row it changes.
+fn joined_rows(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    tables: &[JoinedTable],
+    from: &[ast::TableWithJoins],
+    selection: Option<&ast::Expr>,
+) -> Result<(RowStream, HashMap<String, usize>), MiniError> {
+    let plan = plan::SelectPlan::build_join(store, session, user, from, selection)?
+        .optimize(store, session);
+    let ExecOutput::ResultSet { rows, .. } = plan.execute(store, session, None)? else {
+        unreachable!("a join returns rows");
+    };
+    // Columns resolve against the tables as the statement names them.
+    let named: Vec<TableDef> = tables
+        .iter()
+        .map(|t| TableDef {
+            name: t.name.clone(),
+            ..t.def.clone()
+        })
+        .collect();
+    let col_map = build_col_map(&named.iter().collect::<Vec<_>>());
+    Ok((rows, col_map))
+}
+
+/// `UPDATE t1 JOIN t2 ON ... SET ...`: every table whose columns are assigned is updated,
+/// each matching row once, however many joined rows it appears in. Assignments are evaluated
+/// in order over the joined row, so later ones see the values earlier ones set; the tables'
+/// rows are updated in the order their first assignments appear.
+fn handle_multi_update(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    update: &ast::Update,
+) -> Result<ExecOutput, MiniError> {
+    let from = std::slice::from_ref(&update.table);
+    let tables = joined_tables(store, session, from)?;
+
+    // (table, column, value) per assignment, in statement order.
+    let mut assignments: Vec<(usize, usize, &ast::Expr)> = Vec::new();
+    for a in &update.assignments {
+        let ast::AssignmentTarget::ColumnName(target) = &a.target else {
+            return Err(MiniError::NotSupported(
+                "UPDATE tuple assignment is not supported".into(),
+            ));
         };
-        if col_name.eq_ignore_ascii_case(&def.primary_key) {
+        let (t_idx, col_idx) = resolve_joined_column(&tables, target)?;
+        let table = &tables[t_idx];
+        table.check_writable()?;
+        let col = &table.def.columns[col_idx];
+        if is_default_keyword(&a.value) && col.default.is_none() && !col.nullable {
+            return Err(MiniError::NoDefault(col.name.clone()));
+        }
+        assignments.push((t_idx, col_idx, &a.value));
+    }
+    // Tables are updated in the order of their first assignment.
+    let mut targets: Vec<usize> = Vec::new();
+    for &(t_idx, _, _) in &assignments {
+        if !targets.contains(&t_idx) {
+            targets.push(t_idx);
+        }
+    }
+    for (i, &a) in targets.iter().enumerate() {
+        for &b in &targets[i + 1..] {
+            if tables[a].db == tables[b].db && tables[a].def.name == tables[b].def.name {
+                return Err(MiniError::NotSupported(format!(
+                    "Updating table '{}' under two names is not supported",
+                    tables[a].def.name
+                )));
+            }
+        }
+    }
+
+    let (rows, col_map) = joined_rows(
+        store,
+        session,
+        user,
+        &tables,
+        from,
+        update.selection.as_ref(),
+    )?;
+    let mut locks = RowLockGuard::new(store, session);
+    let mut stmt_rows: Vec<StatementRows> =
+        tables.iter().map(|t| StatementRows::new(&t.def)).collect();
+    let mut seen: Vec<HashSet<i64>> = vec![HashSet::new(); tables.len()];
+    let mut affected = 0u64;
+
+    for values in rows {
+        let mut joined = Row { values: values? };
+        for &t_idx in &targets {
+            let table = &tables[t_idx];
+            let Some(pk) = table.pk_in(&joined) else {
+                continue;
+            };
+            if !seen[t_idx].insert(pk) {
+                continue;
+            }
+            lock_row_for_write(session, &mut locks, &table.db, &table.def.name, pk)?;
+            let Some(old_row) = stmt_rows[t_idx].get(store, session, pk)? else {
+                continue;
+            };
+            let width = table.def.columns.len();
+            joined.values[table.offset..table.offset + width].clone_from_slice(&old_row.values);
+
+            let mut row = old_row.clone();
+            for &(_, idx, value) in assignments.iter().filter(|(t, _, _)| *t == t_idx) {
+                let col = &table.def.columns[idx];
+                let v = if is_default_keyword(value) {
+                    eval_column_default(session, col.default.as_ref(), &col.ty)?
+                } else {
+                    let v = eval_row_expr(session, value, &joined, &col_map)?;
+                    if matches!(v, Cell::Null) && !col.nullable {
+                        return Err(MiniError::Invalid(format!(
+                            "column {} cannot be NULL",
+                            col.name
+                        )));
+                    }
+                    coerce_cell(v, &col.ty)?
+                };
+                joined.values[table.offset + idx] = v.clone();
+                row.values[idx] = v;
+            }
+            if row.values != old_row.values {
+                for (idx, col) in table.def.columns.iter().enumerate() {
+                    let assigned = assignments.iter().any(|&(t, i, _)| t == t_idx && i == idx);
+                    if col.on_update_current_timestamp && !assigned {
+                        row.values[idx] = Cell::DateTime(session.statement_time);
+                    }
+                }
+            }
+            update_row(
+                store,
+                session,
+                &mut locks,
+                &mut stmt_rows[t_idx],
+                pk,
+                &old_row,
+                row,
+            )?;
+            affected += 1;
+        }
+    }
+
+    for (table, changes) in tables.iter().zip(stmt_rows) {
+        stage_row_changes(
+            session,
+            &mut locks,
+            &table.db,
+            &table.def.name,
+            changes.rows,
+        );
+    }
+    Ok(ExecOutput::Ok {
+        affected_rows: affected,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
+/// Stage an UPDATE's new version of the row under `pk`, checking it against the primary key
+/// and unique indexes; on a duplicate nothing is staged. When the primary key changed, the row
+/// moves: the old key is deleted and the row inserted under the new one, which is locked as for
+/// an INSERT and must be free.
+fn update_row(
+    store: &Store,
+    session: &SessionState,
+    locks: &mut RowLockGuard<'_>,
+    stmt_rows: &mut StatementRows<'_>,
+    pk: i64,
+    old_row: &Row,
+    row: Row,
+) -> Result<(), MiniError> {
+    let def = stmt_rows.def;
+    let pk_idx = def
+        .columns
+        .iter()
+        .position(|c| c.name.eq_ignore_ascii_case(&def.primary_key))
+        .ok_or_else(|| MiniError::Invalid("corrupt table: missing primary key column".into()))?;
+    let new_pk = row.values[pk_idx]
+        .as_i64()
+        .ok_or_else(|| MiniError::Invalid("PRIMARY KEY must be INT".into()))?;
+
+    if new_pk == pk {
+        if row.values != old_row.values {
+            let conflicts = stmt_rows.conflicts(store, session, pk, &row, true)?;
+            if let Some((_, duplicate)) = conflicts.into_iter().next() {
+                return Err(duplicate);
+            }
+        }
+        lock_insert_intentions(locks, def, pk, &row, Some(old_row))?;
+        stmt_rows.put(pk, Some(row));
+        return Ok(());
+    }
+
+    lock_insert_intentions(locks, def, new_pk, &row, None)?;
+    locks.lock_row(&def.db, &def.name, new_pk)?;
+    // The row's old version is about to go, so it cannot collide with the new one.
+    let conflicts = stmt_rows.conflicts(store, session, new_pk, &row, false)?;
+    if let Some((_, duplicate)) = conflicts.into_iter().find(|(other, _)| *other != pk) {
+        return Err(duplicate);
+    }
+    stmt_rows.put(pk, None);
+    if def.auto_increment {
+        store.bump_auto_increment_next(&def.db, &def.name, new_pk.saturating_add(1))?;
+    }
+    stmt_rows.put(new_pk, Some(row));
+    Ok(())
+}
+
+/// The table and column an UPDATE assignment of a multi-table UPDATE sets: `col`, `t.col` or
+/// `db.t.col`. An unqualified name must belong to exactly one table.
+fn resolve_joined_column(
+    tables: &[JoinedTable],
+    target: &ObjectName,
+) -> Result<(usize, usize), MiniError> {
+    let parts: Vec<String> = target.0.iter().map(get_ident_name).collect();
+    let (qualifier, col_name) = match parts.as_slice() {
+        [col] => (None, col),
+        [table, col] => (Some(ObjectName::from(vec![Ident::new(table)])), col),
+        [db, table, col] => (
+            Some(ObjectName::from(vec![Ident::new(db), Ident::new(table)])),
+            col,
+        ),
+        _ => {
             return Err(MiniError::NotSupported(
-                "Updating PRIMARY KEY is not supported".into(),
-            ));
+                "column name with more than 3 parts is not supported".into(),
+            ))
         }
-        let idx = def
+    };
+    let mut found = None;
+    for (t_idx, table) in tables.iter().enumerate() {
+        if let Some(q) = &qualifier {
+            if !table.is_named(q)? {
+                continue;
+            }
+        }
+        let Some(col_idx) = table
+            .def
             .columns
             .iter()
-            .position(|c| c.name.eq_ignore_ascii_case(&col_name))
-            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {col_name}")))?;
-        let val = eval_expr(&a.value)?;
-        if matches!(val, Cell::Null) && !def.columns[idx].nullable {
+            .position(|c| c.name.eq_ignore_ascii_case(col_name))
+        else {
+            continue;
+        };
+        if found.is_some() {
             return Err(MiniError::Invalid(format!(
-                "column {col_name} cannot be NULL"
+                "Column '{col_name}' in field list is ambiguous"
             )));
         }
-        let coerced = coerce_cell(val, &def.columns[idx].ty)?;
-        assignments.push((idx, coerced));
+        found = Some((t_idx, col_idx));
     }
+    found.ok_or_else(|| MiniError::NotFound(format!("unknown column: {target}")))
+}
 
-    // WHERE
-    let mut target_pks: Vec<i64> = Vec::new();
-    if let Some(selection) = &update.selection {
-        let (where_col, where_val) = parse_eq_predicate(selection)?;
-        if where_col.eq_ignore_ascii_case(&def.primary_key) {
-            let pk = where_val
-                .as_i64()
-                .ok_or_else(|| MiniError::Invalid("PRIMARY KEY must be INT".into()))?;
-            target_pks.push(pk);
-        } else {
-            let idxw = def
-                .columns
-                .iter()
-                .position(|c| c.name.eq_ignore_ascii_case(&where_col))
-                .ok_or_else(|| MiniError::NotFound(format!("unknown column: {where_col}")))?;
-            for (pk, row) in txn_scan_rows(store, session, &db, &table_name)? {
-                if row.values.get(idxw) == Some(&where_val) {
-                    target_pks.push(pk);
-                }
-            }
-        }
-    } else {
-        // MySQL updates all rows without a WHERE clause; keep it explicit for now.
+/// `DELETE t1[, t2] FROM t1 JOIN t2 ...` and `DELETE FROM t1[, t2] USING t1 JOIN t2 ...`:
+/// every row of the named tables that appears in a joined row is deleted.
+fn handle_multi_delete(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    delete: &ast::Delete,
+) -> Result<ExecOutput, MiniError> {
+    if !delete.order_by.is_empty() || delete.limit.is_some() {
         return Err(MiniError::NotSupported(
-            "UPDATE without WHERE is not supported".into(),
+            "Multi-table DELETE with ORDER BY or LIMIT is not supported".into(),
         ));
     }
-
-    target_pks.sort_unstable();
-    target_pks.dedup();
-
-    let buffer_writes = should_buffer_writes(session);
-    let mut locks = RowLockGuard::new(store, session.conn_id);
-    let mut stmt_rows: BTreeMap<i64, Row> = BTreeMap::new();
-    let mut affected = 0u64;
-
-    for pk in target_pks {
-        locks.lock_row(&db, &table_name, pk)?;
-        let Some(mut row) = txn_get_row(store, session, &db, &table_name, pk)? else {
-            continue;
+    let from_tables = match &delete.from {
+        ast::FromTable::WithFromKeyword(t) | ast::FromTable::WithoutKeyword(t) => t,
+    };
+    let (from, names): (&[ast::TableWithJoins], Vec<&ObjectName>) =
+        match &delete.using {
+            Some(using) => {
+                let mut names = Vec::new();
+                for t in from_tables {
+                    match &t.relation {
+                        TableFactor::Table {
+                            name, alias: None, ..
+                        } if t.joins.is_empty() => names.push(name),
+                        _ => return Err(MiniError::Parse(
+                            "DELETE FROM ... USING names the tables to delete from before USING"
+                                .into(),
+                        )),
+                    }
+                }
+                (using, names)
+            }
+            None if !delete.tables.is_empty() => (from_tables, delete.tables.iter().collect()),
+            None => {
+                return Err(MiniError::Parse(
+                    "DELETE with joins names the tables to delete from: DELETE t1 FROM t1 JOIN ..."
+                        .into(),
+                ))
+            }
         };
-        for (idx, val) in &assignments {
-            if *idx >= row.values.len() {
-                return Err(MiniError::Invalid("corrupt row".into()));
+    let tables = joined_tables(store, session, from)?;
+
+    let mut targets: Vec<usize> = Vec::new();
+    for name in names {
+        let mut matched = None;
+        for (t_idx, table) in tables.iter().enumerate() {
+            if table.is_named(name)? {
+                matched = Some(t_idx);
+                break;
             }
-            row.values[*idx] = val.clone();
         }
-        stmt_rows.insert(pk, row);
-        affected += 1;
-    }
+        let t_idx = matched.ok_or_else(|| {
+            MiniError::NotFound(format!("Unknown table '{name}' in MULTI DELETE"))
+        })?;
+        tables[t_idx].check_writable()?;
+        if !targets.contains(&t_idx) {
+            targets.push(t_idx);
+        }
+    }
+
+    let (rows, _) = joined_rows(
+        store,
+        session,
+        user,
+        &tables,
+        from,
+        delete.selection.as_ref(),
+    )?;
+    let mut locks = RowLockGuard::new(store, session);
+    // Keyed by stored table, so a table deleted from under two aliases loses each row once.
+    let mut deletes: BTreeMap<(&str, &str), BTreeSet<i64>> = BTreeMap::new();
+    let mut affected = 0u64;
 
-    if buffer_writes {
-        session.txn.in_txn = true;
-        for (pk, row) in stmt_rows {
-            session.txn.pending_rows.insert(
-                RowKey {
-                    db: db.clone(),
-                    table: table_name.clone(),
-                    pk,
-                },
-                Some(row),
-            );
+    for values in rows {
+        let joined = Row { values: values? };
+        for &t_idx in &targets {
+            let table = &tables[t_idx];
+            let Some(pk) = table.pk_in(&joined) else {
+                continue;
+            };
+            let pks = deletes
+                .entry((table.db.as_str(), table.def.name.as_str()))
+                .or_default();
+            if pks.contains(&pk) {
+                continue;
+            }
+            lock_row_for_write(session, &mut locks, &table.db, &table.def.name, pk)?;
+            if txn_get_row(store, session, &table.db, &table.def.name, pk)?.is_none() {
+                continue;
+            }
+            pks.insert(pk);
+            affected += 1;
         }
-        locks.keep_locks();
-    } else {
-        let changes = stmt_rows
-            .iter()
-            .map(|(pk, row)| (db.as_str(), table_name.as_str(), *pk, Some(row)));
-        store.apply_row_changes(changes)?;
     }
 
+    for ((db, table), pks) in deletes {
+        stage_row_changes(
+            session,
+            &mut locks,
+            db,
+            table,
+            pks.into_iter().map(|pk| (pk, None)),
+        );
+    }
     Ok(ExecOutput::Ok {
         affected_rows: affected,
         last_insert_id: 0,
@@ -2045,124 +4728,112 @@ fn handle_update(
     })
 }
 
-fn handle_delete(
-    store: &Store,
-    session: &mut SessionState,
+/// The select list of a `RETURNING` clause. It is MariaDB syntax, accepted only with
+/// `mariadb_compat` set, and reading the written rows needs the SELECT privilege.
+fn returning_items<'a>(
+    session: &SessionState,
     user: &UserRecord,
-    delete: &ast::Delete,
-) -> Result<ExecOutput, MiniError> {
-    require_priv(user, session.current_db.as_deref(), Priv::DELETE)?;
-
-    if delete.using.is_some()
-        || delete.returning.is_some()
-        || !delete.order_by.is_empty()
-        || delete.limit.is_some()
-        || !delete.tables.is_empty()
-    {
-        return Err(MiniError::NotSupported(
-            "Only simple DELETE FROM <table> WHERE ... is supported".into(),
-        ));
-    }
-
-    let from_tables = match &delete.from {
-        ast::FromTable::WithFromKeyword(t) | ast::FromTable::WithoutKeyword(t) => t,
+    returning: Option<&'a Vec<ast::SelectItem>>,
+) -> Result<Option<&'a [ast::SelectItem]>, MiniError> {
+    let Some(items) = returning else {
+        return Ok(None);
     };
-    if from_tables.len() != 1 {
-        return Err(MiniError::NotSupported(
-            "DELETE supports only a single table".into(),
-        ));
-    }
-    if !from_tables[0].joins.is_empty() {
-        return Err(MiniError::NotSupported(
-            "DELETE with joins is not supported".into(),
+    if !session.mariadb_compat {
+        return Err(MiniError::Parse(
+            "RETURNING is MariaDB syntax; enable it with SET mariadb_compat = ON".into(),
         ));
     }
+    require_priv(user, session.current_db.as_deref(), Priv::SELECT)?;
+    Ok(Some(items))
+}
 
-    let (db_opt, table_name) = match &from_tables[0].relation {
-        TableFactor::Table { name, .. } => object_name_to_parts(name)?,
-        _ => {
-            return Err(MiniError::NotSupported(
-                "Only simple DELETE FROM <table> is supported".into(),
-            ))
+/// The result of `RETURNING`: its select list evaluated over each row the statement wrote (or,
+/// for DELETE, removed), in the order it did so.
+fn returning_result(
+    session: &SessionState,
+    def: &TableDef,
+    items: &[ast::SelectItem],
+    rows: &[Row],
+) -> Result<ExecOutput, MiniError> {
+    let col_map = build_col_map(&[def]);
+    let mut exprs: Vec<(String, ast::Expr)> = Vec::new();
+    let all_columns = |exprs: &mut Vec<(String, ast::Expr)>| {
+        for c in &def.columns {
+            exprs.push((c.name.clone(), ast::Expr::Identifier(Ident::new(&c.name))));
         }
     };
-    let db = db_opt
-        .or_else(|| session.current_db.clone())
-        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
-    let def = store.get_table(&db, &table_name)?;
-
-    let selection = delete
-        .selection
-        .as_ref()
-        .ok_or_else(|| MiniError::NotSupported("DELETE without WHERE is not supported".into()))?;
-    let (where_col, where_val) = parse_eq_predicate(selection)?;
-
-    let mut target_pks: Vec<i64> = Vec::new();
-    if where_col.eq_ignore_ascii_case(&def.primary_key) {
-        let pk = where_val
-            .as_i64()
-            .ok_or_else(|| MiniError::Invalid("PRIMARY KEY must be INT".into()))?;
-        target_pks.push(pk);
-    } else {
-        let idxw = def
-            .columns
-            .iter()
-            .position(|c| c.name.eq_ignore_ascii_case(&where_col))
-            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {where_col}")))?;
-        for (pk, row) in txn_scan_rows(store, session, &db, &table_name)? {
-            if row.values.get(idxw) == Some(&where_val) {
-                target_pks.push(pk);
+    for item in items {
+        match item {
+            ast::SelectItem::Wildcard(_) => all_columns(&mut exprs),
+            ast::SelectItem::QualifiedWildcard(
+                ast::SelectItemQualifiedWildcardKind::ObjectName(name),
+                _,
+            ) if object_name_to_parts(name)?
+                .1
+                .eq_ignore_ascii_case(&def.name) =>
+            {
+                all_columns(&mut exprs)
+            }
+            ast::SelectItem::QualifiedWildcard(kind, _) => {
+                return Err(MiniError::NotFound(format!(
+                    "unknown table in wildcard: {kind}"
+                )))
+            }
+            ast::SelectItem::UnnamedExpr(expr) => {
+                let name = match expr {
+                    ast::Expr::Identifier(ident) => ident.value.clone(),
+                    ast::Expr::CompoundIdentifier(ids) => ids.last().unwrap().value.clone(),
+                    other => other.to_string(),
+                };
+                exprs.push((name, expr.clone()));
+            }
+            ast::SelectItem::ExprWithAlias { expr, alias } => {
+                exprs.push((alias.value.clone(), expr.clone()))
             }
         }
     }
 
-    target_pks.sort_unstable();
-    target_pks.dedup();
-
-    let buffer_writes = should_buffer_writes(session);
-    let mut locks = RowLockGuard::new(store, session.conn_id);
-    let mut stmt_deletes: Vec<i64> = Vec::new();
-    let mut affected = 0u64;
-
-    for pk in target_pks {
-        locks.lock_row(&db, &table_name, pk)?;
-        if txn_get_row(store, session, &db, &table_name, pk)?.is_none() {
-            continue;
-        }
-        stmt_deletes.push(pk);
-        affected += 1;
-    }
-
-    if buffer_writes {
-        session.txn.in_txn = true;
-        for pk in stmt_deletes {
-            session.txn.pending_rows.insert(
-                RowKey {
-                    db: db.clone(),
-                    table: table_name.clone(),
-                    pk,
-                },
-                None,
-            );
-        }
-        locks.keep_locks();
+    // With no rows, evaluate over a row of NULLs anyway so bad references still fail.
+    let null_row = Row {
+        values: vec![Cell::Null; def.columns.len()],
+    };
+    let eval_rows: &[Row] = if rows.is_empty() {
+        std::slice::from_ref(&null_row)
     } else {
-        let changes = stmt_deletes
+        rows
+    };
+    let mut out = Vec::with_capacity(rows.len());
+    for row in eval_rows {
+        let values = exprs
             .iter()
-            .map(|pk| (db.as_str(), table_name.as_str(), *pk, None));
-        store.apply_row_changes(changes)?;
+            .map(|(_, expr)| eval_row_expr(session, expr, row, &col_map))
+            .collect::<Result<Vec<_>, _>>()?;
+        out.push(values);
+    }
+    if rows.is_empty() {
+        out.clear();
     }
 
-    Ok(ExecOutput::Ok {
-        affected_rows: affected,
-        last_insert_id: 0,
-        info: "".into(),
+    let columns = exprs
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, expr))| {
+            let coltype = order_by_expr_to_base_col_idx(expr, &col_map)
+                .map(|col| plan::column_type(&def.columns[col].ty))
+                .or_else(|| plan::infer_column_type(&out, idx))
+                .unwrap_or(ColumnType::MYSQL_TYPE_VAR_STRING);
+            plan::result_column(name.clone(), coltype)
+        })
+        .collect();
+    Ok(ExecOutput::ResultSet {
+        columns,
+        rows: out.into(),
     })
 }
 
 fn handle_query(
     store: &Store,
-    session: &SessionState,
+    session: &mut SessionState,
     user: &UserRecord,
     query: &ast::Query,
 ) -> Result<ExecOutput, MiniError> {
@@ -2254,7 +4925,7 @@ fn handle_query(
             };
 
             if let Some(var) = sysvar_name {
-                let value = sysvar_value(session, &var)
+                let value = sysvar_value(store, session, &var)
                     .ok_or_else(|| MiniError::UnknownSystemVariable(var.clone()))?;
                 if alias.is_none() {
                     col_name = expr.to_string();
@@ -2281,7 +4952,7 @@ fn handle_query(
 
         return Ok(ExecOutput::ResultSet {
             columns: cols,
-            rows: vec![row],
+            rows: vec![row].into(),
         });
     }
 
@@ -2294,243 +4965,552 @@ fn handle_query(
         return Err(MiniError::Invalid("Unexpected empty FROM clause".into()));
     }
 
-    let mut accumulated_rows: Vec<Row> = Vec::new();
-    let mut accumulated_def_indices: Vec<usize> = Vec::new(); // Indices into loaded_defs
-    let mut loaded_defs: Vec<TableDef> = Vec::new();
+    let locked_rows = select_locked_rows(store, session, user, query, select)?;
+    let plan =
+        plan::SelectPlan::build(store, session, user, query, select)?.optimize(store, session);
+    plan.execute(store, session, locked_rows.as_ref())
+}
 
-    // Helper to scan a table relation
-    let scan_table = |relation: &TableFactor| -> Result<(TableDef, Vec<Row>), MiniError> {
-        let (db_opt, table_name, alias_name) = match relation {
-            TableFactor::Table { name, alias, .. } => {
-                let (db_opt, table_name) = object_name_to_parts(name)?;
-                let alias_name = alias.as_ref().map(|a| a.name.value.clone());
-                (db_opt, table_name, alias_name)
-            }
-            _ => {
-                return Err(MiniError::NotSupported(
-                    "Only simple table joins supported".into(),
-                ))
+/// Rows read by a locking SELECT, per `(db, table)`.
+type LockedRows = HashMap<(String, String), Vec<Row>>;
+
+/// Locking reads (FOR UPDATE / FOR SHARE) lock their rows up front and then read those rows'
+/// latest committed versions instead of the snapshot. Like InnoDB, SERIALIZABLE turns plain
+/// SELECTs into FOR SHARE unless the statement is its own autocommit transaction.
+fn select_locked_rows(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    query: &ast::Query,
+    select: &ast::Select,
+) -> Result<Option<LockedRows>, MiniError> {
+    let implicit_share;
+    let lock_clauses = if query.locks.is_empty()
+        && session.txn.isolation == IsolationLevel::Serializable
+        && should_buffer_writes(session)
+    {
+        implicit_share = [ast::LockClause {
+            lock_type: ast::LockType::Share,
+            of: None,
+            nonblock: None,
+        }];
+        &implicit_share[..]
+    } else {
+        &query.locks[..]
+    };
+    if lock_clauses.is_empty() {
+        return Ok(None);
+    }
+    lock_query_rows(store, session, user, query, select, lock_clauses).map(Some)
+}
+
+/// `EXPLAIN [ANALYZE] [FORMAT=...] SELECT ...`. EXPLAIN ANALYZE runs the query, taking the
+/// locks a locking read would, and only reports in the tree format.
+fn handle_explain(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    statement: &Statement,
+    analyze: bool,
+    format: Option<&ast::AnalyzeFormatKind>,
+) -> Result<ExecOutput, MiniError> {
+    let Statement::Query(query) = statement else {
+        return Err(MiniError::NotSupported(
+            "EXPLAIN is only supported for SELECT".into(),
+        ));
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return Err(MiniError::NotSupported(
+            "EXPLAIN is only supported for SELECT".into(),
+        ));
+    };
+    let format = match format {
+        Some(ast::AnalyzeFormatKind::Keyword(f) | ast::AnalyzeFormatKind::Assignment(f)) => {
+            match f {
+                ast::AnalyzeFormat::TRADITIONAL => plan::ExplainFormat::Traditional,
+                // `FORMAT=TREE` arrives as TEXT; see `normalize_explain_format`.
+                ast::AnalyzeFormat::TREE | ast::AnalyzeFormat::TEXT => plan::ExplainFormat::Tree,
+                ast::AnalyzeFormat::JSON => plan::ExplainFormat::Json,
+                other => {
+                    return Err(MiniError::NotSupported(format!(
+                        "EXPLAIN FORMAT={other} is not supported"
+                    )))
+                }
             }
+        }
+        None if analyze => plan::ExplainFormat::Tree,
+        None => plan::ExplainFormat::Traditional,
+    };
+    if analyze && format != plan::ExplainFormat::Tree {
+        return Err(MiniError::NotSupported(
+            "EXPLAIN ANALYZE only supports FORMAT=TREE".into(),
+        ));
+    }
+
+    if select.from.is_empty() {
+        if !analyze {
+            return Ok(plan::explain_without_tables(format));
+        }
+        let start = std::time::Instant::now();
+        let rows = match handle_query(store, session, user, query)? {
+            ExecOutput::ResultSet { rows, .. } => rows.collect::<Result<Vec<_>, _>>()?.len(),
+            _ => 0,
+        };
+        return Ok(plan::analyze_without_tables(rows, start.elapsed()));
+    }
+    let locked_rows = if analyze {
+        select_locked_rows(store, session, user, query, select)?
+    } else {
+        None
+    };
+    let plan =
+        plan::SelectPlan::build(store, session, user, query, select)?.optimize(store, session);
+    if analyze {
+        plan.analyze(store, session, locked_rows.as_ref())
+    } else {
+        plan.explain(store, session, format)
+    }
+}
+
+/// Lock the rows read by `SELECT ... FOR UPDATE / FOR SHARE` and return them per `(db, table)`,
+/// as of the latest committed state. A single-table query only locks rows matching its WHERE
+/// clause and, when rows come out in primary key order, stops once LIMIT is satisfied; joins lock
+/// every row of each table they read. Rows skipped by SKIP LOCKED are left out.
+///
+/// Under REPEATABLE READ and SERIALIZABLE the gaps of the scanned range are locked too (see
+/// `lock_next_keys`), over the whole range even when LIMIT stops the row scan early, and so is
+/// every row examined in the range, matching the rest of the WHERE clause or not.
+fn lock_query_rows(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    query: &ast::Query,
+    select: &ast::Select,
+    lock_clauses: &[ast::LockClause],
+) -> Result<LockedRows, MiniError> {
+    let relations: Vec<&TableFactor> = select
+        .from
+        .iter()
+        .flat_map(|t| std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation)))
+        .collect();
+    let single_table = relations.len() == 1;
+
+    let mut locks = RowLockGuard::new(store, session);
+    let mut locked_keys = Vec::new();
+    let mut out = HashMap::new();
+    for relation in relations {
+        let TableFactor::Table { name, alias, .. } = relation else {
+            continue;
         };
+        let (db_opt, table_name) = object_name_to_parts(name)?;
         let db = db_opt
             .or_else(|| session.current_db.clone())
             .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
-
-        let mut def;
-        let rows;
-        if is_information_schema(&db) {
-            require_priv(user, None, Priv::SELECT)?;
-            (def, rows) = build_information_schema_table(store, session, &table_name)?;
-        } else if is_system_schema(&db) {
-            return Err(MiniError::NotSupported(format!(
-                "Reading system schema {db} is not supported"
-            )));
-        } else {
-            require_priv(user, Some(&db), Priv::SELECT)?;
-            def = store.get_table(&db, &table_name)?;
-            rows = txn_scan_rows(store, session, &db, &table_name)?
-                .into_iter()
-                .map(|(_, r)| r)
-                .collect();
+        if is_information_schema(&db) || is_system_schema(&db) {
+            continue;
+        }
+        let db = table_schema(session, db, &table_name);
+        let alias_name = alias.as_ref().map(|a| a.name.value.as_str());
+        let Some(clause) = lock_clause_for(lock_clauses, &table_name, alias_name) else {
+            continue;
+        };
+        if out.contains_key(&(db.clone(), table_name.clone())) {
+            continue;
         }
+        require_priv(user, Some(&db), Priv::SELECT)?;
+
+        let mode = match clause.lock_type {
+            ast::LockType::Update => LockMode::Exclusive,
+            ast::LockType::Share => LockMode::Shared,
+        };
+        let wait = match clause.nonblock {
+            Some(ast::NonBlock::Nowait) => LockWait::NoWait,
+            Some(ast::NonBlock::SkipLocked) => LockWait::SkipLocked,
+            None => LockWait::Block(Duration::from_secs(session.innodb_lock_wait_timeout)),
+        };
 
+        let mut def = store.get_table(&db, &table_name)?;
         if let Some(alias) = alias_name {
-            def.name = alias;
+            def.name = alias.to_string();
         }
-        Ok((def, rows))
-    };
+        let col_map = build_col_map(&[&def]);
+        // WHERE can only be checked per table when there is nothing to join with.
+        let filter = if single_table {
+            select.selection.as_ref()
+        } else {
+            None
+        };
+        let stop_after = if single_table && rows_in_scan_order(query, select, &def) {
+            let (offset, limit) = query_offset_limit(query)?;
+            limit.map(|l| offset.saturating_add(l))
+        } else {
+            None
+        };
 
-    // Flatten FROM clause: explicit commas + explicit JOINs
-    for (i, table_with_joins) in select.from.iter().enumerate() {
-        // 1. Process the main relation
-        let (def, rows) = scan_table(&table_with_joins.relation)?;
-        loaded_defs.push(def);
-        let curr_def_idx = loaded_defs.len() - 1;
+        let gap_locking = matches!(
+            session.txn.isolation,
+            IsolationLevel::RepeatableRead | IsolationLevel::Serializable
+        ) && wait != LockWait::SkipLocked;
+        let range = key_range(filter, &def);
+        if gap_locking {
+            lock_next_keys(&mut locks, &db, &table_name, &range, mode, wait)?;
+        }
+        // Rows outside the WHERE clause's primary key bounds cannot match, so only read those
+        // inside.
+        let pks = match &range {
+            KeyRange::Pk(lo, hi) => lo.unwrap_or(i64::MIN)..=hi.unwrap_or(i64::MAX),
+            KeyRange::IndexEq(..) => i64::MIN..=i64::MAX,
+        };
 
-        if i == 0 {
-            accumulated_rows = rows;
-            accumulated_def_indices.push(curr_def_idx);
-        } else {
-            // Cartesian Product with previous result
-            let mut new_rows = Vec::with_capacity(accumulated_rows.len() * rows.len());
-            for left in &accumulated_rows {
-                for right in &rows {
-                    let mut combined = left.values.clone();
-                    combined.extend(right.values.clone());
-                    new_rows.push(Row { values: combined });
+        let mut rows = Vec::new();
+        for (pk, row) in txn_scan_rows_current(store, session, &db, &table_name, pks)? {
+            if stop_after.is_some_and(|n| rows.len() >= n) {
+                break;
+            }
+            // With gap locks, like InnoDB, every record examined in the locked range is locked
+            // whether or not it matches the rest of the WHERE clause. Otherwise another session
+            // could change it to match, and a repeated read would see a phantom.
+            let examined = gap_locking && range_contains(&range, &def, &row);
+            if let (Some(expr), false) = (filter, examined) {
+                if !eval_condition(session, expr, &row, &col_map)? {
+                    continue;
+                }
+            }
+            if locks.lock_row_with(&db, &table_name, pk, mode, wait)? == LockGrant::Skipped {
+                continue;
+            }
+            let key = RowKey {
+                db: db.clone(),
+                table: table_name.clone(),
+                pk,
+            };
+            // The row may have changed while we waited for its lock.
+            let latest = match session.txn.pending_rows.get(&key) {
+                Some(row) => row.clone(),
+                None => {
+                    let current = store.txn_manager.current_view(session.txn.tx_id);
+                    store.get_row_mvcc(&db, &table_name, pk, &current)?
+                }
+            };
+            locked_keys.push(key);
+            let Some(latest) = latest else {
+                continue;
+            };
+            if let Some(expr) = filter {
+                if !eval_condition(session, expr, &latest, &col_map)? {
+                    continue;
                 }
             }
-            accumulated_rows = new_rows;
-            accumulated_def_indices.push(curr_def_idx);
+            rows.push(latest);
         }
+        out.insert((db, table_name), rows);
+    }
 
-        // 2. Process chained Joins
-        for join in &table_with_joins.joins {
-            let (j_def, j_rows) = scan_table(&join.relation)?;
-            let right_col_count = j_def.columns.len();
-            loaded_defs.push(j_def);
-            let j_def_idx = loaded_defs.len() - 1;
-
-            #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-            enum JoinKind {
-                Inner,
-                Left,
-                Right,
-            }
+    // Outside a transaction the locks only last for the statement.
+    if should_buffer_writes(session) {
+        session.txn.in_txn = true;
+        session.txn.locked_reads.extend(locked_keys);
+        locks.keep_locks();
+    }
+    Ok(out)
+}
 
-            let (join_kind, constraint) = match &join.join_operator {
-                ast::JoinOperator::Join(c)
-                | ast::JoinOperator::Inner(c)
-                | ast::JoinOperator::CrossJoin(c)
-                | ast::JoinOperator::StraightJoin(c) => (JoinKind::Inner, c),
-                ast::JoinOperator::Left(c) | ast::JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
-                ast::JoinOperator::Right(c) | ast::JoinOperator::RightOuter(c) => {
-                    (JoinKind::Right, c)
-                }
-                ast::JoinOperator::FullOuter(_) => {
-                    return Err(MiniError::NotSupported(
-                        "FULL OUTER joins are not supported".into(),
-                    ))
-                }
-                other => {
-                    return Err(MiniError::NotSupported(format!(
-                        "JOIN operator not supported: {other:?}"
-                    )))
-                }
-            };
+/// The part of a table a WHERE clause confines a read to: what a locking read next-key locks,
+/// and what a planned scan reads.
+#[derive(Debug)]
+enum KeyRange {
+    /// Primary keys within inclusive bounds (`None`: unbounded).
+    Pk(Option<i64>, Option<i64>),
+    /// Entries of a secondary index equal to a value.
+    IndexEq(String, Cell),
+}
 
-            let right_def = &loaded_defs[j_def_idx];
-            let left_defs: Vec<&TableDef> = accumulated_def_indices
+/// Whether a row within the scanned primary key bounds lies in `range`: for an index equality,
+/// whether its indexed column holds the value.
+fn range_contains(range: &KeyRange, def: &TableDef, row: &Row) -> bool {
+    let KeyRange::IndexEq(index, val) = range else {
+        return true;
+    };
+    def.indexes
+        .iter()
+        .find(|i| i.name == *index)
+        .and_then(|i| i.columns.first())
+        .and_then(|col| {
+            def.columns
                 .iter()
-                .map(|&idx| &loaded_defs[idx])
-                .collect();
-            let left_col_count: usize = left_defs.iter().map(|d| d.columns.len()).sum();
+                .position(|c| c.name.eq_ignore_ascii_case(col))
+        })
+        .and_then(|idx| row.values.get(idx))
+        .is_some_and(|cell| cell == val)
+}
 
-            let derived_on_expr: Option<ast::Expr> = match constraint {
-                ast::JoinConstraint::Using(cols) => {
-                    Some(build_using_join_on_expr(&left_defs, right_def, cols)?)
-                }
-                ast::JoinConstraint::Natural => build_natural_join_on_expr(&left_defs, right_def)?,
+/// Narrow a read to a primary key range from the ANDed `pk <op> literal` and `pk BETWEEN`
+/// terms of its WHERE clause, else to an index equality, else the whole table. Terms that
+/// cannot be used only widen the range.
+fn key_range(selection: Option<&ast::Expr>, def: &TableDef) -> KeyRange {
+    fn conjuncts<'e>(expr: &'e ast::Expr, out: &mut Vec<&'e ast::Expr>) {
+        match expr {
+            ast::Expr::BinaryOp {
+                left,
+                op: ast::BinaryOperator::And,
+                right,
+            } => {
+                conjuncts(left, out);
+                conjuncts(right, out);
+            }
+            ast::Expr::Nested(inner) => conjuncts(inner, out),
+            _ => out.push(expr),
+        }
+    }
+    fn column(expr: &ast::Expr) -> Option<&str> {
+        match expr {
+            ast::Expr::Identifier(i) => Some(&i.value),
+            ast::Expr::CompoundIdentifier(ids) => ids.last().map(|i| i.value.as_str()),
+            _ => None,
+        }
+    }
+    fn literal(expr: &ast::Expr) -> Option<Cell> {
+        match expr {
+            ast::Expr::Value(_) => eval_expr(expr).ok(),
+            ast::Expr::UnaryOp {
+                op: ast::UnaryOperator::Minus,
+                expr,
+            } => match literal(expr)? {
+                Cell::Int(i) => i.checked_neg().map(Cell::Int),
+                Cell::Float(f) => Some(Cell::Float(-f)),
                 _ => None,
-            };
+            },
+            _ => None,
+        }
+    }
 
-            let on_expr: Option<&ast::Expr> = match constraint {
-                ast::JoinConstraint::On(expr) => Some(expr),
-                ast::JoinConstraint::None => None,
-                ast::JoinConstraint::Using(_) | ast::JoinConstraint::Natural => {
-                    derived_on_expr.as_ref()
-                }
-            };
+    let mut terms = Vec::new();
+    if let Some(expr) = selection {
+        conjuncts(expr, &mut terms);
+    }
+    let col_type = |name: &str| {
+        def.columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+            .map(|c| &c.ty)
+    };
+    let pk_ty = col_type(&def.primary_key);
+    let pk_value = |expr: &ast::Expr| -> Option<i64> {
+        match key_cell(literal(expr)?, pk_ty?).ok()? {
+            Cell::Int(i) => Some(i),
+            _ => None,
+        }
+    };
 
-            // JOIN output shape always appends the right table's columns.
-            accumulated_def_indices.push(j_def_idx);
-            let temp_defs: Vec<&TableDef> = accumulated_def_indices
-                .iter()
-                .map(|&idx| &loaded_defs[idx])
-                .collect();
-            let temp_col_map = build_col_map(&temp_defs);
-
-            let left_rows = std::mem::take(&mut accumulated_rows);
-            let equi_join_pairs = on_expr
-                .and_then(|expr| extract_equi_join_pairs(expr, &temp_col_map, left_col_count));
-
-            let mut new_rows = Vec::with_capacity(
-                left_rows
-                    .len()
-                    .saturating_mul(std::cmp::max(1, j_rows.len())),
-            );
-
-            let right_nulls = vec![Cell::Null; right_col_count];
-            let left_nulls = vec![Cell::Null; left_col_count];
-
-            match join_kind {
-                JoinKind::Inner | JoinKind::Left => {
-                    for left in &left_rows {
-                        let mut matched = false;
-                        for right in &j_rows {
-                            if let Some(pairs) = &equi_join_pairs {
-                                if eval_equi_join_pairs(left, right, pairs) {
-                                    matched = true;
-                                    let mut combined = left.values.clone();
-                                    combined.extend(right.values.clone());
-                                    new_rows.push(Row { values: combined });
-                                }
-                            } else {
-                                let mut combined = left.values.clone();
-                                combined.extend(right.values.clone());
-                                let row = Row { values: combined };
-                                let ok = match on_expr {
-                                    Some(expr) => {
-                                        eval_condition(session, expr, &row, &temp_col_map)?
-                                    }
-                                    None => true,
-                                };
-                                if ok {
-                                    matched = true;
-                                    new_rows.push(row);
-                                }
-                            }
+    // Bounds are i128 so `pk > i64::MAX`-style terms do not overflow.
+    let (mut lo, mut hi) = (i128::from(i64::MIN), i128::from(i64::MAX));
+    let mut bounded = false;
+    let mut index_eq = None;
+    for term in terms {
+        match term {
+            ast::Expr::Between {
+                expr,
+                negated: false,
+                low,
+                high,
+            } if column(expr).is_some_and(|c| c.eq_ignore_ascii_case(&def.primary_key)) => {
+                if let (Some(l), Some(h)) = (pk_value(low), pk_value(high)) {
+                    lo = lo.max(l.into());
+                    hi = hi.min(h.into());
+                    bounded = true;
+                }
+            }
+            ast::Expr::BinaryOp { left, op, right } => {
+                use ast::BinaryOperator as Op;
+                let (col, val, op) = match (column(left), column(right)) {
+                    (Some(c), None) => (c, right.as_ref(), op.clone()),
+                    (None, Some(c)) => {
+                        let flipped = match op {
+                            Op::Gt => Op::Lt,
+                            Op::GtEq => Op::LtEq,
+                            Op::Lt => Op::Gt,
+                            Op::LtEq => Op::GtEq,
+                            other => other.clone(),
+                        };
+                        (c, left.as_ref(), flipped)
+                    }
+                    _ => continue,
+                };
+                if col.eq_ignore_ascii_case(&def.primary_key) {
+                    let Some(v) = pk_value(val).map(i128::from) else {
+                        continue;
+                    };
+                    match op {
+                        Op::Eq => {
+                            lo = lo.max(v);
+                            hi = hi.min(v);
                         }
-
-                        if join_kind == JoinKind::Left && !matched {
-                            let mut combined = left.values.clone();
-                            combined.extend(right_nulls.clone());
-                            new_rows.push(Row { values: combined });
+                        Op::Gt => lo = lo.max(v + 1),
+                        Op::GtEq => lo = lo.max(v),
+                        Op::Lt => hi = hi.min(v - 1),
+                        Op::LtEq => hi = hi.min(v),
+                        _ => continue,
+                    }
+                    bounded = true;
+                } else if op == Op::Eq && index_eq.is_none() {
+                    let Some(index) = def.indexes.iter().find(|i| {
+                        i.columns
+                            .first()
+                            .is_some_and(|c| c.eq_ignore_ascii_case(col))
+                    }) else {
+                        continue;
+                    };
+                    let Some(ty) = col_type(col) else {
+                        continue;
+                    };
+                    match literal(val).map(|v| key_cell(v, ty)) {
+                        Some(Ok(v)) if !matches!(v, Cell::Null) => {
+                            index_eq = Some(KeyRange::IndexEq(index.name.clone(), v));
                         }
+                        _ => {}
                     }
                 }
-                JoinKind::Right => {
-                    let mut new_rows = Vec::with_capacity(
-                        j_rows
-                            .len()
-                            .saturating_mul(std::cmp::max(1, left_rows.len())),
-                    );
-                    for right in &j_rows {
-                        let mut matched = false;
-                        for left in &left_rows {
-                            if let Some(pairs) = &equi_join_pairs {
-                                if eval_equi_join_pairs(left, right, pairs) {
-                                    matched = true;
-                                    let mut combined = left.values.clone();
-                                    combined.extend(right.values.clone());
-                                    new_rows.push(Row { values: combined });
-                                }
-                            } else {
-                                let mut combined = left.values.clone();
-                                combined.extend(right.values.clone());
-                                let row = Row { values: combined };
-                                let ok = match on_expr {
-                                    Some(expr) => {
-                                        eval_condition(session, expr, &row, &temp_col_map)?
-                                    }
-                                    None => true,
-                                };
-                                if ok {
-                                    matched = true;
-                                    new_rows.push(row);
-                                }
-                            }
-                        }
+            }
+            _ => {}
+        }
+    }
+    match index_eq {
+        Some(range) if !bounded => range,
+        // No key satisfies the bounds.
+        _ if lo > hi => KeyRange::Pk(Some(1), Some(0)),
+        _ => {
+            let bound = |b: i128, unbounded: i64| i64::try_from(b).ok().filter(|b| *b != unbounded);
+            KeyRange::Pk(bound(lo, i64::MIN), bound(hi, i64::MAX))
+        }
+    }
+}
 
-                        if !matched {
-                            let mut combined = left_nulls.clone();
-                            combined.extend(right.values.clone());
-                            new_rows.push(Row { values: combined });
-                        }
-                    }
-                    accumulated_rows = new_rows;
-                    continue;
+/// Next-key lock a range as of the latest committed state: gap locks before each record in it
+/// and on the gap after it, and a record lock on the record after it. A primary key lookup that
+/// finds its row only locks the row. Records inside the range get their locks from the caller's
+/// scan. Inserts that commit while we wait change the plan, so lock until it is stable.
+fn lock_next_keys(
+    locks: &mut RowLockGuard<'_>,
+    db: &str,
+    table: &str,
+    range: &KeyRange,
+    mode: LockMode,
+    wait: LockWait,
+) -> Result<(), MiniError> {
+    let store = locks.store;
+    let mut locked = None;
+    loop {
+        let current = store.txn_manager.current_view(None);
+        let mut gaps = Vec::new();
+        let mut records = Vec::new();
+        match range {
+            KeyRange::Pk(Some(lo), Some(hi)) if lo > hi => {}
+            KeyRange::Pk(lo, hi) => {
+                let in_range = store
+                    .scan_rows_range_iter(
+                        db,
+                        table,
+                        current.clone(),
+                        lo.unwrap_or(i64::MIN),
+                        hi.unwrap_or(i64::MAX),
+                    )
+                    .map(|row| row.map(|(pk, _)| pk))
+                    .collect::<Result<Vec<i64>, _>>()?;
+                if lo.is_some() && lo == hi && !in_range.is_empty() {
+                    records = in_range;
+                } else {
+                    gaps.extend(in_range.into_iter().map(|pk| Gap::Primary(Some(pk))));
+                    let next = match hi {
+                        Some(hi) => store.next_row_after(db, table, *hi, &current)?,
+                        None => None,
+                    };
+                    gaps.push(Gap::Primary(next));
+                    records.extend(next);
                 }
             }
-            accumulated_rows = new_rows;
+            KeyRange::IndexEq(index, val) => {
+                let mut pks: Vec<i64> = store
+                    .index_lookup_mvcc(db, table, index, val, &current)?
+                    .into_iter()
+                    .map(|(pk, _)| pk)
+                    .collect();
+                // Index order: entries with equal values sort by big-endian PK bytes.
+                pks.sort_by_key(|pk| pk.to_be_bytes());
+                let last = pks.last().copied().unwrap_or(0);
+                gaps.extend(
+                    pks.into_iter()
+                        .map(|pk| Gap::Index(index.clone(), Some((val.clone(), pk)))),
+                );
+                let next = store.next_index_entry_after(db, table, index, val, last, &current)?;
+                records.extend(next.as_ref().map(|(_, pk)| *pk));
+                gaps.push(Gap::Index(index.clone(), next));
+            }
+        }
+        if locked.as_ref() == Some(&(gaps.clone(), records.clone())) {
+            return Ok(());
+        }
+        for gap in &gaps {
+            locks.lock_gap(db, table, gap.clone(), LockMode::Gap, wait)?;
+        }
+        for pk in &records {
+            locks.lock_row_with(db, table, *pk, mode, wait)?;
         }
+        locked = Some((gaps, records));
     }
+}
 
-    let final_defs: Vec<&TableDef> = accumulated_def_indices
+/// The lock clause that applies to a table: one naming it (or its alias) in `OF`, else one
+/// without `OF`.
+fn lock_clause_for<'q>(
+    locks: &'q [ast::LockClause],
+    table: &str,
+    alias: Option<&str>,
+) -> Option<&'q ast::LockClause> {
+    let named = |clause: &&ast::LockClause| {
+        clause.of.as_ref().is_some_and(|of| {
+            of.0.last().map(get_ident_name).is_some_and(|n| {
+                n.eq_ignore_ascii_case(alias.unwrap_or(table)) || n.eq_ignore_ascii_case(table)
+            })
+        })
+    };
+    locks
         .iter()
-        .map(|&idx| &loaded_defs[idx])
-        .collect();
-    execute_select_from_rows(session, &final_defs, accumulated_rows, select, query)
+        .find(named)
+        .or_else(|| locks.iter().find(|c| c.of.is_none()))
+}
+
+/// Whether a single-table query returns plain rows in primary key order, so a locking scan can
+/// stop as soon as LIMIT rows are locked.
+fn rows_in_scan_order(query: &ast::Query, select: &ast::Select, def: &TableDef) -> bool {
+    let plain_projection = select.projection.iter().all(|item| match item {
+        ast::SelectItem::UnnamedExpr(e) | ast::SelectItem::ExprWithAlias { expr: e, .. } => {
+            matches!(
+                e,
+                ast::Expr::Identifier(_) | ast::Expr::CompoundIdentifier(_)
+            )
+        }
+        ast::SelectItem::Wildcard(_) | ast::SelectItem::QualifiedWildcard(..) => true,
+    });
+    let ungrouped = matches!(&select.group_by, ast::GroupByExpr::Expressions(e, m) if e.is_empty() && m.is_empty())
+        && select.having.is_none()
+        && select.distinct.is_none();
+    let pk_order = match query.order_by.as_ref().map(|o| &o.kind) {
+        None => true,
+        Some(ast::OrderByKind::Expressions(exprs)) => match exprs.as_slice() {
+            [e] => {
+                let col = match &e.expr {
+                    ast::Expr::Identifier(i) => Some(&i.value),
+                    ast::Expr::CompoundIdentifier(ids) => ids.last().map(|i| &i.value),
+                    _ => None,
+                };
+                e.options.asc != Some(false)
+                    && col.is_some_and(|c| c.eq_ignore_ascii_case(&def.primary_key))
+            }
+            _ => false,
+        },
+        Some(ast::OrderByKind::All(_)) => false,
+    };
+    plain_projection && ungrouped && pk_order
 }
 
 fn build_information_schema_table(
@@ -2560,46 +5540,56 @@ fn build_information_schema_table(
             let def = information_schema_tables_def();
             let mut rows = Vec::new();
 
+            let mut tables = Vec::new();
             for db in store.list_databases()? {
                 for table in store.list_tables(&db)? {
-                    let row_count = store.count_rows(&db, &table)?.min(i64::MAX as u64) as i64;
-                    let tdef = store.get_table(&db, &table)?;
-                    let auto_inc = if tdef.auto_increment {
-                        store.auto_increment_next(&db, &table)?.unwrap_or(1)
-                    } else {
-                        0
-                    };
-                    rows.push(Row {
-                        values: vec![
-                            Cell::Text("def".into()),
-                            Cell::Text(db.clone()),
-                            Cell::Text(table),
-                            Cell::Text("BASE TABLE".into()),
-                            Cell::Text("InnoDB".into()),
-                            Cell::Int(10),
-                            Cell::Text("Dynamic".into()),
-                            Cell::Int(row_count),
-                            Cell::Int(0),
-                            Cell::Int(0),
-                            Cell::Int(0),
-                            Cell::Int(0),
-                            Cell::Int(0),
-                            if tdef.auto_increment {
-                                Cell::Int(auto_inc)
-                            } else {
-                                Cell::Null
-                            },
-                            Cell::Null,
-                            Cell::Null,
-                            Cell::Null,
-                            Cell::Text(session.collation_connection.clone()),
-                            Cell::Null,
-                            Cell::Text("".into()),
-                            Cell::Text("".into()),
-                        ],
-                    });
+                    tables.push((db.clone(), db.clone(), table, "BASE TABLE"));
                 }
             }
+            // Like MariaDB, list the session's own temporary tables; other sessions' are private.
+            for (db, table) in &session.temp_tables {
+                let schema = Store::temp_schema(session.conn_id, db);
+                tables.push((db.clone(), schema, table.clone(), "TEMPORARY"));
+            }
+            for (db, schema, table, table_type) in tables {
+                let stats = store.table_stats(&schema, &table)?;
+                let analysis = store.table_analysis(&schema, &table)?;
+                let tdef = store.get_table(&schema, &table)?;
+                let auto_inc = if tdef.auto_increment {
+                    store.auto_increment_next(&schema, &table)?.unwrap_or(1)
+                } else {
+                    0
+                };
+                rows.push(Row {
+                    values: vec![
+                        Cell::Text("def".into()),
+                        Cell::Text(db),
+                        Cell::Text(table),
+                        Cell::Text(table_type.into()),
+                        Cell::Text("InnoDB".into()),
+                        Cell::Int(10),
+                        Cell::Text("Dynamic".into()),
+                        stat_cell(stats.rows),
+                        stat_cell(stats.avg_row_length()),
+                        stat_cell(stats.data_length),
+                        Cell::Int(0),
+                        stat_cell(stats.index_length),
+                        Cell::Int(0),
+                        if tdef.auto_increment {
+                            Cell::Int(auto_inc)
+                        } else {
+                            Cell::Null
+                        },
+                        Cell::Null,
+                        datetime_text(stats.update_time),
+                        datetime_text(analysis.and_then(|a| a.analyzed_at)),
+                        Cell::Text(session.collation_connection.clone()),
+                        Cell::Null,
+                        Cell::Text("".into()),
+                        Cell::Text("".into()),
+                    ],
+                });
+            }
 
             for table in information_schema_table_names() {
                 rows.push(Row {
@@ -2656,18 +5646,8 @@ fn build_information_schema_table(
                             ),
                             _ => (Cell::Null, Cell::Null),
                         };
-                        let column_key = if col.name.eq_ignore_ascii_case(&tdef.primary_key) {
-                            "PRI"
-                        } else {
-                            ""
-                        };
-                        let extra = if tdef.auto_increment
-                            && col.name.eq_ignore_ascii_case(&tdef.primary_key)
-                        {
-                            "auto_increment"
-                        } else {
-                            ""
-                        };
+                        let column_key = column_key(&tdef, col);
+                        let extra = column_extra(&tdef, col);
                         rows.push(Row {
                             values: vec![
                                 Cell::Text("def".into()),
@@ -2675,7 +5655,7 @@ fn build_information_schema_table(
                                 Cell::Text(table.clone()),
                                 Cell::Text(col.name.clone()),
                                 Cell::Int(ordinal),
-                                Cell::Null,
+                                column_default_text(col),
                                 Cell::Text(is_nullable.into()),
                                 Cell::Text(data_type.into()),
                                 Cell::Null,
@@ -2695,7 +5675,7 @@ fn build_information_schema_table(
                                 coll,
                                 Cell::Text(col_type.into()),
                                 Cell::Text(column_key.into()),
-                                Cell::Text(extra.into()),
+                                Cell::Text(extra),
                                 Cell::Text("select,insert,update,references".into()),
                                 Cell::Text("".into()),
                             ],
@@ -2723,11 +5703,7 @@ fn build_information_schema_table(
                         ),
                         _ => (Cell::Null, Cell::Null),
                     };
-                    let column_key = if col.name.eq_ignore_ascii_case(&tdef.primary_key) {
-                        "PRI"
-                    } else {
-                        ""
-                    };
+                    let column_key = column_key(&tdef, col);
                     rows.push(Row {
                         values: vec![
                             Cell::Text("def".into()),
@@ -2779,29 +5755,71 @@ fn build_information_schema_table(
                         .find(|c| c.name.eq_ignore_ascii_case(&pk_name))
                         .map(|c| c.nullable)
                         .unwrap_or(false);
-                    let row_count = store.count_rows(&db, &table)?.min(i64::MAX as u64) as i64;
-                    rows.push(Row {
+                    let stats = store.table_stats(&db, &table)?;
+                    let analysis = store.table_analysis(&db, &table)?;
+                    let index_row = |non_unique, index: &str, seq, column, nullable: bool| Row {
                         values: vec![
                             Cell::Text("def".into()),
                             Cell::Text(db.clone()),
                             Cell::Text(table.clone()),
-                            Cell::Int(0),
+                            Cell::Int(non_unique),
                             Cell::Text(db.clone()),
-                            Cell::Text("PRIMARY".into()),
-                            Cell::Int(1),
-                            Cell::Text(pk_name),
+                            Cell::Text(index.into()),
+                            Cell::Int(seq),
+                            Cell::Text(column),
                             Cell::Text("A".into()),
-                            Cell::Int(row_count),
+                            index_cardinality(analysis.as_ref(), &stats, index),
                             Cell::Null,
                             Cell::Null,
-                            Cell::Text(if pk_nullable { "YES" } else { "NO" }.into()),
+                            Cell::Text(if nullable { "YES" } else { "NO" }.into()),
                             Cell::Text("BTREE".into()),
                             Cell::Text("".into()),
                             Cell::Text("".into()),
                             Cell::Text("YES".into()),
                             Cell::Null,
                         ],
-                    });
+                    };
+                    rows.push(index_row(0, "PRIMARY", 1, pk_name, pk_nullable));
+                    for idx in &tdef.indexes {
+                        for (seq, col) in idx.columns.iter().enumerate() {
+                            let non_unique = if idx.unique { 0 } else { 1 };
+                            rows.push(index_row(
+                                non_unique,
+                                &idx.name,
+                                seq as i64 + 1,
+                                col.clone(),
+                                true,
+                            ));
+                        }
+                    }
+                }
+            }
+
+            Ok((def, rows))
+        }
+        "column_statistics" => {
+            let def = information_schema_column_statistics_def();
+            let mut rows = Vec::new();
+
+            for db in store.list_databases()? {
+                for table in store.list_tables(&db)? {
+                    let Some(analysis) = store.table_analysis(&db, &table)? else {
+                        continue;
+                    };
+                    let tdef = store.get_table(&db, &table)?;
+                    for (column, histogram) in &analysis.histograms {
+                        let Some(col) = tdef.columns.iter().find(|c| &c.name == column) else {
+                            continue;
+                        };
+                        rows.push(Row {
+                            values: vec![
+                                Cell::Text(db.clone()),
+                                Cell::Text(table.clone()),
+                                Cell::Text(column.clone()),
+                                Cell::Text(histogram_json(histogram, &col.ty)),
+                            ],
+                        });
+                    }
                 }
             }
 
@@ -2818,31 +5836,11 @@ fn information_schema_schemata_def() -> TableDef {
         db: "information_schema".into(),
         name: "SCHEMATA".into(),
         columns: vec![
-            ColumnDef {
-                name: "CATALOG_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "SCHEMA_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "DEFAULT_CHARACTER_SET_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "DEFAULT_COLLATION_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "SQL_PATH".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
+            ColumnDef::new("CATALOG_NAME", SqlType::Text, false),
+            ColumnDef::new("SCHEMA_NAME", SqlType::Text, false),
+            ColumnDef::new("DEFAULT_CHARACTER_SET_NAME", SqlType::Text, false),
+            ColumnDef::new("DEFAULT_COLLATION_NAME", SqlType::Text, false),
+            ColumnDef::new("SQL_PATH", SqlType::Text, true),
         ],
         primary_key: "SCHEMA_NAME".into(),
         auto_increment: false,
@@ -2855,111 +5853,27 @@ fn information_schema_tables_def() -> TableDef {
         db: "information_schema".into(),
         name: "TABLES".into(),
         columns: vec![
-            ColumnDef {
-                name: "TABLE_CATALOG".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_SCHEMA".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_TYPE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "ENGINE".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "VERSION".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "ROW_FORMAT".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "TABLE_ROWS".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "AVG_ROW_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "DATA_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "MAX_DATA_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "INDEX_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "DATA_FREE".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "AUTO_INCREMENT".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CREATE_TIME".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "UPDATE_TIME".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CHECK_TIME".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "TABLE_COLLATION".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CHECKSUM".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CREATE_OPTIONS".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "TABLE_COMMENT".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
+            ColumnDef::new("TABLE_CATALOG", SqlType::Text, false),
+            ColumnDef::new("TABLE_SCHEMA", SqlType::Text, false),
+            ColumnDef::new("TABLE_NAME", SqlType::Text, false),
+            ColumnDef::new("TABLE_TYPE", SqlType::Text, false),
+            ColumnDef::new("ENGINE", SqlType::Text, true),
+            ColumnDef::new("VERSION", SqlType::Int, true),
+            ColumnDef::new("ROW_FORMAT", SqlType::Text, true),
+            ColumnDef::new("TABLE_ROWS", SqlType::Int, true),
+            ColumnDef::new("AVG_ROW_LENGTH", SqlType::Int, true),
+            ColumnDef::new("DATA_LENGTH", SqlType::Int, true),
+            ColumnDef::new("MAX_DATA_LENGTH", SqlType::Int, true),
+            ColumnDef::new("INDEX_LENGTH", SqlType::Int, true),
+            ColumnDef::new("DATA_FREE", SqlType::Int, true),
+            ColumnDef::new("AUTO_INCREMENT", SqlType::Int, true),
+            ColumnDef::new("CREATE_TIME", SqlType::Text, true),
+            ColumnDef::new("UPDATE_TIME", SqlType::Text, true),
+            ColumnDef::new("CHECK_TIME", SqlType::Text, true),
+            ColumnDef::new("TABLE_COLLATION", SqlType::Text, true),
+            ColumnDef::new("CHECKSUM", SqlType::Int, true),
+            ColumnDef::new("CREATE_OPTIONS", SqlType::Text, true),
+            ColumnDef::new("TABLE_COMMENT", SqlType::Text, false),
         ],
         primary_key: "TABLE_NAME".into(),
         auto_increment: false,
@@ -2972,106 +5886,26 @@ fn information_schema_columns_def() -> TableDef {
         db: "information_schema".into(),
         name: "COLUMNS".into(),
         columns: vec![
-            ColumnDef {
-                name: "TABLE_CATALOG".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_SCHEMA".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLUMN_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "ORDINAL_POSITION".into(),
-                ty: SqlType::Int,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLUMN_DEFAULT".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "IS_NULLABLE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "DATA_TYPE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "CHARACTER_MAXIMUM_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CHARACTER_OCTET_LENGTH".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "NUMERIC_PRECISION".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "NUMERIC_SCALE".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "DATETIME_PRECISION".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CHARACTER_SET_NAME".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "COLLATION_NAME".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "COLUMN_TYPE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLUMN_KEY".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "EXTRA".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "PRIVILEGES".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLUMN_COMMENT".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
+            ColumnDef::new("TABLE_CATALOG", SqlType::Text, false),
+            ColumnDef::new("TABLE_SCHEMA", SqlType::Text, false),
+            ColumnDef::new("TABLE_NAME", SqlType::Text, false),
+            ColumnDef::new("COLUMN_NAME", SqlType::Text, false),
+            ColumnDef::new("ORDINAL_POSITION", SqlType::Int, false),
+            ColumnDef::new("COLUMN_DEFAULT", SqlType::Text, true),
+            ColumnDef::new("IS_NULLABLE", SqlType::Text, false),
+            ColumnDef::new("DATA_TYPE", SqlType::Text, false),
+            ColumnDef::new("CHARACTER_MAXIMUM_LENGTH", SqlType::Int, true),
+            ColumnDef::new("CHARACTER_OCTET_LENGTH", SqlType::Int, true),
+            ColumnDef::new("NUMERIC_PRECISION", SqlType::Int, true),
+            ColumnDef::new("NUMERIC_SCALE", SqlType::Int, true),
+            ColumnDef::new("DATETIME_PRECISION", SqlType::Int, true),
+            ColumnDef::new("CHARACTER_SET_NAME", SqlType::Text, true),
+            ColumnDef::new("COLLATION_NAME", SqlType::Text, true),
+            ColumnDef::new("COLUMN_TYPE", SqlType::Text, false),
+            ColumnDef::new("COLUMN_KEY", SqlType::Text, false),
+            ColumnDef::new("EXTRA", SqlType::Text, false),
+            ColumnDef::new("PRIVILEGES", SqlType::Text, false),
+            ColumnDef::new("COLUMN_COMMENT", SqlType::Text, false),
         ],
         primary_key: "COLUMN_NAME".into(),
         auto_increment: false,
@@ -3084,96 +5918,24 @@ fn information_schema_statistics_def() -> TableDef {
         db: "information_schema".into(),
         name: "STATISTICS".into(),
         columns: vec![
-            ColumnDef {
-                name: "TABLE_CATALOG".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_SCHEMA".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "TABLE_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "NON_UNIQUE".into(),
-                ty: SqlType::Int,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "INDEX_SCHEMA".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "INDEX_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "SEQ_IN_INDEX".into(),
-                ty: SqlType::Int,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLUMN_NAME".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COLLATION".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "CARDINALITY".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "SUB_PART".into(),
-                ty: SqlType::Int,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "PACKED".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
-            ColumnDef {
-                name: "NULLABLE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "INDEX_TYPE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "COMMENT".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "INDEX_COMMENT".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "IS_VISIBLE".into(),
-                ty: SqlType::Text,
-                nullable: false,
-            },
-            ColumnDef {
-                name: "EXPRESSION".into(),
-                ty: SqlType::Text,
-                nullable: true,
-            },
+            ColumnDef::new("TABLE_CATALOG", SqlType::Text, false),
+            ColumnDef::new("TABLE_SCHEMA", SqlType::Text, false),
+            ColumnDef::new("TABLE_NAME", SqlType::Text, false),
+            ColumnDef::new("NON_UNIQUE", SqlType::Int, false),
+            ColumnDef::new("INDEX_SCHEMA", SqlType::Text, false),
+            ColumnDef::new("INDEX_NAME", SqlType::Text, false),
+            ColumnDef::new("SEQ_IN_INDEX", SqlType::Int, false),
+            ColumnDef::new("COLUMN_NAME", SqlType::Text, false),
+            ColumnDef::new("COLLATION", SqlType::Text, true),
+            ColumnDef::new("CARDINALITY", SqlType::Int, true),
+            ColumnDef::new("SUB_PART", SqlType::Int, true),
+            ColumnDef::new("PACKED", SqlType::Text, true),
+            ColumnDef::new("NULLABLE", SqlType::Text, false),
+            ColumnDef::new("INDEX_TYPE", SqlType::Text, false),
+            ColumnDef::new("COMMENT", SqlType::Text, false),
+            ColumnDef::new("INDEX_COMMENT", SqlType::Text, false),
+            ColumnDef::new("IS_VISIBLE", SqlType::Text, false),
+            ColumnDef::new("EXPRESSION", SqlType::Text, true),
         ],
         primary_key: "INDEX_NAME".into(),
         auto_increment: false,
@@ -3181,12 +5943,32 @@ fn information_schema_statistics_def() -> TableDef {
     }
 }
 
+fn information_schema_column_statistics_def() -> TableDef {
+    TableDef {
+        db: "information_schema".into(),
+        name: "COLUMN_STATISTICS".into(),
+        columns: vec![
+            ColumnDef::new("SCHEMA_NAME", SqlType::Text, false),
+            ColumnDef::new("TABLE_NAME", SqlType::Text, false),
+            ColumnDef::new("COLUMN_NAME", SqlType::Text, false),
+            ColumnDef::new("HISTOGRAM", SqlType::Text, false),
+        ],
+        primary_key: "COLUMN_NAME".into(),
+        auto_increment: false,
+        indexes: vec![],
+    }
+}
+
 fn information_schema_defs() -> Vec<(String, TableDef)> {
     vec![
         ("SCHEMATA".into(), information_schema_schemata_def()),
         ("TABLES".into(), information_schema_tables_def()),
         ("COLUMNS".into(), information_schema_columns_def()),
         ("STATISTICS".into(), information_schema_statistics_def()),
+        (
+            "COLUMN_STATISTICS".into(),
+            information_schema_column_statistics_def(),
+        ),
     ]
 }
 
@@ -3243,670 +6025,18 @@ fn order_by_expr_to_base_col_idx(
                 let last_two = format!("{}.{}", ids[ids.len() - 2].value, ids[ids.len() - 1].value)
                     .to_ascii_lowercase();
                 if let Some(&idx) = col_map.get(&last_two) {
-                    if idx != usize::MAX {
-                        return Some(idx);
-                    }
-                }
-            }
-
-            ids.last()
-                .and_then(|ident| col_map.get(&ident.value.to_ascii_lowercase()).copied())
-                .filter(|idx| *idx != usize::MAX)
-        }
-        _ => None,
-    }
-}
-
-fn try_apply_order_by_on_base_rows(
-    rows: &mut [Row],
-    query: &ast::Query,
-    col_map: &std::collections::HashMap<String, usize>,
-) -> Result<bool, MiniError> {
-    let Some(order_by) = &query.order_by else {
-        return Ok(false);
-    };
-    let exprs = match &order_by.kind {
-        ast::OrderByKind::Expressions(e) => e,
-        _ => return Err(MiniError::NotSupported("Order By ALL not supported".into())),
-    };
-
-    let mut sort_keys: Vec<(usize, bool)> = Vec::new(); // (col idx, desc)
-    for e in exprs {
-        let Some(idx) = order_by_expr_to_base_col_idx(&e.expr, col_map) else {
-            return Ok(false);
-        };
-        let desc = e.options.asc == Some(false);
-        sort_keys.push((idx, desc));
-    }
-
-    if sort_keys.is_empty() {
-        return Ok(false);
-    }
-
-    rows.sort_by(|a, b| {
-        for (idx, desc) in &sort_keys {
-            let cmp = compare_cell_for_order(&a.values[*idx], &b.values[*idx]);
-            let cmp = if *desc { cmp.reverse() } else { cmp };
-            if cmp != std::cmp::Ordering::Equal {
-                return cmp;
-            }
-        }
-        std::cmp::Ordering::Equal
-    });
-    Ok(true)
-}
-
-fn apply_distinct_rows(rows: Vec<Vec<Cell>>) -> Vec<Vec<Cell>> {
-    let mut seen: std::collections::HashSet<Vec<Cell>> = std::collections::HashSet::new();
-    let mut out = Vec::new();
-    for row in rows {
-        if seen.insert(row.clone()) {
-            out.push(row);
-        }
-    }
-    out
-}
-
-fn execute_select_from_rows(
-    session: &SessionState,
-    defs: &[&TableDef],
-    mut rows: Vec<Row>,
-    select: &ast::Select,
-    query: &ast::Query,
-) -> Result<ExecOutput, MiniError> {
-    use std::collections::HashMap;
-
-    let col_map = build_col_map(defs);
-
-    // 1. WHERE Filtering
-    if let Some(selection) = &select.selection {
-        let mut new_rows = Vec::with_capacity(rows.len());
-        for row in rows {
-            if eval_condition(session, selection, &row, &col_map)? {
-                new_rows.push(row);
-            }
-        }
-        rows = new_rows;
-    }
-
-    // 2. Projections & Aggregation Analysis
-    #[derive(Clone, Debug)]
-    enum ProjKind {
-        Scalar(Box<ast::Expr>), // Standard expression
-        Aggregate(usize),       // Index into accumulators
-    }
-
-    let mut projection_plan: Vec<(String, ProjKind)> = Vec::new(); // (Alias, Kind)
-    let mut aggs_to_compute: Vec<(String, Option<ast::Expr>)> = Vec::new(); // (Func, ArgExpr)
-
-    // 3. Projections Analysis
-    fn is_agg(expr: &ast::Expr) -> Option<(String, Option<ast::Expr>)> {
-        match expr {
-            ast::Expr::Function(f) => {
-                let name = f.name.to_string().to_ascii_lowercase();
-                if matches!(name.as_str(), "count" | "sum" | "avg" | "min" | "max") {
-                    let arg = match &f.args {
-                        ast::FunctionArguments::List(l) => {
-                            if l.args.len() == 1 {
-                                match &l.args[0] {
-                                    ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => {
-                                        Some(e.clone())
-                                    }
-                                    ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard) => {
-                                        None
-                                    } // count(*)
-                                    _ => None,
-                                }
-                            } else {
-                                None
-                            }
-                        }
-                        _ => None,
-                    };
-                    return Some((name, arg));
-                }
-                None
-            }
-            _ => None,
-        }
-    }
-
-    for item in &select.projection {
-        match item {
-            ast::SelectItem::Wildcard(_) => {
-                if defs.len() == 1 {
-                    // Expand * to all cols from the single table.
-                    for c in &defs[0].columns {
-                        projection_plan.push((
-                            c.name.clone(),
-                            ProjKind::Scalar(Box::new(ast::Expr::Identifier(ast::Ident::new(
-                                &c.name,
-                            )))),
-                        ));
-                    }
-                } else {
-                    // For multi-table queries, qualify wildcards to avoid ambiguous column names
-                    // (e.g. `id` from two tables).
-                    for def in defs {
-                        for c in &def.columns {
-                            projection_plan.push((
-                                c.name.clone(),
-                                ProjKind::Scalar(Box::new(ast::Expr::CompoundIdentifier(vec![
-                                    ast::Ident::new(&def.name),
-                                    ast::Ident::new(&c.name),
-                                ]))),
-                            ));
-                        }
-                    }
-                }
-            }
-            ast::SelectItem::QualifiedWildcard(kind, _) => {
-                let obj_name = match kind {
-                    ast::SelectItemQualifiedWildcardKind::ObjectName(obj_name) => obj_name,
-                    ast::SelectItemQualifiedWildcardKind::Expr(_) => {
-                        return Err(MiniError::NotSupported(
-                            "Wildcard on expression is not supported".into(),
-                        ));
-                    }
-                };
-
-                let (_db_opt, qualifier) = object_name_to_parts(obj_name)?;
-                let def = defs
-                    .iter()
-                    .find(|d| d.name.eq_ignore_ascii_case(&qualifier));
-                let Some(def) = def else {
-                    return Err(MiniError::NotFound(format!(
-                        "unknown table in wildcard: {qualifier}"
-                    )));
-                };
-
-                for c in &def.columns {
-                    projection_plan.push((
-                        c.name.clone(),
-                        ProjKind::Scalar(Box::new(ast::Expr::CompoundIdentifier(vec![
-                            ast::Ident::new(&def.name),
-                            ast::Ident::new(&c.name),
-                        ]))),
-                    ));
-                }
-            }
-            ast::SelectItem::UnnamedExpr(expr) => {
-                let alias = match expr {
-                    ast::Expr::Identifier(i) => i.value.clone(),
-                    _ => format!("col_{}", projection_plan.len()),
-                };
-                if let Some((fname, arg)) = is_agg(expr) {
-                    let idx = aggs_to_compute.len();
-                    aggs_to_compute.push((fname, arg));
-                    projection_plan.push((alias, ProjKind::Aggregate(idx)));
-                } else {
-                    projection_plan.push((alias, ProjKind::Scalar(Box::new(expr.clone()))));
-                }
-            }
-            ast::SelectItem::ExprWithAlias { expr, alias } => {
-                if let Some((fname, arg)) = is_agg(expr) {
-                    let idx = aggs_to_compute.len();
-                    aggs_to_compute.push((fname, arg));
-                    projection_plan.push((alias.value.clone(), ProjKind::Aggregate(idx)));
-                } else {
-                    projection_plan.push((
-                        alias.value.clone(),
-                        ProjKind::Scalar(Box::new(expr.clone())),
-                    ));
-                }
-            }
-        }
-    }
-
-    // 3. Group By Analysis
-    let group_by_exprs = match &select.group_by {
-        ast::GroupByExpr::Expressions(exprs, _) => exprs.clone(),
-        ast::GroupByExpr::All(_) => {
-            return Err(MiniError::NotSupported("GROUP BY ALL not supported".into()))
-        }
-    };
-
-    let is_grouped = !group_by_exprs.is_empty() || !aggs_to_compute.is_empty();
-
-    if !is_grouped {
-        let order_applied_pre_projection =
-            try_apply_order_by_on_base_rows(&mut rows, query, &col_map)?;
-
-        // Simple case: Just Map standard rows
-        let mut final_rows = Vec::new();
-        for row in rows {
-            let mut out_row = Vec::new();
-            for (_, kind) in &projection_plan {
-                if let ProjKind::Scalar(e) = kind {
-                    out_row.push(eval_row_expr(session, e.as_ref(), &row, &col_map)?);
-                } else {
-                    return Err(MiniError::Invalid(
-                        "Unexpected aggregate in non-grouped query".into(),
-                    ));
-                }
-            }
-            final_rows.push(out_row);
-        }
-
-        if select.distinct.is_some() {
-            final_rows = apply_distinct_rows(final_rows);
-        }
-
-        let aliases: Vec<String> = projection_plan.into_iter().map(|(a, _)| a).collect();
-        return finish_select(
-            defs, // Fixed: def -> defs
-            final_rows,
-            aliases,
-            query,
-            order_applied_pre_projection,
-        );
-    }
-
-    // 4. Grouping Execution
-    #[derive(Debug, PartialEq, Eq, Hash, Clone)]
-    struct GroupKey(Vec<Cell>);
-
-    struct GroupState {
-        first_row: Row,
-        accumulators: Vec<Box<dyn Accumulator>>,
-    }
-
-    trait Accumulator {
-        fn add(&mut self, val: Cell);
-        fn inc(&mut self);
-        fn finish(&self) -> Cell;
-    }
-
-    struct CountAcc(i64);
-    impl Accumulator for CountAcc {
-        fn add(&mut self, _v: Cell) {
-            self.0 += 1;
-        }
-        fn inc(&mut self) {
-            self.0 += 1;
-        }
-        fn finish(&self) -> Cell {
-            Cell::Int(self.0)
-        }
-    }
-
-    struct SumAcc(Cell);
-    impl Accumulator for SumAcc {
-        fn add(&mut self, v: Cell) {
-            if matches!(v, Cell::Null) {
-                return;
-            }
-            if let Some(res) = self.0.add(&v) {
-                self.0 = res;
-            }
-        }
-        fn inc(&mut self) {}
-        fn finish(&self) -> Cell {
-            self.0.clone()
-        }
-    }
-
-    struct AVGAcc {
-        sum: Cell,
-        count: i64,
-    }
-    impl Accumulator for AVGAcc {
-        fn add(&mut self, v: Cell) {
-            if matches!(v, Cell::Null) {
-                return;
-            }
-            if let Some(res) = self.sum.add(&v) {
-                self.sum = res;
-                self.count += 1;
-            }
-        }
-        fn inc(&mut self) {}
-        fn finish(&self) -> Cell {
-            if self.count == 0 {
-                return Cell::Null;
-            }
-            self.sum
-                .div_count(self.count as usize)
-                .unwrap_or(Cell::Null)
-        }
-    }
-
-    struct MinMaxAcc {
-        val: Cell,
-        is_min: bool,
-    }
-    impl Accumulator for MinMaxAcc {
-        fn add(&mut self, v: Cell) {
-            if matches!(v, Cell::Null) {
-                return;
-            }
-            if matches!(self.val, Cell::Null) {
-                self.val = v;
-            } else {
-                let cmp = compare_cell_for_order(&v, &self.val);
-                if self.is_min {
-                    if cmp == std::cmp::Ordering::Less {
-                        self.val = v;
-                    }
-                } else if cmp == std::cmp::Ordering::Greater {
-                    self.val = v;
-                }
-            }
-        }
-        fn inc(&mut self) {}
-        fn finish(&self) -> Cell {
-            self.val.clone()
-        }
-    }
-
-    let mut groups: HashMap<GroupKey, GroupState> = HashMap::new();
-
-    // Initialize implicit single group if needed (Standard SQL: SELECT count(*) FROM t returns 0 if empty)
-    if rows.is_empty() && group_by_exprs.is_empty() {
-        let mut accs: Vec<Box<dyn Accumulator>> = Vec::new();
-        for (fname, _) in &aggs_to_compute {
-            match fname.as_str() {
-                "count" => accs.push(Box::new(CountAcc(0))),
-                "sum" => accs.push(Box::new(SumAcc(Cell::Null))),
-                "avg" => accs.push(Box::new(AVGAcc {
-                    sum: Cell::Int(0),
-                    count: 0,
-                })), // Init at 0/0 -> Null
-                "min" | "max" => accs.push(Box::new(MinMaxAcc {
-                    val: Cell::Null,
-                    is_min: fname == "min",
-                })),
-                _ => accs.push(Box::new(CountAcc(0))),
-            }
-        }
-        groups.insert(
-            GroupKey(vec![]),
-            GroupState {
-                first_row: Row { values: vec![] },
-                accumulators: accs,
-            },
-        );
-    }
-
-    for row in rows {
-        // Calc Key
-        let mut key_cells = Vec::new();
-        for expr in &group_by_exprs {
-            key_cells.push(eval_row_expr(session, expr, &row, &col_map)?);
-        }
-        let key = GroupKey(key_cells);
-
-        let entry = groups.entry(key).or_insert_with(|| {
-            let mut accs: Vec<Box<dyn Accumulator>> = Vec::new();
-            for (fname, _) in &aggs_to_compute {
-                match fname.as_str() {
-                    "count" => accs.push(Box::new(CountAcc(0))),
-                    "sum" => accs.push(Box::new(SumAcc(Cell::Int(0)))),
-                    "avg" => accs.push(Box::new(AVGAcc {
-                        sum: Cell::Int(0),
-                        count: 0,
-                    })),
-                    "min" | "max" => accs.push(Box::new(MinMaxAcc {
-                        val: Cell::Null,
-                        is_min: fname == "min",
-                    })),
-                    _ => accs.push(Box::new(CountAcc(0))),
-                }
-            }
-            GroupState {
-                first_row: row.clone(),
-                accumulators: accs,
-            }
-        });
-
-        // Update Accumulators
-        for (i, (fname, arg_expr)) in aggs_to_compute.iter().enumerate() {
-            if fname == "count" && arg_expr.is_none() {
-                entry.accumulators[i].inc();
-            } else if let Some(expr) = arg_expr {
-                let val = eval_row_expr(session, expr, &row, &col_map)?;
-                entry.accumulators[i].add(val);
-            }
-        }
-    }
-
-    // 5. Generate Results
-    let mut result_rows = Vec::new();
-    for (_key, state) in groups {
-        let mut out_row = Vec::new();
-        for (_, kind) in &projection_plan {
-            match kind {
-                ProjKind::Scalar(expr) => {
-                    // Evaluate against representative row
-                    out_row.push(eval_row_expr(
-                        session,
-                        expr.as_ref(),
-                        &state.first_row,
-                        &col_map,
-                    )?);
-                }
-                ProjKind::Aggregate(idx) => {
-                    out_row.push(state.accumulators[*idx].finish());
-                }
-            }
-        }
-        result_rows.push(out_row);
-    }
-
-    // 6. HAVING (Post-Aggregation Filtering)
-    if let Some(having) = &select.having {
-        let aliases: Vec<String> = projection_plan.iter().map(|(a, _)| a.clone()).collect();
-        let out_map: HashMap<String, usize> = aliases
-            .iter()
-            .enumerate()
-            .map(|(i, name)| (name.to_ascii_lowercase(), i))
-            .collect();
-
-        let mut filtered_rows = Vec::new();
-        for row in result_rows {
-            // Create a temporary Row wrapper for evaluation
-            let r = Row {
-                values: row.clone(),
-            };
-            if eval_condition(session, having, &r, &out_map)? {
-                filtered_rows.push(row);
-            }
-        }
-        result_rows = filtered_rows;
-    }
-
-    if select.distinct.is_some() {
-        result_rows = apply_distinct_rows(result_rows);
-    }
-
-    let aliases: Vec<String> = projection_plan.into_iter().map(|(a, _)| a).collect();
-    finish_select(defs, result_rows, aliases, query, false)
-}
-
-fn finish_select(
-    defs: &[&TableDef],
-    mut rows: Vec<Vec<Cell>>,
-    aliases: Vec<String>,
-    query: &ast::Query,
-    order_applied_pre_projection: bool,
-) -> Result<ExecOutput, MiniError> {
-    // 6. Order By
-    if !order_applied_pre_projection {
-        if let Some(order_by) = &query.order_by {
-            let exprs = match &order_by.kind {
-                ast::OrderByKind::Expressions(e) => e,
-                _ => return Err(MiniError::NotSupported("Order By ALL not supported".into())),
-            };
-
-            // Simplified: sort by alias or column index if possible
-            // For now, strict limitation: ORDER BY must match output column alias OR index (1-based)
-
-            let mut sort_keys = Vec::new();
-            for e in exprs {
-                let (idx, desc) = match &e.expr {
-                    ast::Expr::Identifier(ident) => {
-                        // Check aliases
-                        if let Some(pos) = aliases
-                            .iter()
-                            .position(|a| a.eq_ignore_ascii_case(&ident.value))
-                        {
-                            (pos, e.options.asc == Some(false))
-                        } else {
-                            // Fallback? Error?
-                            // Maybe it's a column name in the original TableDef?
-                            // If so, we need to locate it in the Output if it passed through.
-                            // For GROUP BY, we lose non-projected columns.
-                            return Err(MiniError::NotSupported(
-                                "Order By must match output column".into(),
-                            ));
-                        }
-                    }
-                    ast::Expr::Value(v) => {
-                        match &v.value {
-                            ast::Value::Number(n, _) => {
-                                // 1-based index
-                                let pos = n.parse::<usize>().map_err(|_| {
-                                    MiniError::Invalid("Order By index must be an integer".into())
-                                })?;
-                                if (1..=aliases.len()).contains(&pos) {
-                                    (pos - 1, e.options.asc == Some(false))
-                                } else {
-                                    return Err(MiniError::Invalid("Order By index OOB".into()));
-                                }
-                            }
-                            _ => {
-                                return Err(MiniError::NotSupported(
-                                    "Complex Order By not implemented".into(),
-                                ))
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(MiniError::NotSupported(
-                            "Complex Order By not implemented".into(),
-                        ))
-                    }
-                };
-                sort_keys.push((idx, desc));
-            }
-
-            rows.sort_by(|a, b| {
-                for (idx, desc) in &sort_keys {
-                    let cmp = compare_cell_for_order(&a[*idx], &b[*idx]);
-                    let cmp = if *desc { cmp.reverse() } else { cmp };
-                    if cmp != std::cmp::Ordering::Equal {
-                        return cmp;
-                    }
-                }
-                std::cmp::Ordering::Equal
-            });
-        }
-    }
-
-    // 7. Output Schema
-    let mut columns = Vec::new();
-    for (idx, alias) in aliases.into_iter().enumerate() {
-        let mut inferred = None::<ColumnType>;
-        for row in &rows {
-            let Some(cell) = row.get(idx) else { continue };
-            match cell {
-                Cell::Null => {}
-                Cell::Int(_) => {
-                    inferred = Some(ColumnType::MYSQL_TYPE_LONGLONG);
-                    break;
-                }
-                Cell::Float(_) => {
-                    inferred = Some(ColumnType::MYSQL_TYPE_DOUBLE);
-                    break;
-                }
-                Cell::Text(_) | Cell::Date(_) | Cell::DateTime(_) => {
-                    inferred = Some(ColumnType::MYSQL_TYPE_VAR_STRING);
-                    break;
-                }
-            }
-        }
-
-        let coltype = inferred.unwrap_or_else(|| {
-            // Check all tables
-            let mut found_type = None;
-            for def in defs {
-                if let Some(c) = def
-                    .columns
-                    .iter()
-                    .find(|c| c.name.eq_ignore_ascii_case(&alias))
-                {
-                    found_type = Some(match c.ty {
-                        SqlType::Int => ColumnType::MYSQL_TYPE_LONGLONG,
-                        SqlType::Float => ColumnType::MYSQL_TYPE_DOUBLE,
-                        SqlType::Text | SqlType::Date | SqlType::DateTime => {
-                            ColumnType::MYSQL_TYPE_VAR_STRING
-                        }
-                    });
-                    break;
-                }
-            }
-            found_type.unwrap_or(ColumnType::MYSQL_TYPE_VAR_STRING)
-        });
-
-        columns.push(Column {
-            table: "".into(),
-            column: alias,
-            coltype,
-            colflags: ColumnFlags::empty(),
-        });
-    }
-
-    // 8. Limit/Offset
-    let eval_nonneg_usize = |expr: &ast::Expr, what: &str| -> Result<usize, MiniError> {
-        let v = eval_expr(expr)?
-            .as_i64()
-            .ok_or_else(|| MiniError::Invalid(format!("{what} must be an integer")))?;
-        if v < 0 {
-            return Err(MiniError::Invalid(format!("{what} cannot be negative")));
-        }
-        usize::try_from(v).map_err(|_| MiniError::Invalid(format!("{what} is too large")))
-    };
-
-    let mut offset = 0usize;
-    let mut limit = None::<usize>;
-    if let Some(limit_clause) = &query.limit_clause {
-        match limit_clause {
-            ast::LimitClause::LimitOffset {
-                limit: lim,
-                offset: off,
-                ..
-            } => {
-                if let Some(lim_expr) = lim {
-                    limit = Some(eval_nonneg_usize(lim_expr, "LIMIT")?);
-                }
-                if let Some(off) = off {
-                    offset = eval_nonneg_usize(&off.value, "OFFSET")?;
+                    if idx != usize::MAX {
+                        return Some(idx);
+                    }
                 }
             }
-            ast::LimitClause::OffsetCommaLimit {
-                offset: off,
-                limit: lim,
-            } => {
-                offset = eval_nonneg_usize(off, "OFFSET")?;
-                limit = Some(eval_nonneg_usize(lim, "LIMIT")?);
-            }
-        }
-    }
 
-    if offset > 0 {
-        if offset >= rows.len() {
-            rows.clear();
-        } else {
-            rows.drain(0..offset);
-        }
-    }
-    if let Some(limit) = limit {
-        if limit < rows.len() {
-            rows.truncate(limit);
+            ids.last()
+                .and_then(|ident| col_map.get(&ident.value.to_ascii_lowercase()).copied())
+                .filter(|idx| *idx != usize::MAX)
         }
+        _ => None,
     }
-
-    Ok(ExecOutput::ResultSet { columns, rows })
 }
 
 fn parse_sql_number_literal(n: &str) -> Result<Cell, MiniError> {
@@ -3941,15 +6071,49 @@ fn eval_row_expr(
                 "version" => Ok(Cell::Text(SERVER_VERSION.to_string())),
                 "connection_id" => Ok(Cell::Int(i64::from(session.conn_id))),
                 "user" | "current_user" => Ok(Cell::Text(session.username.clone())),
-                _ => Err(MiniError::NotSupported(format!(
-                    "Function not supported in expressions: {}",
-                    f.name
-                ))),
+                "now" | "current_timestamp" | "localtime" | "localtimestamp" | "sysdate" => {
+                    Ok(Cell::DateTime(session.statement_time))
+                }
+                "curdate" | "current_date" => {
+                    Ok(Cell::Date(session.statement_time.div_euclid(86_400_000)))
+                }
+                "uuid" => Ok(Cell::Text(new_uuid())),
+                _ => eval_function(session, &name, f, row, col_map),
             }
         }
+        ast::Expr::UnaryOp {
+            op: op @ (ast::UnaryOperator::Minus | ast::UnaryOperator::Plus),
+            expr: inner,
+        } => {
+            let value = eval_row_expr(session, inner, row, col_map)?;
+            match op {
+                ast::UnaryOperator::Minus => {
+                    eval_arithmetic(&ast::BinaryOperator::Minus, Cell::Int(0), value)
+                }
+                _ => Ok(value),
+            }
+        }
+        ast::Expr::BinaryOp { left, op, right }
+            if matches!(
+                op,
+                ast::BinaryOperator::Plus
+                    | ast::BinaryOperator::Minus
+                    | ast::BinaryOperator::Multiply
+                    | ast::BinaryOperator::Divide
+                    | ast::BinaryOperator::MyIntegerDivide
+                    | ast::BinaryOperator::Modulo
+            ) =>
+        {
+            let l = eval_row_expr(session, left, row, col_map)?;
+            let r = eval_row_expr(session, right, row, col_map)?;
+            eval_arithmetic(op, l, r)
+        }
         ast::Expr::Value(v) => match &v.value {
             ast::Value::Number(n, _) => parse_sql_number_literal(n),
-            ast::Value::SingleQuotedString(s) => Ok(Cell::Text(s.clone())),
+            ast::Value::SingleQuotedString(s) | ast::Value::DoubleQuotedString(s) => {
+                Ok(Cell::Text(s.clone()))
+            }
+            ast::Value::Boolean(b) => Ok(Cell::Int(i64::from(*b))),
             ast::Value::Null => Ok(Cell::Null),
             _ => Err(MiniError::NotSupported(format!(
                 "Value type not supported: {}",
@@ -4037,6 +6201,331 @@ fn eval_row_expr(
     }
 }
 
+/// Names the value expressions of an INSERT can use for the row being inserted: its columns,
+/// bare or qualified by the table or by the row alias of `VALUES (...) AS new(...)`, and
+/// `VALUES(col)`.
+fn insert_row_col_map(
+    def: &TableDef,
+    targets: &[usize],
+    alias: Option<&ast::InsertAliases>,
+) -> Result<HashMap<String, usize>, MiniError> {
+    let mut map = build_col_map(&[def]);
+    for (idx, col) in def.columns.iter().enumerate() {
+        map.insert(format!("values({})", col.name.to_ascii_lowercase()), idx);
+    }
+    let Some(alias) = alias else {
+        return Ok(map);
+    };
+    let row_alias = alias.row_alias.to_string().to_ascii_lowercase();
+    match alias.col_aliases.as_deref() {
+        None | Some([]) => {
+            for col in &def.columns {
+                let idx = map[&col.name.to_ascii_lowercase()];
+                map.insert(
+                    format!("{row_alias}.{}", col.name.to_ascii_lowercase()),
+                    idx,
+                );
+            }
+        }
+        Some(names) if names.len() == targets.len() => {
+            for (name, &idx) in names.iter().zip(targets) {
+                let name = name.value.to_ascii_lowercase();
+                map.insert(format!("{row_alias}.{name}"), idx);
+                map.entry(name).or_insert(idx);
+            }
+        }
+        Some(_) => {
+            return Err(MiniError::Invalid(
+                "column alias count does not match the inserted columns".into(),
+            ))
+        }
+    }
+    Ok(map)
+}
+
+/// A function call with arguments; the argument-less ones are handled by `eval_row_expr`.
+fn eval_function(
+    session: &SessionState,
+    name: &str,
+    f: &ast::Function,
+    row: &Row,
+    col_map: &HashMap<String, usize>,
+) -> Result<Cell, MiniError> {
+    let unsupported =
+        || MiniError::NotSupported(format!("Function not supported in expressions: {}", f.name));
+    let arg_exprs: Vec<&ast::Expr> = match &f.args {
+        ast::FunctionArguments::List(list) => list
+            .args
+            .iter()
+            .map(|arg| match arg {
+                ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => Ok(e),
+                _ => Err(unsupported()),
+            })
+            .collect::<Result<_, _>>()?,
+        ast::FunctionArguments::None => Vec::new(),
+        ast::FunctionArguments::Subquery(_) => return Err(unsupported()),
+    };
+    let arity = |min: usize, max: usize| {
+        if arg_exprs.len() < min || arg_exprs.len() > max {
+            Err(MiniError::Invalid(format!(
+                "Incorrect parameter count in the call to native function '{}'",
+                f.name
+            )))
+        } else {
+            Ok(())
+        }
+    };
+
+    // Functions that do not evaluate every argument.
+    match name {
+        "if" => {
+            arity(3, 3)?;
+            let branch = if eval_condition(session, arg_exprs[0], row, col_map)? {
+                arg_exprs[1]
+            } else {
+                arg_exprs[2]
+            };
+            return eval_row_expr(session, branch, row, col_map);
+        }
+        "coalesce" | "ifnull" => {
+            if name == "ifnull" {
+                arity(2, 2)?;
+            } else {
+                arity(1, usize::MAX)?;
+            }
+            for arg in &arg_exprs {
+                let value = eval_row_expr(session, arg, row, col_map)?;
+                if !matches!(value, Cell::Null) {
+                    return Ok(value);
+                }
+            }
+            return Ok(Cell::Null);
+        }
+        // The value the statement inserts into a column, by the names `insert_row_col_map`
+        // registers.
+        "values" => {
+            arity(1, 1)?;
+            let column = match arg_exprs[0] {
+                ast::Expr::Identifier(id) => &id.value,
+                ast::Expr::CompoundIdentifier(ids) => &ids.last().ok_or_else(unsupported)?.value,
+                _ => return Err(unsupported()),
+            };
+            let key = format!("values({})", column.to_ascii_lowercase());
+            return Ok(col_map
+                .get(&key)
+                .and_then(|&idx| row.values.get(idx).cloned())
+                .unwrap_or(Cell::Null));
+        }
+        _ => {}
+    }
+
+    let args = arg_exprs
+        .iter()
+        .map(|e| eval_row_expr(session, e, row, col_map))
+        .collect::<Result<Vec<_>, _>>()?;
+    let any_null = args.iter().any(|a| matches!(a, Cell::Null));
+    match name {
+        "concat" => {
+            arity(1, usize::MAX)?;
+            if any_null {
+                return Ok(Cell::Null);
+            }
+            Ok(Cell::Text(args.iter().map(cell_to_string).collect()))
+        }
+        "concat_ws" => {
+            arity(2, usize::MAX)?;
+            if matches!(args[0], Cell::Null) {
+                return Ok(Cell::Null);
+            }
+            let parts: Vec<String> = args[1..]
+                .iter()
+                .filter(|a| !matches!(a, Cell::Null))
+                .map(cell_to_string)
+                .collect();
+            Ok(Cell::Text(parts.join(&cell_to_string(&args[0]))))
+        }
+        "upper" | "ucase" | "lower" | "lcase" | "length" | "octet_length" | "char_length"
+        | "character_length" => {
+            arity(1, 1)?;
+            if any_null {
+                return Ok(Cell::Null);
+            }
+            let text = cell_to_string(&args[0]);
+            Ok(match name {
+                "upper" | "ucase" => Cell::Text(text.to_uppercase()),
+                "lower" | "lcase" => Cell::Text(text.to_lowercase()),
+                "length" | "octet_length" => Cell::Int(text.len() as i64),
+                _ => Cell::Int(text.chars().count() as i64),
+            })
+        }
+        "nullif" => {
+            arity(2, 2)?;
+            let equal = !any_null
+                && compare_cell_for_order(&args[0], &args[1]) == std::cmp::Ordering::Equal;
+            Ok(if equal { Cell::Null } else { args[0].clone() })
+        }
+        "abs" => {
+            arity(1, 1)?;
+            match to_number(&args[0])? {
+                Cell::Int(i) => i
+                    .checked_abs()
+                    .map(Cell::Int)
+                    .ok_or_else(|| out_of_range(&args[0])),
+                Cell::Float(x) => Ok(Cell::Float(x.abs())),
+                other => Ok(other),
+            }
+        }
+        "mod" => {
+            arity(2, 2)?;
+            eval_arithmetic(
+                &ast::BinaryOperator::Modulo,
+                args[0].clone(),
+                args[1].clone(),
+            )
+        }
+        "round" => {
+            arity(1, 2)?;
+            if any_null {
+                return Ok(Cell::Null);
+            }
+            let places = match args.get(1).map(to_number).transpose()? {
+                Some(Cell::Int(d)) => d,
+                Some(Cell::Float(d)) => d as i64,
+                _ => 0,
+            };
+            match to_number(&args[0])? {
+                Cell::Int(i) if places >= 0 => Ok(Cell::Int(i)),
+                Cell::Int(i) => {
+                    let scale = 10f64.powi(places.clamp(-18, 0).unsigned_abs() as i32);
+                    Ok(Cell::Int(((i as f64 / scale).round() * scale) as i64))
+                }
+                Cell::Float(x) => {
+                    let scale = 10f64.powi(places.clamp(-30, 30) as i32);
+                    let rounded = (x * scale).round() / scale;
+                    Ok(if places <= 0 {
+                        Cell::Int(rounded as i64)
+                    } else {
+                        Cell::Float(rounded)
+                    })
+                }
+                other => Ok(other),
+            }
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+/// A value as an operand of arithmetic: numbers as they are, text read as a number (0 when it
+/// is not one, as MySQL does with a warning). Dates are not numbers here.
+fn to_number(cell: &Cell) -> Result<Cell, MiniError> {
+    match cell {
+        Cell::Null | Cell::Int(_) | Cell::Float(_) => Ok(cell.clone()),
+        Cell::Text(s) => {
+            let s = s.trim();
+            Ok(match s.parse::<i64>() {
+                Ok(i) => Cell::Int(i),
+                Err(_) => Cell::Float(s.parse::<f64>().unwrap_or(0.0)),
+            })
+        }
+        Cell::Date(_) | Cell::DateTime(_) => Err(MiniError::NotSupported(
+            "Arithmetic on date values is not supported".into(),
+        )),
+    }
+}
+
+fn out_of_range(value: &Cell) -> MiniError {
+    MiniError::Invalid(format!(
+        "BIGINT value is out of range in '{}'",
+        cell_to_string(value)
+    ))
+}
+
+/// Apply an arithmetic operator the way MySQL does: NULL in, NULL out; integer operands stay
+/// integers (overflow is an error) except for `/`; dividing by zero is NULL.
+fn eval_arithmetic(op: &ast::BinaryOperator, l: Cell, r: Cell) -> Result<Cell, MiniError> {
+    use ast::BinaryOperator as Op;
+    let (l, r) = (to_number(&l)?, to_number(&r)?);
+    match (&l, &r) {
+        (Cell::Null, _) | (_, Cell::Null) => Ok(Cell::Null),
+        (Cell::Int(a), Cell::Int(b)) => {
+            let (a, b) = (*a, *b);
+            let result = match op {
+                Op::Plus => a.checked_add(b),
+                Op::Minus => a.checked_sub(b),
+                Op::Multiply => a.checked_mul(b),
+                Op::Divide if b == 0 => return Ok(Cell::Null),
+                Op::Divide => return Ok(Cell::Float(a as f64 / b as f64)),
+                Op::MyIntegerDivide | Op::Modulo if b == 0 => return Ok(Cell::Null),
+                Op::MyIntegerDivide => a.checked_div(b),
+                Op::Modulo => a.checked_rem(b),
+                _ => {
+                    return Err(MiniError::NotSupported(format!(
+                        "Operator not supported: {op}"
+                    )))
+                }
+            };
+            result.map(Cell::Int).ok_or_else(|| {
+                MiniError::Invalid(format!("BIGINT value is out of range in '({a} {op} {b})'"))
+            })
+        }
+        _ => {
+            let (a, b) = (l.as_f64().unwrap_or(0.0), r.as_f64().unwrap_or(0.0));
+            match op {
+                Op::Plus => Ok(Cell::Float(a + b)),
+                Op::Minus => Ok(Cell::Float(a - b)),
+                Op::Multiply => Ok(Cell::Float(a * b)),
+                Op::Divide | Op::MyIntegerDivide | Op::Modulo if b == 0.0 => Ok(Cell::Null),
+                Op::Divide => Ok(Cell::Float(a / b)),
+                Op::MyIntegerDivide => Ok(Cell::Int((a / b).trunc() as i64)),
+                Op::Modulo => Ok(Cell::Float(a % b)),
+                _ => Err(MiniError::NotSupported(format!(
+                    "Operator not supported: {op}"
+                ))),
+            }
+        }
+    }
+}
+
+/// Evaluate a query's `LIMIT`/`OFFSET` clause into `(offset, limit)`.
+fn query_offset_limit(query: &ast::Query) -> Result<(usize, Option<usize>), MiniError> {
+    let eval_nonneg_usize = |expr: &ast::Expr, what: &str| -> Result<usize, MiniError> {
+        let v = eval_expr(expr)?
+            .as_i64()
+            .ok_or_else(|| MiniError::Invalid(format!("{what} must be an integer")))?;
+        if v < 0 {
+            return Err(MiniError::Invalid(format!("{what} cannot be negative")));
+        }
+        usize::try_from(v).map_err(|_| MiniError::Invalid(format!("{what} is too large")))
+    };
+
+    let mut offset = 0usize;
+    let mut limit = None::<usize>;
+    if let Some(limit_clause) = &query.limit_clause {
+        match limit_clause {
+            ast::LimitClause::LimitOffset {
+                limit: lim,
+                offset: off,
+                ..
+            } => {
+                if let Some(lim_expr) = lim {
+                    limit = Some(eval_nonneg_usize(lim_expr, "LIMIT")?);
+                }
+                if let Some(off) = off {
+                    offset = eval_nonneg_usize(&off.value, "OFFSET")?;
+                }
+            }
+            ast::LimitClause::OffsetCommaLimit {
+                offset: off,
+                limit: lim,
+            } => {
+                offset = eval_nonneg_usize(off, "OFFSET")?;
+                limit = Some(eval_nonneg_usize(lim, "LIMIT")?);
+            }
+        }
+    }
+    Ok((offset, limit))
+}
+
 fn eval_condition(
     session: &SessionState,
     expr: &ast::Expr,
@@ -4290,7 +6779,38 @@ fn eval_condition(
 }
 
 fn coerce_cell(cell: Cell, target: &SqlType) -> Result<Cell, MiniError> {
+    // Doubles become integers by rounding half away from zero, as in MySQL.
+    let float_to_int = |f: f64| {
+        let rounded = f.round();
+        if rounded >= -(2f64.powi(63)) && rounded < 2f64.powi(63) {
+            Ok(Cell::Int(rounded as i64))
+        } else {
+            Err(MiniError::Invalid(format!(
+                "Out of range value for integer column: {f}"
+            )))
+        }
+    };
     match (target, &cell) {
+        (SqlType::Int, Cell::Float(f)) => float_to_int(*f),
+        (SqlType::Int, Cell::Text(s)) => {
+            let t = s.trim();
+            if let Ok(i) = t.parse::<i64>() {
+                Ok(Cell::Int(i))
+            } else if let Ok(f) = t.parse::<f64>() {
+                float_to_int(f)
+            } else {
+                Err(MiniError::Invalid(format!(
+                    "Incorrect integer value: '{s}'"
+                )))
+            }
+        }
+        (SqlType::Text, Cell::Int(_) | Cell::Float(_) | Cell::Date(_) | Cell::DateTime(_)) => {
+            Ok(Cell::Text(cell_to_string(&cell)))
+        }
+        (SqlType::Date, Cell::DateTime(millis)) => Ok(Cell::Date(millis.div_euclid(86_400_000))),
+        (SqlType::DateTime, Cell::Date(days)) => {
+            Ok(Cell::DateTime(days.saturating_mul(86_400_000)))
+        }
         (SqlType::Float, Cell::Int(i)) => Ok(Cell::Float(*i as f64)),
         (SqlType::Float, Cell::Text(s)) => {
             let f = s
@@ -4321,6 +6841,133 @@ fn coerce_cell(cell: Cell, target: &SqlType) -> Result<Cell, MiniError> {
     }
 }
 
+/// Convert a value compared with a key column for a primary key or index lookup. Only the
+/// conversions that keep equality intact apply: text compared with a number column (or a
+/// number with a text column) is left as is, and so finds no key.
+fn key_cell(cell: Cell, ty: &SqlType) -> Result<Cell, MiniError> {
+    match (ty, &cell) {
+        (SqlType::Int | SqlType::Text, _)
+        | (SqlType::Date, Cell::DateTime(_))
+        | (SqlType::DateTime, Cell::Date(_)) => Ok(cell),
+        _ => coerce_cell(cell, ty),
+    }
+}
+
+/// A random (version 4) UUID in MySQL's text form.
+fn new_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Whether `expr` is `CURRENT_TIMESTAMP` or a synonym, the only value `ON UPDATE` takes.
+fn is_current_timestamp(expr: &ast::Expr) -> bool {
+    let ast::Expr::Function(f) = expr else {
+        return false;
+    };
+    let no_args = match &f.args {
+        ast::FunctionArguments::None => true,
+        ast::FunctionArguments::List(l) => l.args.is_empty(),
+        ast::FunctionArguments::Subquery(_) => false,
+    };
+    let name = f.name.to_string().to_ascii_lowercase();
+    no_args
+        && matches!(
+            name.as_str(),
+            "current_timestamp" | "now" | "localtime" | "localtimestamp"
+        )
+}
+
+/// The `DEFAULT` keyword in place of a value in `INSERT ... VALUES` or `UPDATE ... SET`.
+fn is_default_keyword(expr: &ast::Expr) -> bool {
+    matches!(expr, ast::Expr::Identifier(id)
+        if id.quote_style.is_none() && id.value.eq_ignore_ascii_case("default"))
+}
+
+/// Parse a column's `DEFAULT` clause. Literals are coerced to the column's type once here;
+/// expressions are checked by evaluating them, so a bad one fails the DDL rather than inserts.
+fn parse_column_default(
+    session: &SessionState,
+    name: &str,
+    ty: &SqlType,
+    nullable: bool,
+    expr: &ast::Expr,
+) -> Result<Option<ColumnDefault>, MiniError> {
+    let invalid = || MiniError::Invalid(format!("Invalid default value for '{name}'"));
+    if is_current_timestamp(expr) {
+        return match ty {
+            SqlType::DateTime => Ok(Some(ColumnDefault::CurrentTimestamp)),
+            _ => Err(invalid()),
+        };
+    }
+    if let ast::Expr::Nested(inner) = expr {
+        let default = ColumnDefault::Expr(inner.to_string());
+        eval_column_default(session, Some(&default), ty)?;
+        return Ok(Some(default));
+    }
+    let value = match expr {
+        ast::Expr::UnaryOp {
+            op: ast::UnaryOperator::Minus,
+            expr: inner,
+        } => match eval_expr(inner)? {
+            Cell::Int(i) => Cell::Int(-i),
+            Cell::Float(f) => Cell::Float(-f),
+            _ => return Err(invalid()),
+        },
+        other => eval_expr(other)?,
+    };
+    match coerce_cell(value, ty).map_err(|_| invalid())? {
+        Cell::Null if !nullable => Err(invalid()),
+        Cell::Null => Ok(None),
+        value => Ok(Some(ColumnDefault::Literal(value))),
+    }
+}
+
+/// Evaluate a column default for one row.
+fn eval_column_default(
+    session: &SessionState,
+    default: Option<&ColumnDefault>,
+    ty: &SqlType,
+) -> Result<Cell, MiniError> {
+    match default {
+        None => Ok(Cell::Null),
+        Some(ColumnDefault::Literal(value)) => Ok(value.clone()),
+        Some(ColumnDefault::CurrentTimestamp) => Ok(Cell::DateTime(session.statement_time)),
+        Some(ColumnDefault::Expr(sql)) => {
+            let expr = Parser::new(&MySqlDialect {})
+                .try_with_sql(sql)
+                .and_then(|mut p| p.parse_expr())
+                .map_err(|e| MiniError::Parse(e.to_string()))?;
+            let value =
+                eval_row_expr(session, &expr, &Row { values: Vec::new() }, &HashMap::new())?;
+            coerce_cell(value, ty)
+        }
+    }
+}
+
+/// The value a row gets for a column it leaves out or sets to `DEFAULT`. A NOT NULL column
+/// without a default has none, except an auto-increment key that is generated later.
+fn column_default_value(
+    session: &SessionState,
+    def: &TableDef,
+    col: &ColumnDef,
+) -> Result<Cell, MiniError> {
+    let generated = def.auto_increment && col.name.eq_ignore_ascii_case(&def.primary_key);
+    if col.default.is_none() && !col.nullable && !generated {
+        return Err(MiniError::NoDefault(col.name.clone()));
+    }
+    eval_column_default(session, col.default.as_ref(), &col.ty)
+}
+
 fn eval_expr(expr: &ast::Expr) -> Result<Cell, MiniError> {
     match expr {
         ast::Expr::Value(v) => match &v.value {
@@ -4365,6 +7012,19 @@ fn parse_eq_predicate(expr: &ast::Expr) -> Result<(String, Cell), MiniError> {
     }
 }
 
+/// The schema `db`.`table` is stored in: a temporary table of the session shadows the base
+/// table of the same name.
+fn table_schema(session: &SessionState, db: String, table: &str) -> String {
+    if session
+        .temp_tables
+        .iter()
+        .any(|(d, t)| *d == db && t == table)
+    {
+        return Store::temp_schema(session.conn_id, &db);
+    }
+    db
+}
+
 fn object_name_to_parts(name: &ObjectName) -> Result<(Option<String>, String), MiniError> {
     match name.0.len() {
         1 => Ok((None, get_ident_name(&name.0[0]))),
@@ -4697,33 +7357,116 @@ fn compare_cell_for_order(a: &Cell, b: &Cell) -> std::cmp::Ordering {
 }
 
 fn cell_to_string(c: &Cell) -> String {
-    match c {
-        Cell::Int(i) => i.to_string(),
-        Cell::Float(f) => f.to_string(),
-        Cell::Text(s) => s.clone(),
-        Cell::Date(days) => {
-            use chrono::TimeZone;
-            let secs = days.saturating_mul(86_400);
-            match chrono::Utc.timestamp_opt(secs, 0).single() {
-                Some(dt) => dt.format("%Y-%m-%d").to_string(),
-                None => secs.to_string(),
-            }
-        }
-        Cell::DateTime(millis) => {
-            use chrono::TimeZone;
-            match chrono::Utc.timestamp_millis_opt(*millis).single() {
-                Some(dt) => dt.format("%Y-%m-%d %H:%M:%S").to_string(),
-                None => millis.to_string(),
-            }
-        }
-        Cell::Null => "NULL".into(),
-    }
+    c.to_string()
 }
 
 fn should_buffer_writes(session: &SessionState) -> bool {
     session.txn.in_txn || !session.autocommit
 }
 
+/// Stage a statement's row changes in the transaction's write set. Autocommit statements are
+/// committed (with conflict validation) by `execute` right after the handler returns; otherwise
+/// the changes and their row locks are held until COMMIT/ROLLBACK.
+fn stage_row_changes(
+    session: &mut SessionState,
+    locks: &mut RowLockGuard<'_>,
+    db: &str,
+    table: &str,
+    changes: impl IntoIterator<Item = (i64, Option<Row>)>,
+) {
+    if should_buffer_writes(session) {
+        session.txn.in_txn = true;
+    }
+    for (pk, row) in changes {
+        session.txn.pending_rows.insert(
+            RowKey {
+                db: db.to_string(),
+                table: table.to_string(),
+                pk,
+            },
+            row,
+        );
+    }
+    locks.keep_locks();
+}
+
+/// Lock a row an UPDATE or DELETE changes and read it from then on like a locking read: the
+/// latest committed version, which commit validation trusts while the lock is held. A write
+/// that waited for the lock so works from the change it waited for, as in InnoDB, instead of
+/// from its snapshot.
+fn lock_row_for_write(
+    session: &mut SessionState,
+    locks: &mut RowLockGuard<'_>,
+    db: &str,
+    table: &str,
+    pk: i64,
+) -> Result<(), MiniError> {
+    locks.lock_row(db, table, pk)?;
+    let key = RowKey {
+        db: db.to_string(),
+        table: table.to_string(),
+        pk,
+    };
+    if session.txn.locked_reads.insert(key.clone()) {
+        session.txn.statement_reads.push(key);
+    }
+    Ok(())
+}
+
+/// Take insert-intention locks on the gaps a row's new keys go into, so the write waits for
+/// range-locking reads that cover them. For an update (`old_row`), only changed index values
+/// count. Gaps are named by committed records, as the readers that lock them see them; a record
+/// committed while we wait splits the gap, so lock again until the gap stays the same.
+fn lock_insert_intentions(
+    locks: &mut RowLockGuard<'_>,
+    def: &TableDef,
+    pk: i64,
+    row: &Row,
+    old_row: Option<&Row>,
+) -> Result<(), MiniError> {
+    let store = locks.store;
+    let wait = LockWait::Block(locks.wait_timeout);
+    let mut lock_gap = |find_gap: &dyn Fn(&ReadView) -> Result<Gap, MiniError>| {
+        let mut locked = None;
+        loop {
+            let gap = find_gap(&store.txn_manager.current_view(None))?;
+            if locked.as_ref() == Some(&gap) {
+                return Ok::<_, MiniError>(());
+            }
+            locks.lock_gap(
+                &def.db,
+                &def.name,
+                gap.clone(),
+                LockMode::InsertIntention,
+                wait,
+            )?;
+            locked = Some(gap);
+        }
+    };
+    if old_row.is_none() {
+        lock_gap(&|view| {
+            Ok(Gap::Primary(
+                store.next_row_after(&def.db, &def.name, pk, view)?,
+            ))
+        })?;
+    }
+    for index in &def.indexes {
+        let Some(col_idx) = def.columns.iter().position(|c| c.name == index.columns[0]) else {
+            continue;
+        };
+        let val = &row.values[col_idx];
+        if old_row.is_some_and(|old| old.values.get(col_idx) == Some(val)) {
+            continue;
+        }
+        lock_gap(&|view| {
+            let next =
+                store.next_index_entry_after(&def.db, &def.name, &index.name, val, pk, view)?;
+            Ok(Gap::Index(index.name.clone(), next))
+        })?;
+    }
+    Ok(())
+}
+
 fn txn_get_row(
     store: &Store,
     session: &SessionState,
@@ -4732,7 +7475,7 @@ fn txn_get_row(
     pk: i64,
 ) -> Result<Option<Row>, MiniError> {
     // Check local writes first (Read My Own Writes)
-    if !session.txn.pending_rows.is_empty() {
+    if !session.txn.pending_rows.is_empty() || !session.txn.locked_reads.is_empty() {
         let key = RowKey {
             db: db.to_string(),
             table: table.to_string(),
@@ -4741,6 +7484,10 @@ fn txn_get_row(
         if let Some(v) = session.txn.pending_rows.get(&key) {
             return Ok(v.clone());
         }
+        if session.txn.locked_reads.contains(&key) {
+            let current = store.txn_manager.current_view(session.txn.tx_id);
+            return store.get_row_mvcc(db, table, pk, &current);
+        }
     }
     // Fallback to store
     let view = session
@@ -4762,15 +7509,66 @@ fn txn_scan_rows(
         .read_view
         .as_ref()
         .ok_or_else(|| MiniError::Invalid("No active transaction view".into()))?;
-    let base = store.scan_rows_mvcc(db, table, view)?;
+    scan_rows_overlaid(store, session, db, table, view, true, i64::MIN..=i64::MAX)
+}
+
+/// Current read of the rows of a table with primary keys in `pks`, for locking reads: the
+/// latest committed rows plus our own writes.
+fn txn_scan_rows_current(
+    store: &Store,
+    session: &SessionState,
+    db: &str,
+    table: &str,
+    pks: RangeInclusive<i64>,
+) -> Result<Vec<(i64, Row)>, MiniError> {
+    let current = store.txn_manager.current_view(session.txn.tx_id);
+    scan_rows_overlaid(store, session, db, table, &current, false, pks)
+}
+
+fn scan_rows_overlaid(
+    store: &Store,
+    session: &SessionState,
+    db: &str,
+    table: &str,
+    view: &ReadView,
+    overlay_locked_reads: bool,
+    pks: RangeInclusive<i64>,
+) -> Result<Vec<(i64, Row)>, MiniError> {
+    let base = store
+        .scan_rows_range_iter(db, table, view.clone(), *pks.start(), *pks.end())
+        .collect::<Result<Vec<_>, _>>()?;
+    let locked: Vec<i64> = if overlay_locked_reads {
+        session
+            .txn
+            .locked_reads
+            .iter()
+            .filter(|k| k.db == db && k.table == table && pks.contains(&k.pk))
+            .map(|k| k.pk)
+            .collect()
+    } else {
+        Vec::new()
+    };
 
-    if session.txn.pending_rows.is_empty() {
+    if session.txn.pending_rows.is_empty() && locked.is_empty() {
         return Ok(base);
     }
 
     let mut merged: BTreeMap<i64, Row> = base.into_iter().collect();
+    if !locked.is_empty() {
+        let current = store.txn_manager.current_view(session.txn.tx_id);
+        for pk in locked {
+            match store.get_row_mvcc(db, table, pk, &current)? {
+                Some(row) => {
+                    merged.insert(pk, row);
+                }
+                None => {
+                    merged.remove(&pk);
+                }
+            }
+        }
+    }
     for (k, v) in &session.txn.pending_rows {
-        if k.db == db && k.table == table {
+        if k.db == db && k.table == table && pks.contains(&k.pk) {
             match v {
                 Some(row) => {
                     merged.insert(k.pk, row.clone());
@@ -4784,36 +7582,170 @@ fn txn_scan_rows(
     Ok(merged.into_iter().collect())
 }
 
+/// Resolve `column = val` through a secondary index on that column, overlaying the session's
+/// pending writes. Returns `None` when no index covers the column so callers fall back to a scan.
+fn txn_index_lookup(
+    store: &Store,
+    session: &SessionState,
+    def: &TableDef,
+    col_idx: usize,
+    val: &Cell,
+) -> Result<Option<Vec<i64>>, MiniError> {
+    let col_name = &def.columns[col_idx].name;
+    let Some(index) = def.indexes.iter().find(|i| {
+        i.columns
+            .first()
+            .is_some_and(|c| c.eq_ignore_ascii_case(col_name))
+    }) else {
+        return Ok(None);
+    };
+    let Ok(val) = key_cell(val.clone(), &def.columns[col_idx].ty) else {
+        return Ok(None);
+    };
+    if matches!(val, Cell::Null) {
+        // `col = NULL` never matches.
+        return Ok(Some(Vec::new()));
+    }
+    let view = session
+        .txn
+        .read_view
+        .as_ref()
+        .ok_or_else(|| MiniError::Invalid("No active transaction view".into()))?;
+    let mut pks: std::collections::BTreeSet<i64> = store
+        .index_lookup_mvcc(&def.db, &def.name, &index.name, &val, view)?
+        .into_iter()
+        .map(|(pk, _)| pk)
+        .collect();
+    let current = store.txn_manager.current_view(session.txn.tx_id);
+    for k in &session.txn.locked_reads {
+        if k.db != def.db || k.table != def.name || session.txn.pending_rows.contains_key(k) {
+            continue;
+        }
+        match store.get_row_mvcc(&k.db, &k.table, k.pk, &current)? {
+            Some(row) if row.values.get(col_idx) == Some(&val) => {
+                pks.insert(k.pk);
+            }
+            _ => {
+                pks.remove(&k.pk);
+            }
+        }
+    }
+    for (k, v) in &session.txn.pending_rows {
+        if k.db != def.db || k.table != def.name {
+            continue;
+        }
+        match v {
+            Some(row) if row.values.get(col_idx) == Some(&val) => {
+                pks.insert(k.pk);
+            }
+            _ => {
+                pks.remove(&k.pk);
+            }
+        }
+    }
+    Ok(Some(pks.into_iter().collect()))
+}
+
 fn ensure_txn_active(store: &Store, session: &mut SessionState) {
     if session.txn.tx_id.is_none() {
         let (tx, view) = store.txn_manager.start_txn();
         session.txn.tx_id = Some(tx);
         session.txn.read_view = Some(view);
+        // One-shot characteristics from `SET TRANSACTION` apply to this transaction only.
+        let isolation = session
+            .next_txn_isolation
+            .take()
+            .unwrap_or_else(|| session.transaction_isolation.clone());
+        session.txn.isolation = IsolationLevel::from_name(&isolation).unwrap_or_default();
+        session.next_txn_read_only = None;
     }
 }
 
-fn txn_commit(store: &Store, session: &mut SessionState) -> Result<(), MiniError> {
+/// READ COMMITTED and READ UNCOMMITTED see other transactions' commits from one statement to
+/// the next, so each statement gets a fresh read view. Uncommitted writes stay in their
+/// session, so READ UNCOMMITTED reads the same rows as READ COMMITTED.
+fn refresh_statement_view(store: &Store, session: &mut SessionState) {
+    let read_uncommitted = match session.txn.isolation {
+        IsolationLevel::ReadCommitted => false,
+        IsolationLevel::ReadUncommitted => true,
+        IsolationLevel::RepeatableRead | IsolationLevel::Serializable => return,
+    };
     if let Some(tx_id) = session.txn.tx_id {
+        session.txn.read_view = Some(store.txn_manager.refresh_view(tx_id, read_uncommitted));
+    }
+}
+
+fn txn_commit(store: &Store, session: &mut SessionState) -> Result<(), MiniError> {
+    if let Some(mut tx_id) = session.txn.tx_id {
         if !session.txn.pending_rows.is_empty() {
-            // Convert BTreeMap iterator to what apply_row_changes_mvcc expects
+            let view = session
+                .txn
+                .read_view
+                .as_ref()
+                .ok_or_else(|| MiniError::Invalid("No active transaction view".into()))?;
+            // Convert BTreeMap iterator to what commit_row_changes expects
             let changes = session
                 .txn
                 .pending_rows
                 .iter()
                 .map(|(k, v)| (k.db.as_str(), k.table.as_str(), k.pk, v.as_ref()));
-            store.apply_row_changes_mvcc(changes, tx_id)?;
+            let locked_reads = &session.txn.locked_reads;
+            let locked_read = |db: &str, table: &str, pk: i64| {
+                locked_reads.contains(&RowKey {
+                    db: db.to_string(),
+                    table: table.to_string(),
+                    pk,
+                })
+            };
+            // Writes cannot commit while another session holds the global read lock.
+            let global = if session.global_read_lock {
+                LockGrant::AlreadyHeld
+            } else {
+                let timeout = Duration::from_secs(session.lock_wait_timeout);
+                store.lock_global(session.conn_id, TableLockMode::Write, timeout)?
+            };
+            let committed = store.commit_row_changes(changes, tx_id, view, locked_read);
+            if global == LockGrant::Acquired {
+                store.unlock_global(session.conn_id);
+            }
+            match committed {
+                // The TxID changes when the commit had to be ordered after a locked read.
+                Ok(committed_as) => tx_id = committed_as,
+                Err(e) => {
+                    // A failed commit (e.g. a write conflict) rolls the whole transaction back.
+                    txn_rollback(store, session);
+                    session.txn.in_txn = false;
+                    return Err(e);
+                }
+            }
         }
         store.txn_manager.commit_txn(tx_id);
+        store.request_purge();
     }
 
     session.txn.tx_id = None;
     session.txn.read_view = None;
     session.txn.pending_rows.clear();
     session.txn.savepoints.clear();
+    session.txn.locked_reads.clear();
     store.unlock_all(session.conn_id);
+    release_metadata_locks(store, session);
     Ok(())
 }
 
+/// Release everything a connection holds when it goes away: an open transaction is rolled back
+/// and its row, table and global locks are dropped.
+pub fn close_session(store: &Store, session: &mut SessionState) {
+    txn_rollback(store, session);
+    session.txn.in_txn = false;
+    session.locked_tables.clear();
+    session.global_read_lock = false;
+    store.unlock_all_tables(session.conn_id);
+    for (db, table) in std::mem::take(&mut session.temp_tables) {
+        let _ = store.drop_table(&Store::temp_schema(session.conn_id, &db), &table);
+    }
+}
+
 fn txn_rollback(store: &Store, session: &mut SessionState) {
     if let Some(tx_id) = session.txn.tx_id {
         store.txn_manager.rollback_txn(tx_id);
@@ -4822,7 +7754,9 @@ fn txn_rollback(store: &Store, session: &mut SessionState) {
     session.txn.read_view = None;
     session.txn.pending_rows.clear();
     session.txn.savepoints.clear();
+    session.txn.locked_reads.clear();
     store.unlock_all(session.conn_id);
+    release_metadata_locks(store, session);
 }
 
 fn get_ident_name(part: &ObjectNamePart) -> String {
@@ -4895,6 +7829,7 @@ fn handle_create_index(
     let db = db_opt
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table);
 
     // Index Name
     let idx_name = if let Some(n) = &create_index.name {
@@ -4912,9 +7847,9 @@ fn handle_create_index(
         }
     };
 
-    if create_index.unique {
+    if create_index.unique && create_index.columns.len() > 1 {
         return Err(MiniError::NotSupported(
-            "UNIQUE index not supported in MVP".into(),
+            "UNIQUE indexes over several columns are not supported".into(),
         ));
     }
 
@@ -4933,6 +7868,7 @@ fn handle_create_index(
     let index_def = IndexDef {
         name: idx_name,
         columns: col_names,
+        unique: create_index.unique,
     };
 
     match store.create_index(&db, &table, index_def) {
@@ -4948,7 +7884,7 @@ fn handle_create_index(
     Ok(ExecOutput::Ok {
         affected_rows: 0,
         last_insert_id: 0,
-        info: "Index created".into(),
+        info: "".into(),
     })
 }
 
@@ -4956,14 +7892,15 @@ fn handle_create_table(
     store: &Store,
     session: &mut SessionState,
     user: &UserRecord,
-    name: &ObjectName,
-    columns: &[ast::ColumnDef],
-    constraints: &[ast::TableConstraint],
-    if_not_exists: bool,
+    create: &ast::CreateTable,
 ) -> Result<ExecOutput, MiniError> {
     require_priv(user, session.current_db.as_deref(), Priv::CREATE)?;
-    txn_commit(store, session)?;
+    // Like MySQL, creating a temporary table does not end the transaction.
+    if !create.temporary {
+        txn_commit(store, session)?;
+    }
 
+    let name = &create.name;
     let (db_opt, table_name) = match name.0.len() {
         1 => (None, get_ident_name(&name.0[0])),
         2 => (Some(get_ident_name(&name.0[0])), get_ident_name(&name.0[1])),
@@ -4973,34 +7910,83 @@ fn handle_create_table(
     let db = db_opt
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    // A temporary table lives in the session's own schema, so it may share the name of a base
+    // table, which it then shadows.
+    let schema = if create.temporary {
+        Store::temp_schema(session.conn_id, &db)
+    } else {
+        db.clone()
+    };
+
+    let created = |affected_rows| {
+        Ok(ExecOutput::Ok {
+            affected_rows,
+            last_insert_id: 0,
+            info: "".into(),
+        })
+    };
+    // Tables made from another table or a query are not touched when they already exist; in
+    // particular, the query's rows are not added.
+    if (create.temporary || create.like.is_some() || create.query.is_some())
+        && store.get_table(&schema, &table_name).is_ok()
+    {
+        if create.if_not_exists {
+            return created(0);
+        }
+        return Err(MiniError::Invalid(format!(
+            "table already exists: {db}.{table_name}"
+        )));
+    }
+
+    // `CREATE TABLE new LIKE old` copies the definition: columns, defaults, primary key,
+    // AUTO_INCREMENT and indexes, but no rows.
+    if let Some(
+        ast::CreateTableLikeKind::Plain(like) | ast::CreateTableLikeKind::Parenthesized(like),
+    ) = &create.like
+    {
+        let (like_db, like_table) = object_name_to_parts(&like.name)?;
+        let like_db = like_db
+            .or_else(|| session.current_db.clone())
+            .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+        if is_system_schema(&like_db) {
+            return Err(MiniError::NotSupported(format!(
+                "Copying a table of {like_db} is not supported"
+            )));
+        }
+        require_priv(user, Some(&like_db), Priv::SELECT)?;
+        let mut def = store.get_table(&table_schema(session, like_db, &like_table), &like_table)?;
+        def.db = schema;
+        def.name = table_name;
+        store.create_table(&def)?;
+        if create.temporary {
+            session.temp_tables.insert((db, def.name));
+        }
+        return created(0);
+    }
 
     let mut my_columns = Vec::new();
     let mut primary_key: Option<String> = None;
     let mut auto_inc_cols: HashSet<String> = HashSet::new();
+    let mut indexes: Vec<IndexDef> = Vec::new();
 
-    for col in columns {
+    for col in &create.columns {
         let col_name = col.name.value.clone();
-        let sql_ty = match &col.data_type {
-            ast::DataType::Int(_)
-            | ast::DataType::BigInt(_)
-            | ast::DataType::Integer(_)
-            | ast::DataType::TinyInt(_)
-            | ast::DataType::SmallInt(_) => SqlType::Int,
-            ast::DataType::Float(_)
-            | ast::DataType::Double(_)
-            | ast::DataType::DoublePrecision
-            | ast::DataType::Real => SqlType::Float,
-            ast::DataType::Date => SqlType::Date,
-            ast::DataType::Datetime(_) | ast::DataType::Timestamp(_, _) => SqlType::DateTime,
-            _ => SqlType::Text, // Fallback
-        };
+        let sql_ty = sql_type_of(&col.data_type);
 
         let mut nullable = true;
         let mut auto_increment = false;
+        let mut default_expr: Option<&ast::Expr> = None;
+        let mut on_update_current_timestamp = false;
         for opt in &col.options {
             match &opt.option {
                 ast::ColumnOption::NotNull => nullable = false,
-                ast::ColumnOption::Unique(_) => { /* Unique but not PK here? */ }
+                ast::ColumnOption::Default(expr) => default_expr = Some(expr),
+                ast::ColumnOption::OnUpdate(expr) => {
+                    on_update_current_timestamp = on_update_clause(&col_name, &sql_ty, expr)?
+                }
+                ast::ColumnOption::Unique(_) => {
+                    add_unique_index(&mut indexes, None, col_name.clone())
+                }
                 ast::ColumnOption::PrimaryKey(_) => primary_key = Some(col_name.clone()),
                 ast::ColumnOption::DialectSpecific(tokens) => {
                     let text = tokens
@@ -5018,33 +8004,84 @@ fn handle_create_table(
         if auto_increment {
             auto_inc_cols.insert(col_name.to_ascii_lowercase());
         }
+        let default = match default_expr {
+            Some(expr) => parse_column_default(session, &col_name, &sql_ty, nullable, expr)?,
+            None => None,
+        };
 
         my_columns.push(crate::model::ColumnDef {
             name: col_name,
             ty: sql_ty,
             nullable,
+            default,
+            on_update_current_timestamp,
         });
     }
 
-    for c in constraints {
+    for c in &create.constraints {
         match c {
-            ast::TableConstraint::Unique(_u) => {
-                // Check if it's primary? No, PrimaryKey is separate.
-            }
-            ast::TableConstraint::PrimaryKey(pk) => {
-                if !pk.columns.is_empty() {
-                    // pk.columns is Vec<IndexColumn>.
-                    // IndexColumn has column: OrderByExpr. OrderByExpr has expr: Expr.
-                    let order_expr = &pk.columns[0].column;
-                    if let ast::Expr::Identifier(ident) = &order_expr.expr {
-                        primary_key = Some(ident.value.clone());
-                    }
+            ast::TableConstraint::Unique(u) => {
+                let [column] = u.columns.as_slice() else {
+                    return Err(MiniError::NotSupported(
+                        "UNIQUE constraints over several columns are not supported".into(),
+                    ));
+                };
+                let ast::Expr::Identifier(ident) = &column.column.expr else {
+                    return Err(MiniError::NotSupported(
+                        "UNIQUE constraints on expressions are not supported".into(),
+                    ));
+                };
+                let name = u.index_name.as_ref().or(u.name.as_ref());
+                add_unique_index(
+                    &mut indexes,
+                    name.map(|n| n.value.clone()),
+                    ident.value.clone(),
+                );
+            }
+            ast::TableConstraint::PrimaryKey(pk) if !pk.columns.is_empty() => {
+                // pk.columns is Vec<IndexColumn>.
+                // IndexColumn has column: OrderByExpr. OrderByExpr has expr: Expr.
+                let order_expr = &pk.columns[0].column;
+                if let ast::Expr::Identifier(ident) = &order_expr.expr {
+                    primary_key = Some(ident.value.clone());
                 }
             }
             _ => {}
         }
     }
 
+    // `CREATE TABLE ... AS SELECT`: each result column fills the defined column of the same
+    // name, or becomes a new column after the defined ones. Columns read unchanged from a table
+    // keep their definition, and, unless the statement defines its own primary key, the first
+    // one that is its table's primary key becomes the new table's.
+    let mut selected = None;
+    if let Some(query) = &create.query {
+        let (select_columns, rows) = run_create_select(store, session, user, query)?;
+        let mut targets: Vec<usize> = Vec::with_capacity(select_columns.len());
+        let mut select_primary_key = None;
+        let mut selected_names = HashSet::new();
+        for (col, is_primary_key) in select_columns {
+            if !selected_names.insert(col.name.to_ascii_lowercase()) {
+                return Err(MiniError::DuplicateColumn(col.name));
+            }
+            let existing = my_columns
+                .iter()
+                .position(|c: &ColumnDef| c.name.eq_ignore_ascii_case(&col.name));
+            if is_primary_key && col.ty == SqlType::Int && select_primary_key.is_none() {
+                select_primary_key = Some(col.name.clone());
+            }
+            match existing {
+                Some(idx) => targets.push(idx),
+                None => {
+                    targets.push(my_columns.len());
+                    my_columns.push(col);
+                }
+            }
+        }
+        primary_key = primary_key.or(select_primary_key);
+        selected = Some((targets, rows));
+    }
+
     let pk = primary_key.ok_or_else(|| MiniError::Invalid("PRIMARY KEY required".into()))?;
     let table_auto_increment = auto_inc_cols.contains(&pk.to_ascii_lowercase());
 
@@ -5056,27 +8093,217 @@ fn handle_create_table(
     if pk_col.ty != SqlType::Int {
         return Err(MiniError::Invalid("PRIMARY KEY must be INT".into()));
     }
+    // The primary key is unique already; other unique columns get their index.
+    indexes.retain(|idx| !idx.columns[0].eq_ignore_ascii_case(&pk));
+    for idx in &mut indexes {
+        idx.columns[0] = my_columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&idx.columns[0]))
+            .map(|c| c.name.clone())
+            .ok_or_else(|| {
+                MiniError::Invalid(format!(
+                    "Key column '{}' doesn't exist in table",
+                    idx.columns[0]
+                ))
+            })?;
+    }
 
     let def = TableDef {
-        db,
+        db: schema,
         name: table_name,
         columns: my_columns,
         primary_key: pk,
         auto_increment: table_auto_increment,
-        indexes: vec![],
+        indexes,
     };
 
+    if let Some((targets, rows)) = selected {
+        let rows = create_select_rows(session, &def, &targets, rows)?;
+        store.create_table_with_rows(&def, &rows)?;
+        if create.temporary {
+            session.temp_tables.insert((db, def.name));
+        }
+        return created(rows.len() as u64);
+    }
+
     match store.create_table(&def) {
         Ok(_) => {}
-        Err(MiniError::Invalid(msg)) if if_not_exists && msg.contains("exists") => {}
+        Err(MiniError::Invalid(msg)) if create.if_not_exists && msg.contains("exists") => {}
         Err(e) => return Err(e),
     }
+    if create.temporary {
+        session.temp_tables.insert((db, def.name));
+    }
 
-    Ok(ExecOutput::Ok {
-        affected_rows: 1,
-        last_insert_id: 0,
-        info: "".into(),
-    })
+    created(1)
+}
+
+/// Add a unique index over `column`. Like MySQL, an unnamed index is named after its column,
+/// with a `_2`, `_3`, ... suffix when that name is taken.
+fn add_unique_index(indexes: &mut Vec<IndexDef>, name: Option<String>, column: String) {
+    let name = name.unwrap_or_else(|| {
+        let taken = |n: &str| indexes.iter().any(|i| i.name.eq_ignore_ascii_case(n));
+        let mut name = column.clone();
+        let mut n = 2;
+        while taken(&name) {
+            name = format!("{column}_{n}");
+            n += 1;
+        }
+        name
+    });
+    indexes.push(IndexDef {
+        name,
+        columns: vec![column],
+        unique: true,
+    });
+}
+
+/// A result column of `CREATE TABLE ... AS SELECT` as a column definition, and whether it
+/// reads its table's primary key.
+type SelectedColumn = (ColumnDef, bool);
+
+/// Run the query of `CREATE TABLE ... AS SELECT`. Each result column is described as a column
+/// definition: a column read unchanged from a table copies that column's definition (and says
+/// whether it is the table's primary key), others take the type of their values.
+fn run_create_select(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    query: &ast::Query,
+) -> Result<(Vec<SelectedColumn>, Vec<Vec<Cell>>), MiniError> {
+    // The DDL's implicit commit ended the transaction; the query reads a fresh snapshot.
+    ensure_txn_active(store, session);
+    let (sources, output) = match query.body.as_ref() {
+        SetExpr::Select(select) if !select.from.is_empty() => {
+            let plan = plan::SelectPlan::build(store, session, user, query, select)?
+                .optimize(store, session);
+            let sources: Vec<_> = plan
+                .source_columns()
+                .into_iter()
+                .map(|source| {
+                    source.map(|(def, col)| {
+                        (col.clone(), col.name.eq_ignore_ascii_case(&def.primary_key))
+                    })
+                })
+                .collect();
+            (sources, plan.execute(store, session, None)?)
+        }
+        _ => (Vec::new(), handle_query(store, session, user, query)?),
+    };
+    let ExecOutput::ResultSet { columns, rows } = output else {
+        return Err(MiniError::Invalid(
+            "CREATE TABLE ... AS needs a query".into(),
+        ));
+    };
+    let rows: Vec<Vec<Cell>> = rows.collect::<Result<_, _>>()?;
+
+    let described = columns
+        .iter()
+        .enumerate()
+        .map(|(idx, column)| match sources.get(idx).cloned().flatten() {
+            Some((mut col, is_primary_key)) => {
+                col.name = column.column.clone();
+                (col, is_primary_key)
+            }
+            None => {
+                let ty = rows
+                    .iter()
+                    .find_map(|row| match row.get(idx) {
+                        Some(Cell::Int(_)) => Some(SqlType::Int),
+                        Some(Cell::Float(_)) => Some(SqlType::Float),
+                        Some(Cell::Text(_)) => Some(SqlType::Text),
+                        Some(Cell::Date(_)) => Some(SqlType::Date),
+                        Some(Cell::DateTime(_)) => Some(SqlType::DateTime),
+                        Some(Cell::Null) | None => None,
+                    })
+                    .unwrap_or(match column.coltype {
+                        ColumnType::MYSQL_TYPE_LONGLONG => SqlType::Int,
+                        ColumnType::MYSQL_TYPE_DOUBLE => SqlType::Float,
+                        _ => SqlType::Text,
+                    });
+                let col = ColumnDef::new(column.column.clone(), ty, true);
+                (col, false)
+            }
+        })
+        .collect();
+    Ok((described, rows))
+}
+
+/// Turn the rows of `CREATE TABLE ... AS SELECT` into the new table's rows, keyed by primary
+/// key. `targets` are the columns the result columns fill; the others take their defaults.
+fn create_select_rows(
+    session: &SessionState,
+    def: &TableDef,
+    targets: &[usize],
+    rows: Vec<Vec<Cell>>,
+) -> Result<Vec<(i64, Row)>, MiniError> {
+    let pk_index = def
+        .columns
+        .iter()
+        .position(|c| c.name.eq_ignore_ascii_case(&def.primary_key))
+        .ok_or_else(|| MiniError::Invalid("corrupt table: missing primary key column".into()))?;
+    let col_map = HashMap::new();
+    let mut out: BTreeMap<i64, Row> = BTreeMap::new();
+    let mut next_auto_inc = 1i64;
+    let unique_keys = unique_key_columns(def);
+    let mut seen_keys: Vec<HashSet<Cell>> = vec![HashSet::new(); unique_keys.len()];
+    for cells in rows {
+        let mut values =
+            insert_row_values(session, def, targets, &col_map, InsertValues::Cells(cells))?;
+        let pk = match values[pk_index].as_i64() {
+            Some(pk) => pk,
+            None if matches!(values[pk_index], Cell::Null) && def.auto_increment => {
+                values[pk_index] = Cell::Int(next_auto_inc);
+                next_auto_inc
+            }
+            _ => {
+                return Err(MiniError::Invalid(
+                    "PRIMARY KEY must be provided (INT)".into(),
+                ))
+            }
+        };
+        next_auto_inc = next_auto_inc.max(pk.saturating_add(1));
+        for ((idx, col_idx), seen) in unique_keys.iter().zip(&mut seen_keys) {
+            let val = &values[*col_idx];
+            if *val != Cell::Null && !seen.insert(val.clone()) {
+                return Err(duplicate_entry(def, &idx.name, val));
+            }
+        }
+        if out.insert(pk, Row { values }).is_some() {
+            return Err(duplicate_entry(def, "PRIMARY", &Cell::Int(pk)));
+        }
+    }
+    Ok(out.into_iter().collect())
+}
+
+/// The storage type a column's declared SQL type maps to.
+fn sql_type_of(data_type: &ast::DataType) -> SqlType {
+    match data_type {
+        ast::DataType::Int(_)
+        | ast::DataType::BigInt(_)
+        | ast::DataType::Integer(_)
+        | ast::DataType::TinyInt(_)
+        | ast::DataType::SmallInt(_) => SqlType::Int,
+        ast::DataType::Float(_)
+        | ast::DataType::Double(_)
+        | ast::DataType::DoublePrecision
+        | ast::DataType::Real => SqlType::Float,
+        ast::DataType::Date => SqlType::Date,
+        ast::DataType::Datetime(_) | ast::DataType::Timestamp(_, _) => SqlType::DateTime,
+        _ => SqlType::Text, // Fallback
+    }
+}
+
+/// Check a column's `ON UPDATE` clause: MySQL only takes `CURRENT_TIMESTAMP`, on date-time
+/// columns.
+fn on_update_clause(name: &str, ty: &SqlType, expr: &ast::Expr) -> Result<bool, MiniError> {
+    if *ty == SqlType::DateTime && is_current_timestamp(expr) {
+        Ok(true)
+    } else {
+        Err(MiniError::Invalid(format!(
+            "Invalid ON UPDATE clause for '{name}' column"
+        )))
+    }
 }
 
 fn handle_alter_table(
@@ -5107,6 +8334,7 @@ fn handle_alter_table(
             "ALTER TABLE is not supported for system schema {db}"
         )));
     }
+    let db = table_schema(session, db, &table_name);
 
     let mut def = match store.get_table(&db, &table_name) {
         Ok(def) => def,
@@ -5121,7 +8349,6 @@ fn handle_alter_table(
     };
 
     let mut new_columns: Vec<ColumnDef> = Vec::new();
-    let mut fill_values: Vec<Cell> = Vec::new();
 
     for op in &alter.operations {
         match op {
@@ -5154,40 +8381,39 @@ fn handle_alter_table(
                     )));
                 }
 
-                let sql_ty = match &column_def.data_type {
-                    ast::DataType::Int(_)
-                    | ast::DataType::BigInt(_)
-                    | ast::DataType::Integer(_)
-                    | ast::DataType::TinyInt(_)
-                    | ast::DataType::SmallInt(_) => SqlType::Int,
-                    _ => SqlType::Text,
-                };
+                let sql_ty = sql_type_of(&column_def.data_type);
 
                 let mut nullable = true;
                 let mut default_expr: Option<&ast::Expr> = None;
+                let mut on_update_current_timestamp = false;
                 for opt in &column_def.options {
                     match &opt.option {
                         ast::ColumnOption::NotNull => nullable = false,
                         ast::ColumnOption::Null => nullable = true,
                         ast::ColumnOption::Default(expr) => default_expr = Some(expr),
+                        ast::ColumnOption::OnUpdate(expr) => {
+                            on_update_current_timestamp =
+                                on_update_clause(&col_name, &sql_ty, expr)?
+                        }
                         ast::ColumnOption::Comment(_)
                         | ast::ColumnOption::CharacterSet(_)
                         | ast::ColumnOption::Collation(_)
                         | ast::ColumnOption::DialectSpecific(_)
                         | ast::ColumnOption::Generated { .. } => {}
-                        _ => {
-                            return Err(MiniError::NotSupported(
-                                "ALTER TABLE ADD COLUMN supports only NULL/NOT NULL/DEFAULT".into(),
-                            ))
-                        }
+                        _ => return Err(MiniError::NotSupported(
+                            "ALTER TABLE ADD COLUMN supports only NULL/NOT NULL/DEFAULT/ON UPDATE"
+                                .into(),
+                        )),
                     }
                 }
 
-                let fill = match default_expr {
-                    Some(expr) => eval_expr(expr)?,
-                    None => Cell::Null,
+                let default = match default_expr {
+                    Some(expr) => {
+                        parse_column_default(session, &col_name, &sql_ty, nullable, expr)?
+                    }
+                    None => None,
                 };
-                if !nullable && matches!(fill, Cell::Null) {
+                if !nullable && default.is_none() {
                     return Err(MiniError::NotSupported(format!(
                         "ADD COLUMN {col_name} NOT NULL requires DEFAULT"
                     )));
@@ -5197,8 +8423,9 @@ fn handle_alter_table(
                     name: col_name,
                     ty: sql_ty,
                     nullable,
+                    default,
+                    on_update_current_timestamp,
                 });
-                fill_values.push(fill);
             }
             _ => {
                 return Err(MiniError::NotSupported(
@@ -5216,18 +8443,17 @@ fn handle_alter_table(
         });
     }
 
+    // Existing rows take each new column's default, evaluated per row like an insert's.
     let mut updated: Vec<(i64, Row)> = Vec::new();
     for (pk, mut row) in store.scan_rows(&db, &table_name)? {
-        row.values.extend(fill_values.iter().cloned());
+        for col in &new_columns {
+            let fill = eval_column_default(session, col.default.as_ref(), &col.ty)?;
+            row.values.push(fill);
+        }
         updated.push((pk, row));
     }
-    let changes = updated
-        .iter()
-        .map(|(pk, row)| (db.as_str(), table_name.as_str(), *pk, Some(row)));
-    store.apply_row_changes(changes)?;
-
     def.columns.extend(new_columns);
-    store.update_table(&def)?;
+    store.alter_table(&def, &updated)?;
 
     Ok(ExecOutput::Ok {
         affected_rows: 0,
@@ -5242,9 +8468,14 @@ fn handle_drop_table(
     user: &UserRecord,
     name: &ObjectName,
     if_exists: bool,
+    temporary: bool,
 ) -> Result<ExecOutput, MiniError> {
-    require_priv(user, session.current_db.as_deref(), Priv::DROP)?;
-    txn_commit(store, session)?;
+    // `DROP TEMPORARY TABLE` only drops the session's temporary tables, and does not end the
+    // transaction; `DROP TABLE` drops the temporary table if there is one, else the base table.
+    if !temporary {
+        require_priv(user, session.current_db.as_deref(), Priv::DROP)?;
+        txn_commit(store, session)?;
+    }
 
     let (db_opt, table_name) = match name.0.len() {
         1 => (None, get_ident_name(&name.0[0])),
@@ -5256,7 +8487,19 @@ fn handle_drop_table(
         .or_else(|| session.current_db.clone())
         .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
 
-    match store.drop_table(&db, &table_name) {
+    let key = (db, table_name);
+    let temp_table = session.temp_tables.remove(&key);
+    let (db, table_name) = key;
+    let dropped = if temp_table {
+        store.drop_table(&Store::temp_schema(session.conn_id, &db), &table_name)
+    } else if temporary {
+        Err(MiniError::NotFound(format!(
+            "unknown table: {db}.{table_name}"
+        )))
+    } else {
+        store.drop_table(&db, &table_name)
+    };
+    match dropped {
         Ok(_) => {}
         Err(MiniError::NotFound(_)) if if_exists => {}
         Err(e) => return Err(e),
@@ -5269,6 +8512,43 @@ fn handle_drop_table(
     })
 }
 
+/// `TRUNCATE [TABLE] t`: empty the table as DDL, committing the open transaction first. Rows
+/// are not deleted one by one, so no row locks are taken and nothing can be rolled back; the
+/// table's exclusive metadata lock waits for transactions that used it.
+fn handle_truncate(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    truncate: &ast::Truncate,
+) -> Result<ExecOutput, MiniError> {
+    require_priv(user, session.current_db.as_deref(), Priv::DROP)?;
+    if truncate.partitions.is_some() {
+        return Err(MiniError::NotSupported(
+            "TRUNCATE ... PARTITION is not supported".into(),
+        ));
+    }
+    txn_commit(store, session)?;
+
+    for target in &truncate.table_names {
+        let (db_opt, table) = object_name_to_parts(&target.name)?;
+        let db = db_opt
+            .or_else(|| session.current_db.clone())
+            .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+        if is_system_schema(&db) {
+            return Err(MiniError::NotSupported(format!(
+                "Writing system schema {db} is not supported"
+            )));
+        }
+        store.truncate_table(&table_schema(session, db, &table), &table)?;
+    }
+
+    Ok(ExecOutput::Ok {
+        affected_rows: 0,
+        last_insert_id: 0,
+        info: "".into(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5321,6 +8601,7 @@ mod tests {
         let res = execute("SHOW INDEX FROM users", &store, &mut session, &user).unwrap();
         match res {
             ExecOutput::ResultSet { rows, .. } => {
+                let rows = rows.collect_rows().unwrap();
                 // Expected: PRIMARY (seq 1), idx_age (seq 1)
                 assert_eq!(
                     rows.len(),
@@ -5353,4 +8634,115 @@ mod tests {
             Err(e) => panic!("Failed to insert after index: {:?}", e),
         }
     }
+
+    #[test]
+    fn test_streamed_select_outlives_statement() {
+        let dir = tempdir().unwrap();
+        let store = Store::open(dir.path()).unwrap();
+        let user = UserRecord {
+            username: "root".into(),
+            host: "%".into(),
+            plugin: "".into(),
+            auth_stage2: None,
+            global_privs: Priv::ALL.bits(),
+            db_privs: Default::default(),
+        };
+        let mut reader = SessionState::new(1);
+        let mut writer = SessionState::new(2);
+        writer.lock_wait_timeout = 1;
+        for sql in [
+            "CREATE DATABASE test",
+            "USE test",
+            "CREATE TABLE t (id INT PRIMARY KEY, v INT)",
+            "INSERT INTO t VALUES (1, 10), (2, 20), (3, 30)",
+        ] {
+            execute(sql, &store, &mut writer, &user).unwrap();
+        }
+        execute("USE test", &store, &mut reader, &user).unwrap();
+
+        let ExecOutput::ResultSet { columns, mut rows } =
+            execute("SELECT v, id FROM t", &store, &mut reader, &user).unwrap()
+        else {
+            panic!("expected a result set");
+        };
+        assert_eq!(columns[0].column, "v");
+        assert_eq!(
+            rows.next().unwrap().unwrap(),
+            vec![Cell::Int(10), Cell::Int(1)]
+        );
+
+        // The autocommit statement has ended, but the unread stream keeps its snapshot and
+        // its table's metadata lock.
+        execute(
+            "UPDATE t SET v = 0 WHERE id = 2",
+            &store,
+            &mut writer,
+            &user,
+        )
+        .unwrap();
+        store.purge_history().unwrap();
+        assert!(execute("DROP TABLE t", &store, &mut writer, &user).is_err());
+        assert_eq!(
+            rows.collect_rows().unwrap(),
+            vec![
+                vec![Cell::Int(20), Cell::Int(2)],
+                vec![Cell::Int(30), Cell::Int(3)],
+            ]
+        );
+        execute("DROP TABLE t", &store, &mut writer, &user).unwrap();
+    }
+
+    #[test]
+    fn test_temp_tables_dropped_with_session() {
+        let dir = tempdir().unwrap();
+        let user = UserRecord {
+            username: "root".into(),
+            host: "%".into(),
+            plugin: "".into(),
+            auth_stage2: None,
+            global_privs: Priv::ALL.bits(),
+            db_privs: Default::default(),
+        };
+        {
+            let store = Store::open(dir.path()).unwrap();
+            let mut session = SessionState::new(1);
+            for sql in [
+                "CREATE DATABASE test",
+                "USE test",
+                "CREATE TABLE t (id INT PRIMARY KEY)",
+                "CREATE TEMPORARY TABLE t (id INT PRIMARY KEY, v INT)",
+                "INSERT INTO t VALUES (1, 10)",
+                "CREATE TEMPORARY TABLE scratch (id INT PRIMARY KEY)",
+            ] {
+                execute(sql, &store, &mut session, &user).unwrap();
+            }
+            let schema = Store::temp_schema(1, "test");
+            assert_eq!(store.scan_rows(&schema, "t").unwrap().len(), 1);
+            assert!(store.scan_rows("test", "t").unwrap().is_empty());
+
+            // Ending the session drops its temporary tables and their rows.
+            close_session(&store, &mut session);
+            assert!(store.get_table(&schema, "t").is_err());
+            assert!(store.scan_rows(&schema, "t").unwrap().is_empty());
+            assert!(store.get_table("test", "t").is_ok());
+
+            // A session that never ended (a crash) leaves them until the store is reopened.
+            let mut session = SessionState::new(2);
+            execute("USE test", &store, &mut session, &user).unwrap();
+            execute(
+                "CREATE TEMPORARY TABLE t (id INT PRIMARY KEY)",
+                &store,
+                &mut session,
+                &user,
+            )
+            .unwrap();
+            assert!(store.get_table(&Store::temp_schema(2, "test"), "t").is_ok());
+        }
+        let store = Store::reopen(dir.path()).unwrap();
+        assert!(store
+            .get_table(&Store::temp_schema(2, "test"), "t")
+            .is_err());
+        assert_eq!(store.list_tables("test").unwrap(), vec!["t".to_string()]);
+        assert_eq!(store.list_databases().unwrap(), vec!["test".to_string()]);
+    }
 }
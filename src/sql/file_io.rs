@@ -0,0 +1,1247 @@
+//! File import and export: `LOAD DATA INFILE` and `SELECT ... INTO OUTFILE | DUMPFILE`.
+//!
+//! Both statements describe the file with the same `FIELDS` and `LINES` clauses
+//! ([`FileFormat`]). Files are read and written by the server, and only inside the
+//! `secure_file_priv` directory. The protocol library cannot ask the client for a file, so
+//! `LOAD DATA LOCAL` is rejected.
+//!
+//! An autocommit `LOAD DATA` streams the file into the store in batches through
+//! [`Store::bulk_write_rows`] instead of holding every row in the transaction's write set; the
+//! batches stay invisible to other sessions until the statement commits.
+
+use super::{
+    build_col_map, cell_to_string, coerce_cell, column_default_value, eval_column_default,
+    eval_row_expr, execute, get_ident_name, insert_primary_key, is_default_keyword,
+    lock_insert_intentions, lock_tables, object_name_to_parts, primary_key_index, require_priv,
+    run_statement, should_buffer_writes, split_sql_tokens, stage_row_changes, table_schema,
+    ExecOutput, RowLockGuard, SessionState, StatementRows, TableAccess, TableRef, ER_DUP_ENTRY,
+};
+use crate::auth::Priv;
+use crate::error::MiniError;
+use crate::model::{Cell, Row, TableDef, UserRecord};
+use crate::store::Store;
+use sqlparser::ast::{self, Ident, ObjectName};
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Location, Token};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Rows an autocommit `LOAD DATA` writes to the store at a time.
+const BULK_LOAD_BATCH: usize = 1000;
+
+/// `ER_WARN_TOO_FEW_RECORDS`: a line had fewer fields than the column list.
+const ER_WARN_TOO_FEW_RECORDS: u16 = 1261;
+/// `ER_WARN_TOO_MANY_RECORDS`: a line had more fields than the column list.
+const ER_WARN_TOO_MANY_RECORDS: u16 = 1262;
+
+/// How fields and lines are laid out in a file (`FIELDS ...` and `LINES ...`).
+#[derive(Debug, Clone, PartialEq)]
+struct FileFormat {
+    field_term: Vec<u8>,
+    enclosed: Option<u8>,
+    // `OPTIONALLY ENCLOSED BY`: only string values are enclosed on export.
+    optionally: bool,
+    escaped: Option<u8>,
+    line_start: Vec<u8>,
+    line_term: Vec<u8>,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            field_term: b"\t".to_vec(),
+            enclosed: None,
+            optionally: false,
+            escaped: Some(b'\\'),
+            line_start: Vec::new(),
+            line_term: b"\n".to_vec(),
+        }
+    }
+}
+
+/// What `LOAD DATA` does with a line whose key is already taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Duplicates {
+    Error,
+    Replace,
+    Ignore,
+}
+
+/// A parsed `LOAD DATA` statement.
+#[derive(Debug)]
+struct LoadData {
+    file: String,
+    duplicates: Duplicates,
+    table: ObjectName,
+    format: FileFormat,
+    ignore_lines: u64,
+    // Columns, or `@var` user variables, the fields of a line are assigned to in order.
+    targets: Vec<Ident>,
+    assignments: Vec<ast::Assignment>,
+}
+
+/// The `INTO OUTFILE` or `INTO DUMPFILE` clause of a SELECT.
+#[derive(Debug)]
+struct OutFile {
+    file: String,
+    dump: bool,
+    format: FileFormat,
+}
+
+fn parse_err(e: ParserError) -> MiniError {
+    MiniError::Parse(e.to_string())
+}
+
+/// Consume `word` if it comes next; for the words sqlparser has no keyword for.
+fn parse_word(p: &mut Parser, word: &str) -> bool {
+    match &p.peek_token().token {
+        Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word) => {
+            p.next_token();
+            true
+        }
+        _ => false,
+    }
+}
+
+fn expect_word(p: &mut Parser, word: &str) -> Result<(), MiniError> {
+    if parse_word(p, word) {
+        return Ok(());
+    }
+    Err(MiniError::Parse(format!(
+        "Expected: {word}, found: {}",
+        p.peek_token()
+    )))
+}
+
+fn expect_keyword(p: &mut Parser, keyword: Keyword) -> Result<(), MiniError> {
+    p.expect_keyword_is(keyword).map_err(parse_err)
+}
+
+/// `BY 'c'` for ENCLOSED and ESCAPED, which take at most one character.
+fn parse_by_char(p: &mut Parser) -> Result<Option<u8>, MiniError> {
+    expect_keyword(p, Keyword::BY)?;
+    let s = p.parse_literal_string().map_err(parse_err)?;
+    match s.as_bytes() {
+        [] => Ok(None),
+        [c] => Ok(Some(*c)),
+        _ => Err(MiniError::Invalid(
+            "Field separator argument is not what is expected; check the manual".into(),
+        )),
+    }
+}
+
+fn parse_by_string(p: &mut Parser) -> Result<Vec<u8>, MiniError> {
+    expect_keyword(p, Keyword::BY)?;
+    Ok(p.parse_literal_string().map_err(parse_err)?.into_bytes())
+}
+
+/// `[CHARACTER SET name]`. Files are read and written as UTF-8.
+fn parse_character_set(p: &mut Parser) -> Result<(), MiniError> {
+    if !p.parse_keywords(&[Keyword::CHARACTER, Keyword::SET]) {
+        return Ok(());
+    }
+    let name = match p.next_token().token {
+        Token::Word(w) => w.value,
+        Token::SingleQuotedString(s) => s,
+        other => {
+            return Err(MiniError::Parse(format!(
+                "Expected: charset, found: {other}"
+            )))
+        }
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "utf8" | "utf8mb3" | "utf8mb4" | "ascii" | "binary" => Ok(()),
+        _ => Err(MiniError::NotSupported(format!(
+            "CHARACTER SET {name} is not supported for data files"
+        ))),
+    }
+}
+
+/// `[{FIELDS | COLUMNS} ...] [LINES ...]`.
+fn parse_file_format(p: &mut Parser) -> Result<FileFormat, MiniError> {
+    let mut format = FileFormat::default();
+    if p.parse_one_of_keywords(&[Keyword::FIELDS, Keyword::COLUMNS])
+        .is_some()
+    {
+        let mut any = false;
+        loop {
+            if p.parse_keyword(Keyword::TERMINATED) {
+                format.field_term = parse_by_string(p)?;
+            } else if parse_word(p, "OPTIONALLY") {
+                expect_word(p, "ENCLOSED")?;
+                format.enclosed = parse_by_char(p)?;
+                format.optionally = true;
+            } else if parse_word(p, "ENCLOSED") {
+                format.enclosed = parse_by_char(p)?;
+                format.optionally = false;
+            } else if p.parse_keyword(Keyword::ESCAPED) {
+                format.escaped = parse_by_char(p)?;
+            } else {
+                break;
+            }
+            any = true;
+        }
+        if !any {
+            return Err(MiniError::Parse(format!(
+                "Expected: TERMINATED, ENCLOSED or ESCAPED BY, found: {}",
+                p.peek_token()
+            )));
+        }
+    }
+    if p.parse_keyword(Keyword::LINES) {
+        let mut any = false;
+        loop {
+            if parse_word(p, "STARTING") {
+                format.line_start = parse_by_string(p)?;
+            } else if p.parse_keyword(Keyword::TERMINATED) {
+                format.line_term = parse_by_string(p)?;
+            } else {
+                break;
+            }
+            any = true;
+        }
+        if !any {
+            return Err(MiniError::Parse(format!(
+                "Expected: STARTING or TERMINATED BY, found: {}",
+                p.peek_token()
+            )));
+        }
+    }
+    if format.line_term.is_empty() {
+        return Err(MiniError::NotSupported(
+            "an empty LINES TERMINATED BY is not supported".into(),
+        ));
+    }
+    if format.field_term.is_empty() && format.enclosed.is_none() {
+        return Err(MiniError::NotSupported(
+            "fixed-width rows (empty FIELDS TERMINATED BY and ENCLOSED BY) are not supported"
+                .into(),
+        ));
+    }
+    Ok(format)
+}
+
+/// `LOAD DATA [LOW_PRIORITY | CONCURRENT] [LOCAL] INFILE 'file' [REPLACE | IGNORE]
+/// INTO TABLE t [CHARACTER SET cs] [FIELDS ...] [LINES ...] [IGNORE n {LINES | ROWS}]
+/// [(col_or_@var, ...)] [SET col = expr, ...]`.
+fn parse_load_data(query: &str) -> Result<LoadData, MiniError> {
+    let dialect = MySqlDialect {};
+    let mut p = Parser::new(&dialect)
+        .try_with_sql(query)
+        .map_err(parse_err)?;
+    expect_keyword(&mut p, Keyword::LOAD)?;
+    expect_keyword(&mut p, Keyword::DATA)?;
+    if !p.parse_keyword(Keyword::LOW_PRIORITY) {
+        parse_word(&mut p, "CONCURRENT");
+    }
+    if p.parse_keyword(Keyword::LOCAL) {
+        // The protocol library cannot ask the client for its file.
+        return Err(MiniError::NotSupported(
+            "LOAD DATA LOCAL INFILE is not supported".into(),
+        ));
+    }
+    expect_word(&mut p, "INFILE")?;
+    let file = p.parse_literal_string().map_err(parse_err)?;
+    let duplicates = if p.parse_keyword(Keyword::REPLACE) {
+        Duplicates::Replace
+    } else if p.parse_keyword(Keyword::IGNORE) {
+        Duplicates::Ignore
+    } else {
+        Duplicates::Error
+    };
+    expect_keyword(&mut p, Keyword::INTO)?;
+    expect_keyword(&mut p, Keyword::TABLE)?;
+    let table = p.parse_object_name(false).map_err(parse_err)?;
+    if p.parse_keyword(Keyword::PARTITION) {
+        return Err(MiniError::NotSupported(
+            "LOAD DATA ... PARTITION is not supported".into(),
+        ));
+    }
+    parse_character_set(&mut p)?;
+    let format = parse_file_format(&mut p)?;
+    let mut ignore_lines = 0;
+    if p.parse_keyword(Keyword::IGNORE) {
+        ignore_lines = p.parse_literal_uint().map_err(parse_err)?;
+        if p.parse_one_of_keywords(&[Keyword::LINES, Keyword::ROWS])
+            .is_none()
+        {
+            return Err(MiniError::Parse(format!(
+                "Expected: LINES or ROWS, found: {}",
+                p.peek_token()
+            )));
+        }
+    }
+    let mut targets = Vec::new();
+    if p.consume_token(&Token::LParen) && !p.consume_token(&Token::RParen) {
+        targets = p
+            .parse_comma_separated(|p| p.parse_identifier())
+            .map_err(parse_err)?;
+        p.expect_token(&Token::RParen).map_err(parse_err)?;
+    }
+    let mut assignments = Vec::new();
+    if p.parse_keyword(Keyword::SET) {
+        assignments = p
+            .parse_comma_separated(Parser::parse_assignment)
+            .map_err(parse_err)?;
+    }
+    if p.peek_token().token != Token::EOF {
+        return Err(MiniError::Parse(format!(
+            "unexpected input in LOAD DATA: {}",
+            p.peek_token()
+        )));
+    }
+    Ok(LoadData {
+        file,
+        duplicates,
+        table,
+        format,
+        ignore_lines,
+        targets,
+        assignments,
+    })
+}
+
+/// The byte offset of a location sqlparser reports (lines from 1, columns in characters).
+fn location_offset(text: &str, loc: Location) -> usize {
+    let mut offset = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if i as u64 + 1 == loc.line {
+            return offset
+                + line
+                    .char_indices()
+                    .nth(loc.column.saturating_sub(1) as usize)
+                    .map_or(line.len(), |(i, _)| i);
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// Cut the `INTO OUTFILE 'file' [CHARACTER SET cs] [FIELDS ...] [LINES ...]` or
+/// `INTO DUMPFILE 'file'` clause out of a SELECT, which may place it before FROM or at the end.
+fn split_into_outfile(query: &str) -> Option<Result<(String, OutFile), MiniError>> {
+    let tokens = split_sql_tokens(query);
+    let into = tokens.windows(2).position(|w| {
+        w[0].eq_ignore_ascii_case("into")
+            && (w[1].eq_ignore_ascii_case("outfile") || w[1].eq_ignore_ascii_case("dumpfile"))
+    })?;
+    let start = tokens[into].as_ptr() as usize - query.as_ptr() as usize;
+    let clause = &query[start..];
+    let parse = || {
+        let dialect = MySqlDialect {};
+        let mut p = Parser::new(&dialect)
+            .try_with_sql(clause)
+            .map_err(parse_err)?;
+        expect_keyword(&mut p, Keyword::INTO)?;
+        let dump = parse_word(&mut p, "DUMPFILE");
+        if !dump {
+            expect_word(&mut p, "OUTFILE")?;
+        }
+        let file = p.parse_literal_string().map_err(parse_err)?;
+        parse_character_set(&mut p)?;
+        let format = if dump {
+            FileFormat::default()
+        } else {
+            parse_file_format(&mut p)?
+        };
+        // The rest of the query resumes where the clause ends.
+        let next = p.peek_token();
+        let end = match next.token {
+            Token::EOF => query.len(),
+            _ => start + location_offset(clause, next.span.start),
+        };
+        let rest = format!("{} {}", &query[..start], &query[end..]);
+        Ok((rest, OutFile { file, dump, format }))
+    };
+    Some(parse())
+}
+
+/// Resolve a data file name under `secure_file_priv`: relative names are taken from that
+/// directory, and the file must be inside it once links and `..` are resolved. Without the
+/// setting, file import and export are disabled.
+fn secure_path(store: &Store, file: &str) -> Result<PathBuf, MiniError> {
+    let dir = store.secure_file_priv().ok_or(MiniError::SecureFilePriv)?;
+    let path = Path::new(file);
+    let path = if path.is_relative() && !dir.as_os_str().is_empty() {
+        dir.join(path)
+    } else {
+        path.to_path_buf()
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| MiniError::Invalid(format!("Incorrect file name '{file}'")))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let not_found = || MiniError::FileNotFound(path.display().to_string());
+    let mut resolved = parent.canonicalize().map_err(|_| not_found())?.join(name);
+    // An existing file may itself be a link.
+    if let Ok(target) = resolved.canonicalize() {
+        resolved = target;
+    }
+    if !dir.as_os_str().is_empty() && !resolved.starts_with(&dir) {
+        return Err(MiniError::SecureFilePriv);
+    }
+    Ok(resolved)
+}
+
+/// A field read from a data file; `None` is NULL (`\N`, or an unenclosed `NULL` when fields
+/// may be enclosed).
+type Field = Option<Vec<u8>>;
+
+/// The result of parsing one line from the start of a buffer.
+enum Parsed {
+    /// The line's fields and the bytes it took, terminator included.
+    Line(Vec<Field>, usize),
+    /// The buffer ends mid-line; read more.
+    NeedMore,
+    End,
+}
+
+/// The character an escape sequence stands for: `\0`, `\b`, `\n`, `\r`, `\t` and `\Z` are
+/// special, any other character stands for itself.
+fn unescape(b: u8) -> u8 {
+    match b {
+        b'0' => 0,
+        b'b' => 0x08,
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'Z' => 0x1a,
+        other => other,
+    }
+}
+
+fn parse_line(buf: &[u8], format: &FileFormat, eof: bool) -> Parsed {
+    if buf.is_empty() && eof {
+        return Parsed::End;
+    }
+    let field_term = format.field_term.as_slice();
+    let line_term = format.line_term.as_slice();
+    let mut pos = 0;
+    if !format.line_start.is_empty() {
+        // Text before the prefix is skipped, and so are lines without it.
+        match buf
+            .windows(format.line_start.len())
+            .position(|w| w == format.line_start.as_slice())
+        {
+            Some(i) => pos = i + format.line_start.len(),
+            None if eof => return Parsed::End,
+            None => return Parsed::NeedMore,
+        }
+    }
+    // Enough input to tell a terminator, escape sequence or closing quote at `pos` apart.
+    let lookahead = 2 + field_term.len().max(line_term.len());
+    let short = |pos: usize| !eof && buf.len() - pos < lookahead;
+    let is_term = |rest: &[u8]| {
+        rest.starts_with(line_term) || (!field_term.is_empty() && rest.starts_with(field_term))
+    };
+
+    let mut fields = Vec::new();
+    loop {
+        if short(pos) {
+            return Parsed::NeedMore;
+        }
+        let start = pos;
+        let mut value = Vec::new();
+        let quote = format.enclosed.filter(|q| buf.get(pos) == Some(q));
+        if let Some(q) = quote {
+            pos += 1;
+            loop {
+                if short(pos) {
+                    return Parsed::NeedMore;
+                }
+                let Some(&b) = buf.get(pos) else {
+                    break;
+                };
+                if Some(b) == format.escaped && pos + 1 < buf.len() {
+                    value.push(unescape(buf[pos + 1]));
+                    pos += 2;
+                    continue;
+                }
+                if b == q {
+                    if buf.get(pos + 1) == Some(&q) {
+                        value.push(q);
+                        pos += 2;
+                        continue;
+                    }
+                    let rest = &buf[pos + 1..];
+                    if rest.is_empty() || is_term(rest) {
+                        pos += 1;
+                        break;
+                    }
+                }
+                value.push(b);
+                pos += 1;
+            }
+        } else {
+            loop {
+                if short(pos) {
+                    return Parsed::NeedMore;
+                }
+                let rest = &buf[pos..];
+                if rest.is_empty() || is_term(rest) {
+                    break;
+                }
+                if Some(rest[0]) == format.escaped && rest.len() > 1 {
+                    value.push(unescape(rest[1]));
+                    pos += 2;
+                    continue;
+                }
+                value.push(rest[0]);
+                pos += 1;
+            }
+        }
+        let raw = &buf[start..pos];
+        let null = quote.is_none()
+            && (format.escaped.is_some_and(|e| raw == [e, b'N'])
+                || (format.enclosed.is_some() && raw == b"NULL"));
+        fields.push(if null { None } else { Some(value) });
+
+        let rest = &buf[pos..];
+        if rest.starts_with(line_term) {
+            return Parsed::Line(fields, pos + line_term.len());
+        }
+        if !field_term.is_empty() && rest.starts_with(field_term) {
+            pos += field_term.len();
+            continue;
+        }
+        // The last line may lack its terminator.
+        return Parsed::Line(fields, pos);
+    }
+}
+
+/// Reads the lines of a data file one at a time.
+struct LineReader<R> {
+    input: R,
+    format: FileFormat,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> LineReader<R> {
+    fn new(input: R, format: FileFormat) -> Self {
+        Self {
+            input,
+            format,
+            buf: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<Vec<Field>>, MiniError> {
+        loop {
+            match parse_line(&self.buf[self.pos..], &self.format, self.eof) {
+                Parsed::Line(fields, len) => {
+                    self.pos += len;
+                    return Ok(Some(fields));
+                }
+                Parsed::End => return Ok(None),
+                Parsed::NeedMore => {
+                    self.buf.drain(..self.pos);
+                    self.pos = 0;
+                    let len = self.buf.len();
+                    self.buf.resize(len + 64 * 1024, 0);
+                    let read = self.input.read(&mut self.buf[len..])?;
+                    self.buf.truncate(len + read);
+                    self.eof = read == 0;
+                }
+            }
+        }
+    }
+}
+
+/// Append one value in the file's format.
+fn write_field(out: &mut Vec<u8>, cell: &Cell, format: &FileFormat) {
+    if *cell == Cell::Null {
+        match format.escaped {
+            Some(e) => out.extend_from_slice(&[e, b'N']),
+            None => out.extend_from_slice(b"NULL"),
+        }
+        return;
+    }
+    let quote = format
+        .enclosed
+        .filter(|_| !format.optionally || matches!(cell, Cell::Text(_)));
+    out.extend(quote);
+    for b in cell_to_string(cell).bytes() {
+        if let Some(e) = format.escaped {
+            // Enclosed values only need the quote escaped; others the terminators too.
+            let special = match quote {
+                Some(q) => b == q,
+                None => {
+                    format.field_term.first() == Some(&b) || format.line_term.first() == Some(&b)
+                }
+            };
+            if b == e || special {
+                out.extend_from_slice(&[e, b]);
+                continue;
+            }
+            if b == 0 {
+                out.extend_from_slice(&[e, b'0']);
+                continue;
+            }
+        }
+        out.push(b);
+    }
+    out.extend(quote);
+}
+
+/// `SELECT ... INTO OUTFILE 'file' [FIELDS ...] [LINES ...]` writes the result rows to a new
+/// file; `INTO DUMPFILE` writes a single row's values with no formatting at all.
+pub(super) fn try_handle_select_into_outfile(
+    query: &str,
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Option<Result<ExecOutput, MiniError>> {
+    let first = split_sql_tokens(query).first().copied()?;
+    let first = first.trim_start_matches('(');
+    if !first.eq_ignore_ascii_case("select") && !first.eq_ignore_ascii_case("with") {
+        return None;
+    }
+    let parsed = split_into_outfile(query)?;
+    Some(parsed.and_then(|(select, outfile)| {
+        handle_select_into_outfile(&select, &outfile, store, session, user)
+    }))
+}
+
+fn handle_select_into_outfile(
+    select: &str,
+    outfile: &OutFile,
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Result<ExecOutput, MiniError> {
+    require_priv(user, None, Priv::FILE)?;
+    let path = secure_path(store, &outfile.file)?;
+    let exists = || MiniError::FileExists(path.display().to_string());
+    if path.exists() {
+        return Err(exists());
+    }
+    let ExecOutput::ResultSet { rows, .. } = execute(select, store, session, user)? else {
+        return Err(MiniError::Parse(
+            "INTO OUTFILE and INTO DUMPFILE need a SELECT".into(),
+        ));
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => exists(),
+            _ => e.into(),
+        })?;
+    let mut writer = BufWriter::new(file);
+    let written = (|| {
+        let format = &outfile.format;
+        let mut count = 0u64;
+        let mut line = Vec::new();
+        for row in rows {
+            let row = row?;
+            count += 1;
+            line.clear();
+            if outfile.dump {
+                if count > 1 {
+                    return Err(MiniError::TooManyRows);
+                }
+                for cell in row.iter().filter(|c| **c != Cell::Null) {
+                    line.extend_from_slice(cell_to_string(cell).as_bytes());
+                }
+            } else {
+                line.extend_from_slice(&format.line_start);
+                for (i, cell) in row.iter().enumerate() {
+                    if i > 0 {
+                        line.extend_from_slice(&format.field_term);
+                    }
+                    write_field(&mut line, cell, format);
+                }
+                line.extend_from_slice(&format.line_term);
+            }
+            writer.write_all(&line)?;
+        }
+        writer.flush()?;
+        Ok(count)
+    })();
+    match written {
+        Ok(count) => Ok(ExecOutput::Ok {
+            affected_rows: count,
+            last_insert_id: 0,
+            info: "".into(),
+        }),
+        Err(e) => {
+            drop(writer);
+            let _ = std::fs::remove_file(&path);
+            Err(e)
+        }
+    }
+}
+
+pub(super) fn try_handle_load_data(
+    query: &str,
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+) -> Option<Result<ExecOutput, MiniError>> {
+    let tokens = split_sql_tokens(query);
+    if tokens.len() < 2
+        || !tokens[0].eq_ignore_ascii_case("load")
+        || !tokens[1].eq_ignore_ascii_case("data")
+    {
+        return None;
+    }
+    let mut run = || {
+        let load = parse_load_data(query)?;
+        let (db, table) = object_name_to_parts(&load.table)?;
+        let db = db
+            .or_else(|| session.current_db.clone())
+            .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+        let db = table_schema(session, db, &table);
+        run_statement(
+            store,
+            session,
+            true,
+            |store, session| {
+                let table = TableRef {
+                    db: db.clone(),
+                    table: table.clone(),
+                    alias: None,
+                    access: TableAccess::Write,
+                };
+                lock_tables(store, session, vec![table], true)
+            },
+            |store, session| handle_load_data(store, session, user, &load, &db, &table),
+        )
+    };
+    Some(run())
+}
+
+/// Where the fields of a line go: a column, or a `@var` the SET clause can read.
+#[derive(Debug, Clone, Copy)]
+enum LoadTarget {
+    Column(usize),
+    Var(usize),
+}
+
+/// Counts for the statement's info message.
+#[derive(Debug, Default)]
+struct LoadCounts {
+    records: u64,
+    inserted: u64,
+    deleted: u64,
+    skipped: u64,
+    first_generated_id: Option<i64>,
+}
+
+fn handle_load_data(
+    store: &Store,
+    session: &mut SessionState,
+    user: &UserRecord,
+    load: &LoadData,
+    db: &str,
+    table: &str,
+) -> Result<ExecOutput, MiniError> {
+    require_priv(user, None, Priv::FILE)?;
+    require_priv(user, Some(db), Priv::INSERT)?;
+    let def = store.get_table(db, table)?;
+
+    let mut targets = Vec::new();
+    let mut vars: Vec<String> = Vec::new();
+    if load.targets.is_empty() {
+        targets.extend((0..def.columns.len()).map(LoadTarget::Column));
+    }
+    for ident in &load.targets {
+        if ident.value.starts_with('@') {
+            let name = ident.value.to_ascii_lowercase();
+            let idx = vars.iter().position(|v| *v == name).unwrap_or_else(|| {
+                vars.push(name);
+                vars.len() - 1
+            });
+            targets.push(LoadTarget::Var(idx));
+            continue;
+        }
+        let idx = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&ident.value))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {}", ident.value)))?;
+        if targets
+            .iter()
+            .any(|t| matches!(t, LoadTarget::Column(i) if *i == idx))
+        {
+            return Err(MiniError::Invalid(format!(
+                "Column '{}' specified twice",
+                ident.value
+            )));
+        }
+        targets.push(LoadTarget::Column(idx));
+    }
+    // SET runs after the fields are assigned, in order; `None` assigns the default.
+    let mut assignments: Vec<(usize, Option<&ast::Expr>)> = Vec::new();
+    for a in &load.assignments {
+        let col_name = match &a.target {
+            ast::AssignmentTarget::ColumnName(name) => get_ident_name(name.0.last().unwrap()),
+            ast::AssignmentTarget::Tuple(_) => {
+                return Err(MiniError::NotSupported(
+                    "Tuple assignment is not supported".into(),
+                ))
+            }
+        };
+        let idx = def
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&col_name))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown column: {col_name}")))?;
+        let value = Some(&a.value).filter(|v| !is_default_keyword(v));
+        assignments.push((idx, value));
+    }
+    // The variables follow the table's columns in the row SET expressions read.
+    let mut col_map = build_col_map(&[&def]);
+    for (i, var) in vars.iter().enumerate() {
+        col_map.insert(var.clone(), def.columns.len() + i);
+    }
+
+    let path = secure_path(store, &load.file)?;
+    let file =
+        File::open(&path).map_err(|_| MiniError::FileNotFound(path.display().to_string()))?;
+    let mut reader = LineReader::new(file, load.format.clone());
+    for _ in 0..load.ignore_lines {
+        if reader.next_line()?.is_none() {
+            break;
+        }
+    }
+
+    let plan = LoadPlan {
+        def: &def,
+        targets,
+        vars: vars.len(),
+        assignments,
+        col_map,
+        duplicates: load.duplicates,
+    };
+    // An autocommit load writes its rows to the store as it goes; inside a transaction they
+    // are staged like any other statement's.
+    let bulk = !should_buffer_writes(session);
+    let mut locks = RowLockGuard::new(store, session);
+    let mut flushed = false;
+    let loaded = load_lines(
+        store,
+        session,
+        &mut locks,
+        &plan,
+        &mut reader,
+        bulk,
+        &mut flushed,
+    );
+    let counts = match loaded {
+        Ok(counts) => counts,
+        Err(e) => {
+            if flushed {
+                if let Some(tx_id) = session.txn.tx_id {
+                    store.abort_bulk_load(tx_id)?;
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    let info = format!(
+        "Records: {}  Deleted: {}  Skipped: {}  Warnings: {}",
+        counts.records,
+        counts.deleted,
+        counts.skipped,
+        session.warnings.len()
+    );
+    Ok(ExecOutput::Ok {
+        affected_rows: counts.inserted + counts.deleted,
+        last_insert_id: counts.first_generated_id.unwrap_or(0).max(0) as u64,
+        info,
+    })
+}
+
+/// How `LOAD DATA` turns the fields of a line into a row.
+struct LoadPlan<'a> {
+    def: &'a TableDef,
+    targets: Vec<LoadTarget>,
+    vars: usize,
+    assignments: Vec<(usize, Option<&'a ast::Expr>)>,
+    col_map: HashMap<String, usize>,
+    duplicates: Duplicates,
+}
+
+/// Insert every remaining line of `reader`. With `bulk`, rows go to the store a batch at a
+/// time (`flushed` tells whether any did, so a failure can undo them); otherwise they are
+/// staged in the transaction's write set.
+fn load_lines<R: Read>(
+    store: &Store,
+    session: &mut SessionState,
+    locks: &mut RowLockGuard<'_>,
+    plan: &LoadPlan<'_>,
+    reader: &mut LineReader<R>,
+    bulk: bool,
+    flushed: &mut bool,
+) -> Result<LoadCounts, MiniError> {
+    let def = plan.def;
+    let (db, table) = (def.db.as_str(), def.name.as_str());
+    let pk_index = primary_key_index(def)?;
+    let mut auto_inc_initialized = false;
+    let mut stmt_rows = StatementRows::new(def);
+    let mut counts = LoadCounts::default();
+    let write_batch = |session: &SessionState, stmt_rows: &StatementRows<'_>| {
+        let (Some(tx_id), Some(view)) = (session.txn.tx_id, session.txn.read_view.as_ref()) else {
+            return Err(MiniError::Invalid("No active transaction view".into()));
+        };
+        let rows = stmt_rows.rows.iter().map(|(pk, row)| (*pk, row.as_ref()));
+        store.bulk_write_rows(db, table, rows, tx_id, view)
+    };
+
+    while let Some(fields) = reader.next_line()? {
+        counts.records += 1;
+        let mut row_vals = load_row_values(session, plan, counts.records, fields)?;
+        let (pk, generated) = insert_primary_key(
+            store,
+            session,
+            def,
+            pk_index,
+            &mut row_vals,
+            &mut auto_inc_initialized,
+        )?;
+        let row = Row { values: row_vals };
+        lock_insert_intentions(locks, def, pk, &row, None)?;
+        locks.lock_row(db, table, pk)?;
+
+        let mut conflicts = stmt_rows.conflicts(store, session, pk, &row, false)?;
+        if !conflicts.is_empty() {
+            match plan.duplicates {
+                Duplicates::Error => return Err(conflicts.swap_remove(0).1),
+                Duplicates::Ignore => {
+                    session.warn(ER_DUP_ENTRY, conflicts.swap_remove(0).1.to_string());
+                    counts.skipped += 1;
+                    continue;
+                }
+                Duplicates::Replace => {
+                    for (other, _) in conflicts {
+                        locks.lock_row(db, table, other)?;
+                        stmt_rows.put(other, None);
+                        counts.deleted += 1;
+                    }
+                }
+            }
+        }
+        stmt_rows.put(pk, Some(row));
+        counts.inserted += 1;
+        if generated && counts.first_generated_id.is_none() {
+            counts.first_generated_id = Some(pk);
+        }
+
+        if bulk && stmt_rows.rows.len() >= BULK_LOAD_BATCH {
+            *flushed = true;
+            write_batch(session, &stmt_rows)?;
+            // Written rows are found in the store from now on.
+            stmt_rows = StatementRows::new(def);
+        }
+    }
+
+    if *flushed {
+        write_batch(session, &stmt_rows)?;
+        if let Some(tx_id) = session.txn.tx_id {
+            store.finish_bulk_load(tx_id)?;
+        }
+        locks.keep_locks();
+    } else {
+        stage_row_changes(session, locks, db, table, stmt_rows.rows);
+    }
+    Ok(counts)
+}
+
+/// Build the row for line number `line` from its fields, then apply the SET clause.
+fn load_row_values(
+    session: &mut SessionState,
+    plan: &LoadPlan<'_>,
+    line: u64,
+    fields: Vec<Field>,
+) -> Result<Vec<Cell>, MiniError> {
+    let def = plan.def;
+    if fields.len() < plan.targets.len() {
+        session.warn(
+            ER_WARN_TOO_FEW_RECORDS,
+            format!("Row {line} doesn't contain data for all columns"),
+        );
+    } else if fields.len() > plan.targets.len() {
+        session.warn(
+            ER_WARN_TOO_MANY_RECORDS,
+            format!(
+                "Row {line} was truncated; it contained more data than there were input columns"
+            ),
+        );
+    }
+
+    // Columns nothing sets take their defaults; the others start from theirs as well, so a
+    // short line leaves them at the default.
+    let mut row = Row {
+        values: Vec::with_capacity(def.columns.len() + plan.vars),
+    };
+    for (idx, col) in def.columns.iter().enumerate() {
+        let set = plan
+            .targets
+            .iter()
+            .any(|t| matches!(t, LoadTarget::Column(i) if *i == idx))
+            || plan.assignments.iter().any(|(i, _)| *i == idx);
+        let initial = if set {
+            eval_column_default(session, col.default.as_ref(), &col.ty)?
+        } else {
+            column_default_value(session, def, col)?
+        };
+        row.values.push(initial);
+    }
+    row.values.resize(def.columns.len() + plan.vars, Cell::Null);
+
+    for (target, field) in plan.targets.iter().zip(fields) {
+        let cell = match field {
+            None => Cell::Null,
+            Some(bytes) => Cell::Text(String::from_utf8(bytes).map_err(|_| {
+                MiniError::Invalid(format!("Invalid utf8 character string at row {line}"))
+            })?),
+        };
+        match *target {
+            LoadTarget::Column(idx) => {
+                row.values[idx] = coerce_cell(cell, &def.columns[idx].ty)?;
+            }
+            LoadTarget::Var(i) => row.values[def.columns.len() + i] = cell,
+        }
+    }
+    for &(idx, value) in &plan.assignments {
+        let col = &def.columns[idx];
+        row.values[idx] = match value {
+            Some(value) => {
+                coerce_cell(eval_row_expr(session, value, &row, &plan.col_map)?, &col.ty)?
+            }
+            None => column_default_value(session, def, col)?,
+        };
+    }
+    row.values.truncate(def.columns.len());
+
+    let generated_key =
+        |name: &str| def.auto_increment && name.eq_ignore_ascii_case(&def.primary_key);
+    for (value, col) in row.values.iter().zip(&def.columns) {
+        if *value == Cell::Null && !col.nullable && !generated_key(&col.name) {
+            return Err(MiniError::Invalid(format!(
+                "column {} cannot be NULL",
+                col.name
+            )));
+        }
+    }
+    Ok(row.values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(input: &str, format: &FileFormat) -> Vec<Vec<Option<String>>> {
+        let mut reader = LineReader::new(input.as_bytes(), format.clone());
+        let mut out = Vec::new();
+        while let Some(fields) = reader.next_line().unwrap() {
+            out.push(
+                fields
+                    .into_iter()
+                    .map(|f| f.map(|b| String::from_utf8(b).unwrap()))
+                    .collect(),
+            );
+        }
+        out
+    }
+
+    fn csv() -> FileFormat {
+        FileFormat {
+            field_term: b",".to_vec(),
+            enclosed: Some(b'"'),
+            optionally: true,
+            ..FileFormat::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let s = |v: &str| Some(v.to_string());
+        // Tab-separated with escapes: \N is NULL, escaped terminators are data.
+        let rows = lines("1\ta\\tb\t\\N\n2\tx\\\ny\t\n", &FileFormat::default());
+        assert_eq!(
+            rows,
+            vec![
+                vec![s("1"), s("a\tb"), None],
+                vec![s("2"), s("x\ny"), s("")]
+            ]
+        );
+
+        // Enclosed fields keep separators and line breaks; quotes double or are escaped.
+        let rows = lines(
+            "1,\"a,b\",\"say \"\"hi\"\"\"\r\n2,\"two\nlines\",NULL\r\n3,\"\\\"q\\\"\",\"NULL\"",
+            &FileFormat {
+                line_term: b"\r\n".to_vec(),
+                ..csv()
+            },
+        );
+        assert_eq!(
+            rows,
+            vec![
+                vec![s("1"), s("a,b"), s("say \"hi\"")],
+                vec![s("2"), s("two\nlines"), None],
+                vec![s("3"), s("\"q\""), s("NULL")],
+            ]
+        );
+
+        // Text before the line prefix is skipped, and so are lines without it.
+        let rows = lines(
+            "xxx1,a\nnope\nskip xxx2,b\n",
+            &FileFormat {
+                line_start: b"xxx".to_vec(),
+                ..csv()
+            },
+        );
+        assert_eq!(rows, vec![vec![s("1"), s("a")], vec![s("2"), s("b")]]);
+
+        // Multi-byte terminators split across reads are still found.
+        let long = "v".repeat(70 * 1024);
+        let input = format!("{long}||1##{long}||2##");
+        let rows = lines(
+            &input,
+            &FileFormat {
+                field_term: b"||".to_vec(),
+                line_term: b"##".to_vec(),
+                ..FileFormat::default()
+            },
+        );
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], vec![s(&long), s("2")]);
+    }
+
+    #[test]
+    fn test_write_fields_round_trip() {
+        let format = csv();
+        let cells = [
+            Cell::Int(7),
+            Cell::Text("a,\"b\"\\c\nd".into()),
+            Cell::Null,
+            Cell::Float(1.5),
+        ];
+        let mut out = Vec::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                out.extend_from_slice(&format.field_term);
+            }
+            write_field(&mut out, cell, &format);
+        }
+        out.extend_from_slice(&format.line_term);
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "7,\"a,\\\"b\\\"\\\\c\nd\",\\N,1.5\n"
+        );
+        let rows = lines(std::str::from_utf8(&out).unwrap(), &format);
+        assert_eq!(
+            rows,
+            vec![vec![
+                Some("7".to_string()),
+                Some("a,\"b\"\\c\nd".to_string()),
+                None,
+                Some("1.5".to_string()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_split_into_outfile() -> Result<(), MiniError> {
+        let (query, outfile) = split_into_outfile(
+            "SELECT a, b INTO OUTFILE 'x.csv' FIELDS TERMINATED BY ',' OPTIONALLY ENCLOSED BY '\"' \
+             LINES TERMINATED BY '\\r\\n' FROM t WHERE a > 1",
+        )
+        .unwrap()?;
+        assert_eq!(
+            query.split_whitespace().collect::<Vec<_>>().join(" "),
+            "SELECT a, b FROM t WHERE a > 1"
+        );
+        assert_eq!(outfile.file, "x.csv");
+        assert!(!outfile.dump);
+        assert_eq!(
+            outfile.format,
+            FileFormat {
+                line_term: b"\r\n".to_vec(),
+                ..csv()
+            }
+        );
+
+        let (query, outfile) =
+            split_into_outfile("SELECT body FROM t WHERE id = 1 INTO DUMPFILE '/tmp/b'")
+                .unwrap()?;
+        assert_eq!(query.trim_end(), "SELECT body FROM t WHERE id = 1");
+        assert!(outfile.dump);
+        assert!(split_into_outfile("SELECT 'into outfile' FROM t").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_privilege_required() -> Result<(), MiniError> {
+        let dir = tempfile::tempdir()?;
+        let store = Store::open(dir.path().join("data"))?;
+        store.set_secure_file_priv(Some(dir.path().canonicalize()?));
+        std::fs::write(dir.path().join("t.tsv"), "1\n")?;
+        let mut user = UserRecord {
+            username: "root".into(),
+            host: "%".into(),
+            plugin: "".into(),
+            auth_stage2: None,
+            global_privs: Priv::ALL.bits(),
+            db_privs: Default::default(),
+        };
+        let mut session = SessionState::new(1);
+        execute("CREATE DATABASE d", &store, &mut session, &user)?;
+        execute("USE d", &store, &mut session, &user)?;
+        execute(
+            "CREATE TABLE t (id INT PRIMARY KEY)",
+            &store,
+            &mut session,
+            &user,
+        )?;
+
+        // Every other privilege, on every database, is not enough to touch files.
+        user.global_privs = (Priv::ALL - Priv::FILE).bits();
+        let load = execute(
+            "LOAD DATA INFILE 't.tsv' INTO TABLE t",
+            &store,
+            &mut session,
+            &user,
+        );
+        assert!(matches!(load, Err(MiniError::AccessDenied(_))), "{load:?}");
+        let dump = execute(
+            "SELECT id INTO OUTFILE 'out.tsv' FROM t",
+            &store,
+            &mut session,
+            &user,
+        );
+        assert!(matches!(dump, Err(MiniError::AccessDenied(_))), "{dump:?}");
+        assert!(!dir.path().join("out.tsv").exists());
+
+        user.global_privs = Priv::ALL.bits();
+        execute(
+            "LOAD DATA INFILE 't.tsv' INTO TABLE t",
+            &store,
+            &mut session,
+            &user,
+        )?;
+        execute(
+            "SELECT id INTO OUTFILE 'out.tsv' FROM t",
+            &store,
+            &mut session,
+            &user,
+        )?;
+        assert_eq!(std::fs::read_to_string(dir.path().join("out.tsv"))?, "1\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,3682 @@
+//! Query planning for `SELECT ... FROM`.
+//!
+//! A statement is first turned into a [`LogicalPlan`] tree (scan, filter, join, project,
+//! aggregate, distinct, sort, limit) that mirrors the SQL clauses. [`SelectPlan::optimize`]
+//! then rewrites the tree with a few rules: constant folding of predicates, predicate pushdown
+//! into scans and inner joins, removal of sorts the primary key scan order already satisfies,
+//! and pruning of columns no expression reads. Finally each node becomes a physical
+//! [`Operator`]; the root is pulled row by row as the client reads the result, and only
+//! blocking operators (sort, aggregate, the build side of a join) hold rows in memory.
+
+use super::{
+    build_and_expr, build_col_map, build_information_schema_table, build_natural_join_on_expr,
+    build_using_join_on_expr, compare_cell_for_order, eval_condition, eval_equi_join_pairs,
+    eval_row_expr, extract_equi_join_pairs, is_information_schema, is_system_schema, key_range,
+    object_name_to_parts, order_by_expr_to_base_col_idx, query_offset_limit, require_priv,
+    should_buffer_writes, table_schema, txn_get_row, txn_index_lookup, txn_scan_rows, ExecOutput,
+    KeyRange, LockedRows, RowStream, SessionState, TableLockGuard,
+};
+use crate::auth::Priv;
+use crate::error::MiniError;
+use crate::model::{Cell, ColumnDef, Histogram, Row, SqlType, TableAnalysis, TableDef, UserRecord};
+use crate::store::{RowScan, Store};
+use opensrv_mysql::{Column, ColumnFlags, ColumnType};
+use parking_lot::Mutex;
+use sqlparser::ast::{self, TableFactor};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod explain;
+
+pub(super) use explain::{analyze_without_tables, explain_without_tables, ExplainFormat};
+
+/// A node of the logical plan. Rows flow from the leaves (scans) up to the root.
+#[derive(Debug, Clone)]
+pub(super) enum LogicalPlan {
+    Scan(Scan),
+    /// No rows at all (a predicate folded to false); the input only supplies the schema.
+    Empty(Box<LogicalPlan>),
+    Filter {
+        input: Box<LogicalPlan>,
+        predicate: ast::Expr,
+    },
+    /// Output rows are the left row's columns followed by the right row's.
+    Join {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        kind: JoinKind,
+        on: Option<ast::Expr>,
+        strategy: JoinStrategy,
+    },
+    Project {
+        input: Box<LogicalPlan>,
+        columns: Vec<(String, ast::Expr)>,
+    },
+    Aggregate {
+        input: Box<LogicalPlan>,
+        group_by: Vec<ast::Expr>,
+        aggregates: Vec<AggCall>,
+        columns: Vec<(String, AggOutput)>,
+    },
+    Distinct(Box<LogicalPlan>),
+    Sort {
+        input: Box<LogicalPlan>,
+        keys: Vec<SortKey>,
+    },
+    Limit {
+        input: Box<LogicalPlan>,
+        offset: usize,
+        limit: Option<usize>,
+    },
+}
+
+/// A table read by the query.
+#[derive(Debug, Clone)]
+pub(super) struct Scan {
+    pub db: String,
+    pub table: String,
+    /// The table as the query names it (alias applied), limited to the columns the scan keeps.
+    pub def: TableDef,
+    /// Positions in the stored row of the columns in `def`.
+    pub columns: Vec<usize>,
+    pub source: ScanSource,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum ScanSource {
+    /// A base table, read through the statement's read view in primary key order.
+    Table,
+    /// An `information_schema` table, built when the query was planned.
+    InformationSchema(Vec<Row>),
+}
+
+/// How a base table scan finds its rows. Whatever the access path, rows come out in primary
+/// key order and the filters above the scan still apply.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Access {
+    /// Every row of the table.
+    FullScan,
+    /// Rows with primary keys within inclusive bounds (`None`: unbounded).
+    PkRange(Option<i64>, Option<i64>),
+    /// Rows whose first indexed column equals `value`, found through the secondary index.
+    IndexEq {
+        index: String,
+        /// Position of the column in the stored row.
+        column: usize,
+        value: Cell,
+    },
+    /// The inner side of an index lookup join: rows whose column equals the outer row's value
+    /// of `outer`, read through the primary key (`index` is `None`) or a secondary index.
+    Lookup {
+        index: Option<String>,
+        /// Position of the column in the stored row.
+        column: usize,
+        outer: Box<ast::Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum JoinKind {
+    Inner,
+    Left,
+    Right,
+}
+
+/// How a join finds the inner rows matching each outer row. The outer side is the left input,
+/// except for RIGHT joins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum JoinStrategy {
+    /// Buffer the inner side and compare each outer row with every buffered row.
+    NestedLoop,
+    /// Buffer the inner side in a hash table on its equi-join columns and probe it with each
+    /// outer row.
+    Hash,
+    /// Read the matching inner rows per outer row; the inner side is a base table scan with
+    /// `Access::Lookup`, possibly under filters.
+    IndexLookup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// An aggregate function call; `arg` is `None` for `COUNT(*)`.
+#[derive(Debug, Clone)]
+pub(super) struct AggCall {
+    pub func: AggFunc,
+    pub arg: Option<ast::Expr>,
+}
+
+/// An output column of an aggregation.
+#[derive(Debug, Clone)]
+pub(super) enum AggOutput {
+    /// An expression evaluated against the first row of the group.
+    Group(Box<ast::Expr>),
+    /// The result of `aggregates[i]`.
+    Aggregate(usize),
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct SortKey {
+    pub expr: SortExpr,
+    pub desc: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum SortExpr {
+    /// A column of the input's tables (sorting before projection).
+    Column(Box<ast::Expr>),
+    /// An output column by position (sorting after projection).
+    Output(usize),
+}
+
+/// The columns of a plan node's rows, for resolving column references.
+enum Schema<'a> {
+    Tables(Vec<&'a TableDef>),
+    Columns(Vec<&'a str>),
+}
+
+impl<'a> Schema<'a> {
+    fn col_map(&self) -> HashMap<String, usize> {
+        match self {
+            Schema::Tables(defs) => build_col_map(defs),
+            Schema::Columns(names) => names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (name.to_ascii_lowercase(), i))
+                .collect(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            Schema::Tables(defs) => defs.iter().map(|d| d.columns.len()).sum(),
+            Schema::Columns(names) => names.len(),
+        }
+    }
+
+    fn column_type(&self, idx: usize) -> Option<&SqlType> {
+        self.column(idx).map(|(_, c)| &c.ty)
+    }
+
+    /// The table column at `idx`, with its table.
+    fn column(&self, idx: usize) -> Option<(&'a TableDef, &'a ColumnDef)> {
+        match self {
+            Schema::Tables(defs) => defs
+                .iter()
+                .flat_map(|d| d.columns.iter().map(move |c| (*d, c)))
+                .nth(idx),
+            Schema::Columns(_) => None,
+        }
+    }
+}
+
+impl LogicalPlan {
+    fn schema(&self) -> Schema<'_> {
+        match self {
+            LogicalPlan::Scan(scan) => Schema::Tables(vec![&scan.def]),
+            LogicalPlan::Join { left, right, .. } => {
+                let (Schema::Tables(mut defs), Schema::Tables(right)) =
+                    (left.schema(), right.schema())
+                else {
+                    unreachable!("joins combine tables");
+                };
+                defs.extend(right);
+                Schema::Tables(defs)
+            }
+            LogicalPlan::Project { columns, .. } => {
+                Schema::Columns(columns.iter().map(|(n, _)| n.as_str()).collect())
+            }
+            LogicalPlan::Aggregate { columns, .. } => {
+                Schema::Columns(columns.iter().map(|(n, _)| n.as_str()).collect())
+            }
+            LogicalPlan::Empty(input)
+            | LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Distinct(input)
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.schema(),
+        }
+    }
+
+    /// Number of table scans under a FROM-clause node (scans, joins and their filters).
+    fn relation_count(&self) -> usize {
+        match self {
+            LogicalPlan::Scan(_) => 1,
+            LogicalPlan::Join { left, right, .. } => left.relation_count() + right.relation_count(),
+            LogicalPlan::Empty(input) | LogicalPlan::Filter { input, .. } => input.relation_count(),
+            _ => 0,
+        }
+    }
+
+    /// Whether this node only combines and filters tables (it has a table schema).
+    fn is_relational(&self) -> bool {
+        match self {
+            LogicalPlan::Scan(_) | LogicalPlan::Join { .. } => true,
+            LogicalPlan::Empty(input) | LogicalPlan::Filter { input, .. } => input.is_relational(),
+            _ => false,
+        }
+    }
+
+    fn inputs_mut(&mut self) -> Vec<&mut LogicalPlan> {
+        match self {
+            LogicalPlan::Scan(_) => Vec::new(),
+            LogicalPlan::Join { left, right, .. } => vec![left.as_mut(), right.as_mut()],
+            LogicalPlan::Empty(input)
+            | LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Distinct(input)
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => vec![input.as_mut()],
+        }
+    }
+
+    fn scans<'a>(&'a self, out: &mut Vec<&'a Scan>) {
+        match self {
+            LogicalPlan::Scan(scan) => out.push(scan),
+            LogicalPlan::Join { left, right, .. } => {
+                left.scans(out);
+                right.scans(out);
+            }
+            LogicalPlan::Empty(input)
+            | LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Distinct(input)
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.scans(out),
+        }
+    }
+
+    /// Every expression the node and its inputs evaluate.
+    fn expressions<'a>(&'a self, out: &mut Vec<&'a ast::Expr>) {
+        match self {
+            LogicalPlan::Filter { predicate, .. } => out.push(predicate),
+            LogicalPlan::Join { on: Some(on), .. } => out.push(on),
+            LogicalPlan::Project { columns, .. } => out.extend(columns.iter().map(|(_, e)| e)),
+            LogicalPlan::Aggregate {
+                group_by,
+                aggregates,
+                columns,
+                ..
+            } => {
+                out.extend(group_by);
+                out.extend(aggregates.iter().filter_map(|a| a.arg.as_ref()));
+                out.extend(columns.iter().filter_map(|(_, c)| match c {
+                    AggOutput::Group(e) => Some(e.as_ref()),
+                    AggOutput::Aggregate(_) => None,
+                }));
+            }
+            LogicalPlan::Sort { keys, .. } => {
+                out.extend(keys.iter().filter_map(|k| match &k.expr {
+                    SortExpr::Column(e) => Some(e.as_ref()),
+                    SortExpr::Output(_) => None,
+                }))
+            }
+            _ => {}
+        }
+        match self {
+            LogicalPlan::Scan(_) => {}
+            LogicalPlan::Join { left, right, .. } => {
+                left.expressions(out);
+                right.expressions(out);
+            }
+            LogicalPlan::Empty(input)
+            | LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Distinct(input)
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.expressions(out),
+        }
+    }
+}
+
+/// A planned `SELECT ... FROM` statement.
+#[derive(Debug)]
+pub(super) struct SelectPlan {
+    pub root: LogicalPlan,
+    /// Every table the query reads, as the query names them, for typing all-NULL columns.
+    tables: Vec<TableDef>,
+    /// Whether joins keep the FROM clause's order (`STRAIGHT_JOIN`).
+    fixed_order: bool,
+}
+
+impl SelectPlan {
+    /// Build the logical plan for a SELECT with a non-empty FROM clause, checking the user's
+    /// privileges on every table it reads.
+    pub fn build(
+        store: &Store,
+        session: &SessionState,
+        user: &UserRecord,
+        query: &ast::Query,
+        select: &ast::Select,
+    ) -> Result<Self, MiniError> {
+        // `SELECT STRAIGHT_JOIN` and `t1 STRAIGHT_JOIN t2` read the tables in FROM order.
+        let mut fixed_order = session
+            .straight_join_selects
+            .contains(&select.select_token.0.span.start);
+        let mut plan = plan_from(store, session, user, &select.from, &mut fixed_order)?;
+
+        let Schema::Tables(defs) = plan.schema() else {
+            unreachable!("FROM items are tables");
+        };
+        let tables: Vec<TableDef> = defs.into_iter().cloned().collect();
+        let table_refs: Vec<&TableDef> = tables.iter().collect();
+        let col_map = build_col_map(&table_refs);
+
+        if let Some(selection) = &select.selection {
+            plan = LogicalPlan::Filter {
+                input: Box::new(plan),
+                predicate: selection.clone(),
+            };
+        }
+
+        // Select list: expand wildcards and pick out aggregate calls.
+        enum Item {
+            Scalar(ast::Expr),
+            Aggregate(AggCall),
+        }
+        let mut items: Vec<(String, Item)> = Vec::new();
+        let column_expr = |def: &TableDef, col: &str| {
+            if tables.len() == 1 {
+                ast::Expr::Identifier(ast::Ident::new(col))
+            } else {
+                // Qualify columns of multi-table queries so a shared name (e.g. `id`) stays
+                // unambiguous.
+                ast::Expr::CompoundIdentifier(vec![
+                    ast::Ident::new(&def.name),
+                    ast::Ident::new(col),
+                ])
+            }
+        };
+        for item in &select.projection {
+            match item {
+                ast::SelectItem::Wildcard(_) => {
+                    for def in &tables {
+                        for c in &def.columns {
+                            items.push((c.name.clone(), Item::Scalar(column_expr(def, &c.name))));
+                        }
+                    }
+                }
+                ast::SelectItem::QualifiedWildcard(kind, _) => {
+                    let obj_name = match kind {
+                        ast::SelectItemQualifiedWildcardKind::ObjectName(obj_name) => obj_name,
+                        ast::SelectItemQualifiedWildcardKind::Expr(_) => {
+                            return Err(MiniError::NotSupported(
+                                "Wildcard on expression is not supported".into(),
+                            ));
+                        }
+                    };
+                    let (_db_opt, qualifier) = object_name_to_parts(obj_name)?;
+                    let Some(def) = tables
+                        .iter()
+                        .find(|d| d.name.eq_ignore_ascii_case(&qualifier))
+                    else {
+                        return Err(MiniError::NotFound(format!(
+                            "unknown table in wildcard: {qualifier}"
+                        )));
+                    };
+                    for c in &def.columns {
+                        items.push((
+                            c.name.clone(),
+                            Item::Scalar(ast::Expr::CompoundIdentifier(vec![
+                                ast::Ident::new(&def.name),
+                                ast::Ident::new(&c.name),
+                            ])),
+                        ));
+                    }
+                }
+                ast::SelectItem::UnnamedExpr(expr) => {
+                    let name = match expr {
+                        ast::Expr::Identifier(i) => i.value.clone(),
+                        // `t.col` and `db.t.col` are named after the column, as in MySQL.
+                        ast::Expr::CompoundIdentifier(ids) if !ids.is_empty() => {
+                            ids[ids.len() - 1].value.clone()
+                        }
+                        _ => format!("col_{}", items.len()),
+                    };
+                    let item = match aggregate_call(expr) {
+                        Some(call) => Item::Aggregate(call),
+                        None => Item::Scalar(expr.clone()),
+                    };
+                    items.push((name, item));
+                }
+                ast::SelectItem::ExprWithAlias { expr, alias } => {
+                    let item = match aggregate_call(expr) {
+                        Some(call) => Item::Aggregate(call),
+                        None => Item::Scalar(expr.clone()),
+                    };
+                    items.push((alias.value.clone(), item));
+                }
+            }
+        }
+
+        let group_by = match &select.group_by {
+            ast::GroupByExpr::Expressions(exprs, _) => exprs.clone(),
+            ast::GroupByExpr::All(_) => {
+                return Err(MiniError::NotSupported("GROUP BY ALL not supported".into()))
+            }
+        };
+        let grouped =
+            !group_by.is_empty() || items.iter().any(|(_, i)| matches!(i, Item::Aggregate(_)));
+
+        let mut sorted = false;
+        if grouped {
+            let mut aggregates = Vec::new();
+            let columns = items
+                .into_iter()
+                .map(|(name, item)| match item {
+                    Item::Scalar(expr) => (name, AggOutput::Group(Box::new(expr))),
+                    Item::Aggregate(call) => {
+                        aggregates.push(call);
+                        (name, AggOutput::Aggregate(aggregates.len() - 1))
+                    }
+                })
+                .collect();
+            plan = LogicalPlan::Aggregate {
+                input: Box::new(plan),
+                group_by,
+                aggregates,
+                columns,
+            };
+        } else {
+            // ORDER BY on the tables' own columns sorts before projection, so it can use
+            // columns the select list leaves out.
+            if let Some(keys) = input_sort_keys(query, &col_map)? {
+                plan = LogicalPlan::Sort {
+                    input: Box::new(plan),
+                    keys,
+                };
+                sorted = true;
+            }
+            let columns = items
+                .into_iter()
+                .map(|(name, item)| match item {
+                    Item::Scalar(expr) => (name, expr),
+                    Item::Aggregate(_) => unreachable!("ungrouped queries have no aggregates"),
+                })
+                .collect();
+            plan = LogicalPlan::Project {
+                input: Box::new(plan),
+                columns,
+            };
+        }
+
+        if let Some(having) = &select.having {
+            plan = LogicalPlan::Filter {
+                input: Box::new(plan),
+                predicate: having.clone(),
+            };
+        }
+        if select.distinct.is_some() {
+            plan = LogicalPlan::Distinct(Box::new(plan));
+        }
+        if !sorted {
+            if let Some(keys) = output_sort_keys(query, &plan)? {
+                plan = LogicalPlan::Sort {
+                    input: Box::new(plan),
+                    keys,
+                };
+            }
+        }
+        let (offset, limit) = query_offset_limit(query)?;
+        if offset > 0 || limit.is_some() {
+            plan = LogicalPlan::Limit {
+                input: Box::new(plan),
+                offset,
+                limit,
+            };
+        }
+
+        Ok(Self {
+            root: plan,
+            tables,
+            fixed_order,
+        })
+    }
+
+    /// Apply the rewrite rules. Folding runs first so pushdown sees simplified predicates,
+    /// access paths are chosen from the pushed-down filters, joins are ordered knowing what
+    /// each table's access path reads, and pruning runs last, once every expression has
+    /// reached its final place.
+    pub fn optimize(mut self, store: &Store, session: &SessionState) -> Self {
+        self.root = fold_constants(self.root, session);
+        self.root = push_down_predicates(self.root);
+        choose_access_paths(&mut self.root);
+        let model = CostModel { store };
+        self.root = reorder_joins(self.root, &model, self.fixed_order);
+        choose_join_strategies(&mut self.root, &model);
+        self.root = eliminate_sorts(self.root);
+        prune_columns(&mut self.root);
+        self
+    }
+
+    /// Plan the rows a multi-table UPDATE or DELETE works on: the FROM items joined and
+    /// filtered by `selection`, each output row holding every column of every table in FROM
+    /// order. Reading the tables needs the SELECT privilege, as for a query.
+    pub fn build_join(
+        store: &Store,
+        session: &SessionState,
+        user: &UserRecord,
+        from: &[ast::TableWithJoins],
+        selection: Option<&ast::Expr>,
+    ) -> Result<Self, MiniError> {
+        let mut fixed_order = false;
+        let mut plan = plan_from(store, session, user, from, &mut fixed_order)?;
+        let Schema::Tables(defs) = plan.schema() else {
+            unreachable!("FROM items are tables");
+        };
+        let tables: Vec<TableDef> = defs.into_iter().cloned().collect();
+        if let Some(selection) = selection {
+            plan = LogicalPlan::Filter {
+                input: Box::new(plan),
+                predicate: selection.clone(),
+            };
+        }
+        let columns = tables
+            .iter()
+            .flat_map(|def| {
+                def.columns.iter().map(|c| {
+                    let expr = ast::Expr::CompoundIdentifier(vec![
+                        ast::Ident::new(&def.name),
+                        ast::Ident::new(&c.name),
+                    ]);
+                    (c.name.clone(), expr)
+                })
+            })
+            .collect();
+        Ok(Self {
+            root: LogicalPlan::Project {
+                input: Box::new(plan),
+                columns,
+            },
+            tables,
+            fixed_order,
+        })
+    }
+
+    /// The table column behind each output column, when the select list reads one unchanged.
+    pub fn source_columns(&self) -> Vec<Option<(&TableDef, &ColumnDef)>> {
+        output_columns(&self.root)
+    }
+
+    /// Turn the plan into operators and return its result. When every output column's type
+    /// is known up front, rows are streamed as they are produced; otherwise the result is
+    /// collected first so types can be inferred from the values.
+    ///
+    /// Streams pin the statement's read view against purge, and an autocommit statement hands
+    /// them its tables' metadata locks, so neither purge nor DDL can cut a result short.
+    pub fn execute(
+        self,
+        store: &Store,
+        session: &mut SessionState,
+        locked_rows: Option<&LockedRows>,
+    ) -> Result<ExecOutput, MiniError> {
+        let names: Vec<String> = match self.root.schema() {
+            Schema::Columns(names) => names.into_iter().map(str::to_string).collect(),
+            Schema::Tables(_) => unreachable!("a select list ends the plan"),
+        };
+        let types = output_types(&self.root);
+
+        // An autocommit statement ends before its rows are sent; the stream keeps the locks.
+        let mut locks = TableLockGuard {
+            store: store.clone(),
+            owner: session.conn_id,
+            tables: Vec::new(),
+            global: false,
+        };
+        let mut scans = Vec::new();
+        self.root.scans(&mut scans);
+        if !should_buffer_writes(session) {
+            for scan in scans {
+                if !matches!(scan.source, ScanSource::Table) {
+                    continue;
+                }
+                let key = (scan.db.clone(), scan.table.clone());
+                if session.txn.metadata_locks.remove(&key) {
+                    locks.tables.push(key);
+                }
+            }
+        }
+
+        let mut ctx = ExecContext {
+            store,
+            session,
+            locked_rows,
+            eval: Arc::new(session.detached()),
+            pin: None,
+            profiles: None,
+            lookup_key: None,
+        };
+        let root = ctx.operator(self.root)?;
+        let pin = ctx.pin.take();
+        let rows = OperatorRows { root: Some(root) };
+
+        if types.iter().all(Option::is_some) {
+            let columns = names
+                .into_iter()
+                .zip(types)
+                .map(|(name, ty)| result_column(name, column_type(&ty.expect("checked above"))))
+                .collect();
+            return Ok(ExecOutput::ResultSet {
+                columns,
+                rows: RowStream::new(Guarded {
+                    rows,
+                    _guard: (pin, locks),
+                }),
+            });
+        }
+
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        let columns = names
+            .into_iter()
+            .zip(types)
+            .enumerate()
+            .map(|(idx, (name, ty))| {
+                let coltype = match ty {
+                    Some(ty) => column_type(&ty),
+                    None => infer_column_type(&rows, idx)
+                        .or_else(|| {
+                            self.tables
+                                .iter()
+                                .flat_map(|d| d.columns.iter())
+                                .find(|c| c.name.eq_ignore_ascii_case(&name))
+                                .map(|c| column_type(&c.ty))
+                        })
+                        .unwrap_or(ColumnType::MYSQL_TYPE_VAR_STRING),
+                };
+                result_column(name, coltype)
+            })
+            .collect();
+        Ok(ExecOutput::ResultSet {
+            columns,
+            rows: rows.into(),
+        })
+    }
+}
+
+/// Plan a FROM clause: comma-separated items are cross joined, and each item's JOINs chain
+/// onto it. `STRAIGHT_JOIN` sets `fixed_order`.
+fn plan_from(
+    store: &Store,
+    session: &SessionState,
+    user: &UserRecord,
+    from_items: &[ast::TableWithJoins],
+    fixed_order: &mut bool,
+) -> Result<LogicalPlan, MiniError> {
+    let mut from: Option<LogicalPlan> = None;
+    for table_with_joins in from_items {
+        let scan = plan_scan(store, session, user, &table_with_joins.relation)?;
+        from = Some(match from.take() {
+            None => scan,
+            Some(left) => LogicalPlan::Join {
+                left: Box::new(left),
+                right: Box::new(scan),
+                kind: JoinKind::Inner,
+                on: None,
+                strategy: JoinStrategy::NestedLoop,
+            },
+        });
+
+        for join in &table_with_joins.joins {
+            let right = plan_scan(store, session, user, &join.relation)?;
+            let (kind, constraint) = match &join.join_operator {
+                ast::JoinOperator::StraightJoin(c) => {
+                    *fixed_order = true;
+                    (JoinKind::Inner, c)
+                }
+                ast::JoinOperator::Join(c)
+                | ast::JoinOperator::Inner(c)
+                | ast::JoinOperator::CrossJoin(c) => (JoinKind::Inner, c),
+                ast::JoinOperator::Left(c) | ast::JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
+                ast::JoinOperator::Right(c) | ast::JoinOperator::RightOuter(c) => {
+                    (JoinKind::Right, c)
+                }
+                ast::JoinOperator::FullOuter(_) => {
+                    return Err(MiniError::NotSupported(
+                        "FULL OUTER joins are not supported".into(),
+                    ))
+                }
+                other => {
+                    return Err(MiniError::NotSupported(format!(
+                        "JOIN operator not supported: {other:?}"
+                    )))
+                }
+            };
+            let left = from.take().expect("a JOIN follows a table");
+            let on = {
+                let Schema::Tables(left_defs) = left.schema() else {
+                    unreachable!("FROM items are tables");
+                };
+                let LogicalPlan::Scan(right_scan) = &right else {
+                    unreachable!("JOINs read tables");
+                };
+                match constraint {
+                    ast::JoinConstraint::On(expr) => Some(expr.clone()),
+                    ast::JoinConstraint::Using(cols) => {
+                        Some(build_using_join_on_expr(&left_defs, &right_scan.def, cols)?)
+                    }
+                    ast::JoinConstraint::Natural => {
+                        build_natural_join_on_expr(&left_defs, &right_scan.def)?
+                    }
+                    ast::JoinConstraint::None => None,
+                }
+            };
+            from = Some(LogicalPlan::Join {
+                left: Box::new(left),
+                right: Box::new(right),
+                kind,
+                on,
+                strategy: JoinStrategy::NestedLoop,
+            });
+        }
+    }
+    from.ok_or_else(|| MiniError::Invalid("Unexpected empty FROM clause".into()))
+}
+
+fn plan_scan(
+    store: &Store,
+    session: &SessionState,
+    user: &UserRecord,
+    relation: &TableFactor,
+) -> Result<LogicalPlan, MiniError> {
+    let TableFactor::Table { name, alias, .. } = relation else {
+        return Err(MiniError::NotSupported(
+            "Only simple table joins supported".into(),
+        ));
+    };
+    let (db_opt, table) = object_name_to_parts(name)?;
+    let db = db_opt
+        .or_else(|| session.current_db.clone())
+        .ok_or_else(|| MiniError::Invalid("no database selected".into()))?;
+    let db = table_schema(session, db, &table);
+
+    let (mut def, source) = if is_information_schema(&db) {
+        require_priv(user, None, Priv::SELECT)?;
+        let (def, rows) = build_information_schema_table(store, session, &table)?;
+        (def, ScanSource::InformationSchema(rows))
+    } else if is_system_schema(&db) {
+        return Err(MiniError::NotSupported(format!(
+            "Reading system schema {db} is not supported"
+        )));
+    } else {
+        require_priv(user, Some(&db), Priv::SELECT)?;
+        (store.get_table(&db, &table)?, ScanSource::Table)
+    };
+    if let Some(alias) = alias {
+        def.name = alias.name.value.clone();
+    }
+    Ok(LogicalPlan::Scan(Scan {
+        db,
+        table,
+        columns: (0..def.columns.len()).collect(),
+        def,
+        source,
+        access: Access::FullScan,
+    }))
+}
+
+fn aggregate_call(expr: &ast::Expr) -> Option<AggCall> {
+    let ast::Expr::Function(f) = expr else {
+        return None;
+    };
+    let func = match f.name.to_string().to_ascii_lowercase().as_str() {
+        "count" => AggFunc::Count,
+        "sum" => AggFunc::Sum,
+        "avg" => AggFunc::Avg,
+        "min" => AggFunc::Min,
+        "max" => AggFunc::Max,
+        _ => return None,
+    };
+    let arg = match &f.args {
+        ast::FunctionArguments::List(l) if l.args.len() == 1 => match &l.args[0] {
+            ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => Some(e.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some(AggCall { func, arg })
+}
+
+fn order_by_exprs(query: &ast::Query) -> Result<&[ast::OrderByExpr], MiniError> {
+    match query.order_by.as_ref().map(|o| &o.kind) {
+        None => Ok(&[]),
+        Some(ast::OrderByKind::Expressions(exprs)) => Ok(exprs),
+        Some(ast::OrderByKind::All(_)) => {
+            Err(MiniError::NotSupported("Order By ALL not supported".into()))
+        }
+    }
+}
+
+/// ORDER BY keys when every one of them is a column of the FROM tables.
+fn input_sort_keys(
+    query: &ast::Query,
+    col_map: &HashMap<String, usize>,
+) -> Result<Option<Vec<SortKey>>, MiniError> {
+    let exprs = order_by_exprs(query)?;
+    if exprs.is_empty()
+        || exprs
+            .iter()
+            .any(|e| order_by_expr_to_base_col_idx(&e.expr, col_map).is_none())
+    {
+        return Ok(None);
+    }
+    Ok(Some(
+        exprs
+            .iter()
+            .map(|e| SortKey {
+                expr: SortExpr::Column(Box::new(e.expr.clone())),
+                desc: e.options.asc == Some(false),
+            })
+            .collect(),
+    ))
+}
+
+/// ORDER BY keys naming output columns, by alias or 1-based position.
+fn output_sort_keys(
+    query: &ast::Query,
+    plan: &LogicalPlan,
+) -> Result<Option<Vec<SortKey>>, MiniError> {
+    let exprs = order_by_exprs(query)?;
+    if exprs.is_empty() {
+        return Ok(None);
+    }
+    let Schema::Columns(aliases) = plan.schema() else {
+        unreachable!("sorting by output columns follows the select list");
+    };
+    let mut keys = Vec::new();
+    for e in exprs {
+        let idx = match &e.expr {
+            ast::Expr::Identifier(ident) => aliases
+                .iter()
+                .position(|a| a.eq_ignore_ascii_case(&ident.value))
+                .ok_or_else(|| {
+                    MiniError::NotSupported("Order By must match output column".into())
+                })?,
+            ast::Expr::Value(v) => match &v.value {
+                ast::Value::Number(n, _) => {
+                    let pos = n.parse::<usize>().map_err(|_| {
+                        MiniError::Invalid("Order By index must be an integer".into())
+                    })?;
+                    if !(1..=aliases.len()).contains(&pos) {
+                        return Err(MiniError::Invalid("Order By index OOB".into()));
+                    }
+                    pos - 1
+                }
+                _ => {
+                    return Err(MiniError::NotSupported(
+                        "Complex Order By not implemented".into(),
+                    ))
+                }
+            },
+            _ => {
+                return Err(MiniError::NotSupported(
+                    "Complex Order By not implemented".into(),
+                ))
+            }
+        };
+        keys.push(SortKey {
+            expr: SortExpr::Output(idx),
+            desc: e.options.asc == Some(false),
+        });
+    }
+    Ok(Some(keys))
+}
+
+/// Static types of the output columns: a column passed through from a table has that column's
+/// type; anything else (`None`) is inferred from the values.
+fn output_types(plan: &LogicalPlan) -> Vec<Option<SqlType>> {
+    output_columns(plan)
+        .into_iter()
+        .map(|col| col.map(|(_, c)| c.ty.clone()))
+        .collect()
+}
+
+/// The table column each output column reads unchanged, if any.
+fn output_columns(plan: &LogicalPlan) -> Vec<Option<(&TableDef, &ColumnDef)>> {
+    match plan {
+        LogicalPlan::Project { input, columns } => {
+            let schema = input.schema();
+            let col_map = schema.col_map();
+            columns
+                .iter()
+                .map(|(_, expr)| {
+                    order_by_expr_to_base_col_idx(expr, &col_map).and_then(|idx| schema.column(idx))
+                })
+                .collect()
+        }
+        LogicalPlan::Aggregate { columns, .. } => vec![None; columns.len()],
+        LogicalPlan::Empty(input)
+        | LogicalPlan::Filter { input, .. }
+        | LogicalPlan::Distinct(input)
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => output_columns(input),
+        LogicalPlan::Scan(_) | LogicalPlan::Join { .. } => {
+            unreachable!("a select list ends the plan")
+        }
+    }
+}
+
+pub(super) fn column_type(ty: &SqlType) -> ColumnType {
+    match ty {
+        SqlType::Int => ColumnType::MYSQL_TYPE_LONGLONG,
+        SqlType::Float => ColumnType::MYSQL_TYPE_DOUBLE,
+        SqlType::Text | SqlType::Date | SqlType::DateTime => ColumnType::MYSQL_TYPE_VAR_STRING,
+    }
+}
+
+/// The type of the first non-NULL value in a result column.
+pub(super) fn infer_column_type(rows: &[Vec<Cell>], idx: usize) -> Option<ColumnType> {
+    rows.iter()
+        .filter_map(|row| row.get(idx))
+        .find_map(|cell| match cell {
+            Cell::Null => None,
+            Cell::Int(_) => Some(ColumnType::MYSQL_TYPE_LONGLONG),
+            Cell::Float(_) => Some(ColumnType::MYSQL_TYPE_DOUBLE),
+            Cell::Text(_) | Cell::Date(_) | Cell::DateTime(_) => {
+                Some(ColumnType::MYSQL_TYPE_VAR_STRING)
+            }
+        })
+}
+
+pub(super) fn result_column(name: String, coltype: ColumnType) -> Column {
+    Column {
+        table: "".into(),
+        column: name,
+        coltype,
+        colflags: ColumnFlags::empty(),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Rewrite rules
+// ---------------------------------------------------------------------------------------------
+
+/// Column references in an expression, as `(qualifier, column)` in lower case. Returns false
+/// for expressions the planner cannot see into, whose references are unknown.
+fn column_refs(expr: &ast::Expr, out: &mut Vec<(Option<String>, String)>) -> bool {
+    match expr {
+        ast::Expr::Identifier(ident) => {
+            out.push((None, ident.value.to_ascii_lowercase()));
+            true
+        }
+        ast::Expr::CompoundIdentifier(ids) => match ids.as_slice() {
+            [.., table, col] => {
+                out.push((
+                    Some(table.value.to_ascii_lowercase()),
+                    col.value.to_ascii_lowercase(),
+                ));
+                true
+            }
+            [col] => {
+                out.push((None, col.value.to_ascii_lowercase()));
+                true
+            }
+            [] => true,
+        },
+        ast::Expr::Value(_) => true,
+        ast::Expr::Nested(e)
+        | ast::Expr::UnaryOp { expr: e, .. }
+        | ast::Expr::IsNull(e)
+        | ast::Expr::IsNotNull(e) => column_refs(e, out),
+        ast::Expr::BinaryOp { left, right, .. } => {
+            column_refs(left, out) && column_refs(right, out)
+        }
+        ast::Expr::InList { expr, list, .. } => {
+            column_refs(expr, out) && list.iter().all(|e| column_refs(e, out))
+        }
+        ast::Expr::Between {
+            expr, low, high, ..
+        } => column_refs(expr, out) && column_refs(low, out) && column_refs(high, out),
+        ast::Expr::Like { expr, pattern, .. } | ast::Expr::ILike { expr, pattern, .. } => {
+            column_refs(expr, out) && column_refs(pattern, out)
+        }
+        ast::Expr::Function(f) => match &f.args {
+            ast::FunctionArguments::None => true,
+            ast::FunctionArguments::List(l) => l.args.iter().all(|arg| match arg {
+                ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => column_refs(e, out),
+                ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard) => true,
+                _ => false,
+            }),
+            ast::FunctionArguments::Subquery(_) => false,
+        },
+        _ => false,
+    }
+}
+
+fn split_conjuncts(expr: ast::Expr, out: &mut Vec<ast::Expr>) {
+    match expr {
+        ast::Expr::BinaryOp {
+            left,
+            op: ast::BinaryOperator::And,
+            right,
+        } => {
+            split_conjuncts(*left, out);
+            split_conjuncts(*right, out);
+        }
+        ast::Expr::Nested(inner)
+            if matches!(
+                inner.as_ref(),
+                ast::Expr::BinaryOp {
+                    op: ast::BinaryOperator::And,
+                    ..
+                }
+            ) =>
+        {
+            split_conjuncts(*inner, out)
+        }
+        other => out.push(other),
+    }
+}
+
+fn join_conjuncts(conjuncts: Vec<ast::Expr>) -> Option<ast::Expr> {
+    conjuncts.into_iter().reduce(build_and_expr)
+}
+
+/// A predicate after folding, in a context where a row passes only if the predicate is TRUE.
+enum Folded {
+    True,
+    /// FALSE or NULL: no row passes.
+    NotTrue,
+    Expr(Box<ast::Expr>),
+}
+
+/// Fold the parts of a predicate that do not depend on the row. Only parts whose value
+/// decides the whole are folded: an AND with a part that is not TRUE, or an OR with a TRUE
+/// part. Parts that fail to evaluate are left for execution to report.
+fn fold_predicate(expr: ast::Expr, session: &SessionState) -> Folded {
+    let mut refs = Vec::new();
+    if column_refs(&expr, &mut refs) && refs.is_empty() {
+        let empty = Row { values: Vec::new() };
+        match eval_condition(session, &expr, &empty, &HashMap::new()) {
+            Ok(true) => return Folded::True,
+            Ok(false) => return Folded::NotTrue,
+            Err(_) => return Folded::Expr(Box::new(expr)),
+        }
+    }
+    match expr {
+        ast::Expr::Nested(inner) => fold_predicate(*inner, session),
+        ast::Expr::BinaryOp {
+            left,
+            op: ast::BinaryOperator::And,
+            right,
+        } => match (
+            fold_predicate(*left, session),
+            fold_predicate(*right, session),
+        ) {
+            (Folded::NotTrue, _) | (_, Folded::NotTrue) => Folded::NotTrue,
+            (Folded::True, other) | (other, Folded::True) => other,
+            (Folded::Expr(l), Folded::Expr(r)) => Folded::Expr(Box::new(build_and_expr(*l, *r))),
+        },
+        ast::Expr::BinaryOp {
+            left,
+            op: ast::BinaryOperator::Or,
+            right,
+        } => match (
+            fold_predicate(*left, session),
+            fold_predicate(*right, session),
+        ) {
+            (Folded::True, _) | (_, Folded::True) => Folded::True,
+            (Folded::NotTrue, other) | (other, Folded::NotTrue) => other,
+            (Folded::Expr(l), Folded::Expr(r)) => Folded::Expr(Box::new(ast::Expr::BinaryOp {
+                left: l,
+                op: ast::BinaryOperator::Or,
+                right: r,
+            })),
+        },
+        other => Folded::Expr(Box::new(other)),
+    }
+}
+
+/// Constant folding: filters whose predicate folds to TRUE are removed, and those that fold
+/// to FALSE or NULL make their input produce no rows.
+fn fold_constants(plan: LogicalPlan, session: &SessionState) -> LogicalPlan {
+    let mut plan = match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            let input = Box::new(fold_constants(*input, session));
+            return match fold_predicate(predicate, session) {
+                Folded::True => *input,
+                Folded::NotTrue => LogicalPlan::Empty(input),
+                Folded::Expr(predicate) => LogicalPlan::Filter {
+                    input,
+                    predicate: *predicate,
+                },
+            };
+        }
+        other => other,
+    };
+    for input in plan.inputs_mut() {
+        let taken = std::mem::replace(input, LogicalPlan::Empty(Box::new(empty_scan())));
+        *input = fold_constants(taken, session);
+    }
+    plan
+}
+
+/// A placeholder node for taking a subtree out of the plan while it is rewritten.
+fn empty_scan() -> LogicalPlan {
+    LogicalPlan::Scan(Scan {
+        db: String::new(),
+        table: String::new(),
+        def: TableDef {
+            db: String::new(),
+            name: String::new(),
+            columns: Vec::new(),
+            indexes: Vec::new(),
+            primary_key: String::new(),
+            auto_increment: false,
+        },
+        columns: Vec::new(),
+        source: ScanSource::InformationSchema(Vec::new()),
+        access: Access::FullScan,
+    })
+}
+
+/// Predicate pushdown: each AND-ed part of a filter over tables moves down to the lowest node
+/// that has every table it reads, into the scan of a single table or the ON condition of an
+/// inner join. Parts never move into the NULL-supplying side of an outer join.
+fn push_down_predicates(plan: LogicalPlan) -> LogicalPlan {
+    let mut plan = match plan {
+        LogicalPlan::Filter { input, predicate } if input.is_relational() => {
+            let mut input = push_down_predicates(*input);
+            let mut conjuncts = Vec::new();
+            split_conjuncts(predicate, &mut conjuncts);
+            let mut kept = Vec::new();
+            for conjunct in conjuncts {
+                match predicate_relations(&conjunct, &input) {
+                    Some(relations) if !relations.is_empty() => {
+                        input = place_predicate(input, conjunct, &relations, 0);
+                    }
+                    _ => kept.push(conjunct),
+                }
+            }
+            return match join_conjuncts(kept) {
+                Some(predicate) => LogicalPlan::Filter {
+                    input: Box::new(input),
+                    predicate,
+                },
+                None => input,
+            };
+        }
+        other => other,
+    };
+    for input in plan.inputs_mut() {
+        let taken = std::mem::replace(input, LogicalPlan::Empty(Box::new(empty_scan())));
+        *input = push_down_predicates(taken);
+    }
+    plan
+}
+
+/// The tables (by position in the FROM clause) a predicate reads, or None if some column
+/// reference does not resolve to exactly one table column.
+fn predicate_relations(predicate: &ast::Expr, input: &LogicalPlan) -> Option<Vec<usize>> {
+    let mut exprs = Vec::new();
+    if !column_ref_exprs(predicate, &mut exprs) {
+        return None;
+    }
+    let Schema::Tables(defs) = input.schema() else {
+        return None;
+    };
+    let col_map = build_col_map(&defs);
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for def in &defs {
+        starts.push(offset);
+        offset += def.columns.len();
+    }
+    let mut relations = Vec::new();
+    for expr in exprs {
+        let idx = order_by_expr_to_base_col_idx(expr, &col_map)?;
+        let rel = starts.iter().rposition(|&s| s <= idx)?;
+        if !relations.contains(&rel) {
+            relations.push(rel);
+        }
+    }
+    Some(relations)
+}
+
+/// The identifier expressions in a predicate; false for expressions the planner cannot see
+/// into.
+fn column_ref_exprs<'a>(expr: &'a ast::Expr, out: &mut Vec<&'a ast::Expr>) -> bool {
+    match expr {
+        ast::Expr::Identifier(_) | ast::Expr::CompoundIdentifier(_) => {
+            out.push(expr);
+            true
+        }
+        ast::Expr::Value(_) => true,
+        ast::Expr::Nested(e)
+        | ast::Expr::UnaryOp { expr: e, .. }
+        | ast::Expr::IsNull(e)
+        | ast::Expr::IsNotNull(e) => column_ref_exprs(e, out),
+        ast::Expr::BinaryOp { left, right, .. } => {
+            column_ref_exprs(left, out) && column_ref_exprs(right, out)
+        }
+        ast::Expr::InList { expr, list, .. } => {
+            column_ref_exprs(expr, out) && list.iter().all(|e| column_ref_exprs(e, out))
+        }
+        ast::Expr::Between {
+            expr, low, high, ..
+        } => {
+            column_ref_exprs(expr, out) && column_ref_exprs(low, out) && column_ref_exprs(high, out)
+        }
+        ast::Expr::Like { expr, pattern, .. } | ast::Expr::ILike { expr, pattern, .. } => {
+            column_ref_exprs(expr, out) && column_ref_exprs(pattern, out)
+        }
+        ast::Expr::Function(f) => matches!(f.args, ast::FunctionArguments::None),
+        _ => false,
+    }
+}
+
+/// Attach `predicate`, which reads the tables `relations` (positions counted from `first`,
+/// the position of the node's first table), as low in `plan` as it can go.
+fn place_predicate(
+    plan: LogicalPlan,
+    predicate: ast::Expr,
+    relations: &[usize],
+    first: usize,
+) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Filter {
+            input,
+            predicate: existing,
+        } => LogicalPlan::Filter {
+            input: Box::new(place_predicate(*input, predicate, relations, first)),
+            predicate: existing,
+        },
+        LogicalPlan::Join {
+            left,
+            right,
+            kind,
+            on,
+            strategy,
+        } => {
+            let split = first + left.relation_count();
+            let in_left = relations.iter().all(|&r| r < split);
+            let in_right = relations.iter().all(|&r| r >= split);
+            if in_left && kind != JoinKind::Right {
+                LogicalPlan::Join {
+                    left: Box::new(place_predicate(*left, predicate, relations, first)),
+                    right,
+                    kind,
+                    on,
+                    strategy,
+                }
+            } else if in_right && kind != JoinKind::Left {
+                LogicalPlan::Join {
+                    left,
+                    right: Box::new(place_predicate(*right, predicate, relations, split)),
+                    kind,
+                    on,
+                    strategy,
+                }
+            } else if kind == JoinKind::Inner {
+                LogicalPlan::Join {
+                    left,
+                    right,
+                    kind,
+                    on: Some(match on {
+                        Some(on) => build_and_expr(on, predicate),
+                        None => predicate,
+                    }),
+                    strategy,
+                }
+            } else {
+                LogicalPlan::Filter {
+                    input: Box::new(LogicalPlan::Join {
+                        left,
+                        right,
+                        kind,
+                        on,
+                        strategy,
+                    }),
+                    predicate,
+                }
+            }
+        }
+        other => LogicalPlan::Filter {
+            input: Box::new(other),
+            predicate,
+        },
+    }
+}
+
+/// Access path selection: a base table scan under filters reads only the primary key range or
+/// index entries those filters confine it to (see `key_range`).
+fn choose_access_paths(plan: &mut LogicalPlan) {
+    let mut predicates = Vec::new();
+    let mut node = &mut *plan;
+    while let LogicalPlan::Filter { input, predicate } = node {
+        predicates.push(predicate.clone());
+        node = input;
+    }
+    if let LogicalPlan::Scan(scan) = node {
+        if let (ScanSource::Table, Some(predicate)) = (&scan.source, join_conjuncts(predicates)) {
+            scan.access = scan_access(&scan.def, &predicate);
+        }
+        return;
+    }
+    for input in node.inputs_mut() {
+        choose_access_paths(input);
+    }
+}
+
+fn scan_access(def: &TableDef, predicate: &ast::Expr) -> Access {
+    match key_range(Some(predicate), def) {
+        KeyRange::Pk(None, None) => Access::FullScan,
+        KeyRange::Pk(lo, hi) => Access::PkRange(lo, hi),
+        KeyRange::IndexEq(index, value) => {
+            let column = def
+                .indexes
+                .iter()
+                .find(|i| i.name == index)
+                .and_then(|i| i.columns.first())
+                .and_then(|col| {
+                    def.columns
+                        .iter()
+                        .position(|c| c.name.eq_ignore_ascii_case(col))
+                });
+            // The index stores values of the column's type; a value of another type may still
+            // compare equal to some of them, so only a matching type can use the index.
+            match column {
+                Some(column) if cell_has_type(&value, &def.columns[column].ty) => Access::IndexEq {
+                    index,
+                    column,
+                    value,
+                },
+                _ => Access::FullScan,
+            }
+        }
+    }
+}
+
+fn cell_has_type(cell: &Cell, ty: &SqlType) -> bool {
+    matches!(
+        (cell, ty),
+        (Cell::Int(_), SqlType::Int)
+            | (Cell::Float(_), SqlType::Float)
+            | (Cell::Text(_), SqlType::Text)
+            | (Cell::Date(_), SqlType::Date)
+            | (Cell::DateTime(_), SqlType::DateTime)
+    )
+}
+
+/// The scan under a chain of filters, if it reads through a key rather than the whole table.
+pub(super) fn keyed_scan(plan: &LogicalPlan) -> Option<&Scan> {
+    match plan {
+        LogicalPlan::Filter { input, .. } => keyed_scan(input),
+        LogicalPlan::Scan(scan) if scan.access != Access::FullScan => Some(scan),
+        _ => None,
+    }
+}
+
+/// The scan under a chain of filters, if it is the inner side of an index lookup join.
+fn lookup_scan(plan: &LogicalPlan) -> Option<&Scan> {
+    match plan {
+        LogicalPlan::Filter { input, .. } => lookup_scan(input),
+        LogicalPlan::Scan(scan) if matches!(scan.access, Access::Lookup { .. }) => Some(scan),
+        _ => None,
+    }
+}
+
+/// Selectivity assumed for a condition the statistics say nothing about, as MySQL assumes for
+/// an equality without an index or histogram.
+const DEFAULT_SELECTIVITY: f64 = 0.1;
+
+/// Inner join groups of up to this many inputs are ordered by trying every left-deep order;
+/// larger ones are ordered greedily.
+const EXHAUSTIVE_JOIN_SEARCH: usize = 10;
+
+/// MySQL's limit on the tables of one join, which keeps a group's inputs within a `u64` mask.
+const MAX_JOIN_INPUTS: usize = 61;
+
+/// What the cost model expects a plan node to produce (`rows`), and the work of producing them
+/// (`cost`), counted in rows read, compared or hashed.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Estimate {
+    pub rows: f64,
+    pub cost: f64,
+}
+
+/// Estimates from the statistics `Store` keeps: row counts maintained by every commit, and the
+/// index cardinalities and histograms recorded by `ANALYZE TABLE`.
+pub(super) struct CostModel<'a> {
+    pub store: &'a Store,
+}
+
+/// A column the inner side of a join can read its matching rows by.
+#[derive(Debug, Clone)]
+struct LookupKey {
+    /// `None` for the primary key.
+    index: Option<String>,
+    /// Position of the column in the stored row.
+    column: usize,
+    /// Rows expected per lookup, before the inner side's filters.
+    rows: f64,
+}
+
+/// An equality between a column of a join's outer side and one of its inner side.
+#[derive(Debug, Clone)]
+struct JoinKey {
+    /// Whether both columns have the same type, so equal values hash alike and the inner
+    /// column's index holds values of the outer column's type.
+    same_type: bool,
+    lookup: Option<LookupKey>,
+}
+
+impl CostModel<'_> {
+    fn table_rows(&self, scan: &Scan) -> f64 {
+        match &scan.source {
+            ScanSource::InformationSchema(rows) => rows.len() as f64,
+            ScanSource::Table => self
+                .store
+                .table_stats(&scan.db, &scan.table)
+                .map_or(0.0, |stats| stats.rows as f64),
+        }
+    }
+
+    /// The analysis of the scan's table, if `ANALYZE TABLE` ran on it.
+    fn analysis(&self, scan: &Scan) -> Option<TableAnalysis> {
+        match scan.source {
+            ScanSource::Table => self.store.table_analysis(&scan.db, &scan.table).ok()?,
+            ScanSource::InformationSchema(_) => None,
+        }
+    }
+
+    /// Distinct values of a column (by position in `scan.def`), when the statistics tell:
+    /// primary keys are unique, and `ANALYZE TABLE` counts them for the first column of each
+    /// index and for columns with a histogram.
+    fn distinct_values(&self, scan: &Scan, column: usize) -> Option<f64> {
+        let name = &scan.def.columns.get(column)?.name;
+        if !matches!(scan.source, ScanSource::Table) {
+            return None;
+        }
+        if name.eq_ignore_ascii_case(&scan.def.primary_key) {
+            return Some(self.table_rows(scan));
+        }
+        let analysis = self.analysis(scan)?;
+        let indexed = scan
+            .def
+            .indexes
+            .iter()
+            .filter(|i| {
+                i.columns
+                    .first()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(name))
+            })
+            .find_map(|i| analysis.cardinality.get(&i.name));
+        if let Some(&distinct) = indexed {
+            return Some(distinct as f64);
+        }
+        let (_, histogram) = analysis
+            .histograms
+            .iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(name))?;
+        Some(histogram.buckets.iter().map(|b| b.distinct).sum::<u64>() as f64)
+    }
+
+    /// Rows of the scan's table whose column (by position in `scan.def`) equals `value`, or
+    /// a value not known yet when it is `None`.
+    fn equal_rows(&self, scan: &Scan, column: usize, value: Option<&Cell>) -> f64 {
+        let rows = self.table_rows(scan);
+        let Some(name) = scan.def.columns.get(column).map(|c| &c.name) else {
+            return rows * DEFAULT_SELECTIVITY;
+        };
+        if name.eq_ignore_ascii_case(&scan.def.primary_key) {
+            return rows.min(1.0);
+        }
+        if let Some(value) = value {
+            let histogram = self.analysis(scan).and_then(|mut a| {
+                let key = a
+                    .histograms
+                    .keys()
+                    .find(|c| c.eq_ignore_ascii_case(name))?
+                    .clone();
+                a.histograms.remove(&key)
+            });
+            if let Some(histogram) = histogram {
+                return rows * histogram_fraction(&histogram, value);
+            }
+        }
+        match self.distinct_values(scan, column) {
+            Some(distinct) => rows / distinct.max(1.0),
+            None => rows * DEFAULT_SELECTIVITY,
+        }
+    }
+
+    /// Rows a scan reads through its access path; for `Access::Lookup`, per lookup.
+    pub(super) fn scan(&self, scan: &Scan) -> Estimate {
+        let rows = self.table_rows(scan);
+        let local = |stored: usize| scan.columns.iter().position(|&c| c == stored);
+        let rows = match &scan.access {
+            Access::FullScan => rows,
+            Access::PkRange(Some(lo), Some(hi)) => (*hi as f64 - *lo as f64 + 1.0).clamp(0.0, rows),
+            // MySQL's guess for a range open at one end.
+            Access::PkRange(..) => rows / 3.0,
+            Access::IndexEq { column, value, .. } => local(*column)
+                .map_or(rows * DEFAULT_SELECTIVITY, |c| {
+                    self.equal_rows(scan, c, Some(value))
+                }),
+            Access::Lookup { column, .. } => local(*column)
+                .map_or(rows * DEFAULT_SELECTIVITY, |c| {
+                    self.equal_rows(scan, c, None)
+                }),
+        };
+        Estimate { rows, cost: rows }
+    }
+
+    /// Estimate a node of the FROM clause.
+    fn estimate(&self, plan: &LogicalPlan) -> Estimate {
+        match plan {
+            LogicalPlan::Scan(scan) => self.scan(scan),
+            LogicalPlan::Empty(_) => Estimate {
+                rows: 0.0,
+                cost: 0.0,
+            },
+            LogicalPlan::Filter { input, .. } => {
+                let input_estimate = self.estimate(input);
+                // The access path already reads only the rows the filters over it allow.
+                let kept = if keyed_scan(input).is_some() {
+                    1.0
+                } else {
+                    DEFAULT_SELECTIVITY
+                };
+                Estimate {
+                    rows: input_estimate.rows * kept,
+                    cost: input_estimate.cost,
+                }
+            }
+            LogicalPlan::Join {
+                left,
+                right,
+                kind,
+                on,
+                ..
+            } => {
+                let (l, r) = (self.estimate(left), self.estimate(right));
+                let mut conditions = Vec::new();
+                if let Some(on) = on {
+                    split_conjuncts(on.clone(), &mut conditions);
+                }
+                let selectivity: f64 = conditions
+                    .iter()
+                    .map(|c| self.selectivity(c, plan))
+                    .product();
+                let joined = l.rows * r.rows * selectivity;
+                let rows = match kind {
+                    JoinKind::Inner => joined,
+                    JoinKind::Left => joined.max(l.rows),
+                    JoinKind::Right => joined.max(r.rows),
+                };
+                Estimate {
+                    rows,
+                    cost: l.cost + r.cost + rows,
+                }
+            }
+            LogicalPlan::Project { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Distinct(input)
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => self.estimate(input),
+        }
+    }
+
+    /// Fraction of the rows of `input` (a node over tables) a condition keeps. An equality
+    /// between columns keeps one over the larger of their counts of distinct values.
+    fn selectivity(&self, condition: &ast::Expr, input: &LogicalPlan) -> f64 {
+        let Some((a, _, b, _)) = equi_columns(condition, &input.schema().col_map()) else {
+            return DEFAULT_SELECTIVITY;
+        };
+        let mut scans = Vec::new();
+        input.scans(&mut scans);
+        let distinct = |idx: usize| {
+            let (scan, column) = column_scan(&scans, idx)?;
+            self.distinct_values(scan, column)
+        };
+        match (distinct(a), distinct(b)) {
+            (None, None) => DEFAULT_SELECTIVITY,
+            (x, y) => 1.0 / x.unwrap_or(0.0).max(y.unwrap_or(0.0)).max(1.0),
+        }
+    }
+
+    /// How the inner side of a join could read the rows whose column `column` (by position in
+    /// its schema) equals a value from the outer row: through the primary key or an index of a
+    /// base table it would otherwise read in full.
+    fn lookup_key(&self, inner: &LogicalPlan, column: usize) -> Option<LookupKey> {
+        let mut node = inner;
+        while let LogicalPlan::Filter { input, .. } = node {
+            node = input;
+        }
+        let LogicalPlan::Scan(scan) = node else {
+            return None;
+        };
+        if !matches!(scan.source, ScanSource::Table) || scan.access != Access::FullScan {
+            return None;
+        }
+        let name = &scan.def.columns.get(column)?.name;
+        let index = if name.eq_ignore_ascii_case(&scan.def.primary_key) {
+            None
+        } else {
+            let index = scan.def.indexes.iter().find(|i| {
+                i.columns
+                    .first()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(name))
+            })?;
+            Some(index.name.clone())
+        };
+        Some(LookupKey {
+            index,
+            column: scan.columns[column],
+            rows: self.equal_rows(scan, column, None),
+        })
+    }
+}
+
+/// Fraction of all rows a histogram puts at `value`.
+fn histogram_fraction(histogram: &Histogram, value: &Cell) -> f64 {
+    let mut below = 0.0;
+    for bucket in &histogram.buckets {
+        let frequency = bucket.cumulative_frequency - below;
+        below = bucket.cumulative_frequency;
+        if compare_cell_for_order(value, &bucket.lower).is_lt() {
+            break;
+        }
+        if compare_cell_for_order(value, &bucket.upper).is_le() {
+            return frequency / bucket.distinct.max(1) as f64;
+        }
+    }
+    0.0
+}
+
+/// The scan holding column `idx` of a row of `scans`' tables, and its position in `scan.def`.
+fn column_scan<'s>(scans: &[&'s Scan], idx: usize) -> Option<(&'s Scan, usize)> {
+    let mut offset = 0;
+    for scan in scans {
+        let width = scan.def.columns.len();
+        if idx < offset + width {
+            return Some((scan, idx - offset));
+        }
+        offset += width;
+    }
+    None
+}
+
+/// The columns (by position in `col_map`'s rows) and expressions of `a = b` between two
+/// columns.
+fn equi_columns<'e>(
+    condition: &'e ast::Expr,
+    col_map: &HashMap<String, usize>,
+) -> Option<(usize, &'e ast::Expr, usize, &'e ast::Expr)> {
+    let ast::Expr::BinaryOp {
+        left,
+        op: ast::BinaryOperator::Eq,
+        right,
+    } = condition
+    else {
+        return None;
+    };
+    let l = order_by_expr_to_base_col_idx(left, col_map)?;
+    let r = order_by_expr_to_base_col_idx(right, col_map)?;
+    (l != r).then_some((l, left.as_ref(), r, right.as_ref()))
+}
+
+/// The cheapest way to join `outer_rows` rows with an inner side estimated as `inner`, given
+/// the equalities between them, with its cost. For an index lookup join, also which key it
+/// looks up.
+fn cheapest_join(
+    outer_rows: f64,
+    inner: Estimate,
+    keys: &[JoinKey],
+) -> (JoinStrategy, Option<usize>, f64) {
+    let mut best = (
+        JoinStrategy::NestedLoop,
+        None,
+        inner.cost + outer_rows * inner.rows,
+    );
+    if keys.iter().any(|k| k.same_type) {
+        let cost = inner.cost + inner.rows + outer_rows;
+        if cost < best.2 {
+            best = (JoinStrategy::Hash, None, cost);
+        }
+    }
+    for (i, key) in keys.iter().enumerate() {
+        let (true, Some(lookup)) = (key.same_type, &key.lookup) else {
+            continue;
+        };
+        let cost = outer_rows * (1.0 + lookup.rows);
+        if cost < best.2 {
+            best = (JoinStrategy::IndexLookup, Some(i), cost);
+        }
+    }
+    best
+}
+
+/// A condition of an inner join group, with the group's inputs it reads as a mask.
+struct JoinCondition {
+    expr: ast::Expr,
+    inputs: u64,
+    selectivity: f64,
+    /// For an equality between columns, their positions in the group's rows.
+    equi: Option<(usize, usize)>,
+}
+
+/// Join ordering: the inputs of each group of inner joins (scans with their filters, and outer
+/// joins, whose own order is fixed) are joined in the order the cost model finds cheapest.
+/// The group's ON conditions are spread over the new joins, each at the first join that has
+/// every table it reads, and conditions on a single input move into that input.
+fn reorder_joins(plan: LogicalPlan, model: &CostModel<'_>, fixed_order: bool) -> LogicalPlan {
+    let mut plan = match plan {
+        LogicalPlan::Join {
+            kind: JoinKind::Inner,
+            ..
+        } if inner_join_inputs(&plan) <= MAX_JOIN_INPUTS => {
+            return reorder_inner_joins(plan, model, fixed_order)
+        }
+        other => other,
+    };
+    for input in plan.inputs_mut() {
+        let taken = std::mem::replace(input, LogicalPlan::Empty(Box::new(empty_scan())));
+        *input = reorder_joins(taken, model, fixed_order);
+    }
+    plan
+}
+
+fn inner_join_inputs(plan: &LogicalPlan) -> usize {
+    match plan {
+        LogicalPlan::Join {
+            left,
+            right,
+            kind: JoinKind::Inner,
+            ..
+        } => inner_join_inputs(left) + inner_join_inputs(right),
+        _ => 1,
+    }
+}
+
+fn inner_join_conditions(plan: &LogicalPlan, out: &mut Vec<ast::Expr>) {
+    if let LogicalPlan::Join {
+        left,
+        right,
+        kind: JoinKind::Inner,
+        on,
+        ..
+    } = plan
+    {
+        inner_join_conditions(left, out);
+        inner_join_conditions(right, out);
+        if let Some(on) = on {
+            split_conjuncts(on.clone(), out);
+        }
+    }
+}
+
+fn take_inner_join_inputs(plan: LogicalPlan, out: &mut Vec<LogicalPlan>) {
+    match plan {
+        LogicalPlan::Join {
+            left,
+            right,
+            kind: JoinKind::Inner,
+            ..
+        } => {
+            take_inner_join_inputs(*left, out);
+            take_inner_join_inputs(*right, out);
+        }
+        other => out.push(other),
+    }
+}
+
+fn reorder_inner_joins(
+    group: LogicalPlan,
+    model: &CostModel<'_>,
+    fixed_order: bool,
+) -> LogicalPlan {
+    let mut exprs = Vec::new();
+    inner_join_conditions(&group, &mut exprs);
+    let schema = group.schema();
+    let col_map = schema.col_map();
+    let types: Vec<Option<SqlType>> = (0..schema.width())
+        .map(|i| schema.column_type(i).cloned())
+        .collect();
+    // Which table a qualified name means depends on the order when two tables share a name.
+    let fixed_order = fixed_order || {
+        let Schema::Tables(defs) = &schema else {
+            unreachable!("joins combine tables");
+        };
+        let names: HashSet<String> = defs.iter().map(|d| d.name.to_ascii_lowercase()).collect();
+        names.len() < defs.len()
+    };
+    let conditions: Vec<_> = exprs
+        .into_iter()
+        .map(|expr| {
+            let tables = predicate_relations(&expr, &group);
+            let selectivity = model.selectivity(&expr, &group);
+            let equi = equi_columns(&expr, &col_map).map(|(a, _, b, _)| (a, b));
+            (expr, tables, selectivity, equi)
+        })
+        .collect();
+    drop(schema);
+
+    let mut inputs = Vec::new();
+    take_inner_join_inputs(group, &mut inputs);
+    let n = inputs.len();
+    let all = (1u64 << n) - 1;
+    // Where each input's tables and columns start in the group's rows.
+    let (mut table_starts, mut column_starts) = (Vec::new(), Vec::new());
+    let (mut tables, mut columns) = (0, 0);
+    for input in &inputs {
+        table_starts.push(tables);
+        column_starts.push(columns);
+        tables += input.relation_count();
+        columns += input.schema().width();
+    }
+    let input_of =
+        |starts: &[usize], pos: usize| starts.iter().rposition(|&s| s <= pos).unwrap_or(0);
+
+    let mut group_conditions = Vec::new();
+    for (expr, tables, selectivity, equi) in conditions {
+        let mask = match &tables {
+            Some(tables) => tables
+                .iter()
+                .fold(0, |m, &t| m | 1 << input_of(&table_starts, t)),
+            None => all,
+        };
+        if let (Some(tables), 1) = (&tables, mask.count_ones()) {
+            let i = mask.trailing_zeros() as usize;
+            let taken = std::mem::replace(&mut inputs[i], empty_scan());
+            inputs[i] = place_predicate(taken, expr, tables, table_starts[i]);
+            choose_access_paths(&mut inputs[i]);
+            continue;
+        }
+        group_conditions.push(JoinCondition {
+            expr,
+            inputs: mask,
+            selectivity,
+            equi,
+        });
+    }
+
+    let order: Vec<usize> = if fixed_order || n < 2 {
+        (0..n).collect()
+    } else {
+        let estimates: Vec<Estimate> = inputs.iter().map(|i| model.estimate(i)).collect();
+        let mut lookups: HashMap<usize, Option<LookupKey>> = HashMap::new();
+        for &(a, b) in group_conditions.iter().filter_map(|c| c.equi.as_ref()) {
+            for col in [a, b] {
+                let i = input_of(&column_starts, col);
+                lookups
+                    .entry(col)
+                    .or_insert_with(|| model.lookup_key(&inputs[i], col - column_starts[i]));
+            }
+        }
+        let rows_of = |mask: u64| {
+            let mut rows: f64 = (0..n)
+                .filter(|i| mask & 1 << i != 0)
+                .map(|i| estimates[i].rows)
+                .product();
+            for c in &group_conditions {
+                if c.inputs & !mask == 0 && c.inputs.count_ones() > 1 {
+                    rows *= c.selectivity;
+                }
+            }
+            rows
+        };
+        // Cost of joining input `next` to the rows of the inputs in `prefix`.
+        let step = |prefix: u64, next: usize| {
+            let keys: Vec<JoinKey> = group_conditions
+                .iter()
+                .filter_map(|c| c.equi)
+                .filter_map(|(a, b)| {
+                    let (ia, ib) = (input_of(&column_starts, a), input_of(&column_starts, b));
+                    let (outer, inner) = if ia == next && prefix & 1 << ib != 0 {
+                        (b, a)
+                    } else if ib == next && prefix & 1 << ia != 0 {
+                        (a, b)
+                    } else {
+                        return None;
+                    };
+                    Some(JoinKey {
+                        same_type: types[outer].is_some() && types[outer] == types[inner],
+                        lookup: lookups.get(&inner).cloned().flatten(),
+                    })
+                })
+                .collect();
+            let (_, _, cost) = cheapest_join(rows_of(prefix), estimates[next], &keys);
+            cost + rows_of(prefix | 1 << next)
+        };
+        let first_costs: Vec<f64> = estimates.iter().map(|e| e.cost).collect();
+        if n <= EXHAUSTIVE_JOIN_SEARCH {
+            exhaustive_join_order(n, &first_costs, step)
+        } else {
+            greedy_join_order(n, &first_costs, step)
+        }
+    };
+
+    let mut inputs: Vec<Option<LogicalPlan>> = inputs.into_iter().map(Some).collect();
+    let mut placed = vec![false; group_conditions.len()];
+    let mut mask = 0u64;
+    let mut plan: Option<LogicalPlan> = None;
+    for &i in &order {
+        let input = inputs[i].take().expect("each input is joined once");
+        let input = reorder_joins(input, model, fixed_order);
+        mask |= 1 << i;
+        plan = Some(match plan {
+            None => input,
+            Some(left) => {
+                let mut on = Vec::new();
+                for (c, placed) in group_conditions.iter().zip(placed.iter_mut()) {
+                    if !*placed && c.inputs & !mask == 0 {
+                        *placed = true;
+                        on.push(c.expr.clone());
+                    }
+                }
+                LogicalPlan::Join {
+                    left: Box::new(left),
+                    right: Box::new(input),
+                    kind: JoinKind::Inner,
+                    on: join_conjuncts(on),
+                    strategy: JoinStrategy::NestedLoop,
+                }
+            }
+        });
+    }
+    plan.expect("a join has inputs")
+}
+
+/// Whether a plan costing `cost` with join order `order` beats `best`. Costs within rounding of
+/// each other tie, and ties go to the order closest to the FROM clause.
+fn better_join_order(cost: f64, order: &[usize], best: Option<&(f64, Vec<usize>)>) -> bool {
+    match best {
+        None => true,
+        Some((best_cost, best_order)) => {
+            if (cost - best_cost).abs() <= 1e-9 * best_cost.abs().max(1.0) {
+                order < best_order.as_slice()
+            } else {
+                cost < *best_cost
+            }
+        }
+    }
+}
+
+/// The cheapest left-deep order of `n` inputs, found by dynamic programming over the sets of
+/// inputs joined so far.
+fn exhaustive_join_order(
+    n: usize,
+    first_costs: &[f64],
+    step: impl Fn(u64, usize) -> f64,
+) -> Vec<usize> {
+    let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; 1 << n];
+    for (i, &cost) in first_costs.iter().enumerate() {
+        best[1 << i] = Some((cost, vec![i]));
+    }
+    for mask in 1..best.len() {
+        let Some((cost, order)) = best[mask].clone() else {
+            continue;
+        };
+        for next in (0..n).filter(|i| mask & 1 << i == 0) {
+            let cost = cost + step(mask as u64, next);
+            let mut order = order.clone();
+            order.push(next);
+            let slot = &mut best[mask | 1 << next];
+            if better_join_order(cost, &order, slot.as_ref()) {
+                *slot = Some((cost, order));
+            }
+        }
+    }
+    best.pop()
+        .flatten()
+        .map_or_else(|| (0..n).collect(), |(_, order)| order)
+}
+
+/// A left-deep order of `n` inputs that starts with the cheapest input and then always joins
+/// the input that is cheapest to add.
+fn greedy_join_order(
+    n: usize,
+    first_costs: &[f64],
+    step: impl Fn(u64, usize) -> f64,
+) -> Vec<usize> {
+    let cheapest = |costs: Vec<(usize, f64)>| {
+        let mut best: Option<(f64, Vec<usize>)> = None;
+        for (i, cost) in costs {
+            if better_join_order(cost, &[i], best.as_ref()) {
+                best = Some((cost, vec![i]));
+            }
+        }
+        best.map(|(_, order)| order[0])
+    };
+    let mut order = Vec::with_capacity(n);
+    let mut mask = 0u64;
+    let mut next = cheapest(first_costs.iter().copied().enumerate().collect());
+    while let Some(i) = next {
+        order.push(i);
+        mask |= 1 << i;
+        next = cheapest(
+            (0..n)
+                .filter(|i| mask & 1 << i == 0)
+                .map(|i| (i, step(mask, i)))
+                .collect(),
+        );
+    }
+    order
+}
+
+/// Join algorithm selection: each join uses the cheapest of a nested loop over its buffered
+/// inner side, a hash join on its equi-join columns, and, when the inner side is a base table
+/// read in full, a lookup per outer row through its primary key or an index.
+fn choose_join_strategies(plan: &mut LogicalPlan, model: &CostModel<'_>) {
+    for input in plan.inputs_mut() {
+        choose_join_strategies(input, model);
+    }
+    let (strategy, lookup) = {
+        let LogicalPlan::Join {
+            left,
+            right,
+            kind,
+            on: Some(on),
+            ..
+        } = &*plan
+        else {
+            return;
+        };
+        let col_map = plan.schema().col_map();
+        let left_width = left.schema().width();
+        let (outer, inner) = match kind {
+            JoinKind::Inner | JoinKind::Left => (left, right),
+            JoinKind::Right => (right, left),
+        };
+        let (outer_schema, inner_schema) = (outer.schema(), inner.schema());
+        let mut conditions = Vec::new();
+        split_conjuncts(on.clone(), &mut conditions);
+        let mut keys = Vec::new();
+        let mut outer_exprs = Vec::new();
+        for condition in &conditions {
+            let Some((a, expr_a, b, expr_b)) = equi_columns(condition, &col_map) else {
+                continue;
+            };
+            // Each side as (position in its input's rows, expression).
+            let (l, r) = if a < left_width && b >= left_width {
+                ((a, expr_a), (b - left_width, expr_b))
+            } else if b < left_width && a >= left_width {
+                ((b, expr_b), (a - left_width, expr_a))
+            } else {
+                continue;
+            };
+            let ((o, outer_expr), (i, _)) = match kind {
+                JoinKind::Inner | JoinKind::Left => (l, r),
+                JoinKind::Right => (r, l),
+            };
+            let outer_type = outer_schema.column_type(o);
+            keys.push(JoinKey {
+                same_type: outer_type.is_some() && outer_type == inner_schema.column_type(i),
+                lookup: model.lookup_key(inner, i),
+            });
+            outer_exprs.push(outer_expr.clone());
+        }
+        let outer_rows = model.estimate(outer).rows;
+        let (strategy, key, _) = cheapest_join(outer_rows, model.estimate(inner), &keys);
+        let lookup = key.and_then(|k| Some((keys[k].lookup.clone()?, outer_exprs[k].clone())));
+        (strategy, lookup)
+    };
+    let LogicalPlan::Join {
+        left,
+        right,
+        kind,
+        strategy: chosen,
+        ..
+    } = plan
+    else {
+        unreachable!("matched above");
+    };
+    *chosen = strategy;
+    if let Some((key, outer)) = lookup {
+        let inner = if *kind == JoinKind::Right {
+            left
+        } else {
+            right
+        };
+        let mut node = inner.as_mut();
+        while let LogicalPlan::Filter { input, .. } = node {
+            node = input;
+        }
+        if let LogicalPlan::Scan(scan) = node {
+            scan.access = Access::Lookup {
+                index: key.index,
+                column: key.column,
+                outer: Box::new(outer),
+            };
+        }
+    }
+}
+
+/// Sort elimination: a base table scan already returns rows in ascending primary key order.
+fn eliminate_sorts(plan: LogicalPlan) -> LogicalPlan {
+    let mut plan = match plan {
+        LogicalPlan::Sort { input, keys } if sorted_by_scan(&input, &keys) => *input,
+        other => other,
+    };
+    for input in plan.inputs_mut() {
+        let taken = std::mem::replace(input, LogicalPlan::Empty(Box::new(empty_scan())));
+        *input = eliminate_sorts(taken);
+    }
+    plan
+}
+
+fn sorted_by_scan(input: &LogicalPlan, keys: &[SortKey]) -> bool {
+    let [SortKey {
+        expr: SortExpr::Column(expr),
+        desc: false,
+    }] = keys
+    else {
+        return false;
+    };
+    let mut node = input;
+    while let LogicalPlan::Filter { input, .. } = node {
+        node = input;
+    }
+    let LogicalPlan::Scan(scan) = node else {
+        return false;
+    };
+    matches!(scan.source, ScanSource::Table)
+        && order_by_expr_to_base_col_idx(expr, &build_col_map(&[&scan.def])).is_some_and(|idx| {
+            scan.def.columns[idx]
+                .name
+                .eq_ignore_ascii_case(&scan.def.primary_key)
+        })
+}
+
+/// Projection pruning: scans keep only the columns some expression of the plan may read.
+/// References are matched by name, so an ambiguous or unresolvable one keeps every column it
+/// could mean and the same errors surface at execution.
+fn prune_columns(plan: &mut LogicalPlan) {
+    let mut exprs = Vec::new();
+    plan.expressions(&mut exprs);
+    let mut refs = Vec::new();
+    if !exprs.iter().all(|e| column_refs(e, &mut refs)) {
+        return;
+    }
+    let mut scans = Vec::new();
+    plan.scans(&mut scans);
+    let table_names: HashSet<String> = scans
+        .iter()
+        .map(|s| s.def.name.to_ascii_lowercase())
+        .collect();
+    prune_scans(plan, &refs, &table_names);
+}
+
+fn prune_scans(
+    plan: &mut LogicalPlan,
+    refs: &[(Option<String>, String)],
+    table_names: &HashSet<String>,
+) {
+    if let LogicalPlan::Scan(scan) = plan {
+        let name = scan.def.name.to_ascii_lowercase();
+        let used = |col: &str| {
+            let col = col.to_ascii_lowercase();
+            refs.iter().any(|(qualifier, c)| {
+                *c == col
+                    && match qualifier {
+                        None => true,
+                        Some(q) => *q == name || !table_names.contains(q),
+                    }
+            })
+        };
+        let (columns, defs): (Vec<usize>, Vec<_>) = scan
+            .columns
+            .iter()
+            .copied()
+            .zip(scan.def.columns.drain(..))
+            .filter(|(_, c)| used(&c.name))
+            .unzip();
+        scan.columns = columns;
+        scan.def.columns = defs;
+        return;
+    }
+    for input in plan.inputs_mut() {
+        prune_scans(input, refs, table_names);
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Physical operators
+// ---------------------------------------------------------------------------------------------
+
+/// A physical operator. The consumer pulls rows from the root one at a time and each operator
+/// pulls from its inputs in turn (the Volcano model); blocking operators (sort, aggregate, the
+/// build side of a join) read their whole input on the first pull.
+pub(super) trait Operator: Send {
+    fn next(&mut self) -> Result<Option<Row>, MiniError>;
+}
+
+type BoxedOperator = Box<dyn Operator>;
+
+fn drain(op: &mut BoxedOperator) -> Result<Vec<Row>, MiniError> {
+    let mut rows = Vec::new();
+    while let Some(row) = op.next()? {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+struct ExecContext<'a> {
+    store: &'a Store,
+    session: &'a SessionState,
+    locked_rows: Option<&'a LockedRows>,
+    /// What operators evaluate expressions with, possibly after the statement has returned.
+    eval: Arc<SessionState>,
+    pin: Option<crate::store::ViewPin>,
+    /// For EXPLAIN ANALYZE: one profile per plan node, in the order the nodes were built
+    /// (pre-order, the outer side of a join first).
+    profiles: Option<Vec<Arc<Mutex<OpProfile>>>>,
+    /// While the inner side of an index lookup join is built: where its lookup scan finds the
+    /// key of the current outer row.
+    lookup_key: Option<LookupSlot>,
+}
+
+impl ExecContext<'_> {
+    fn operator(&mut self, plan: LogicalPlan) -> Result<BoxedOperator, MiniError> {
+        let Some(profiles) = &mut self.profiles else {
+            return self.build_operator(plan);
+        };
+        let profile = Arc::new(Mutex::new(OpProfile::default()));
+        profiles.push(profile.clone());
+        Ok(Box::new(ProfiledOp {
+            input: self.build_operator(plan)?,
+            profile,
+            started: false,
+        }))
+    }
+
+    fn build_operator(&mut self, plan: LogicalPlan) -> Result<BoxedOperator, MiniError> {
+        let col_map = |plan: &LogicalPlan| plan.schema().col_map();
+        Ok(match plan {
+            LogicalPlan::Scan(scan) => self.scan(scan)?,
+            LogicalPlan::Empty(_) => Box::new(EmptyOp),
+            LogicalPlan::Filter { input, predicate } => Box::new(FilterOp {
+                col_map: col_map(&input),
+                input: self.operator(*input)?,
+                predicate,
+                session: self.eval.clone(),
+            }),
+            LogicalPlan::Join {
+                left,
+                right,
+                kind,
+                on,
+                strategy,
+            } => {
+                let left_width = left.schema().width();
+                let right_width = right.schema().width();
+                let (combined, types) = {
+                    let (Schema::Tables(mut defs), Schema::Tables(right_defs)) =
+                        (left.schema(), right.schema())
+                    else {
+                        unreachable!("joins combine tables");
+                    };
+                    defs.extend(right_defs);
+                    let schema = Schema::Tables(defs);
+                    let types: Vec<Option<SqlType>> = (0..left_width + right_width)
+                        .map(|i| schema.column_type(i).cloned())
+                        .collect();
+                    (schema.col_map(), types)
+                };
+                let equi = on
+                    .as_ref()
+                    .and_then(|e| extract_equi_join_pairs(e, &combined, left_width));
+                let outer_is_left = kind != JoinKind::Right;
+                // Equalities between same-typed columns, as (outer column, inner column).
+                let mut hash_keys = Vec::new();
+                if strategy == JoinStrategy::Hash {
+                    let mut conditions = Vec::new();
+                    if let Some(on) = &on {
+                        split_conjuncts(on.clone(), &mut conditions);
+                    }
+                    for condition in &conditions {
+                        let Some((a, _, b, _)) = equi_columns(condition, &combined) else {
+                            continue;
+                        };
+                        let (l, r) = (a.min(b), a.max(b));
+                        if l >= left_width || r < left_width || types[l].is_none() {
+                            continue;
+                        }
+                        if types[l] == types[r] {
+                            hash_keys.push(if outer_is_left {
+                                (l, r - left_width)
+                            } else {
+                                (r - left_width, l)
+                            });
+                        }
+                    }
+                }
+                // The outer side streams; the other side is built into memory, or looked up
+                // per outer row.
+                let (outer, inner, null_width) = if outer_is_left {
+                    (*left, *right, right_width)
+                } else {
+                    (*right, *left, left_width)
+                };
+                let lookup_column = match strategy {
+                    JoinStrategy::IndexLookup => {
+                        let outer_expr = lookup_scan(&inner).and_then(|scan| match &scan.access {
+                            Access::Lookup { outer, .. } => Some(outer.as_ref()),
+                            _ => None,
+                        });
+                        let column = outer_expr.and_then(|e| {
+                            order_by_expr_to_base_col_idx(e, &outer.schema().col_map())
+                        });
+                        Some(column.ok_or_else(|| {
+                            MiniError::Invalid("Index lookup join without a lookup key".into())
+                        })?)
+                    }
+                    JoinStrategy::NestedLoop | JoinStrategy::Hash => None,
+                };
+                let outer = self.operator(outer)?;
+                let inner = match lookup_column {
+                    Some(column) => {
+                        let key = Arc::new(Mutex::new(None));
+                        self.lookup_key = Some(key.clone());
+                        let input = self.operator(inner)?;
+                        self.lookup_key = None;
+                        JoinBuild::Lookup { input, key, column }
+                    }
+                    None => JoinBuild::Pending(self.operator(inner)?),
+                };
+                Box::new(JoinOp {
+                    outer,
+                    inner,
+                    hash_keys,
+                    outer_is_left,
+                    preserve_outer: kind != JoinKind::Inner,
+                    on,
+                    equi,
+                    col_map: combined,
+                    session: self.eval.clone(),
+                    nulls: vec![Cell::Null; null_width],
+                    pending: VecDeque::new(),
+                })
+            }
+            LogicalPlan::Project { input, columns } => Box::new(ProjectOp {
+                col_map: col_map(&input),
+                input: self.operator(*input)?,
+                exprs: columns.into_iter().map(|(_, e)| e).collect(),
+                session: self.eval.clone(),
+            }),
+            LogicalPlan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+                columns,
+            } => Box::new(AggregateOp {
+                col_map: col_map(&input),
+                input: Some(self.operator(*input)?),
+                group_by,
+                aggregates,
+                outputs: columns.into_iter().map(|(_, c)| c).collect(),
+                session: self.eval.clone(),
+                results: Vec::new().into_iter(),
+            }),
+            LogicalPlan::Distinct(input) => Box::new(DistinctOp {
+                input: self.operator(*input)?,
+                seen: HashSet::new(),
+            }),
+            LogicalPlan::Sort { input, keys } => {
+                let map = col_map(&input);
+                let keys = keys
+                    .into_iter()
+                    .map(|k| {
+                        let idx = match &k.expr {
+                            SortExpr::Column(e) => order_by_expr_to_base_col_idx(e, &map)
+                                .ok_or_else(|| {
+                                    MiniError::Invalid(format!("Column not found: {e}"))
+                                })?,
+                            SortExpr::Output(idx) => *idx,
+                        };
+                        Ok((idx, k.desc))
+                    })
+                    .collect::<Result<_, MiniError>>()?;
+                Box::new(SortOp {
+                    input: Some(self.operator(*input)?),
+                    keys,
+                    sorted: Vec::new().into_iter(),
+                })
+            }
+            LogicalPlan::Limit {
+                input,
+                offset,
+                limit,
+            } => Box::new(LimitOp {
+                input: self.operator(*input)?,
+                offset,
+                remaining: limit,
+            }),
+        })
+    }
+
+    fn scan(&mut self, scan: Scan) -> Result<BoxedOperator, MiniError> {
+        let whole_prefix = scan.columns.iter().copied().eq(0..scan.columns.len());
+        if let Access::Lookup { index, column, .. } = &scan.access {
+            let key = self
+                .lookup_key
+                .take()
+                .ok_or_else(|| MiniError::Invalid("Index lookup outside a join".into()))?;
+            let locked = self
+                .locked_rows
+                .and_then(|tables| tables.get(&(scan.db.clone(), scan.table.clone())))
+                .cloned();
+            if self.pin.is_none() {
+                let view = self
+                    .session
+                    .txn
+                    .read_view
+                    .as_ref()
+                    .ok_or_else(|| MiniError::Invalid("No active transaction view".into()))?;
+                self.pin = Some(self.store.txn_manager.pin_view(view));
+            }
+            return Ok(Box::new(LookupOp {
+                store: self.store.clone(),
+                session: Arc::new(self.session.clone()),
+                def: self.store.get_table(&scan.db, &scan.table)?,
+                primary: index.is_none(),
+                column: *column,
+                key,
+                locked,
+                columns: scan.columns,
+                whole_prefix,
+                rows: None,
+            }));
+        }
+        let rows: Box<dyn Iterator<Item = Result<Row, MiniError>> + Send> = match scan.source {
+            ScanSource::InformationSchema(rows) => Box::new(rows.into_iter().map(Ok)),
+            ScanSource::Table => {
+                let locked = self
+                    .locked_rows
+                    .and_then(|tables| tables.get(&(scan.db.clone(), scan.table.clone())));
+                let (lo, hi) = match &scan.access {
+                    Access::PkRange(lo, hi) => (*lo, *hi),
+                    Access::FullScan | Access::IndexEq { .. } | Access::Lookup { .. } => {
+                        (None, None)
+                    }
+                };
+                match (locked, &scan.access) {
+                    (Some(rows), _) => Box::new(rows.clone().into_iter().map(Ok)),
+                    (None, Access::IndexEq { column, value, .. }) => {
+                        Box::new(self.index_scan(&scan, *column, value)?.into_iter().map(Ok))
+                    }
+                    (None, _) => Box::new(
+                        self.table_scan(
+                            &scan.db,
+                            &scan.table,
+                            lo.unwrap_or(i64::MIN),
+                            hi.unwrap_or(i64::MAX),
+                        )?
+                        .map(|r| r.map(|(_, row)| row)),
+                    ),
+                }
+            }
+        };
+        Ok(Box::new(ScanOp {
+            rows,
+            columns: scan.columns,
+            whole_prefix,
+        }))
+    }
+
+    /// A lazy read of the rows of a table with primary keys in `lo..=hi`, through the
+    /// statement's read view. Rows read with FOR UPDATE/FOR SHARE show their latest committed
+    /// version, and the session's own pending writes override both.
+    fn table_scan(
+        &mut self,
+        db: &str,
+        table: &str,
+        lo: i64,
+        hi: i64,
+    ) -> Result<OverlaidScan, MiniError> {
+        let session = self.session;
+        let view = session
+            .txn
+            .read_view
+            .clone()
+            .ok_or_else(|| MiniError::Invalid("No active transaction view".into()))?;
+        let mut overrides: BTreeMap<i64, Option<Row>> = BTreeMap::new();
+        let current = self.store.txn_manager.current_view(session.txn.tx_id);
+        let in_range = |pk: i64| (lo..=hi).contains(&pk);
+        for key in &session.txn.locked_reads {
+            if key.db == db && key.table == table && in_range(key.pk) {
+                overrides.insert(
+                    key.pk,
+                    self.store.get_row_mvcc(db, table, key.pk, &current)?,
+                );
+            }
+        }
+        for (key, row) in &session.txn.pending_rows {
+            if key.db == db && key.table == table && in_range(key.pk) {
+                overrides.insert(key.pk, row.clone());
+            }
+        }
+        if self.pin.is_none() {
+            self.pin = Some(self.store.txn_manager.pin_view(&view));
+        }
+        Ok(OverlaidScan {
+            base: self
+                .store
+                .scan_rows_range_iter(db, table, view, lo, hi)
+                .peekable(),
+            overrides: overrides.into_iter().peekable(),
+        })
+    }
+
+    /// The rows of a table whose column equals `value`, looked up through its index. Index
+    /// lookups are selective, so the matching rows are read up front, in primary key order.
+    fn index_scan(
+        &mut self,
+        scan: &Scan,
+        column: usize,
+        value: &Cell,
+    ) -> Result<Vec<Row>, MiniError> {
+        let def = self.store.get_table(&scan.db, &scan.table)?;
+        let Some(pks) = txn_index_lookup(self.store, self.session, &def, column, value)? else {
+            return self
+                .table_scan(&scan.db, &scan.table, i64::MIN, i64::MAX)?
+                .map(|r| r.map(|(_, row)| row))
+                .collect();
+        };
+        let mut rows = Vec::with_capacity(pks.len());
+        for pk in pks {
+            rows.extend(txn_get_row(
+                self.store,
+                self.session,
+                &scan.db,
+                &scan.table,
+                pk,
+            )?);
+        }
+        Ok(rows)
+    }
+}
+
+/// What an operator did while a query ran, for EXPLAIN ANALYZE. Times include the time spent
+/// in its inputs.
+#[derive(Debug, Default, Clone)]
+struct OpProfile {
+    rows: u64,
+    /// Time until the first row was returned.
+    first_row: Option<Duration>,
+    /// Time until the last pull returned.
+    elapsed: Duration,
+    /// How many times the operator was started: once, or per outer row for the inner side of
+    /// an index lookup join.
+    loops: u64,
+}
+
+struct ProfiledOp {
+    input: BoxedOperator,
+    profile: Arc<Mutex<OpProfile>>,
+    started: bool,
+}
+
+impl Operator for ProfiledOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        let start = Instant::now();
+        let row = self.input.next();
+        let took = start.elapsed();
+        let mut profile = self.profile.lock();
+        if !self.started {
+            self.started = true;
+            profile.loops += 1;
+        }
+        profile.elapsed += took;
+        match &row {
+            Ok(Some(_)) => {
+                profile.rows += 1;
+                if profile.first_row.is_none() {
+                    profile.first_row = Some(profile.elapsed);
+                }
+            }
+            // The inner side of an index lookup join is read again for the next outer row.
+            Ok(None) => self.started = false,
+            Err(_) => {}
+        }
+        row
+    }
+}
+
+/// The rows of an operator tree as result rows. Stops after the first error.
+struct OperatorRows {
+    root: Option<BoxedOperator>,
+}
+
+impl Iterator for OperatorRows {
+    type Item = Result<Vec<Cell>, MiniError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.root.as_mut()?.next();
+        match result {
+            Ok(Some(row)) => Some(Ok(row.values)),
+            Ok(None) => {
+                self.root = None;
+                None
+            }
+            Err(e) => {
+                self.root = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Rows of a stream together with whatever must stay held while they are read.
+struct Guarded<I, G> {
+    rows: I,
+    _guard: G,
+}
+
+impl<I: Iterator, G> Iterator for Guarded<I, G> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+}
+
+/// A table scan with the session's own versions of some rows merged in by primary key.
+struct OverlaidScan {
+    base: std::iter::Peekable<RowScan>,
+    overrides: std::iter::Peekable<std::collections::btree_map::IntoIter<i64, Option<Row>>>,
+}
+
+impl Iterator for OverlaidScan {
+    type Item = Result<(i64, Row), MiniError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let base_pk = match self.base.peek() {
+                Some(Ok((pk, _))) => Some(*pk),
+                Some(Err(_)) => return self.base.next(),
+                None => None,
+            };
+            let Some(&(pk, _)) = self.overrides.peek() else {
+                return self.base.next();
+            };
+            if base_pk.is_some_and(|b| b < pk) {
+                return self.base.next();
+            }
+            if base_pk == Some(pk) {
+                self.base.next();
+            }
+            if let Some((pk, Some(row))) = self.overrides.next() {
+                return Some(Ok((pk, row)));
+            }
+        }
+    }
+}
+
+struct ScanOp {
+    rows: Box<dyn Iterator<Item = Result<Row, MiniError>> + Send>,
+    /// Stored row positions of the columns to keep.
+    columns: Vec<usize>,
+    /// Whether `columns` is `0..n`, so a stored row of `n` values is kept as it is.
+    whole_prefix: bool,
+}
+
+impl Operator for ScanOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        let Some(row) = self.rows.next().transpose()? else {
+            return Ok(None);
+        };
+        Ok(Some(keep_columns(row, &self.columns, self.whole_prefix)))
+    }
+}
+
+/// The values at `columns` of a stored row; `whole_prefix` as in `ScanOp`.
+fn keep_columns(row: Row, columns: &[usize], whole_prefix: bool) -> Row {
+    if whole_prefix && row.values.len() == columns.len() {
+        return row;
+    }
+    Row {
+        values: columns
+            .iter()
+            .map(|&i| row.values.get(i).cloned().unwrap_or(Cell::Null))
+            .collect(),
+    }
+}
+
+type LookupSlot = Arc<Mutex<Option<Cell>>>;
+
+/// The inner side of an index lookup join. Each pass returns the rows of the table whose
+/// column equals the value in `key`, then `None`; the next pass reads the key again.
+struct LookupOp {
+    store: Store,
+    /// The statement's session, whose transaction the rows are read through.
+    session: Arc<SessionState>,
+    def: TableDef,
+    /// Whether `column` is the primary key rather than the first column of an index.
+    primary: bool,
+    /// Position of the column in the stored row.
+    column: usize,
+    key: LookupSlot,
+    /// Rows read by FOR UPDATE/FOR SHARE, matched instead of reading the table.
+    locked: Option<Vec<Row>>,
+    columns: Vec<usize>,
+    whole_prefix: bool,
+    rows: Option<std::vec::IntoIter<Row>>,
+}
+
+impl LookupOp {
+    fn lookup(&self, key: Option<Cell>) -> Result<Vec<Row>, MiniError> {
+        let key = match key {
+            None | Some(Cell::Null) => return Ok(Vec::new()),
+            Some(key) => key,
+        };
+        let matches = |row: &Row| {
+            row.values.get(self.column).is_some_and(|v| {
+                !matches!(v, Cell::Null) && compare_cell_for_order(v, &key).is_eq()
+            })
+        };
+        if let Some(locked) = &self.locked {
+            return Ok(locked.iter().filter(|row| matches(row)).cloned().collect());
+        }
+        let (db, table) = (&self.def.db, &self.def.name);
+        if self.primary {
+            let Some(pk) = key.as_i64() else {
+                return Ok(Vec::new());
+            };
+            return Ok(txn_get_row(&self.store, &self.session, db, table, pk)?
+                .into_iter()
+                .collect());
+        }
+        let Some(pks) = txn_index_lookup(&self.store, &self.session, &self.def, self.column, &key)?
+        else {
+            return Ok(txn_scan_rows(&self.store, &self.session, db, table)?
+                .into_iter()
+                .map(|(_, row)| row)
+                .filter(matches)
+                .collect());
+        };
+        let mut rows = Vec::with_capacity(pks.len());
+        for pk in pks {
+            rows.extend(txn_get_row(&self.store, &self.session, db, table, pk)?);
+        }
+        Ok(rows)
+    }
+}
+
+impl Operator for LookupOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        if self.rows.is_none() {
+            let key = self.key.lock().clone();
+            self.rows = Some(self.lookup(key)?.into_iter());
+        }
+        match self.rows.as_mut().and_then(Iterator::next) {
+            Some(row) => Ok(Some(keep_columns(row, &self.columns, self.whole_prefix))),
+            None => {
+                self.rows = None;
+                Ok(None)
+            }
+        }
+    }
+}
+
+struct EmptyOp;
+
+impl Operator for EmptyOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        Ok(None)
+    }
+}
+
+struct FilterOp {
+    input: BoxedOperator,
+    predicate: ast::Expr,
+    col_map: HashMap<String, usize>,
+    session: Arc<SessionState>,
+}
+
+impl Operator for FilterOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        while let Some(row) = self.input.next()? {
+            if eval_condition(&self.session, &self.predicate, &row, &self.col_map)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+enum JoinBuild {
+    /// Not read yet: the inner side is read into memory on the first pull.
+    Pending(BoxedOperator),
+    Rows(Vec<Row>),
+    /// Inner rows by the values of their hash key columns. Rows with a NULL there match
+    /// nothing and are left out.
+    Hashed(HashMap<Vec<Cell>, Vec<Row>>),
+    /// Read per outer row: the outer row's value of `column` goes in `key`, and a pass over
+    /// `input` returns the inner rows matching it.
+    Lookup {
+        input: BoxedOperator,
+        key: LookupSlot,
+        column: usize,
+    },
+}
+
+/// Joins each outer row with the matching rows of the inner side, found as the join's strategy
+/// says. Equality ON conditions between the two sides are compared directly; anything else is
+/// evaluated per pair.
+struct JoinOp {
+    outer: BoxedOperator,
+    inner: JoinBuild,
+    /// For a hash join, the outer and inner column of each equality the hash key is made of.
+    hash_keys: Vec<(usize, usize)>,
+    /// Whether the outer side is the join's left input (INNER and LEFT joins).
+    outer_is_left: bool,
+    /// Whether unmatched outer rows are kept, padded with NULLs (LEFT and RIGHT joins).
+    preserve_outer: bool,
+    on: Option<ast::Expr>,
+    equi: Option<Vec<(usize, usize)>>,
+    col_map: HashMap<String, usize>,
+    session: Arc<SessionState>,
+    nulls: Vec<Cell>,
+    pending: VecDeque<Row>,
+}
+
+/// The values of `columns` as a hash key, so that numbers comparing equal hash alike; `None`
+/// when one of them is NULL.
+fn hash_key(row: &Row, columns: impl Iterator<Item = usize>) -> Option<Vec<Cell>> {
+    columns
+        .map(|i| match row.values.get(i)? {
+            Cell::Null => None,
+            // Turns -0.0 into 0.0.
+            Cell::Float(f) => Some(Cell::Float(f + 0.0)),
+            other => Some(other.clone()),
+        })
+        .collect()
+}
+
+impl Operator for JoinOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        if let JoinBuild::Pending(input) = &mut self.inner {
+            let rows = drain(input)?;
+            self.inner = if self.hash_keys.is_empty() {
+                JoinBuild::Rows(rows)
+            } else {
+                let mut table: HashMap<Vec<Cell>, Vec<Row>> = HashMap::new();
+                for row in rows {
+                    if let Some(key) = hash_key(&row, self.hash_keys.iter().map(|k| k.1)) {
+                        table.entry(key).or_default().push(row);
+                    }
+                }
+                JoinBuild::Hashed(table)
+            };
+        }
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            let Some(outer) = self.outer.next()? else {
+                return Ok(None);
+            };
+            let fetched;
+            let inner: &[Row] = match &mut self.inner {
+                JoinBuild::Rows(rows) => rows,
+                JoinBuild::Hashed(table) => hash_key(&outer, self.hash_keys.iter().map(|k| k.0))
+                    .and_then(|key| table.get(&key))
+                    .map_or(&[], Vec::as_slice),
+                JoinBuild::Lookup { input, key, column } => {
+                    *key.lock() = outer.values.get(*column).cloned();
+                    fetched = drain(input)?;
+                    &fetched
+                }
+                JoinBuild::Pending(_) => unreachable!("built above"),
+            };
+            let combine = |left: &Row, right: &Row| {
+                let mut values = left.values.clone();
+                values.extend(right.values.iter().cloned());
+                Row { values }
+            };
+            let mut matched = false;
+            for inner_row in inner {
+                let (left, right) = if self.outer_is_left {
+                    (&outer, inner_row)
+                } else {
+                    (inner_row, &outer)
+                };
+                let row = match &self.equi {
+                    Some(pairs) => {
+                        if !eval_equi_join_pairs(left, right, pairs) {
+                            continue;
+                        }
+                        combine(left, right)
+                    }
+                    None => {
+                        let row = combine(left, right);
+                        if let Some(on) = &self.on {
+                            if !eval_condition(&self.session, on, &row, &self.col_map)? {
+                                continue;
+                            }
+                        }
+                        row
+                    }
+                };
+                matched = true;
+                self.pending.push_back(row);
+            }
+            if self.preserve_outer && !matched {
+                let nulls = Row {
+                    values: self.nulls.clone(),
+                };
+                self.pending.push_back(if self.outer_is_left {
+                    combine(&outer, &nulls)
+                } else {
+                    combine(&nulls, &outer)
+                });
+            }
+        }
+    }
+}
+
+struct ProjectOp {
+    input: BoxedOperator,
+    exprs: Vec<ast::Expr>,
+    col_map: HashMap<String, usize>,
+    session: Arc<SessionState>,
+}
+
+impl Operator for ProjectOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        let Some(row) = self.input.next()? else {
+            return Ok(None);
+        };
+        let values = self
+            .exprs
+            .iter()
+            .map(|e| eval_row_expr(&self.session, e, &row, &self.col_map))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Row { values }))
+    }
+}
+
+trait Accumulator: Send {
+    fn add(&mut self, val: Cell);
+    fn inc(&mut self);
+    fn finish(&self) -> Cell;
+}
+
+struct CountAcc(i64);
+
+impl Accumulator for CountAcc {
+    fn add(&mut self, _v: Cell) {
+        self.0 += 1;
+    }
+    fn inc(&mut self) {
+        self.0 += 1;
+    }
+    fn finish(&self) -> Cell {
+        Cell::Int(self.0)
+    }
+}
+
+struct SumAcc(Cell);
+
+impl Accumulator for SumAcc {
+    fn add(&mut self, v: Cell) {
+        if matches!(v, Cell::Null) {
+            return;
+        }
+        if let Some(res) = self.0.add(&v) {
+            self.0 = res;
+        }
+    }
+    fn inc(&mut self) {}
+    fn finish(&self) -> Cell {
+        self.0.clone()
+    }
+}
+
+struct AvgAcc {
+    sum: Cell,
+    count: i64,
+}
+
+impl Accumulator for AvgAcc {
+    fn add(&mut self, v: Cell) {
+        if matches!(v, Cell::Null) {
+            return;
+        }
+        if let Some(res) = self.sum.add(&v) {
+            self.sum = res;
+            self.count += 1;
+        }
+    }
+    fn inc(&mut self) {}
+    fn finish(&self) -> Cell {
+        if self.count == 0 {
+            return Cell::Null;
+        }
+        self.sum
+            .div_count(self.count as usize)
+            .unwrap_or(Cell::Null)
+    }
+}
+
+struct MinMaxAcc {
+    val: Cell,
+    is_min: bool,
+}
+
+impl Accumulator for MinMaxAcc {
+    fn add(&mut self, v: Cell) {
+        if matches!(v, Cell::Null) {
+            return;
+        }
+        if matches!(self.val, Cell::Null) {
+            self.val = v;
+        } else {
+            let cmp = compare_cell_for_order(&v, &self.val);
+            if self.is_min {
+                if cmp == std::cmp::Ordering::Less {
+                    self.val = v;
+                }
+            } else if cmp == std::cmp::Ordering::Greater {
+                self.val = v;
+            }
+        }
+    }
+    fn inc(&mut self) {}
+    fn finish(&self) -> Cell {
+        self.val.clone()
+    }
+}
+
+/// Accumulators for one group. SUM starts at 0 for a group with rows and at NULL for the
+/// implicit group of an empty, ungrouped input.
+fn accumulators(aggregates: &[AggCall], empty: bool) -> Vec<Box<dyn Accumulator>> {
+    aggregates
+        .iter()
+        .map(|call| -> Box<dyn Accumulator> {
+            match call.func {
+                AggFunc::Count => Box::new(CountAcc(0)),
+                AggFunc::Sum if empty => Box::new(SumAcc(Cell::Null)),
+                AggFunc::Sum => Box::new(SumAcc(Cell::Int(0))),
+                AggFunc::Avg => Box::new(AvgAcc {
+                    sum: Cell::Int(0),
+                    count: 0,
+                }),
+                AggFunc::Min | AggFunc::Max => Box::new(MinMaxAcc {
+                    val: Cell::Null,
+                    is_min: call.func == AggFunc::Min,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Hash aggregation: reads its whole input on the first pull, then returns one row per group
+/// in the order groups were first seen.
+struct AggregateOp {
+    input: Option<BoxedOperator>,
+    group_by: Vec<ast::Expr>,
+    aggregates: Vec<AggCall>,
+    outputs: Vec<AggOutput>,
+    col_map: HashMap<String, usize>,
+    session: Arc<SessionState>,
+    results: std::vec::IntoIter<Row>,
+}
+
+impl AggregateOp {
+    fn aggregate(&self, mut input: BoxedOperator) -> Result<Vec<Row>, MiniError> {
+        struct Group {
+            first_row: Row,
+            accumulators: Vec<Box<dyn Accumulator>>,
+        }
+        let mut index: HashMap<Vec<Cell>, usize> = HashMap::new();
+        let mut groups: Vec<Group> = Vec::new();
+        while let Some(row) = input.next()? {
+            let key = self
+                .group_by
+                .iter()
+                .map(|e| eval_row_expr(&self.session, e, &row, &self.col_map))
+                .collect::<Result<Vec<_>, _>>()?;
+            let pos = match index.get(&key) {
+                Some(&pos) => pos,
+                None => {
+                    index.insert(key, groups.len());
+                    groups.push(Group {
+                        first_row: row.clone(),
+                        accumulators: accumulators(&self.aggregates, false),
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[pos];
+            for (call, acc) in self.aggregates.iter().zip(&mut group.accumulators) {
+                match &call.arg {
+                    None if call.func == AggFunc::Count => acc.inc(),
+                    None => {}
+                    Some(expr) => acc.add(eval_row_expr(&self.session, expr, &row, &self.col_map)?),
+                }
+            }
+        }
+        // Without GROUP BY an empty input still has one (empty) group: COUNT(*) is 0.
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push(Group {
+                first_row: Row { values: Vec::new() },
+                accumulators: accumulators(&self.aggregates, true),
+            });
+        }
+
+        let mut rows = Vec::with_capacity(groups.len());
+        for group in groups {
+            let values = self
+                .outputs
+                .iter()
+                .map(|out| match out {
+                    AggOutput::Group(expr) => {
+                        eval_row_expr(&self.session, expr, &group.first_row, &self.col_map)
+                    }
+                    AggOutput::Aggregate(i) => Ok(group.accumulators[*i].finish()),
+                })
+                .collect::<Result<_, _>>()?;
+            rows.push(Row { values });
+        }
+        Ok(rows)
+    }
+}
+
+impl Operator for AggregateOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        if let Some(input) = self.input.take() {
+            self.results = self.aggregate(input)?.into_iter();
+        }
+        Ok(self.results.next())
+    }
+}
+
+struct DistinctOp {
+    input: BoxedOperator,
+    seen: HashSet<Vec<Cell>>,
+}
+
+impl Operator for DistinctOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        while let Some(row) = self.input.next()? {
+            if self.seen.insert(row.values.clone()) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+struct SortOp {
+    input: Option<BoxedOperator>,
+    /// `(column, descending)` pairs, most significant first.
+    keys: Vec<(usize, bool)>,
+    sorted: std::vec::IntoIter<Row>,
+}
+
+impl Operator for SortOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        if let Some(mut input) = self.input.take() {
+            let mut rows = drain(&mut input)?;
+            rows.sort_by(|a, b| {
+                for &(idx, desc) in &self.keys {
+                    let cmp = compare_cell_for_order(
+                        a.values.get(idx).unwrap_or(&Cell::Null),
+                        b.values.get(idx).unwrap_or(&Cell::Null),
+                    );
+                    let cmp = if desc { cmp.reverse() } else { cmp };
+                    if cmp != std::cmp::Ordering::Equal {
+                        return cmp;
+                    }
+                }
+                std::cmp::Ordering::Equal
+            });
+            self.sorted = rows.into_iter();
+        }
+        Ok(self.sorted.next())
+    }
+}
+
+/// Skips `offset` rows, then returns up to `remaining` rows without pulling any more.
+struct LimitOp {
+    input: BoxedOperator,
+    offset: usize,
+    remaining: Option<usize>,
+}
+
+impl Operator for LimitOp {
+    fn next(&mut self) -> Result<Option<Row>, MiniError> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        while self.offset > 0 {
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        let row = self.input.next()?;
+        if row.is_some() {
+            if let Some(remaining) = &mut self.remaining {
+                *remaining -= 1;
+            }
+        }
+        Ok(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::execute;
+    use sqlparser::dialect::MySqlDialect;
+    use sqlparser::parser::Parser;
+    use tempfile::tempdir;
+
+    fn root_user() -> UserRecord {
+        UserRecord {
+            username: "root".into(),
+            host: "%".into(),
+            plugin: "".into(),
+            auth_stage2: None,
+            global_privs: Priv::ALL.bits(),
+            db_privs: Default::default(),
+        }
+    }
+
+    fn setup() -> (tempfile::TempDir, Store, SessionState) {
+        let dir = tempdir().unwrap();
+        let store = Store::open(dir.path()).unwrap();
+        let mut session = SessionState::new(1);
+        for sql in [
+            "CREATE DATABASE test",
+            "USE test",
+            "CREATE TABLE a (id INT PRIMARY KEY, name TEXT, note TEXT)",
+            "CREATE TABLE b (id INT PRIMARY KEY, a_id INT, v INT)",
+            "INSERT INTO a VALUES (1, 'x', 'n1'), (2, 'y', 'n2'), (3, 'z', 'n3')",
+            "INSERT INTO b VALUES (10, 1, 5), (11, 1, 0), (12, 2, 7)",
+        ] {
+            execute(sql, &store, &mut session, &root_user()).unwrap();
+        }
+        (dir, store, session)
+    }
+
+    fn plan(store: &Store, session: &SessionState, sql: &str) -> LogicalPlan {
+        let (sql, straight_join_selects) = crate::sql::normalize_straight_join(sql);
+        let mut session = session.clone();
+        session.straight_join_selects = straight_join_selects;
+        let session = &session;
+        let statement = Parser::parse_sql(&MySqlDialect {}, &sql).unwrap().remove(0);
+        let ast::Statement::Query(query) = statement else {
+            panic!("expected a query");
+        };
+        let ast::SetExpr::Select(select) = query.body.as_ref() else {
+            panic!("expected a SELECT");
+        };
+        SelectPlan::build(store, session, &root_user(), &query, select)
+            .unwrap()
+            .optimize(store, session)
+            .root
+    }
+
+    fn scan_columns(plan: &LogicalPlan) -> Vec<String> {
+        let LogicalPlan::Scan(scan) = plan else {
+            panic!("expected a scan, got {plan:?}");
+        };
+        scan.def.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_where_pushdown_and_pruning() {
+        let (_dir, store, session) = setup();
+        let root = plan(
+            &store,
+            &session,
+            "SELECT a.name FROM a, b WHERE a.id = b.a_id AND b.v > 1 AND 1 = 1",
+        );
+
+        // The join predicate becomes the comma join's ON condition, the single-table part
+        // filters `b`'s scan, `1 = 1` is gone, and each scan keeps only what is read. The
+        // filtered `b` is read first and looks `a` up by its primary key.
+        let LogicalPlan::Project { input, .. } = root else {
+            panic!("expected a projection");
+        };
+        let LogicalPlan::Join {
+            left,
+            right,
+            on,
+            strategy,
+            ..
+        } = *input
+        else {
+            panic!("expected the join right under the projection");
+        };
+        assert_eq!(on.unwrap().to_string(), "a.id = b.a_id");
+        assert_eq!(strategy, JoinStrategy::IndexLookup);
+        let LogicalPlan::Filter { input, predicate } = *left else {
+            panic!("expected a filter on b");
+        };
+        assert_eq!(predicate.to_string(), "b.v > 1");
+        assert_eq!(scan_columns(&input), ["a_id", "v"]);
+        assert_eq!(scan_columns(&right), ["id", "name"]);
+    }
+
+    #[test]
+    fn test_outer_join_predicates_stay_above_null_side() {
+        let (_dir, store, session) = setup();
+        let root = plan(
+            &store,
+            &session,
+            "SELECT a.id FROM a LEFT JOIN b ON a.id = b.a_id WHERE b.v IS NULL AND a.id > 1",
+        );
+        let LogicalPlan::Project { input, .. } = root else {
+            panic!("expected a projection");
+        };
+        let LogicalPlan::Filter { input, predicate } = *input else {
+            panic!("expected the IS NULL filter above the join");
+        };
+        assert_eq!(predicate.to_string(), "b.v IS NULL");
+        let LogicalPlan::Join { left, .. } = *input else {
+            panic!("expected a join");
+        };
+        assert!(matches!(*left, LogicalPlan::Filter { .. }));
+    }
+
+    #[test]
+    fn test_constant_false_where_and_sort_elimination() {
+        let (_dir, store, session) = setup();
+        let root = plan(
+            &store,
+            &session,
+            "SELECT COUNT(*) FROM a WHERE 1 = 0 OR 2 < 1",
+        );
+        let LogicalPlan::Aggregate { input, .. } = root else {
+            panic!("expected an aggregate");
+        };
+        assert!(matches!(*input, LogicalPlan::Empty(_)));
+
+        let root = plan(&store, &session, "SELECT name FROM a ORDER BY id LIMIT 2");
+        let LogicalPlan::Limit { input, .. } = root else {
+            panic!("expected a limit");
+        };
+        let LogicalPlan::Project { input, .. } = *input else {
+            panic!("expected no sort under the limit");
+        };
+        assert!(matches!(*input, LogicalPlan::Scan(_)));
+    }
+
+    #[test]
+    fn test_access_paths() {
+        let (_dir, store, mut session) = setup();
+        execute(
+            "CREATE INDEX idx_a ON b (a_id)",
+            &store,
+            &mut session,
+            &root_user(),
+        )
+        .unwrap();
+        let access = |sql: &str| {
+            let mut scans = Vec::new();
+            let root = plan(&store, &session, sql);
+            root.scans(&mut scans);
+            scans.iter().map(|s| s.access.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            access("SELECT * FROM a WHERE id = 2"),
+            [Access::PkRange(Some(2), Some(2))]
+        );
+        assert_eq!(
+            access("SELECT * FROM a WHERE id > 1 AND name = 'y'"),
+            [Access::PkRange(Some(2), None)]
+        );
+        assert_eq!(
+            access("SELECT * FROM b WHERE a_id = 1"),
+            [Access::IndexEq {
+                index: "idx_a".into(),
+                column: 1,
+                value: Cell::Int(1),
+            }]
+        );
+        // A value of another type than the column may match rows the index cannot find.
+        assert_eq!(
+            access("SELECT * FROM b WHERE a_id = '1'"),
+            [Access::FullScan]
+        );
+        // The join reads the range of `b` and looks each `a` row up by its primary key.
+        let joined = access("SELECT * FROM a, b WHERE a.id = b.a_id AND b.id BETWEEN 11 AND 20");
+        assert_eq!(joined[0], Access::PkRange(Some(11), Some(20)));
+        let Access::Lookup {
+            index: None,
+            column: 0,
+            outer,
+        } = &joined[1]
+        else {
+            panic!("expected a primary key lookup, got {:?}", joined[1]);
+        };
+        assert_eq!(outer.to_string(), "b.a_id");
+    }
+
+    #[test]
+    fn test_explain() {
+        let (_dir, store, mut session) = setup();
+        let user = root_user();
+        execute(
+            "CREATE INDEX idx_a ON b (a_id)",
+            &store,
+            &mut session,
+            &user,
+        )
+        .unwrap();
+        let mut query = |sql: &str| match execute(sql, &store, &mut session, &user).unwrap() {
+            ExecOutput::ResultSet { rows, .. } => rows.collect_rows().unwrap(),
+            other => panic!("expected a result set, got {other:?}"),
+        };
+        let text = |s: &str| Cell::Text(s.into());
+
+        // Tabular: one row per table in join order, with access type, key and estimated rows.
+        let rows =
+            query("EXPLAIN SELECT a.name, b.v FROM a JOIN b ON a.id = b.a_id WHERE b.a_id = 1");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][2], text("b"));
+        assert_eq!(rows[0][4], text("ref"));
+        assert_eq!(rows[0][6], text("idx_a"));
+        assert_eq!(rows[0][8], text("const"));
+        assert_eq!(rows[0][9], Cell::Int(2));
+        assert_eq!(rows[0][11], text("Using where"));
+        assert_eq!(rows[1][2], text("a"));
+        assert_eq!(rows[1][4], text("eq_ref"));
+        assert_eq!(rows[1][6], text("PRIMARY"));
+        assert_eq!(rows[1][8], text("b.a_id"));
+        assert_eq!(rows[1][9], Cell::Int(1));
+        assert_eq!(rows[1][11], Cell::Null);
+
+        // STRAIGHT_JOIN keeps the written order and joins through the join buffer.
+        let rows = query(
+            "EXPLAIN SELECT STRAIGHT_JOIN a.name FROM a JOIN b ON a.id = b.a_id WHERE b.a_id = 1",
+        );
+        assert_eq!((&rows[0][2], &rows[0][4]), (&text("a"), &text("ALL")));
+        assert_eq!((&rows[1][2], &rows[1][4]), (&text("b"), &text("ref")));
+        assert_eq!(
+            rows[1][11],
+            text("Using where; Using join buffer (Block Nested Loop)")
+        );
+
+        let rows = query("EXPLAIN SELECT * FROM a WHERE id = 2");
+        assert_eq!(
+            (&rows[0][4], &rows[0][6]),
+            (&text("const"), &text("PRIMARY"))
+        );
+        let rows = query("EXPLAIN SELECT * FROM a WHERE id > 1 ORDER BY name");
+        assert_eq!(rows[0][4], text("range"));
+        assert_eq!(rows[0][9], Cell::Int(2));
+        assert_eq!(rows[0][11], text("Using where; Using filesort"));
+        let rows = query("EXPLAIN SELECT * FROM a WHERE 1 = 0");
+        assert_eq!(rows[0][11], text("Impossible WHERE"));
+
+        let tree = query("EXPLAIN FORMAT=TREE SELECT name FROM a WHERE id >= 2 LIMIT 1");
+        assert_eq!(
+            tree[0][0],
+            text(
+                "-> Limit: 1 row(s)  (rows=1)\n\
+                 \x20   -> Filter: (id >= 2)  (rows=2)\n\
+                 \x20       -> Index range scan on a using PRIMARY over (2 <= id)  (rows=2)"
+            )
+        );
+
+        let json = query("EXPLAIN FORMAT=JSON SELECT * FROM b WHERE a_id = 1");
+        let Cell::Text(json) = &json[0][0] else {
+            panic!("expected text");
+        };
+        assert!(json.contains("\"access_type\": \"ref\""), "{json}");
+        assert!(json.contains("\"key\": \"idx_a\""), "{json}");
+        assert!(json.contains("\"rows_examined_per_scan\": 2"), "{json}");
+
+        // EXPLAIN ANALYZE runs the query: the right join reads `b` first and looks `a` up
+        // once per `b` row.
+        let analyzed = query("EXPLAIN ANALYZE SELECT a.id FROM a RIGHT JOIN b ON a.id = b.a_id");
+        let Cell::Text(analyzed) = &analyzed[0][0] else {
+            panic!("expected text");
+        };
+        let lines: Vec<&str> = analyzed.lines().collect();
+        assert!(lines[0].starts_with("-> Nested loop left join (a.id = b.a_id)"));
+        assert!(lines[0].ends_with("rows=3 loops=1)"), "{analyzed}");
+        assert!(lines[1].trim_start().starts_with("-> Table scan on b"));
+        assert!(lines[2]
+            .trim_start()
+            .starts_with("-> Single-row index lookup on a using PRIMARY (id=b.a_id)"));
+        assert!(lines[2].ends_with("rows=3 loops=3)"), "{analyzed}");
+        assert!(lines.iter().all(|l| l.contains("(actual time=")));
+    }
+
+    #[test]
+    fn test_plan_results() {
+        let (_dir, store, mut session) = setup();
+        let user = root_user();
+        let mut query = |sql: &str| match execute(sql, &store, &mut session, &user).unwrap() {
+            ExecOutput::ResultSet { rows, .. } => rows.collect_rows().unwrap(),
+            other => panic!("expected a result set, got {other:?}"),
+        };
+        let text = |s: &str| Cell::Text(s.into());
+
+        assert_eq!(
+            query("SELECT a.name, b.v FROM a, b WHERE a.id = b.a_id AND b.v > 1 ORDER BY b.v"),
+            vec![vec![text("x"), Cell::Int(5)], vec![text("y"), Cell::Int(7)]]
+        );
+        assert_eq!(
+            query("SELECT a.id FROM a LEFT JOIN b ON a.id = b.a_id WHERE b.v IS NULL"),
+            vec![vec![Cell::Int(3)]]
+        );
+        assert_eq!(
+            query("SELECT COUNT(*), SUM(id) FROM a WHERE 1 = 0"),
+            vec![vec![Cell::Int(0), Cell::Null]]
+        );
+        assert_eq!(
+            query("SELECT note FROM a WHERE name <> 'y' ORDER BY id DESC LIMIT 1"),
+            vec![vec![text("n3")]]
+        );
+        assert_eq!(
+            query("SELECT a_id, COUNT(*) AS n FROM b GROUP BY a_id HAVING n > 1"),
+            vec![vec![Cell::Int(1), Cell::Int(2)]]
+        );
+    }
+
+    #[test]
+    fn test_join_order_and_strategies() {
+        let (_dir, store, mut session) = setup();
+        let user = root_user();
+        for sql in [
+            "CREATE TABLE c (id INT PRIMARY KEY, note TEXT)",
+            "INSERT INTO c VALUES (1, 'n2'), (2, NULL), (3, 'n3'), (4, 'n9')",
+        ] {
+            execute(sql, &store, &mut session, &user).unwrap();
+        }
+        let order = |root: &LogicalPlan| {
+            let mut scans = Vec::new();
+            root.scans(&mut scans);
+            scans.iter().map(|s| s.table.clone()).collect::<Vec<_>>()
+        };
+        let join = |root: LogicalPlan| {
+            let LogicalPlan::Project { input, .. } = root else {
+                panic!("expected a projection");
+            };
+            let LogicalPlan::Join { strategy, .. } = *input else {
+                panic!("expected a join under the projection");
+            };
+            strategy
+        };
+
+        // The single-row side of the join is read first, however the query lists it.
+        let root = plan(
+            &store,
+            &session,
+            "SELECT b.v FROM b JOIN c ON b.a_id = c.id JOIN a ON a.id = c.id WHERE a.id = 2",
+        );
+        assert_eq!(order(&root), ["a", "c", "b"]);
+        let root = plan(
+            &store,
+            &session,
+            "SELECT STRAIGHT_JOIN b.v FROM b JOIN c ON b.a_id = c.id JOIN a ON a.id = c.id \
+             WHERE a.id = 2",
+        );
+        assert_eq!(order(&root), ["b", "c", "a"]);
+        let root = plan(
+            &store,
+            &session,
+            "SELECT b.v FROM b STRAIGHT_JOIN a ON a.id = b.a_id WHERE a.id = 2",
+        );
+        assert_eq!(order(&root), ["b", "a"]);
+        // The outer side of a left join stays first.
+        let root = plan(
+            &store,
+            &session,
+            "SELECT b.v FROM b LEFT JOIN a ON a.id = b.a_id AND a.id = 2",
+        );
+        assert_eq!(order(&root), ["b", "a"]);
+
+        // An equality on columns without an index builds a hash table; one on a key looks
+        // rows up through it.
+        let root = plan(
+            &store,
+            &session,
+            "SELECT a.id FROM a JOIN c ON a.note = c.note",
+        );
+        assert_eq!(join(root), JoinStrategy::Hash);
+        let root = plan(&store, &session, "SELECT a.id FROM a JOIN c ON a.id = c.id");
+        assert_eq!(join(root), JoinStrategy::IndexLookup);
+        let root = plan(&store, &session, "SELECT a.id FROM a JOIN c ON a.id < c.id");
+        assert_eq!(join(root), JoinStrategy::NestedLoop);
+
+        let mut query = |sql: &str| match execute(sql, &store, &mut session, &user).unwrap() {
+            ExecOutput::ResultSet { rows, .. } => rows.collect_rows().unwrap(),
+            other => panic!("expected a result set, got {other:?}"),
+        };
+        assert_eq!(
+            query("SELECT a.id, c.id FROM a JOIN c ON a.note = c.note ORDER BY a.id"),
+            vec![
+                vec![Cell::Int(2), Cell::Int(1)],
+                vec![Cell::Int(3), Cell::Int(3)]
+            ]
+        );
+        assert_eq!(
+            query("SELECT c.id, a.name FROM c LEFT JOIN a ON a.note = c.note ORDER BY c.id"),
+            vec![
+                vec![Cell::Int(1), Cell::Text("y".into())],
+                vec![Cell::Int(2), Cell::Null],
+                vec![Cell::Int(3), Cell::Text("z".into())],
+                vec![Cell::Int(4), Cell::Null]
+            ]
+        );
+        assert_eq!(
+            query("SELECT b.id, a.name FROM b, a WHERE a.id = b.a_id ORDER BY b.id"),
+            vec![
+                vec![Cell::Int(10), Cell::Text("x".into())],
+                vec![Cell::Int(11), Cell::Text("x".into())],
+                vec![Cell::Int(12), Cell::Text("y".into())]
+            ]
+        );
+    }
+}
@@ -0,0 +1,926 @@
+//! `EXPLAIN` for planned SELECTs, in MySQL's tabular format, `FORMAT=TREE` and `FORMAT=JSON`,
+//! and `EXPLAIN ANALYZE`, which runs the query and reports what each operator did.
+//!
+//! Row estimates come from reading the plan's scans through the statement's read view, so
+//! they are exact for scans and guesses above them: a filter the access path does not cover
+//! keeps a tenth of its input, as MySQL assumes without statistics. The inner side of an index
+//! lookup join cannot be read without an outer row; its rows per lookup come from the table
+//! statistics the optimizer uses.
+
+use super::{
+    drain, keyed_scan, lookup_scan, result_column, Access, AggFunc, CostModel, ExecContext,
+    JoinKind, JoinStrategy, LogicalPlan, OpProfile, Scan, ScanSource, Schema, SelectPlan, SortExpr,
+};
+use crate::error::MiniError;
+use crate::model::{Cell, SqlType};
+use crate::sql::{cell_to_string, ExecOutput, LockedRows, SessionState};
+use crate::store::Store;
+use opensrv_mysql::ColumnType;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::sql) enum ExplainFormat {
+    /// One row per table, as MySQL's default `EXPLAIN`.
+    Traditional,
+    Tree,
+    Json,
+}
+
+/// A plan node as EXPLAIN shows it.
+struct Node<'p> {
+    plan: &'p LogicalPlan,
+    /// Position in the order operators are built, which is how their profiles are stored.
+    id: usize,
+    rows: u64,
+    /// Inputs in execution order: the outer side of a join first.
+    children: Vec<Node<'p>>,
+}
+
+impl SelectPlan {
+    /// Describe the plan without running it.
+    pub fn explain(
+        &self,
+        store: &Store,
+        session: &SessionState,
+        format: ExplainFormat,
+    ) -> Result<ExecOutput, MiniError> {
+        let mut ctx = estimate_context(store, session);
+        let node = describe(&mut ctx, &self.root, &mut 0)?;
+        Ok(match format {
+            ExplainFormat::Traditional => traditional(&node),
+            ExplainFormat::Tree => text_result(tree(&node, None)),
+            ExplainFormat::Json => text_result(json(&node)),
+        }
+        .into_output())
+    }
+
+    /// Run the query, discard its rows, and describe the plan in the tree format with each
+    /// operator's actual row count and timings.
+    pub fn analyze(
+        self,
+        store: &Store,
+        session: &SessionState,
+        locked_rows: Option<&LockedRows>,
+    ) -> Result<ExecOutput, MiniError> {
+        let mut ctx = estimate_context(store, session);
+        let node = describe(&mut ctx, &self.root, &mut 0)?;
+        drop(ctx);
+
+        let mut ctx = ExecContext {
+            store,
+            session,
+            locked_rows,
+            eval: Arc::new(session.detached()),
+            pin: None,
+            profiles: Some(Vec::new()),
+            lookup_key: None,
+        };
+        let mut root = ctx.operator(self.root.clone())?;
+        drain(&mut root)?;
+        let profiles: Vec<OpProfile> = ctx
+            .profiles
+            .take()
+            .unwrap_or_default()
+            .iter()
+            .map(|p| p.lock().clone())
+            .collect();
+        Ok(text_result(tree(&node, Some(&profiles))).into_output())
+    }
+}
+
+/// EXPLAIN of a SELECT without FROM.
+pub(in crate::sql) fn explain_without_tables(format: ExplainFormat) -> ExecOutput {
+    match format {
+        ExplainFormat::Traditional => {
+            let mut row = vec![Cell::Int(1), Cell::Text("SIMPLE".into())];
+            row.extend(std::iter::repeat_n(Cell::Null, 9));
+            row.push(Cell::Text("No tables used".into()));
+            Tabular {
+                columns: traditional_columns(),
+                rows: vec![row],
+            }
+        }
+        ExplainFormat::Tree => text_result("-> Rows fetched before execution  (rows=1)".into()),
+        ExplainFormat::Json => text_result(render_json(&Json::Obj(vec![(
+            "query_block",
+            Json::Obj(vec![
+                ("select_id", Json::Num(1)),
+                ("message", Json::Str("No tables used".into())),
+            ]),
+        )]))),
+    }
+    .into_output()
+}
+
+/// EXPLAIN ANALYZE of a SELECT without FROM, which returned `rows` rows in `elapsed`.
+pub(in crate::sql) fn analyze_without_tables(rows: usize, elapsed: Duration) -> ExecOutput {
+    let profile = OpProfile {
+        rows: rows as u64,
+        first_row: Some(elapsed),
+        elapsed,
+        loops: 1,
+    };
+    text_result(format!(
+        "-> Rows fetched before execution  (rows=1){}",
+        actual(Some(&profile))
+    ))
+    .into_output()
+}
+
+struct Tabular {
+    columns: Vec<(&'static str, ColumnType)>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Tabular {
+    fn into_output(self) -> ExecOutput {
+        ExecOutput::ResultSet {
+            columns: self
+                .columns
+                .into_iter()
+                .map(|(name, ty)| result_column(name.into(), ty))
+                .collect(),
+            rows: self.rows.into(),
+        }
+    }
+}
+
+/// A one-column, one-row result holding a rendered plan.
+fn text_result(text: String) -> Tabular {
+    Tabular {
+        columns: vec![("EXPLAIN", ColumnType::MYSQL_TYPE_VAR_STRING)],
+        rows: vec![vec![Cell::Text(text)]],
+    }
+}
+
+/// A context for reading scans to estimate their sizes. Estimates never lock, so they read the
+/// snapshot even for FOR UPDATE queries.
+fn estimate_context<'a>(store: &'a Store, session: &'a SessionState) -> ExecContext<'a> {
+    ExecContext {
+        store,
+        session,
+        locked_rows: None,
+        eval: Arc::new(session.detached()),
+        pin: None,
+        profiles: None,
+        lookup_key: None,
+    }
+}
+
+fn describe<'p>(
+    ctx: &mut ExecContext<'_>,
+    plan: &'p LogicalPlan,
+    next_id: &mut usize,
+) -> Result<Node<'p>, MiniError> {
+    let id = *next_id;
+    *next_id += 1;
+    let inputs: Vec<&LogicalPlan> = match plan {
+        LogicalPlan::Scan(_) => Vec::new(),
+        LogicalPlan::Join {
+            left,
+            right,
+            kind: JoinKind::Right,
+            ..
+        } => vec![right, left],
+        LogicalPlan::Join { left, right, .. } => vec![left, right],
+        LogicalPlan::Empty(_) => Vec::new(),
+        LogicalPlan::Filter { input, .. }
+        | LogicalPlan::Project { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Distinct(input)
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => vec![input],
+    };
+    let children = inputs
+        .into_iter()
+        .map(|input| describe(ctx, input, next_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let input_rows = children.first().map_or(0, |c| c.rows);
+    let rows = match plan {
+        LogicalPlan::Scan(scan) => match &scan.source {
+            ScanSource::InformationSchema(rows) => rows.len() as u64,
+            ScanSource::Table if matches!(scan.access, Access::Lookup { .. }) => {
+                let model = CostModel { store: ctx.store };
+                model.scan(scan).rows.ceil().max(1.0) as u64
+            }
+            ScanSource::Table => {
+                let mut op = ctx.scan(scan.clone())?;
+                drain(&mut op)?.len() as u64
+            }
+        },
+        LogicalPlan::Empty(_) => 0,
+        LogicalPlan::Filter { input, .. } => {
+            if keyed_scan(input).is_some() {
+                input_rows
+            } else {
+                input_rows.div_ceil(10)
+            }
+        }
+        LogicalPlan::Join { kind, on, .. } => {
+            let outer = input_rows;
+            let joined = outer.saturating_mul(children[1].rows);
+            // Rows per lookup already match the outer row.
+            let joined = if on.is_some() && lookup_scan(children[1].plan).is_none() {
+                joined.div_ceil(10)
+            } else {
+                joined
+            };
+            if *kind == JoinKind::Inner {
+                joined
+            } else {
+                joined.max(outer)
+            }
+        }
+        LogicalPlan::Aggregate { group_by, .. } if group_by.is_empty() => 1,
+        LogicalPlan::Limit { offset, limit, .. } => {
+            let rows = input_rows.saturating_sub(*offset as u64);
+            limit.map_or(rows, |l| rows.min(l as u64))
+        }
+        LogicalPlan::Project { .. }
+        | LogicalPlan::Aggregate { .. }
+        | LogicalPlan::Distinct(_)
+        | LogicalPlan::Sort { .. } => input_rows,
+    };
+    Ok(Node {
+        plan,
+        id,
+        rows,
+        children,
+    })
+}
+
+/// Why the query returns no rows at all, if a predicate folded to false.
+fn impossible(node: &Node<'_>) -> Option<&'static str> {
+    if let LogicalPlan::Empty(input) = node.plan {
+        return Some(if input.is_relational() {
+            "Impossible WHERE"
+        } else {
+            "Impossible HAVING"
+        });
+    }
+    node.children.iter().find_map(impossible)
+}
+
+// ---------------------------------------------------------------------------------------------
+// Tabular format
+// ---------------------------------------------------------------------------------------------
+
+/// One table of the query, in join order.
+struct TableAccess<'p> {
+    scan: &'p Scan,
+    rows: u64,
+    /// Conditions checked on the rows, from filters and join conditions.
+    conditions: Vec<String>,
+    /// The join buffer the table is read into, as MySQL names its algorithm.
+    join_buffer: Option<&'static str>,
+}
+
+impl TableAccess<'_> {
+    fn access_type(&self) -> &'static str {
+        match &self.scan.access {
+            Access::FullScan => "ALL",
+            Access::PkRange(Some(lo), Some(hi)) if lo == hi => "const",
+            Access::PkRange(..) => "range",
+            Access::IndexEq { .. } => "ref",
+            Access::Lookup { index: None, .. } => "eq_ref",
+            Access::Lookup { .. } => "ref",
+        }
+    }
+
+    fn key(&self) -> Option<&str> {
+        match &self.scan.access {
+            Access::FullScan => None,
+            Access::PkRange(..) => Some("PRIMARY"),
+            Access::IndexEq { index, .. } => Some(index),
+            Access::Lookup { index, .. } => Some(index.as_deref().unwrap_or("PRIMARY")),
+        }
+    }
+
+    fn key_column(&self) -> Option<&str> {
+        match &self.scan.access {
+            Access::FullScan => None,
+            Access::PkRange(..) | Access::Lookup { index: None, .. } => {
+                Some(&self.scan.def.primary_key)
+            }
+            Access::IndexEq { index, .. }
+            | Access::Lookup {
+                index: Some(index), ..
+            } => self
+                .scan
+                .def
+                .indexes
+                .iter()
+                .find(|i| i.name == *index)
+                .and_then(|i| i.columns.first())
+                .map(String::as_str),
+        }
+    }
+
+    /// Bytes of the key used, as MySQL reports them for the column's type. An index is only
+    /// used with a value of its column's type, so the value tells the type.
+    fn key_len(&self) -> Option<u64> {
+        match &self.scan.access {
+            Access::FullScan => None,
+            Access::PkRange(..) => Some(8),
+            Access::IndexEq { value, .. } => match value {
+                Cell::Int(_) | Cell::Float(_) => Some(8),
+                Cell::Date(_) => Some(3),
+                Cell::DateTime(_) => Some(5),
+                Cell::Text(_) | Cell::Null => None,
+            },
+            Access::Lookup { .. } => {
+                let column = self.key_column()?;
+                let def = self.scan.def.columns.iter();
+                match def
+                    .map(|c| (&c.name, &c.ty))
+                    .find(|(n, _)| n.eq_ignore_ascii_case(column))
+                {
+                    Some((_, SqlType::Int | SqlType::Float)) => Some(8),
+                    Some((_, SqlType::Date)) => Some(3),
+                    Some((_, SqlType::DateTime)) => Some(5),
+                    Some((_, SqlType::Text)) | None => None,
+                }
+            }
+        }
+    }
+
+    /// What the key is compared with: a constant, or the outer row's column for a lookup join.
+    fn reference(&self) -> Option<String> {
+        match &self.scan.access {
+            Access::Lookup { outer, .. } => Some(outer.to_string()),
+            _ => matches!(self.access_type(), "const" | "ref").then(|| "const".into()),
+        }
+    }
+
+    /// Percentage of examined rows expected to pass the conditions.
+    fn filtered(&self) -> f64 {
+        if self.conditions.is_empty() || self.scan.access != Access::FullScan {
+            100.0
+        } else {
+            10.0
+        }
+    }
+
+    fn extra(&self) -> Vec<&'static str> {
+        let mut extra = Vec::new();
+        if !self.conditions.is_empty() {
+            extra.push("Using where");
+        }
+        match self.join_buffer {
+            Some("hash join") => extra.push("Using join buffer (hash join)"),
+            Some(_) => extra.push("Using join buffer (Block Nested Loop)"),
+            None => {}
+        }
+        extra
+    }
+}
+
+fn table_accesses<'p>(node: &Node<'p>, out: &mut Vec<TableAccess<'p>>) {
+    match node.plan {
+        LogicalPlan::Scan(scan) => out.push(TableAccess {
+            scan,
+            rows: node.rows,
+            conditions: Vec::new(),
+            join_buffer: None,
+        }),
+        LogicalPlan::Join { on, strategy, .. } => {
+            table_accesses(&node.children[0], out);
+            let inner = out.len();
+            table_accesses(&node.children[1], out);
+            let join_buffer = match strategy {
+                JoinStrategy::NestedLoop => Some("Block Nested Loop"),
+                JoinStrategy::Hash => Some("hash join"),
+                JoinStrategy::IndexLookup => None,
+            };
+            for table in &mut out[inner..] {
+                table.join_buffer = join_buffer;
+            }
+            // An index lookup join's key equality is checked by the lookup itself.
+            let on = match (on, strategy) {
+                (Some(on), JoinStrategy::IndexLookup) => {
+                    let mut conditions = Vec::new();
+                    super::split_conjuncts(on.clone(), &mut conditions);
+                    let key = lookup_scan(node.children[1].plan);
+                    conditions.retain(|c| !key.is_some_and(|scan| is_lookup_key(c, scan)));
+                    super::join_conjuncts(conditions).map(|c| c.to_string())
+                }
+                (on, _) => on.as_ref().map(ToString::to_string),
+            };
+            if let (Some(on), Some(last)) = (on, out.last_mut()) {
+                last.conditions.push(on);
+            }
+        }
+        LogicalPlan::Filter { predicate, .. } => {
+            let first = out.len();
+            table_accesses(&node.children[0], out);
+            // A filter over tables is checked once its last table's row is read.
+            if out.len() > first {
+                if let Some(last) = out.last_mut() {
+                    last.conditions.push(predicate.to_string());
+                }
+            }
+        }
+        _ => {
+            for child in &node.children {
+                table_accesses(child, out);
+            }
+        }
+    }
+}
+
+/// Whether `condition` is the equality an index lookup join's inner scan looks its rows up by.
+fn is_lookup_key(condition: &sqlparser::ast::Expr, scan: &Scan) -> bool {
+    use sqlparser::ast::{BinaryOperator, Expr};
+    let Access::Lookup { outer, column, .. } = &scan.access else {
+        return false;
+    };
+    let Expr::BinaryOp {
+        left,
+        op: BinaryOperator::Eq,
+        right,
+    } = condition
+    else {
+        return false;
+    };
+    let Some(name) = scan
+        .columns
+        .iter()
+        .position(|c| c == column)
+        .map(|i| &scan.def.columns[i].name)
+    else {
+        return false;
+    };
+    let names_key = |e: &Expr| match e {
+        Expr::Identifier(id) => id.value.eq_ignore_ascii_case(name),
+        Expr::CompoundIdentifier(ids) => ids.last().is_some_and(|id| {
+            id.value.eq_ignore_ascii_case(name)
+                && (ids.len() < 2
+                    || ids[ids.len() - 2]
+                        .value
+                        .eq_ignore_ascii_case(&scan.def.name))
+        }),
+        _ => false,
+    };
+    (left == outer && names_key(right)) || (right == outer && names_key(left))
+}
+
+/// Steps the query takes after reading its tables, as the `Extra` of the first table.
+fn post_processing(node: &Node<'_>, out: &mut Vec<&'static str>) {
+    match node.plan {
+        LogicalPlan::Aggregate { group_by, .. }
+            if !group_by.is_empty() && !out.contains(&"Using temporary") =>
+        {
+            out.push("Using temporary")
+        }
+        LogicalPlan::Distinct(_) if !out.contains(&"Using temporary") => {
+            out.push("Using temporary")
+        }
+        LogicalPlan::Sort { .. } => out.push("Using filesort"),
+        _ => {}
+    }
+    for child in &node.children {
+        post_processing(child, out);
+    }
+}
+
+fn traditional_columns() -> Vec<(&'static str, ColumnType)> {
+    let text = ColumnType::MYSQL_TYPE_VAR_STRING;
+    let int = ColumnType::MYSQL_TYPE_LONGLONG;
+    vec![
+        ("id", int),
+        ("select_type", text),
+        ("table", text),
+        ("partitions", text),
+        ("type", text),
+        ("possible_keys", text),
+        ("key", text),
+        ("key_len", text),
+        ("ref", text),
+        ("rows", int),
+        ("filtered", ColumnType::MYSQL_TYPE_DOUBLE),
+        ("Extra", text),
+    ]
+}
+
+fn traditional(node: &Node<'_>) -> Tabular {
+    let text = |s: &str| Cell::Text(s.into());
+    let opt = |s: Option<&str>| s.map_or(Cell::Null, text);
+    if let Some(reason) = impossible(node) {
+        let mut row = vec![Cell::Int(1), text("SIMPLE")];
+        row.extend(std::iter::repeat_n(Cell::Null, 9));
+        row.push(text(reason));
+        return Tabular {
+            columns: traditional_columns(),
+            rows: vec![row],
+        };
+    }
+
+    let mut tables = Vec::new();
+    table_accesses(node, &mut tables);
+    let mut post = Vec::new();
+    post_processing(node, &mut post);
+    let rows = tables
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let mut extra = t.extra();
+            if i == 0 {
+                extra.extend(&post);
+            }
+            vec![
+                Cell::Int(1),
+                text("SIMPLE"),
+                text(&t.scan.def.name),
+                Cell::Null,
+                text(t.access_type()),
+                opt(t.key()),
+                opt(t.key()),
+                t.key_len()
+                    .map_or(Cell::Null, |l| Cell::Text(l.to_string())),
+                opt(t.reference().as_deref()),
+                Cell::Int(t.rows as i64),
+                Cell::Float(t.filtered()),
+                if extra.is_empty() {
+                    Cell::Null
+                } else {
+                    Cell::Text(extra.join("; "))
+                },
+            ]
+        })
+        .collect();
+    Tabular {
+        columns: traditional_columns(),
+        rows,
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Tree format
+// ---------------------------------------------------------------------------------------------
+
+fn tree(node: &Node<'_>, profiles: Option<&[OpProfile]>) -> String {
+    let mut out = String::new();
+    tree_lines(node, profiles, 0, &mut out);
+    out.truncate(out.trim_end().len());
+    out
+}
+
+fn tree_lines(node: &Node<'_>, profiles: Option<&[OpProfile]>, depth: usize, out: &mut String) {
+    // Projection is part of producing each row, not a step of its own.
+    let depth = match node.plan {
+        LogicalPlan::Project { .. } => depth,
+        _ => {
+            let profile = profiles.map(|p| p.get(node.id));
+            let _ = writeln!(
+                out,
+                "{:indent$}-> {}  (rows={}){}",
+                "",
+                tree_label(node),
+                node.rows,
+                profile.map_or(String::new(), actual),
+                indent = depth * 4
+            );
+            depth + 1
+        }
+    };
+    if matches!(node.plan, LogicalPlan::Empty(_)) {
+        return;
+    }
+    for child in &node.children {
+        tree_lines(child, profiles, depth, out);
+    }
+}
+
+/// EXPLAIN ANALYZE's measurements of an operator, in milliseconds.
+fn actual(profile: Option<&OpProfile>) -> String {
+    match profile {
+        Some(p) if p.loops > 0 => {
+            let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+            format!(
+                " (actual time={:.3}..{:.3} rows={} loops={})",
+                ms(p.first_row.unwrap_or(p.elapsed)),
+                ms(p.elapsed),
+                p.rows,
+                p.loops
+            )
+        }
+        _ => " (never executed)".into(),
+    }
+}
+
+fn tree_label(node: &Node<'_>) -> String {
+    match node.plan {
+        LogicalPlan::Scan(scan) => scan_label(scan),
+        LogicalPlan::Empty(_) => {
+            format!(
+                "Zero rows ({})",
+                impossible(node).unwrap_or("Impossible WHERE")
+            )
+        }
+        LogicalPlan::Filter { predicate, .. } => format!("Filter: ({predicate})"),
+        LogicalPlan::Join {
+            kind, on, strategy, ..
+        } => {
+            let kind = match kind {
+                JoinKind::Inner => "inner",
+                // A right join runs as a left join with its inputs swapped.
+                JoinKind::Left | JoinKind::Right => "left",
+            };
+            let join = match strategy {
+                JoinStrategy::Hash => {
+                    let mut kind = kind.to_string();
+                    kind[..1].make_ascii_uppercase();
+                    format!("{kind} hash join")
+                }
+                JoinStrategy::NestedLoop | JoinStrategy::IndexLookup => {
+                    format!("Nested loop {kind} join")
+                }
+            };
+            match on {
+                Some(on) => format!("{join} ({on})"),
+                None => join,
+            }
+        }
+        LogicalPlan::Project { .. } => "Project".into(),
+        LogicalPlan::Aggregate {
+            group_by,
+            aggregates,
+            ..
+        } => {
+            if group_by.is_empty() {
+                let calls: Vec<String> = aggregates
+                    .iter()
+                    .map(|a| {
+                        let func = match a.func {
+                            AggFunc::Count => "count",
+                            AggFunc::Sum => "sum",
+                            AggFunc::Avg => "avg",
+                            AggFunc::Min => "min",
+                            AggFunc::Max => "max",
+                        };
+                        match &a.arg {
+                            Some(arg) => format!("{func}({arg})"),
+                            None => format!("{func}(*)"),
+                        }
+                    })
+                    .collect();
+                format!("Aggregate: {}", calls.join(", "))
+            } else {
+                "Aggregate using temporary table".into()
+            }
+        }
+        LogicalPlan::Distinct(_) => "Temporary table with deduplication".into(),
+        LogicalPlan::Sort { input, keys } => {
+            let names: Vec<String> = match input.schema() {
+                Schema::Columns(names) => names.into_iter().map(str::to_string).collect(),
+                Schema::Tables(_) => Vec::new(),
+            };
+            let keys: Vec<String> = keys
+                .iter()
+                .map(|k| {
+                    let key = match &k.expr {
+                        SortExpr::Column(e) => e.to_string(),
+                        SortExpr::Output(i) => names.get(*i).cloned().unwrap_or_default(),
+                    };
+                    if k.desc {
+                        format!("{key} DESC")
+                    } else {
+                        key
+                    }
+                })
+                .collect();
+            format!("Sort: {}", keys.join(", "))
+        }
+        LogicalPlan::Limit { offset, limit, .. } => {
+            let limit = limit.map_or(u64::MAX, |l| l as u64);
+            if *offset > 0 {
+                format!("Limit/Offset: {limit}/{offset} row(s)")
+            } else {
+                format!("Limit: {limit} row(s)")
+            }
+        }
+    }
+}
+
+fn scan_label(scan: &Scan) -> String {
+    let table = &scan.def.name;
+    let pk = &scan.def.primary_key;
+    match &scan.access {
+        Access::FullScan => format!("Table scan on {table}"),
+        Access::PkRange(Some(lo), Some(hi)) if lo == hi => {
+            format!("Single-row index lookup on {table} using PRIMARY ({pk}={lo})")
+        }
+        Access::PkRange(lo, hi) => {
+            let range = match (lo, hi) {
+                (Some(lo), Some(hi)) => format!("{lo} <= {pk} <= {hi}"),
+                (Some(lo), None) => format!("{lo} <= {pk}"),
+                (None, Some(hi)) => format!("{pk} <= {hi}"),
+                (None, None) => pk.clone(),
+            };
+            format!("Index range scan on {table} using PRIMARY over ({range})")
+        }
+        Access::IndexEq { index, value, .. } => {
+            let column = scan
+                .def
+                .indexes
+                .iter()
+                .find(|i| i.name == *index)
+                .and_then(|i| i.columns.first())
+                .cloned()
+                .unwrap_or_default();
+            format!(
+                "Index lookup on {table} using {index} ({column}={})",
+                literal(value)
+            )
+        }
+        Access::Lookup {
+            index: None, outer, ..
+        } => format!("Single-row index lookup on {table} using PRIMARY ({pk}={outer})"),
+        Access::Lookup {
+            index: Some(index),
+            outer,
+            ..
+        } => {
+            let column = scan
+                .def
+                .indexes
+                .iter()
+                .find(|i| i.name == *index)
+                .and_then(|i| i.columns.first())
+                .cloned()
+                .unwrap_or_default();
+            format!("Index lookup on {table} using {index} ({column}={outer})")
+        }
+    }
+}
+
+/// A value as it would be written in SQL.
+fn literal(cell: &Cell) -> String {
+    match cell {
+        Cell::Null => "NULL".into(),
+        Cell::Int(i) => i.to_string(),
+        Cell::Float(f) => f.to_string(),
+        other => format!("'{}'", cell_to_string(other).replace('\'', "''")),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// JSON format
+// ---------------------------------------------------------------------------------------------
+
+enum Json {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Arr(Vec<Json>),
+    Obj(Vec<(&'static str, Json)>),
+}
+
+fn json(node: &Node<'_>) -> String {
+    let mut block = vec![("select_id", Json::Num(1))];
+    if let Some(reason) = impossible(node) {
+        block.push(("message", Json::Str(reason.into())));
+        return render_json(&Json::Obj(vec![("query_block", Json::Obj(block))]));
+    }
+
+    let mut tables = Vec::new();
+    table_accesses(node, &mut tables);
+    let mut tables: Vec<Json> = tables.iter().map(json_table).collect();
+    let mut body = if tables.len() == 1 {
+        ("table", tables.remove(0))
+    } else {
+        (
+            "nested_loop",
+            Json::Arr(
+                tables
+                    .into_iter()
+                    .map(|t| Json::Obj(vec![("table", t)]))
+                    .collect(),
+            ),
+        )
+    };
+    // Wrap the tables in the steps that follow them, innermost first.
+    let mut steps = Vec::new();
+    post_processing_steps(node, &mut steps);
+    for step in steps.into_iter().rev() {
+        let (name, flag) = match step {
+            Step::Grouping => ("grouping_operation", "using_temporary_table"),
+            Step::Deduplication => ("duplicates_removal", "using_temporary_table"),
+            Step::Ordering => ("ordering_operation", "using_filesort"),
+        };
+        body = (name, Json::Obj(vec![(flag, Json::Bool(true)), body]));
+    }
+    block.push(body);
+    render_json(&Json::Obj(vec![("query_block", Json::Obj(block))]))
+}
+
+enum Step {
+    Grouping,
+    Deduplication,
+    Ordering,
+}
+
+/// The steps after reading the tables, outermost first.
+fn post_processing_steps(node: &Node<'_>, out: &mut Vec<Step>) {
+    match node.plan {
+        LogicalPlan::Aggregate { group_by, .. } if !group_by.is_empty() => out.push(Step::Grouping),
+        LogicalPlan::Distinct(_) => out.push(Step::Deduplication),
+        LogicalPlan::Sort { .. } => out.push(Step::Ordering),
+        _ => {}
+    }
+    for child in &node.children {
+        post_processing_steps(child, out);
+    }
+}
+
+fn json_table(table: &TableAccess<'_>) -> Json {
+    let text = |s: &str| Json::Str(s.into());
+    let mut fields = vec![
+        ("table_name", text(&table.scan.def.name)),
+        ("access_type", text(table.access_type())),
+    ];
+    if let Some(key) = table.key() {
+        fields.push(("possible_keys", Json::Arr(vec![text(key)])));
+        fields.push(("key", text(key)));
+        if let Some(column) = table.key_column() {
+            fields.push(("used_key_parts", Json::Arr(vec![text(column)])));
+        }
+        if let Some(len) = table.key_len() {
+            fields.push(("key_length", Json::Str(len.to_string())));
+        }
+    }
+    if let Some(reference) = table.reference() {
+        fields.push(("ref", Json::Arr(vec![text(&reference)])));
+    }
+    let filtered = table.filtered();
+    fields.push(("rows_examined_per_scan", Json::Num(table.rows)));
+    fields.push((
+        "rows_produced_per_join",
+        Json::Num((table.rows as f64 * filtered / 100.0).ceil() as u64),
+    ));
+    fields.push(("filtered", Json::Str(format!("{filtered:.2}"))));
+    if let Some(join_buffer) = table.join_buffer {
+        fields.push(("using_join_buffer", text(join_buffer)));
+    }
+    if !table.conditions.is_empty() {
+        let conditions: Vec<String> = table.conditions.iter().map(|c| format!("({c})")).collect();
+        fields.push(("attached_condition", Json::Str(conditions.join(" and "))));
+    }
+    if let ScanSource::InformationSchema(_) = table.scan.source {
+        fields.push(("message", text("information_schema table")));
+    }
+    Json::Obj(fields)
+}
+
+fn render_json(value: &Json) -> String {
+    let mut out = String::new();
+    write_json(value, 0, &mut out);
+    out
+}
+
+fn write_json(value: &Json, indent: usize, out: &mut String) {
+    let pad = |n: usize| " ".repeat(n * 2);
+    match value {
+        Json::Str(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    c if c.is_control() => {
+                        let _ = write!(out, "\\u{:04x}", c as u32);
+                    }
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Json::Num(n) => out.push_str(&n.to_string()),
+        Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Json::Arr(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&pad(indent + 1));
+                write_json(item, indent + 1, out);
+            }
+            out.push('\n');
+            out.push_str(&pad(indent));
+            out.push(']');
+        }
+        Json::Obj(fields) => {
+            out.push('{');
+            for (i, (key, item)) in fields.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                let _ = write!(out, "{}\"{key}\": ", pad(indent + 1));
+                write_json(item, indent + 1, out);
+            }
+            out.push('\n');
+            out.push_str(&pad(indent));
+            out.push('}');
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use crate::auth::{stage2_from_password, Priv};
 use crate::error::MiniError;
-use crate::model::{Cell, IndexDef, Row, TableDef, TransactionId, UserRecord};
-use parking_lot::{Mutex, RwLock};
-use serde::{Deserialize, Serialize};
-use sled::{Batch, IVec};
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use crate::model::{
+    Cell, Histogram, IndexDef, Row, TableAnalysis, TableDef, TableStats, TransactionId, UserRecord,
+};
+use parking_lot::{Condvar, Mutex, RwLock};
+use sled::transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree};
+use sled::{Batch, IVec, Transactional};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
 pub struct Store {
@@ -15,23 +18,204 @@ pub struct Store {
     catalog: sled::Tree,
     data: sled::Tree,
     locks: Arc<LockManager>,
+    // Table and global locks. They outlive transactions, so they are kept apart from row locks.
+    table_locks: Arc<LockManager<TableLockMode>>,
     pub txn_manager: Arc<TransactionManager>,
+    // Serializes version/index writes with purge so index cleanup never races a commit.
+    write_lock: Arc<Mutex<()>>,
+    // Rows that received a new version and may have older versions to purge.
+    history: Arc<Mutex<Vec<PurgeItem>>>,
+    // Background purge requested by commits; see `request_purge`.
+    purge: Arc<Mutex<PurgeState>>,
+    deadlock_detect: Arc<AtomicBool>,
+    // Makes commits durable according to the flush policy.
+    commit_flush: Arc<GroupCommit>,
+    // `secure_file_priv`: the directory file import and export are limited to. `None` disables
+    // them; an empty path allows any location.
+    secure_file_priv: Arc<RwLock<Option<PathBuf>>>,
+    // Test hook: the DDL step named here fails as if the process died mid-way.
+    #[cfg(test)]
+    fail_at: Arc<Mutex<Option<&'static str>>>,
+}
+
+/// A row whose older versions become purgeable once `tx_id` is visible to every snapshot.
+#[derive(Debug, Clone)]
+struct PurgeItem {
+    tx_id: TransactionId,
+    db: String,
+    table: String,
+    pk: i64,
+}
+
+/// Versions purged under one hold of the write lock, so commits can run between batches.
+const PURGE_BATCH: usize = 256;
+
+#[derive(Default)]
+struct PurgeState {
+    // The purge horizon the latest requested purge runs against.
+    horizon: TransactionId,
+    // A worker thread is purging; `requested` asks it for another pass before it exits.
+    running: bool,
+    requested: bool,
+}
+
+/// When a commit's writes are flushed to disk (`innodb_flush_log_at_trx_commit`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// 0: commits return without flushing; a background flusher syncs once per second.
+    PerSecond,
+    /// 1: every commit waits for a flush; concurrent committers share one (group commit).
+    PerCommit,
+    /// 2: commits never flush; sled's own background writer syncs them.
+    OsManaged,
+}
+
+impl FlushPolicy {
+    pub fn from_level(level: i64) -> Option<Self> {
+        match level {
+            0 => Some(Self::PerSecond),
+            1 => Some(Self::PerCommit),
+            2 => Some(Self::OsManaged),
+            _ => None,
+        }
+    }
+
+    pub fn level(self) -> u8 {
+        match self {
+            Self::PerSecond => 0,
+            Self::PerCommit => 1,
+            Self::OsManaged => 2,
+        }
+    }
+}
+
+/// Group commit: committers take a ticket after writing their batch, and whoever finds no
+/// flush in progress flushes for every ticket issued so far while the rest wait for it.
+struct GroupCommit {
+    db: sled::Db,
+    policy: AtomicU8,
+    state: Mutex<GroupState>,
+    flushed: Condvar,
+}
+
+#[derive(Default)]
+struct GroupState {
+    // Tickets handed out / covered by a completed flush.
+    requested: u64,
+    flushed: u64,
+    flushing: bool,
+    // Completed flushes, for tests.
+    flushes: u64,
+}
+
+impl GroupCommit {
+    fn new(db: sled::Db) -> Self {
+        Self {
+            db,
+            policy: AtomicU8::new(FlushPolicy::PerCommit.level()),
+            state: Mutex::new(GroupState::default()),
+            flushed: Condvar::new(),
+        }
+    }
+
+    fn policy(&self) -> FlushPolicy {
+        FlushPolicy::from_level(self.policy.load(Ordering::SeqCst) as i64)
+            .unwrap_or(FlushPolicy::PerCommit)
+    }
+
+    /// Called once a commit's batch is applied; returns when the policy considers it durable.
+    fn commit(&self) -> Result<(), MiniError> {
+        let ticket = {
+            let mut state = self.state.lock();
+            state.requested += 1;
+            state.requested
+        };
+        if self.policy() == FlushPolicy::PerCommit {
+            self.flush_through(ticket)?;
+        }
+        Ok(())
+    }
+
+    /// Flush until every ticket up to `ticket` is on disk, leading a flush or joining one.
+    fn flush_through(&self, ticket: u64) -> Result<(), MiniError> {
+        let mut state = self.state.lock();
+        loop {
+            if state.flushed >= ticket {
+                return Ok(());
+            }
+            if state.flushing {
+                self.flushed.wait(&mut state);
+                continue;
+            }
+            // Batches behind every ticket issued so far are applied; one flush covers them all.
+            let target = state.requested;
+            state.flushing = true;
+            drop(state);
+            let res = self.db.flush();
+            state = self.state.lock();
+            state.flushing = false;
+            if res.is_ok() {
+                state.flushed = state.flushed.max(target);
+                state.flushes += 1;
+            }
+            self.flushed.notify_all();
+            res?;
+        }
+    }
+
+    /// Background flusher for `FlushPolicy::PerSecond`; exits once the store is dropped.
+    fn spawn_flusher(this: Weak<Self>) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let Some(group) = this.upgrade() else {
+                return;
+            };
+            if group.policy() == FlushPolicy::PerSecond {
+                let pending = group.state.lock().requested;
+                if let Err(e) = group.flush_through(pending) {
+                    eprintln!("background flush failed: {e}");
+                }
+            }
+        });
+    }
 }
 
 pub struct TransactionManager {
     // Global counter for Transction IDs.
     // We use a simple counter. Real systems use timestamps or hybrid clocks.
     next_tx_id: AtomicU64,
-    // Set of currently active (uncommitted) transaction IDs.
-    // Used to compute snapshots: "what was active when I started?"
-    active_txns: RwLock<BTreeSet<TransactionId>>,
+    // Currently active (uncommitted) transaction IDs, mapped to the low limit of their read view
+    // (the oldest TxID that view cannot see). Keys compute snapshots; values bound purge.
+    active_txns: RwLock<BTreeMap<TransactionId, TransactionId>>,
+    // Low limits of read views pinned by result streams that outlive their transaction,
+    // with a count per limit.
+    pinned_views: Mutex<BTreeMap<TransactionId, usize>>,
+}
+
+/// Holds back purge for a read view until dropped; see `TransactionManager::pin_view`.
+pub struct ViewPin {
+    txn_manager: Arc<TransactionManager>,
+    low_limit: TransactionId,
+}
+
+impl Drop for ViewPin {
+    fn drop(&mut self) {
+        let mut pinned = self.txn_manager.pinned_views.lock();
+        if let Some(count) = pinned.get_mut(&self.low_limit) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&self.low_limit);
+            }
+        }
+    }
 }
 
 impl TransactionManager {
     pub fn new() -> Self {
         Self {
             next_tx_id: AtomicU64::new(1),
-            active_txns: RwLock::new(BTreeSet::new()),
+            active_txns: RwLock::new(BTreeMap::new()),
+            pinned_views: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -39,17 +223,24 @@ impl TransactionManager {
         self.next_tx_id.store(id, Ordering::SeqCst);
     }
 
+    /// The highest TxID handed out so far.
+    pub fn last_tx_id(&self) -> TransactionId {
+        self.next_tx_id.load(Ordering::SeqCst) - 1
+    }
+
     /// Start a new transaction. Returns the new TxID and a "Read View".
     /// The Read View is the list of transactions that were active when this one started.
     /// In Snapshot Isolation, we should NOT see writes from these transactions (unless it's us).
     pub fn start_txn(&self) -> (TransactionId, ReadView) {
-        let tx_id = self.next_tx_id.fetch_add(1, Ordering::SeqCst);
+        // Allocate under the lock so no snapshot can miss a TxID that is already handed out.
         let mut active = self.active_txns.write();
+        let tx_id = self.next_tx_id.fetch_add(1, Ordering::SeqCst);
 
         // Construct the Read View: copy current active set
-        let snapshot = active.clone();
+        let snapshot: BTreeSet<TransactionId> = active.keys().copied().collect();
+        let low_limit = snapshot.first().copied().unwrap_or(tx_id).min(tx_id);
 
-        active.insert(tx_id);
+        active.insert(tx_id, low_limit);
 
         (
             tx_id,
@@ -61,6 +252,56 @@ impl TransactionManager {
         )
     }
 
+    /// A view of the latest committed state, used by locking (current) reads. Unlike a
+    /// snapshot it is not registered, so it does not hold back purge.
+    pub fn current_view(&self, own_tx_id: Option<TransactionId>) -> ReadView {
+        let active = self.active_txns.read();
+        ReadView {
+            visible_up_to: self.next_tx_id.load(Ordering::SeqCst),
+            active: active
+                .keys()
+                .copied()
+                .filter(|tx| Some(*tx) != own_tx_id)
+                .collect(),
+            own_tx_id,
+        }
+    }
+
+    /// Give an active transaction a new read view as of now (READ COMMITTED takes one per
+    /// statement) and move its purge low limit forward to match. With `read_uncommitted` the
+    /// view sees every version, including those of transactions still committing.
+    pub fn refresh_view(&self, tx_id: TransactionId, read_uncommitted: bool) -> ReadView {
+        let mut active = self.active_txns.write();
+        let next = self.next_tx_id.load(Ordering::SeqCst);
+        let snapshot: BTreeSet<TransactionId> = if read_uncommitted {
+            BTreeSet::new()
+        } else {
+            active.keys().copied().filter(|tx| *tx != tx_id).collect()
+        };
+        let low_limit = snapshot.first().copied().unwrap_or(next).min(next);
+        active.insert(tx_id, low_limit);
+        ReadView {
+            visible_up_to: if read_uncommitted {
+                TransactionId::MAX
+            } else {
+                next
+            },
+            active: snapshot,
+            own_tx_id: Some(tx_id),
+        }
+    }
+
+    /// Move an active transaction to a fresh TxID, keeping its purge low limit. Used at commit
+    /// when the transaction overwrites versions newer than its own TxID (rows it read with a
+    /// locking read), so that its versions still sort as the newest.
+    pub fn renew_txn_id(&self, tx_id: TransactionId) -> TransactionId {
+        let mut active = self.active_txns.write();
+        let new_id = self.next_tx_id.fetch_add(1, Ordering::SeqCst);
+        let low_limit = active.remove(&tx_id).unwrap_or(new_id);
+        active.insert(new_id, low_limit);
+        new_id
+    }
+
     pub fn commit_txn(&self, tx_id: TransactionId) {
         let mut active = self.active_txns.write();
         active.remove(&tx_id);
@@ -70,6 +311,99 @@ impl TransactionManager {
         let mut active = self.active_txns.write();
         active.remove(&tx_id);
     }
+
+    /// Every version written by a TxID below the horizon is visible to all current and future
+    /// read views, so anything such a version supersedes can be purged.
+    pub fn purge_horizon(&self) -> TransactionId {
+        let active = self.active_txns.read();
+        let pinned = self.pinned_views.lock().keys().next().copied();
+        active
+            .values()
+            .copied()
+            .chain(pinned)
+            .min()
+            .unwrap_or_else(|| self.next_tx_id.load(Ordering::SeqCst))
+    }
+
+    /// Keep the versions `view` can see from being purged until the pin is dropped, even after
+    /// the transaction that owns the view has ended. Result streams hold one while they read.
+    pub fn pin_view(self: &Arc<Self>, view: &ReadView) -> ViewPin {
+        let low_limit = view
+            .active
+            .first()
+            .copied()
+            .unwrap_or(view.visible_up_to)
+            .min(view.visible_up_to);
+        *self.pinned_views.lock().entry(low_limit).or_insert(0) += 1;
+        ViewPin {
+            txn_manager: self.clone(),
+            low_limit,
+        }
+    }
+}
+
+/// The rows of one table visible to a read view, in primary key order; see
+/// `Store::scan_rows_iter`.
+pub struct RowScan {
+    cursor: std::iter::Chain<sled::Iter, sled::Iter>,
+    prefix_len: usize,
+    view: ReadView,
+    current_pk: Option<i64>,
+    // Whether a visible version of `current_pk` was found already.
+    pk_found: bool,
+}
+
+impl Iterator for RowScan {
+    type Item = Result<(i64, Row), MiniError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Keys: [prefix] [pk: 8 bytes] [tx_inv: 8 bytes]
+        // Sorted by pk ASC, then tx_inv ASC (Newest first).
+        loop {
+            let (k, v) = match self.cursor.next()? {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e.into())),
+            };
+            // k should be prefix.len() + 16 bytes.
+            if k.len() < self.prefix_len + 16 {
+                continue; // Should not happen if consistent
+            }
+
+            // Extract PK.
+            let pk_start = self.prefix_len;
+            let pk_bytes: [u8; 8] = k[pk_start..pk_start + 8].try_into().unwrap();
+            let pk = i64::from_be_bytes(pk_bytes);
+
+            // Extract TxID.
+            let tx_id = match Store::parse_tx_id_from_key(&k) {
+                Ok(tx_id) => tx_id,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if Some(pk) != self.current_pk {
+                // New PK.
+                self.current_pk = Some(pk);
+                self.pk_found = false;
+            }
+
+            if self.pk_found {
+                // We already found a visible version for this PK. Skip older versions.
+                continue;
+            }
+
+            if self.view.is_visible(tx_id) {
+                // Found the visible version.
+                self.pk_found = true;
+                match bincode::deserialize::<Option<Row>>(&v) {
+                    Ok(Some(row)) => return Some(Ok((pk, row))),
+                    // Deleted. pk_found stays set so older versions (where it might exist) are
+                    // skipped.
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e.into())),
+                }
+            }
+        }
+    }
 }
 
 /// Defines which transaction IDs are visible to a reader.
@@ -118,18 +452,43 @@ impl Store {
         let txn_manager = Arc::new(TransactionManager::new());
         txn_manager.set_next_tx_id(next_id);
 
-        Ok(Self {
+        let commit_flush = Arc::new(GroupCommit::new(db.clone()));
+        GroupCommit::spawn_flusher(Arc::downgrade(&commit_flush));
+
+        let store = Self {
             db,
             catalog,
             data,
             locks: Arc::new(LockManager::default()),
+            table_locks: Arc::new(LockManager::default()),
             txn_manager,
-        })
+            write_lock: Arc::new(Mutex::new(())),
+            history: Arc::new(Mutex::new(Vec::new())),
+            purge: Arc::new(Mutex::new(PurgeState::default())),
+            deadlock_detect: Arc::new(AtomicBool::new(true)),
+            commit_flush,
+            secure_file_priv: Arc::new(RwLock::new(None)),
+            #[cfg(test)]
+            fail_at: Arc::new(Mutex::new(None)),
+        };
+        // A bulk load the previous run did not finish never committed.
+        store.recover_bulk_loads()?;
+        // Temporary tables belong to connections of the previous run.
+        store.drop_temp_tables()?;
+        // Purge history is kept in memory; sweep whatever the previous run left behind.
+        store.purge_all()?;
+        Ok(store)
     }
 
     pub fn ensure_root_user(&self, password: &str) -> Result<(), MiniError> {
         let key = Self::user_key("root", "%");
-        if self.catalog.get(&key)?.is_some() {
+        if let Some(v) = self.catalog.get(&key)? {
+            // Root holds every privilege, including ones added after its record was stored.
+            let mut record: UserRecord = bincode::deserialize(&v)?;
+            if record.global_privs & Priv::ALL.bits() != Priv::ALL.bits() {
+                record.global_privs |= Priv::ALL.bits();
+                self.put_user(&record)?;
+            }
             return Ok(());
         }
         let record = UserRecord {
@@ -189,6 +548,11 @@ impl Store {
     }
 
     pub fn create_database(&self, name: &str) -> Result<(), MiniError> {
+        if name.starts_with(TEMP_SCHEMA_PREFIX) {
+            return Err(MiniError::Invalid(format!(
+                "Incorrect database name '{name}'"
+            )));
+        }
         let k = Self::db_key(name);
         if self.catalog.get(&k)?.is_some() {
             return Err(MiniError::Invalid(format!(
@@ -205,41 +569,41 @@ impl Store {
         if self.catalog.get(&k)?.is_none() {
             return Err(MiniError::NotFound(format!("unknown database: {name}")));
         }
-        // Drop tables + rows.
-        let prefix = Self::table_prefix(name);
-        let tables: Vec<Vec<u8>> = self
-            .catalog
-            .scan_prefix(prefix)
-            .map(|r| r.map(|(k, _)| k.to_vec()))
-            .collect::<Result<_, _>>()?;
-        for tkey in tables {
-            self.catalog.remove(tkey)?;
-        }
-
-        let ai_prefix = Self::auto_inc_prefix(name);
-        let ai_keys: Vec<Vec<u8>> = self
-            .catalog
-            .scan_prefix(ai_prefix)
-            .map(|r| r.map(|(k, _)| k.to_vec()))
-            .collect::<Result<_, _>>()?;
-        for akey in ai_keys {
-            self.catalog.remove(akey)?;
-        }
-
-        let row_prefix = Self::row_prefix(name, "");
-        let row_keys: Vec<Vec<u8>> = self
-            .data
-            .scan_prefix(row_prefix)
-            .map(|r| r.map(|(k, _)| k.to_vec()))
-            .collect::<Result<_, _>>()?;
-        for rkey in row_keys {
-            self.data.remove(rkey)?;
-        }
 
-        self.catalog.remove(k)?;
-        self.catalog.flush()?;
-        self.data.flush()?;
-        Ok(())
+        let _guard = self.write_lock.lock();
+        // Tables, auto-increment counters, statistics, rows and index entries go in one
+        // transaction.
+        let catalog_keys = Self::collect_keys(&self.catalog, Self::table_prefix(name))?
+            .into_iter()
+            .chain(Self::collect_keys(
+                &self.catalog,
+                Self::auto_inc_prefix(name),
+            )?)
+            .chain(Self::collect_keys(
+                &self.catalog,
+                Self::analysis_prefix(name),
+            )?)
+            .chain([k])
+            .collect::<Vec<_>>();
+        let data_keys = Self::collect_keys(&self.data, Self::row_prefix(name, ""))?
+            .into_iter()
+            .chain(Self::collect_keys(
+                &self.data,
+                Self::index_table_prefix(name, ""),
+            )?)
+            .chain(Self::collect_keys(&self.data, Self::stats_prefix(name))?)
+            .collect::<Vec<_>>();
+
+        self.ddl_transaction(|catalog, data| {
+            for key in &catalog_keys {
+                catalog.remove(key.as_slice())?;
+            }
+            self.fail_point("drop_database")?;
+            for key in &data_keys {
+                data.remove(key.as_slice())?;
+            }
+            Ok(())
+        })
     }
 
     pub fn list_tables(&self, db: &str) -> Result<Vec<String>, MiniError> {
@@ -268,10 +632,7 @@ impl Store {
 
     pub fn create_table(&self, def: &TableDef) -> Result<(), MiniError> {
         // Ensure db exists
-        let dbk = Self::db_key(&def.db);
-        if self.catalog.get(&dbk)?.is_none() {
-            return Err(MiniError::NotFound(format!("unknown database: {}", def.db)));
-        }
+        self.check_table_db(def)?;
         let key = Self::table_key(&def.db, &def.name);
         if self.catalog.get(&key)?.is_some() {
             return Err(MiniError::Invalid(format!(
@@ -284,16 +645,78 @@ impl Store {
         Ok(())
     }
 
-    pub fn update_table(&self, def: &TableDef) -> Result<(), MiniError> {
+    /// Create a table with its first rows (`CREATE TABLE ... AS SELECT`). The definition and
+    /// the rows commit in one transaction, so a crash never leaves a half-filled table.
+    pub fn create_table_with_rows(
+        &self,
+        def: &TableDef,
+        rows: &[(i64, Row)],
+    ) -> Result<(), MiniError> {
+        self.check_table_db(def)?;
         let key = Self::table_key(&def.db, &def.name);
-        if self.catalog.get(&key)?.is_none() {
-            return Err(MiniError::NotFound(format!(
-                "unknown table: {}.{}",
+        if self.catalog.get(&key)?.is_some() {
+            return Err(MiniError::Invalid(format!(
+                "table already exists: {}.{}",
                 def.db, def.name
             )));
         }
-        self.catalog.insert(key, bincode::serialize(def)?)?;
-        self.catalog.flush()?;
+        let def_bytes = bincode::serialize(def)?;
+        let (tx_id, _) = self.txn_manager.start_txn();
+        let res: Result<(), MiniError> = (|| {
+            let _guard = self.write_lock.lock();
+            let changes = rows
+                .iter()
+                .map(|(pk, row)| (def.db.as_str(), def.name.as_str(), *pk, Some(row)))
+                .collect();
+            let (batch, purge_items) = self.version_batch(changes, tx_id, Some(def))?;
+            self.ddl_transaction(|catalog, data| {
+                catalog.insert(key.as_slice(), def_bytes.as_slice())?;
+                self.fail_point("create_table_with_rows")?;
+                data.apply_batch(&batch)?;
+                Ok(())
+            })?;
+            self.history.lock().extend(purge_items);
+            Ok(())
+        })();
+        match res {
+            Ok(()) => self.txn_manager.commit_txn(tx_id),
+            Err(_) => self.txn_manager.rollback_txn(tx_id),
+        }
+        res
+    }
+
+    // A table's database must exist; a temporary table's is the one it was created in.
+    fn check_table_db(&self, def: &TableDef) -> Result<(), MiniError> {
+        let db = Self::temp_schema_db(&def.db).unwrap_or(&def.db);
+        if self.catalog.get(Self::db_key(db))?.is_none() {
+            return Err(MiniError::NotFound(format!("unknown database: {db}")));
+        }
+        Ok(())
+    }
+
+    /// The schema connection `conn_id` keeps its temporary tables of database `db` in. They are
+    /// stored like base tables, but no database lists them, and they are dropped when the
+    /// connection ends (or, after a crash, when the store is opened again).
+    pub fn temp_schema(conn_id: u32, db: &str) -> String {
+        format!("{TEMP_SCHEMA_PREFIX}{conn_id}-{db}")
+    }
+
+    /// The database a temporary table schema stands for; `None` for other schemas.
+    pub fn temp_schema_db(schema: &str) -> Option<&str> {
+        let rest = schema.strip_prefix(TEMP_SCHEMA_PREFIX)?;
+        rest.split_once('-').map(|(_, db)| db)
+    }
+
+    fn drop_temp_tables(&self) -> Result<(), MiniError> {
+        let mut prefix = b"t\0".to_vec();
+        prefix.extend_from_slice(TEMP_SCHEMA_PREFIX.as_bytes());
+        for key in Self::collect_keys(&self.catalog, prefix)? {
+            // key: t\0<schema>\0<table>
+            let mut parts = key[2..].splitn(2, |b| *b == 0);
+            let schema = String::from_utf8_lossy(parts.next().unwrap_or(&[])).to_string();
+            let table = String::from_utf8_lossy(parts.next().unwrap_or(&[])).to_string();
+            self.drop_table(&schema, &table)?;
+        }
         Ok(())
     }
 
@@ -322,78 +745,109 @@ impl Store {
             }
         }
 
+        // Hold the write lock across the catalog update and the backfill so a concurrent commit
+        // either lands before (and is backfilled) or sees the new index definition.
+        let _guard = self.write_lock.lock();
+
         def.indexes.push(index.clone());
         let new_def_bytes = bincode::serialize(&def)?;
-        self.catalog.insert(&key, new_def_bytes)?;
 
         // 2. Backfill
-        // Scan all rows (latest version) and insert index entries.
-        // We use a simplified scan that ignores visibility? No, we need LATEST committed data.
-        // Or we use a snapshot?
-        // Backfill usually runs in a transaction or blocks?
-        // For MVP, simplistic scan.
-        // Warning: This is not atomic with respect to concurrent writes if we don't lock.
-        // But we are in `create_index`, maybe we should lock table?
-        // `self.locks` is row-level.
-        // Let's assume generic lock or just proceed.
-
-        // Scan all MVCC row versions in this table. Keys are ordered by:
-        // (pk ASC, inverted_tx_id ASC), so the first key per PK is the newest version.
-        let table_prefix = Self::row_prefix(db, table);
-
-        // We need to group by PK to find latest version.
-        // Scan gives keys sorted.
-        // `r/db/table/pk1/MAX-tx1`
-        // `r/db/table/pk1/MAX-tx2`
-        // `r/db/table/pk2/...`
-        // So we encounter LATEST version of PK1 first.
+        // Index entries are version-aware: every stored version (not just the latest) gets an
+        // entry so older snapshots can still find the values they see. Readers recheck each hit
+        // against their own view, and purge drops entries once no snapshot needs them.
+        // Assuming single column for MVP
+        let col_name = &index.columns[0];
+        let col_idx = def
+            .columns
+            .iter()
+            .position(|c| &c.name == col_name)
+            .unwrap();
+
+        if index.unique {
+            // Latest versions only: older ones are history and may repeat a value legitimately.
+            let mut seen = HashSet::new();
+            let mut duplicate = None;
+            self.for_each_latest_row(db, table, |_, _, row| {
+                let val = &row.values[col_idx];
+                if duplicate.is_none() && *val != Cell::Null && !seen.insert(val.clone()) {
+                    duplicate = Some(val.clone());
+                }
+            })?;
+            if let Some(val) = duplicate {
+                return Err(MiniError::DuplicateEntry(
+                    val.to_string(),
+                    format!("{table}.{}", index.name),
+                ));
+            }
+        }
 
-        let mut current_pk: Option<i64> = None;
+        let table_prefix = Self::row_prefix(db, table);
         let mut batch = Batch::default();
 
         for item in self.data.scan_prefix(&table_prefix) {
             let (k, v) = item?;
-            // Parse PK from key.
-            // Key format: `r\0db\0table\0` ... then what?
-            // `row_prefix_mvcc` does: `b"r\0" + db + 0 + table + 0 + pk_bytes`.
-            // So we can extract PK.
-            let header_len = table_prefix.len();
-            if k.len() < header_len + 8 + 8 {
-                // pk(8) + tx(8)
+            let Some(pk) = Self::parse_pk_from_row_key(&k, table_prefix.len()) else {
                 continue;
-            }
-            let pk_bytes: [u8; 8] = k[header_len..header_len + 8].try_into().unwrap();
-            let pk = i64::from_be_bytes(pk_bytes);
-
-            if Some(pk) != current_pk {
-                current_pk = Some(pk);
-                // This is the latest version for this PK (because scan is ordered and TxID inverted).
-                // Deserialize and Add Index.
-                // Value is Option<Row>
-                let row_opt: Option<Row> = bincode::deserialize(&v)?;
-                let Some(row) = row_opt else {
-                    // Tombstone (deleted row), skip index creation
-                    continue;
-                };
-
-                // Add index entry
-                // Assuming single column for MVP
-                let col_name = &index.columns[0];
-                let col_idx = def
-                    .columns
-                    .iter()
-                    .position(|c| &c.name == col_name)
-                    .unwrap();
-                let val = &row.values[col_idx];
-
-                let idx_key = Self::index_key(db, table, &index.name, val, pk);
-                batch.insert(idx_key, vec![]);
-            }
+            };
+            // Value is Option<Row>; tombstones have nothing to index.
+            let row_opt: Option<Row> = bincode::deserialize(&v)?;
+            let Some(row) = row_opt else {
+                continue;
+            };
+            let val = &row.values[col_idx];
+            let idx_key = Self::index_key(db, table, &index.name, val, pk);
+            batch.insert(idx_key, vec![]);
         }
+        // The new entries count towards the table's index length.
+        let stats_key = Self::stats_key(db, table);
+        let mut stats = self.measure_table(&def)?;
+        stats.update_time = self.stored_stats(db, table)?.and_then(|s| s.update_time);
+        batch.insert(stats_key, bincode::serialize(&stats)?);
+
+        // The definition and its entries land together: no index is ever half built.
+        self.ddl_transaction(|catalog, data| {
+            catalog.insert(key.as_slice(), new_def_bytes.as_slice())?;
+            self.fail_point("create_index")?;
+            data.apply_batch(&batch)?;
+            Ok(())
+        })
+    }
 
-        self.data.apply_batch(batch)?;
-        self.flush()?;
-
+    /// Add columns to a table: `rows` are the rewritten rows, stored as new versions in the same
+    /// transaction as the new definition.
+    pub fn alter_table(&self, def: &TableDef, rows: &[(i64, Row)]) -> Result<(), MiniError> {
+        let key = Self::table_key(&def.db, &def.name);
+        if self.catalog.get(&key)?.is_none() {
+            return Err(MiniError::NotFound(format!(
+                "unknown table: {}.{}",
+                def.db, def.name
+            )));
+        }
+        let def_bytes = bincode::serialize(def)?;
+        let (tx_id, _) = self.txn_manager.start_txn();
+        let res: Result<(), MiniError> = (|| {
+            let _guard = self.write_lock.lock();
+            let changes = rows
+                .iter()
+                .map(|(pk, row)| (def.db.as_str(), def.name.as_str(), *pk, Some(row)))
+                .collect();
+            let (batch, purge_items) = self.version_batch(changes, tx_id, Some(def))?;
+            self.ddl_transaction(|catalog, data| {
+                data.apply_batch(&batch)?;
+                self.fail_point("alter_table")?;
+                catalog.insert(key.as_slice(), def_bytes.as_slice())?;
+                Ok(())
+            })?;
+            self.history.lock().extend(purge_items);
+            Ok(())
+        })();
+        match res {
+            Ok(()) => self.txn_manager.commit_txn(tx_id),
+            Err(_) => self.txn_manager.rollback_txn(tx_id),
+        }
+        res?;
+        self.request_purge();
         Ok(())
     }
 
@@ -402,24 +856,127 @@ impl Store {
         if self.catalog.get(&key)?.is_none() {
             return Err(MiniError::NotFound(format!("unknown table: {db}.{table}")));
         }
-        self.catalog.remove(key)?;
-        self.catalog.remove(Self::auto_inc_key(db, table))?;
 
-        let prefix = Self::row_prefix(db, table);
-        let row_keys: Vec<Vec<u8>> = self
-            .data
-            .scan_prefix(prefix)
-            .map(|r| r.map(|(k, _)| k.to_vec()))
-            .collect::<Result<_, _>>()?;
-        for rkey in row_keys {
-            self.data.remove(rkey)?;
+        let _guard = self.write_lock.lock();
+        let data_keys = Self::collect_keys(&self.data, Self::row_prefix(db, table))?
+            .into_iter()
+            .chain(Self::collect_keys(
+                &self.data,
+                Self::index_table_prefix(db, table),
+            )?)
+            .collect::<Vec<_>>();
+        let auto_inc_key = Self::auto_inc_key(db, table);
+        let analysis_key = Self::analysis_key(db, table);
+        let stats_key = Self::stats_key(db, table);
+
+        self.ddl_transaction(|catalog, data| {
+            catalog.remove(key.as_slice())?;
+            catalog.remove(auto_inc_key.as_slice())?;
+            catalog.remove(analysis_key.as_slice())?;
+            data.remove(stats_key.as_slice())?;
+            for (i, k) in data_keys.iter().enumerate() {
+                if i == data_keys.len() / 2 {
+                    self.fail_point("drop_table")?;
+                }
+                data.remove(k.as_slice())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// `TRUNCATE TABLE`: remove every row version and index entry of the table in one batch,
+    /// keeping its definition and `ANALYZE` results. The auto-increment counter starts over and
+    /// the size counters go back to zero.
+    pub fn truncate_table(&self, db: &str, table: &str) -> Result<(), MiniError> {
+        let key = Self::table_key(db, table);
+        if self.catalog.get(&key)?.is_none() {
+            return Err(MiniError::NotFound(format!("unknown table: {db}.{table}")));
         }
 
-        self.catalog.flush()?;
-        self.data.flush()?;
+        let _guard = self.write_lock.lock();
+        let data_keys = Self::collect_keys(&self.data, Self::row_prefix(db, table))?
+            .into_iter()
+            .chain(Self::collect_keys(
+                &self.data,
+                Self::index_table_prefix(db, table),
+            )?)
+            .collect::<Vec<_>>();
+        let auto_inc_key = Self::auto_inc_key(db, table);
+        let stats_key = Self::stats_key(db, table);
+        let stats = bincode::serialize(&TableStats::default())?;
+
+        self.ddl_transaction(|catalog, data| {
+            catalog.remove(auto_inc_key.as_slice())?;
+            data.insert(stats_key.as_slice(), stats.as_slice())?;
+            for (i, k) in data_keys.iter().enumerate() {
+                if i == data_keys.len() / 2 {
+                    self.fail_point("truncate_table")?;
+                }
+                data.remove(k.as_slice())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Apply a DDL change to the catalog and data trees as one sled transaction, so a crash
+    /// leaves either all of it or none of it on disk.
+    fn ddl_transaction<F>(&self, f: F) -> Result<(), MiniError>
+    where
+        F: Fn(
+            &TransactionalTree,
+            &TransactionalTree,
+        ) -> ConflictableTransactionResult<(), MiniError>,
+    {
+        (&self.catalog, &self.data)
+            .transaction(|(catalog, data)| f(catalog, data))
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => MiniError::Storage(e),
+            })?;
+        self.flush()
+    }
+
+    /// Open a store whose previous handle was just dropped. sled may release its file lock
+    /// late, and so does a background purge that is still finishing.
+    #[cfg(test)]
+    pub(crate) fn reopen(path: impl AsRef<Path>) -> Result<Store, MiniError> {
+        for _ in 0..50 {
+            match Store::open(path.as_ref()) {
+                // sled reports a held file lock as `Other`, naming the lock in the message.
+                Err(MiniError::Storage(sled::Error::Io(e)))
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.to_string().contains("could not acquire lock") =>
+                {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                res => return res,
+            }
+        }
+        Store::open(path)
+    }
+
+    #[cfg(test)]
+    fn fail_point(&self, name: &str) -> ConflictableTransactionResult<(), MiniError> {
+        if *self.fail_at.lock() == Some(name) {
+            return Err(sled::transaction::ConflictableTransactionError::Abort(
+                MiniError::Invalid(format!("simulated crash in {name}")),
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(test))]
+    fn fail_point(&self, _name: &str) -> ConflictableTransactionResult<(), MiniError> {
         Ok(())
     }
 
+    fn collect_keys(tree: &sled::Tree, prefix: Vec<u8>) -> Result<Vec<Vec<u8>>, MiniError> {
+        Ok(tree
+            .scan_prefix(prefix)
+            .map(|r| r.map(|(k, _)| k.to_vec()))
+            .collect::<Result<_, _>>()?)
+    }
+
     // MVCC: Read with snapshot isolation.
     pub fn get_row_mvcc(
         &self,
@@ -454,48 +1011,344 @@ impl Store {
         Ok(None)
     }
 
-    pub fn apply_row_changes_mvcc<'a, I>(
+    /// Commit a transaction's write set with first-committer-wins validation: if any written
+    /// row already has a version our `view` cannot see, another transaction committed it after
+    /// our snapshot was taken and the whole commit is rejected with `MiniError::Deadlock`.
+    ///
+    /// Rows for which `locked_read` returns true are exempt: a locking SELECT or the
+    /// UPDATE/DELETE that changed them read them with a current read and locked them, so nobody
+    /// else can have committed them since. If such a row already has a version newer than
+    /// `tx_id`, the transaction is moved to a fresh TxID first. Returns the TxID the versions
+    /// were written with.
+    pub fn commit_row_changes<'a, I>(
         &self,
         changes: I,
         tx_id: TransactionId,
-    ) -> Result<(), MiniError>
+        view: &ReadView,
+        locked_read: impl Fn(&str, &str, i64) -> bool,
+    ) -> Result<TransactionId, MiniError>
     where
         I: IntoIterator<Item = (&'a str, &'a str, i64, Option<&'a Row>)>,
     {
-        let mut batch = Batch::default();
-        for (db, table, pk, new_row) in changes {
-            // Write a NEW version.
-            let key = Self::row_key_mvcc(db, table, pk, tx_id);
-            // Value is Option<Row>.
-            let val = bincode::serialize(&new_row.cloned())?;
-            batch.insert(key, val);
+        let changes: Vec<_> = changes.into_iter().collect();
+        // Validation and the write happen under the same lock, so no commit can slip in between.
+        let _guard = self.write_lock.lock();
+        let renew = self.check_write_conflicts(&changes, tx_id, view, locked_read)?;
+        self.check_unique_keys(&changes)?;
+        let tx_id = if renew {
+            self.txn_manager.renew_txn_id(tx_id)
+        } else {
+            tx_id
+        };
+        self.write_versions_locked(changes, tx_id)?;
+        // Flush outside the write lock so commits queued behind it share the flush.
+        drop(_guard);
+        self.commit_flush.commit()?;
+        Ok(tx_id)
+    }
 
-            // Index Maintenance
-            // 1. Get Old Row (Latest version in DB)
-            // We scan prefix. First item is latest (inverted tx_id).
-            let prefix = Self::row_prefix_mvcc(db, table, pk);
-            let old_row: Option<Row> = if let Some(res) = self.data.scan_prefix(&prefix).next() {
-                let (_, v) = res?;
-                bincode::deserialize(&v)?
-            } else {
-                None
+    /// First-committer-wins validation of a write set; the caller holds `write_lock`. Returns
+    /// whether a row read with a locking read already has a version newer than `tx_id`.
+    fn check_write_conflicts(
+        &self,
+        changes: &[(&str, &str, i64, Option<&Row>)],
+        tx_id: TransactionId,
+        view: &ReadView,
+        locked_read: impl Fn(&str, &str, i64) -> bool,
+    ) -> Result<bool, MiniError> {
+        let mut renew = false;
+        for (db, table, pk, _) in changes {
+            let prefix = Self::row_prefix_mvcc(db, table, *pk);
+            // Newest version first (inverted TxID).
+            let Some(item) = self.data.scan_prefix(&prefix).next() else {
+                continue;
             };
+            let (k, _) = item?;
+            let newest = Self::parse_tx_id_from_key(&k)?;
+            if locked_read(db, table, *pk) {
+                renew |= newest > tx_id;
+            } else if !view.is_visible(newest) {
+                return Err(MiniError::Deadlock(format!(
+                    "row {pk} in {db}.{table} was changed by a transaction that committed after this one started"
+                )));
+            }
+        }
+        Ok(renew)
+    }
+
+    /// Write one batch of a bulk load (`LOAD DATA`) before the transaction commits. The
+    /// versions carry `tx_id`, which stays active until the load commits, so other snapshots
+    /// cannot see them yet; each batch is validated like `commit_row_changes`. A marker keeps
+    /// track of the load until `finish_bulk_load`, so a crash in between is undone at startup.
+    pub fn bulk_write_rows<'a, I>(
+        &self,
+        db: &str,
+        table: &str,
+        rows: I,
+        tx_id: TransactionId,
+        view: &ReadView,
+    ) -> Result<(), MiniError>
+    where
+        I: IntoIterator<Item = (i64, Option<&'a Row>)>,
+    {
+        let changes: Vec<_> = rows
+            .into_iter()
+            .map(|(pk, row)| (db, table, pk, row))
+            .collect();
+        let _guard = self.write_lock.lock();
+        self.check_write_conflicts(&changes, tx_id, view, |_, _, _| false)?;
+        self.check_unique_keys(&changes)?;
+        let (mut batch, purge_items) = self.version_batch(changes, tx_id, None)?;
+        batch.insert(
+            Self::bulk_load_key(tx_id),
+            bincode::serialize(&(db, table))?,
+        );
+        self.data.apply_batch(batch)?;
+        self.history.lock().extend(purge_items);
+        Ok(())
+    }
+
+    /// Complete a bulk load after its last batch: drop the marker and make the batches durable.
+    /// The rows become visible when the transaction commits.
+    pub fn finish_bulk_load(&self, tx_id: TransactionId) -> Result<(), MiniError> {
+        self.data.remove(Self::bulk_load_key(tx_id))?;
+        self.commit_flush.commit()
+    }
+
+    /// Undo a bulk load that failed: remove every version it wrote, along with the index
+    /// entries no other version needs.
+    pub fn abort_bulk_load(&self, tx_id: TransactionId) -> Result<(), MiniError> {
+        let _guard = self.write_lock.lock();
+        self.history.lock().retain(|item| item.tx_id != tx_id);
+        self.discard_bulk_load(tx_id)
+    }
+
+    /// Undo the bulk loads a crash interrupted. Run at startup, before anything reads rows.
+    fn recover_bulk_loads(&self) -> Result<(), MiniError> {
+        let prefix = Self::bulk_load_key(0);
+        let prefix = &prefix[..prefix.len() - 8];
+        let _guard = self.write_lock.lock();
+        for key in Self::collect_keys(&self.data, prefix.to_vec())? {
+            self.discard_bulk_load(Self::parse_tx_id_from_key(&key)?)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the versions of the bulk load `tx_id` and then its marker; the caller holds
+    /// `write_lock`. Running it again after a crash finishes the job.
+    fn discard_bulk_load(&self, tx_id: TransactionId) -> Result<(), MiniError> {
+        let marker = Self::bulk_load_key(tx_id);
+        let Some(v) = self.data.get(&marker)? else {
+            return Ok(());
+        };
+        let (db, table): (String, String) = bincode::deserialize(&v)?;
+        let def = match self.get_table(&db, &table) {
+            Ok(def) => def,
+            // Dropped since; DROP TABLE already removed everything.
+            Err(MiniError::NotFound(_)) => {
+                self.data.remove(&marker)?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Versions of one row are adjacent; the load wrote at most one of them.
+        let prefix = Self::row_prefix(&db, &table);
+        let mut batch = Batch::default();
+        let mut discard = |versions: &mut Vec<(IVec, TransactionId, Option<Row>)>| {
+            let Some(pos) = versions.iter().position(|(_, tx, _)| *tx == tx_id) else {
+                versions.clear();
+                return;
+            };
+            let (key, _, row) = versions.remove(pos);
+            batch.remove(key.clone());
+            if let Some(row) = row {
+                let pk = Self::parse_pk_from_row_key(&key, prefix.len()).unwrap_or_default();
+                for idx in &def.indexes {
+                    let Some(col_idx) = def.columns.iter().position(|c| c.name == idx.columns[0])
+                    else {
+                        continue;
+                    };
+                    let val = &row.values[col_idx];
+                    let still_used = versions
+                        .iter()
+                        .any(|(_, _, r)| r.as_ref().is_some_and(|r| &r.values[col_idx] == val));
+                    if !still_used {
+                        batch.remove(Self::index_key(&db, &table, &idx.name, val, pk));
+                    }
+                }
+            }
+            versions.clear();
+        };
+        let mut current: Option<i64> = None;
+        let mut versions = Vec::new();
+        for item in self.data.scan_prefix(&prefix) {
+            let (k, v) = item?;
+            let Some(pk) = Self::parse_pk_from_row_key(&k, prefix.len()) else {
+                continue;
+            };
+            if current != Some(pk) {
+                discard(&mut versions);
+                current = Some(pk);
+            }
+            let tx = Self::parse_tx_id_from_key(&k)?;
+            versions.push((k, tx, bincode::deserialize(&v)?));
+        }
+        discard(&mut versions);
+        self.data.apply_batch(batch)?;
+
+        // The counters included the discarded rows.
+        let mut stats = self.measure_table(&def)?;
+        stats.update_time = self.stored_stats(&db, &table)?.and_then(|s| s.update_time);
+        let mut batch = Batch::default();
+        batch.insert(Self::stats_key(&db, &table), bincode::serialize(&stats)?);
+        batch.remove(marker);
+        self.data.apply_batch(batch)?;
+        self.flush()
+    }
+
+    /// Reject a commit that would leave two rows with the same non-NULL value in a unique
+    /// index. Statements check this against their own view as they write; this catches rows
+    /// committed concurrently by transactions that could not see each other. The caller holds
+    /// `write_lock`, so the latest versions are the committed state.
+    fn check_unique_keys(
+        &self,
+        changes: &[(&str, &str, i64, Option<&Row>)],
+    ) -> Result<(), MiniError> {
+        let mut defs: HashMap<(&str, &str), TableDef> = HashMap::new();
+        let mut written: HashSet<(&str, &str, i64)> = HashSet::new();
+        for (db, table, pk, _) in changes {
+            written.insert((db, table, *pk));
+            if !defs.contains_key(&(*db, *table)) {
+                defs.insert((db, table), self.get_table(db, table)?);
+            }
+        }
+        let mut claimed: HashMap<(&str, &str, &str, &Cell), i64> = HashMap::new();
+        for (db, table, pk, row) in changes {
+            let Some(row) = row else {
+                continue;
+            };
+            let def = &defs[&(*db, *table)];
+            for idx in def.indexes.iter().filter(|i| i.unique) {
+                let Some(col_idx) = def.columns.iter().position(|c| c.name == idx.columns[0])
+                else {
+                    continue;
+                };
+                let val = &row.values[col_idx];
+                if *val == Cell::Null {
+                    continue;
+                }
+                let duplicate =
+                    || MiniError::DuplicateEntry(val.to_string(), format!("{table}.{}", idx.name));
+                if claimed
+                    .insert((db, table, idx.name.as_str(), val), *pk)
+                    .is_some_and(|other| other != *pk)
+                {
+                    return Err(duplicate());
+                }
+                let prefix = Self::index_value_prefix(db, table, &idx.name, val);
+                for item in self.data.scan_prefix(&prefix) {
+                    let (k, _) = item?;
+                    if k.len() != prefix.len() + 8 {
+                        continue;
+                    }
+                    let other = i64::from_be_bytes(k[prefix.len()..].try_into().unwrap());
+                    // Rows this commit writes are judged by their new versions above.
+                    if written.contains(&(*db, *table, other)) {
+                        continue;
+                    }
+                    let latest = self
+                        .data
+                        .scan_prefix(Self::row_prefix_mvcc(db, table, other))
+                        .next()
+                        .transpose()?;
+                    if let Some((_, v)) = latest {
+                        let current: Option<Row> = bincode::deserialize(&v)?;
+                        if current.is_some_and(|r| r.values.get(col_idx) == Some(val)) {
+                            return Err(duplicate());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write new row versions and their index entries; the caller holds `write_lock` and then
+    /// makes the commit durable through `commit_flush`.
+    fn write_versions_locked(
+        &self,
+        changes: Vec<(&str, &str, i64, Option<&Row>)>,
+        tx_id: TransactionId,
+    ) -> Result<(), MiniError> {
+        let (batch, purge_items) = self.version_batch(changes, tx_id, None)?;
+        self.data.apply_batch(batch)?;
+        self.history.lock().extend(purge_items);
+        Ok(())
+    }
+
+    /// Build the data-tree batch for new row versions, their index entries and the updated
+    /// `TableStats` of every table they touch. `def` overrides the stored definition of the
+    /// table being altered.
+    fn version_batch(
+        &self,
+        changes: Vec<(&str, &str, i64, Option<&Row>)>,
+        tx_id: TransactionId,
+        def: Option<&TableDef>,
+    ) -> Result<(Batch, Vec<PurgeItem>), MiniError> {
+        let mut batch = Batch::default();
+        let mut defs: HashMap<(String, String), TableDef> = HashMap::new();
+        if let Some(def) = def {
+            defs.insert((def.db.clone(), def.name.clone()), def.clone());
+        }
+        let mut purge_items = Vec::new();
+        // Per table: change in rows, data bytes and index bytes.
+        let mut deltas: HashMap<(String, String), [i64; 3]> = HashMap::new();
+        for (db, table, pk, new_row) in changes {
+            // The version this one replaces; we hold `write_lock`, so it is the latest committed
+            // (or an earlier batch of the same bulk load, which this version overwrites).
+            let mut overwritten = false;
+            let old = match self
+                .data
+                .scan_prefix(Self::row_prefix_mvcc(db, table, pk))
+                .next()
+            {
+                Some(item) => {
+                    let (k, v) = item?;
+                    overwritten = Self::parse_tx_id_from_key(&k)? == tx_id;
+                    bincode::deserialize::<Option<Row>>(&v)?.map(|row| (v.len(), row))
+                }
+                None => None,
+            };
+
+            // Write a NEW version.
+            let key = Self::row_key_mvcc(db, table, pk, tx_id);
+            // Value is Option<Row>.
+            let val = bincode::serialize(&new_row.cloned())?;
+            let new_len = val.len();
+            batch.insert(key, val);
+
+            // Index Maintenance
+            // Entries for older versions stay in place: snapshots that still see those versions
+            // must keep finding them. They are removed by purge once no view can see them.
+            let def_key = (db.to_string(), table.to_string());
+            if !defs.contains_key(&def_key) {
+                defs.insert(def_key.clone(), self.get_table(db, table)?);
+            }
+            let def = &defs[&def_key];
+
+            let delta = deltas.entry(def_key.clone()).or_default();
+            if let Some((len, row)) = &old {
+                delta[0] -= 1;
+                delta[1] -= *len as i64;
+                delta[2] -= Self::index_entries_len(def, pk, row) as i64;
+            }
+            if let Some(row) = new_row {
+                delta[0] += 1;
+                delta[1] += new_len as i64;
+                delta[2] += Self::index_entries_len(def, pk, row) as i64;
+            }
 
-            // Index Maintenance
-            // We need TableDef to know indexes.
-            // We need TableDef to know indexes.
-            // Lookup catalog: use table_key helper
-            let cat_key = Self::table_key(db, table);
-
-            let def_bytes = self
-                .catalog
-                .get(&cat_key)?
-                .ok_or(MiniError::Invalid(format!(
-                    "Table {}.{} not found",
-                    db, table
-                )))?;
-            let def: TableDef = bincode::deserialize(&def_bytes)?;
-
             for idx in &def.indexes {
                 // Assuming single column index for MVP
                 let col_name = &idx.columns[0];
@@ -504,60 +1357,356 @@ impl Store {
                     .iter()
                     .position(|c| &c.name == col_name)
                     .unwrap();
-
-                // Remove Old
-                if let Some(old) = &old_row {
-                    // Check if old row was "deleted" (Option<Row> in standard storage?)
-                    // Wait, in my design `val` is `Option<Row>` serialized?
-                    // In `scan_rows_mvcc`: `let val: Option<Row> = bincode::deserialize(&v)?;`
-                    // Yes.
-                    let old_val = &old.values[col_idx];
-                    let k = Self::index_key(db, table, &idx.name, old_val, pk);
-                    batch.remove(k);
+                let new_val = new_row.map(|r| &r.values[col_idx]);
+                // An overwritten version is gone, so no snapshot needs its entries.
+                if let (true, Some((_, old_r))) = (overwritten, &old) {
+                    let old_val = &old_r.values[col_idx];
+                    if new_val != Some(old_val) {
+                        batch.remove(Self::index_key(db, table, &idx.name, old_val, pk));
+                    }
                 }
-
-                // Add New
-                if let Some(new_r) = new_row {
-                    let new_val = &new_r.values[col_idx];
+                if let Some(new_val) = new_val {
                     let k = Self::index_key(db, table, &idx.name, new_val, pk);
                     batch.insert(k, vec![]); // Value empty
                 }
             }
+
+            purge_items.push(PurgeItem {
+                tx_id,
+                db: db.to_string(),
+                table: table.to_string(),
+                pk,
+            });
         }
 
-        // Also persist the Max TxID to catalog so we resume correctly on restart.
-        // We update 'sys_max_tx_id' to `tx_id`.
-        // Since this is in the same atomic batch (applied to different trees? No, batch is tree-specific in sled usually?
-        // Wait, sled::Batch is for a single Tree?
-        // Sled documentation: db.apply_batch(batch) applies to default tree?
-        // Actually batch can contain operations for multiple trees? No, verify sled API.
-        // If sled::Batch is simple, we might need to put metadata in data tree or use transactions.
-        // Sled `apply_batch` is on `Tree`. `db.apply_batch` applies to default tree.
-        // Our data is in `self.data` (a Tree). `catalog` is another Tree.
-        // Atomicity across trees requires `db.transaction(...)`.
-        // But `transaction` closure is complex.
-        // HACK: Store metadata in `data` tree with special prefix for MVP durability.
-        // Prefix: "m\0" (metadata).
+        // The counters ride in the same batch, so they always match the rows on disk.
+        let now = chrono::Utc::now().timestamp_millis();
+        for ((db, table), [rows, data_length, index_length]) in deltas {
+            let mut stats = match self.stored_stats(&db, &table)? {
+                Some(stats) => stats,
+                None => self.measure_table(&defs[&(db.clone(), table.clone())])?,
+            };
+            stats.rows = stats.rows.saturating_add_signed(rows);
+            stats.data_length = stats.data_length.saturating_add_signed(data_length);
+            stats.index_length = stats.index_length.saturating_add_signed(index_length);
+            stats.update_time = Some(now);
+            batch.insert(Self::stats_key(&db, &table), bincode::serialize(&stats)?);
+        }
 
+        // Also persist the Max TxID so we resume correctly on restart. It lives in the `data`
+        // tree under the "m\0" prefix so it shares the row batch; DDL that also touches the
+        // catalog goes through `ddl_transaction` instead. Commits land out of TxID order, so
+        // store the highest TxID issued: it never goes backwards and covers every version.
         let meta_key = b"m\0max_tx_id";
-        batch.insert(meta_key, tx_id.to_be_bytes().to_vec());
+        let max_tx_id = self.txn_manager.last_tx_id().max(tx_id);
+        batch.insert(meta_key, max_tx_id.to_be_bytes().to_vec());
+        Ok((batch, purge_items))
+    }
 
-        self.data.apply_batch(batch)?;
-        self.flush()?;
-        Ok(())
+    /// Purge history in the background once the oldest read view has moved past the horizon of
+    /// the last purge; before that nothing new can be purged. Returns straight away: a worker
+    /// thread, started when none is running, purges until no request is left, then exits.
+    pub fn request_purge(&self) {
+        let horizon = self.txn_manager.purge_horizon();
+        {
+            let mut state = self.purge.lock();
+            if horizon <= state.horizon {
+                return;
+            }
+            state.horizon = horizon;
+            if state.running {
+                state.requested = true;
+                return;
+            }
+            state.running = true;
+        }
+        let store = self.clone();
+        std::thread::spawn(move || loop {
+            if let Err(e) = store.purge_history() {
+                eprintln!("background purge failed: {e}");
+            }
+            let mut state = store.purge.lock();
+            if !std::mem::take(&mut state.requested) {
+                state.running = false;
+                return;
+            }
+        });
     }
 
-    pub fn apply_row_changes<'a, I>(&self, changes: I) -> Result<(), MiniError>
-    where
-        I: IntoIterator<Item = (&'a str, &'a str, i64, Option<&'a Row>)>,
-    {
-        // Legacy: auto-assign a transaction ID?
-        // This is dangerous but good for backward compat if any calls remain.
-        // We'll treat this as a "system transaction".
-        let (tx, _) = self.txn_manager.start_txn();
-        self.apply_row_changes_mvcc(changes, tx)?;
-        self.txn_manager.commit_txn(tx);
-        Ok(())
+    /// Purge row versions (and the index entries only they referenced) for rows whose newer
+    /// version has become visible to every read view. Returns the number of versions removed.
+    /// Works in batches of `PURGE_BATCH` rows, taking the write lock for one batch at a time.
+    pub fn purge_history(&self) -> Result<usize, MiniError> {
+        let horizon = self.txn_manager.purge_horizon();
+        let ready: Vec<PurgeItem> = {
+            let mut history = self.history.lock();
+            if !history.iter().any(|item| item.tx_id < horizon) {
+                return Ok(0);
+            }
+            let (ready, pending) = std::mem::take(&mut *history)
+                .into_iter()
+                .partition(|item| item.tx_id < horizon);
+            *history = pending;
+            ready
+        };
+
+        let mut seen: HashSet<(String, String, i64)> = HashSet::new();
+        let ready: Vec<PurgeItem> = ready
+            .into_iter()
+            .filter(|item| seen.insert((item.db.clone(), item.table.clone(), item.pk)))
+            .collect();
+        let mut purged = 0;
+        for chunk in ready.chunks(PURGE_BATCH) {
+            let _guard = self.write_lock.lock();
+            // Read under the lock: DDL may run between batches.
+            let mut defs: HashMap<(String, String), Option<TableDef>> = HashMap::new();
+            let mut batch = Batch::default();
+            let mut batch_purged = 0;
+            for item in chunk {
+                let def_key = (item.db.clone(), item.table.clone());
+                if !defs.contains_key(&def_key) {
+                    let def = match self.get_table(&item.db, &item.table) {
+                        Ok(def) => Some(def),
+                        // Dropped since the write; DROP TABLE already removed everything.
+                        Err(MiniError::NotFound(_)) => None,
+                        Err(e) => return Err(e),
+                    };
+                    defs.insert(def_key.clone(), def);
+                }
+                let Some(def) = &defs[&def_key] else {
+                    continue;
+                };
+                batch_purged += self.purge_row_versions(&mut batch, def, item.pk, horizon)?;
+            }
+            if batch_purged > 0 {
+                self.data.apply_batch(batch)?;
+            }
+            purged += batch_purged;
+        }
+        Ok(purged)
+    }
+
+    /// Sweep every table for versions no read view can see. Run at startup, when no snapshots
+    /// exist and the in-memory purge history from the previous run is gone.
+    fn purge_all(&self) -> Result<usize, MiniError> {
+        let horizon = self.txn_manager.purge_horizon();
+        let _guard = self.write_lock.lock();
+        let mut purged = 0;
+        for db in self.list_databases()? {
+            for table in self.list_tables(&db)? {
+                let def = self.get_table(&db, &table)?;
+                let prefix = Self::row_prefix(&db, &table);
+                let mut version_counts: BTreeMap<i64, (usize, bool)> = BTreeMap::new();
+                for item in self.data.scan_prefix(&prefix) {
+                    let (k, v) = item?;
+                    let Some(pk) = Self::parse_pk_from_row_key(&k, prefix.len()) else {
+                        continue;
+                    };
+                    let entry = version_counts.entry(pk).or_insert((0, false));
+                    entry.0 += 1;
+                    if entry.0 == 1 {
+                        // Newest version: a tombstone here can be dropped outright.
+                        entry.1 = bincode::deserialize::<Option<Row>>(&v)?.is_none();
+                    }
+                }
+                let mut batch = Batch::default();
+                for (pk, (versions, tombstone)) in version_counts {
+                    if versions > 1 || tombstone {
+                        purged += self.purge_row_versions(&mut batch, &def, pk, horizon)?;
+                    }
+                }
+                self.data.apply_batch(batch)?;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn purge_row_versions(
+        &self,
+        batch: &mut Batch,
+        def: &TableDef,
+        pk: i64,
+        horizon: TransactionId,
+    ) -> Result<usize, MiniError> {
+        let prefix = Self::row_prefix_mvcc(&def.db, &def.name, pk);
+        let mut versions: Vec<(IVec, Option<Row>)> = Vec::new();
+        let mut cut = None;
+        // Newest first; the first version below the horizon is what every view sees.
+        for item in self.data.scan_prefix(&prefix) {
+            let (k, v) = item?;
+            if cut.is_none() && Self::parse_tx_id_from_key(&k)? < horizon {
+                cut = Some(versions.len());
+            }
+            versions.push((k, bincode::deserialize(&v)?));
+        }
+        let Some(cut) = cut else {
+            return Ok(0);
+        };
+        // A universally visible tombstone hides everything older, so it can go as well.
+        let keep = if versions[cut].1.is_some() {
+            cut + 1
+        } else {
+            cut
+        };
+        if keep == versions.len() {
+            return Ok(0);
+        }
+        let (kept, purged) = versions.split_at(keep);
+
+        for (k, _) in purged {
+            batch.remove(k.clone());
+        }
+        for idx in &def.indexes {
+            let Some(col_idx) = def.columns.iter().position(|c| c.name == idx.columns[0]) else {
+                continue;
+            };
+            let still_used: Vec<&Cell> = kept
+                .iter()
+                .filter_map(|(_, row)| row.as_ref().map(|r| &r.values[col_idx]))
+                .collect();
+            for row in purged.iter().filter_map(|(_, row)| row.as_ref()) {
+                let val = &row.values[col_idx];
+                if !still_used.contains(&val) {
+                    batch.remove(Self::index_key(&def.db, &def.name, &idx.name, val, pk));
+                }
+            }
+        }
+        Ok(purged.len())
+    }
+
+    /// Look up rows whose indexed column equals `val`, as seen by `view`.
+    /// Index entries exist for every live version, so each hit is rechecked against the
+    /// version the view actually sees.
+    pub fn index_lookup_mvcc(
+        &self,
+        db: &str,
+        table: &str,
+        index_name: &str,
+        val: &Cell,
+        view: &ReadView,
+    ) -> Result<Vec<(i64, Row)>, MiniError> {
+        let def = self.get_table(db, table)?;
+        let index = def
+            .indexes
+            .iter()
+            .find(|i| i.name == index_name)
+            .ok_or_else(|| MiniError::NotFound(format!("unknown index: {index_name}")))?;
+        let col_idx = def
+            .columns
+            .iter()
+            .position(|c| c.name == index.columns[0])
+            .ok_or_else(|| MiniError::Invalid("corrupt index definition".into()))?;
+
+        let prefix = Self::index_value_prefix(db, table, index_name, val);
+        let mut pks = BTreeSet::new();
+        for item in self.data.scan_prefix(&prefix) {
+            let (k, _) = item?;
+            // Longer keys belong to other values sharing this prefix (e.g. text with NULs).
+            if k.len() != prefix.len() + 8 {
+                continue;
+            }
+            let pk_bytes: [u8; 8] = k[prefix.len()..].try_into().unwrap();
+            pks.insert(i64::from_be_bytes(pk_bytes));
+        }
+
+        let mut out = Vec::new();
+        for pk in pks {
+            if let Some(row) = self.get_row_mvcc(db, table, pk, view)? {
+                if row.values.get(col_idx) == Some(val) {
+                    out.push((pk, row));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// The smallest PK above `pk` with a live row in `view`. Gap locks are named after it.
+    pub fn next_row_after(
+        &self,
+        db: &str,
+        table: &str,
+        pk: i64,
+        view: &ReadView,
+    ) -> Result<Option<i64>, MiniError> {
+        let Some(start) = pk.checked_add(1) else {
+            return Ok(None);
+        };
+        // Keys hold the PK big-endian, so negative PKs sort after the non-negative ones.
+        let ranges = if start < 0 {
+            vec![(start, -1), (0, i64::MAX)]
+        } else {
+            vec![(start, i64::MAX)]
+        };
+        let prefix_len = Self::row_prefix(db, table).len();
+        for (lo, hi) in ranges {
+            let lo_key = Self::row_prefix_mvcc(db, table, lo);
+            let mut hi_key = Self::row_prefix_mvcc(db, table, hi);
+            hi_key.extend_from_slice(&[0xFF; 8]);
+            let mut decided = None;
+            for item in self.data.range(lo_key..=hi_key) {
+                let (k, v) = item?;
+                let Some(cur) = Self::parse_pk_from_row_key(&k, prefix_len) else {
+                    continue;
+                };
+                if decided == Some(cur) || !view.is_visible(Self::parse_tx_id_from_key(&k)?) {
+                    continue;
+                }
+                // The newest visible version decides whether the row exists.
+                decided = Some(cur);
+                let row: Option<Row> = bincode::deserialize(&v)?;
+                if row.is_some() {
+                    return Ok(Some(cur));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The first entry of an index after `(val, pk)` in index order whose row is live in `view`
+    /// with that value. Entries only older row versions point to are skipped.
+    pub fn next_index_entry_after(
+        &self,
+        db: &str,
+        table: &str,
+        index_name: &str,
+        val: &Cell,
+        pk: i64,
+        view: &ReadView,
+    ) -> Result<Option<(Cell, i64)>, MiniError> {
+        let def = self.get_table(db, table)?;
+        let col_idx = def
+            .indexes
+            .iter()
+            .find(|i| i.name == index_name)
+            .and_then(|index| def.columns.iter().position(|c| c.name == index.columns[0]))
+            .ok_or_else(|| MiniError::NotFound(format!("unknown index: {index_name}")))?;
+        let mut prefix = Self::index_table_prefix(db, table);
+        prefix.extend_from_slice(index_name.as_bytes());
+        prefix.push(0);
+        let start = Self::index_key(db, table, index_name, val, pk);
+        for item in self
+            .data
+            .range::<Vec<u8>, _>((std::ops::Bound::Excluded(start), std::ops::Bound::Unbounded))
+        {
+            let (k, _) = item?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let Some(pk_bytes) = k.len().checked_sub(8).map(|at| &k[at..]) else {
+                continue;
+            };
+            let entry_pk = i64::from_be_bytes(pk_bytes.try_into().unwrap());
+            let Some(row) = self.get_row_mvcc(db, table, entry_pk, view)? else {
+                continue;
+            };
+            let Some(current) = row.values.get(col_idx) else {
+                continue;
+            };
+            if Self::index_key(db, table, index_name, current, entry_pk) == k.as_ref() {
+                return Ok(Some((current.clone(), entry_pk)));
+            }
+        }
+        Ok(None)
     }
 
     pub fn allocate_auto_increment(&self, db: &str, table: &str) -> Result<i64, MiniError> {
@@ -635,9 +1784,70 @@ impl Store {
         Ok(Some(i64::from_be_bytes(raw)))
     }
 
-    pub fn lock_row(&self, owner: u32, db: &str, table: &str, pk: i64) -> Result<bool, MiniError> {
+    /// Lock a row for `owner` in `mode`. Blocking requests wait up to their timeout
+    /// (`innodb_lock_wait_timeout`) for conflicting owners to release it and fail with
+    /// `MiniError::Deadlock` when waiting would deadlock.
+    pub fn lock_row(
+        &self,
+        owner: u32,
+        db: &str,
+        table: &str,
+        pk: i64,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<LockGrant, MiniError> {
         let key = Self::row_key(db, table, pk);
-        self.locks.lock(owner, key)
+        let detect = self.deadlock_detect.load(Ordering::SeqCst);
+        self.locks.lock(owner, key, mode, wait, detect)
+    }
+
+    /// Lock the gap before a record (see `Gap`) in `mode` (`Gap` or `InsertIntention`).
+    pub fn lock_gap(
+        &self,
+        owner: u32,
+        db: &str,
+        table: &str,
+        gap: &Gap,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<LockGrant, MiniError> {
+        let key = Self::gap_key(db, table, gap);
+        let detect = self.deadlock_detect.load(Ordering::SeqCst);
+        self.locks.lock(owner, key, mode, wait, detect)
+    }
+
+    pub fn unlock_gap(&self, owner: u32, db: &str, table: &str, gap: &Gap) {
+        let key = Self::gap_key(db, table, gap);
+        self.locks.unlock(owner, &key);
+    }
+
+    /// Global `innodb_deadlock_detect`. When disabled, deadlocks resolve via lock wait timeout.
+    pub fn deadlock_detect(&self) -> bool {
+        self.deadlock_detect.load(Ordering::SeqCst)
+    }
+
+    pub fn set_deadlock_detect(&self, enabled: bool) {
+        self.deadlock_detect.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Global `innodb_flush_log_at_trx_commit`.
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.commit_flush.policy()
+    }
+
+    pub fn set_flush_policy(&self, policy: FlushPolicy) {
+        self.commit_flush
+            .policy
+            .store(policy.level(), Ordering::SeqCst);
+    }
+
+    /// Global `secure_file_priv`, set at startup.
+    pub fn secure_file_priv(&self) -> Option<PathBuf> {
+        self.secure_file_priv.read().clone()
+    }
+
+    pub fn set_secure_file_priv(&self, dir: Option<PathBuf>) {
+        *self.secure_file_priv.write() = dir;
     }
 
     pub fn unlock_row(&self, owner: u32, db: &str, table: &str, pk: i64) {
@@ -649,64 +1859,104 @@ impl Store {
         self.locks.unlock_all(owner);
     }
 
-    pub fn scan_rows_mvcc(
+    /// Lock a table for `owner`, waiting up to `timeout` (`lock_wait_timeout`). Table locks are
+    /// not released by commit or rollback; waits across table and row locks are not checked for
+    /// deadlocks and end in a timeout.
+    pub fn lock_table(
         &self,
+        owner: u32,
         db: &str,
         table: &str,
-        view: &ReadView,
-    ) -> Result<Vec<(i64, Row)>, MiniError> {
-        // row_prefix returns "r\0db\0table\0".
-        // That is the correct prefix for ALL rows.
-        let prefix = Self::row_prefix(db, table);
-
-        let mut out = Vec::new();
-        let cursor = self.data.scan_prefix(&prefix);
+        mode: TableLockMode,
+        timeout: Duration,
+    ) -> Result<LockGrant, MiniError> {
+        let key = Self::table_lock_key(db, table);
+        let detect = self.deadlock_detect.load(Ordering::SeqCst);
+        self.table_locks
+            .lock(owner, key, mode, LockWait::Block(timeout), detect)
+    }
 
-        // We need to iterate and group by PK.
-        // Keys: [prefix] [pk: 8 bytes] [tx_inv: 8 bytes]
-        // Sorted by pk ASC, then tx_inv ASC (Newest first).
+    pub fn unlock_table(&self, owner: u32, db: &str, table: &str) {
+        self.table_locks
+            .unlock(owner, &Self::table_lock_key(db, table));
+    }
 
-        let mut current_pk: Option<i64> = None;
-        let mut pk_found = false; // did we find a visible version for current_pk?
+    /// Take the global lock: `ReadOnly` is the global read lock, `Write` is what
+    /// writers and DDL hold while they run.
+    pub fn lock_global(
+        &self,
+        owner: u32,
+        mode: TableLockMode,
+        timeout: Duration,
+    ) -> Result<LockGrant, MiniError> {
+        let detect = self.deadlock_detect.load(Ordering::SeqCst);
+        self.table_locks.lock(
+            owner,
+            GLOBAL_LOCK_KEY.to_vec(),
+            mode,
+            LockWait::Block(timeout),
+            detect,
+        )
+    }
 
-        for item in cursor {
-            let (k, v) = item?;
-            // k should be prefix.len() + 16 bytes.
-            if k.len() < prefix.len() + 16 {
-                continue; // Should not happen if consistent
-            }
+    pub fn unlock_global(&self, owner: u32) {
+        self.table_locks.unlock(owner, GLOBAL_LOCK_KEY);
+    }
 
-            // Extract PK.
-            let pk_start = prefix.len();
-            let pk_bytes: [u8; 8] = k[pk_start..pk_start + 8].try_into().unwrap();
-            let pk = i64::from_be_bytes(pk_bytes);
+    /// Drop every table and global lock `owner` holds.
+    pub fn unlock_all_tables(&self, owner: u32) {
+        self.table_locks.unlock_all(owner);
+    }
 
-            // Extract TxID.
-            let tx_id = Self::parse_tx_id_from_key(&k)?;
+    fn table_lock_key(db: &str, table: &str) -> Vec<u8> {
+        format!("t\0{db}\0{table}").into_bytes()
+    }
 
-            if Some(pk) != current_pk {
-                // New PK.
-                current_pk = Some(pk);
-                pk_found = false;
-            }
+    pub fn scan_rows_mvcc(
+        &self,
+        db: &str,
+        table: &str,
+        view: &ReadView,
+    ) -> Result<Vec<(i64, Row)>, MiniError> {
+        self.scan_rows_iter(db, table, view.clone()).collect()
+    }
 
-            if pk_found {
-                // We already found a visible version for this PK. Skip older versions.
-                continue;
-            }
+    /// Lazy form of `scan_rows_mvcc`: rows are read from sled as the iterator advances. Pin the
+    /// view (`TransactionManager::pin_view`) if the scan may outlive its transaction.
+    pub fn scan_rows_iter(&self, db: &str, table: &str, view: ReadView) -> RowScan {
+        self.scan_rows_range_iter(db, table, view, i64::MIN, i64::MAX)
+    }
 
-            if view.is_visible(tx_id) {
-                // Found the visible version.
-                pk_found = true;
-                let val: Option<Row> = bincode::deserialize(&v)?;
-                if let Some(row) = val {
-                    out.push((pk, row));
-                }
-                // If None, it's deleted. We still mark pk_found=true so we skip older versions (where it might exist).
+    /// `scan_rows_iter` limited to primary keys in `lo..=hi`.
+    pub fn scan_rows_range_iter(
+        &self,
+        db: &str,
+        table: &str,
+        view: ReadView,
+        lo: i64,
+        hi: i64,
+    ) -> RowScan {
+        // row_prefix returns "r\0db\0table\0".
+        let prefix = Self::row_prefix(db, table);
+        // Keys order PKs by their big-endian bytes, which puts negative PKs last; scan those
+        // first so rows come out in numeric PK order.
+        let part = |lo: i64, hi: i64| {
+            if lo > hi {
+                return self.data.range(prefix.clone()..prefix.clone());
             }
+            let start = Self::row_prefix_mvcc(db, table, lo);
+            let mut end = Self::row_prefix_mvcc(db, table, hi);
+            end.extend_from_slice(&[0xFF; 8]);
+            self.data.range(start..=end)
+        };
+        let cursor = part(lo, hi.min(-1)).chain(part(lo.max(0), hi));
+        RowScan {
+            prefix_len: prefix.len(),
+            cursor,
+            view,
+            current_pk: None,
+            pk_found: false,
         }
-
-        Ok(out)
     }
 
     pub fn scan_rows(&self, db: &str, table: &str) -> Result<Vec<(i64, Row)>, MiniError> {
@@ -718,10 +1968,152 @@ impl Store {
         self.scan_rows_mvcc(db, table, &view)
     }
 
-    pub fn count_rows(&self, db: &str, table: &str) -> Result<u64, MiniError> {
-        // Expensive legacy count.
-        let rows = self.scan_rows(db, table)?;
-        Ok(rows.len() as u64)
+    /// Approximate row count and sizes of a table, as of the last commit.
+    pub fn table_stats(&self, db: &str, table: &str) -> Result<TableStats, MiniError> {
+        match self.stored_stats(db, table)? {
+            Some(stats) => Ok(stats),
+            // Tables created before the counters existed are measured until their next commit.
+            None => self.measure_table(&self.get_table(db, table)?),
+        }
+    }
+
+    fn stored_stats(&self, db: &str, table: &str) -> Result<Option<TableStats>, MiniError> {
+        match self.data.get(Self::stats_key(db, table))? {
+            Some(v) => Ok(Some(bincode::deserialize(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Compute `TableStats` from scratch (without `update_time`).
+    fn measure_table(&self, def: &TableDef) -> Result<TableStats, MiniError> {
+        let mut stats = TableStats::default();
+        self.for_each_latest_row(&def.db, &def.name, |pk, len, row| {
+            stats.rows += 1;
+            stats.data_length += len as u64;
+            stats.index_length += Self::index_entries_len(def, pk, row);
+        })?;
+        Ok(stats)
+    }
+
+    /// Visit the latest version of every row that is not deleted, with its serialized size.
+    fn for_each_latest_row(
+        &self,
+        db: &str,
+        table: &str,
+        mut f: impl FnMut(i64, usize, &Row),
+    ) -> Result<(), MiniError> {
+        let prefix = Self::row_prefix(db, table);
+        let mut current_pk = None;
+        for item in self.data.scan_prefix(&prefix) {
+            let (k, v) = item?;
+            let Some(pk) = Self::parse_pk_from_row_key(&k, prefix.len()) else {
+                continue;
+            };
+            // Newest version first: skip the older ones.
+            if current_pk == Some(pk) {
+                continue;
+            }
+            current_pk = Some(pk);
+            if let Some(row) = bincode::deserialize::<Option<Row>>(&v)? {
+                f(pk, v.len(), &row);
+            }
+        }
+        Ok(())
+    }
+
+    fn index_entries_len(def: &TableDef, pk: i64, row: &Row) -> u64 {
+        def.indexes
+            .iter()
+            .filter_map(|idx| {
+                let col_idx = def.columns.iter().position(|c| c.name == idx.columns[0])?;
+                let val = row.values.get(col_idx)?;
+                Some(Self::index_key(&def.db, &def.name, &idx.name, val, pk).len() as u64)
+            })
+            .sum()
+    }
+
+    /// The result of the last `ANALYZE TABLE`, if any.
+    pub fn table_analysis(
+        &self,
+        db: &str,
+        table: &str,
+    ) -> Result<Option<TableAnalysis>, MiniError> {
+        match self.catalog.get(Self::analysis_key(db, table))? {
+            Some(v) => Ok(Some(bincode::deserialize(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `ANALYZE TABLE`: recount the table's latest committed rows, resetting any drift in its
+    /// `TableStats`, and record the cardinality of every index. Histograms are kept.
+    pub fn analyze_table(&self, db: &str, table: &str) -> Result<TableAnalysis, MiniError> {
+        let def = self.get_table(db, table)?;
+        let _guard = self.write_lock.lock();
+        let update_time = self.stored_stats(db, table)?.and_then(|s| s.update_time);
+        let columns: Vec<Option<usize>> = def
+            .indexes
+            .iter()
+            .map(|idx| def.columns.iter().position(|c| c.name == idx.columns[0]))
+            .collect();
+        let mut distinct: Vec<HashSet<Cell>> = vec![HashSet::new(); def.indexes.len()];
+        let mut stats = TableStats {
+            update_time,
+            ..TableStats::default()
+        };
+        self.for_each_latest_row(db, table, |pk, len, row| {
+            stats.rows += 1;
+            stats.data_length += len as u64;
+            stats.index_length += Self::index_entries_len(&def, pk, row);
+            for (values, col) in distinct.iter_mut().zip(&columns) {
+                if let Some(val) = col.and_then(|i| row.values.get(i)) {
+                    values.insert(val.clone());
+                }
+            }
+        })?;
+
+        let mut analysis = self.table_analysis(db, table)?.unwrap_or_default();
+        analysis.analyzed_at = Some(chrono::Utc::now().timestamp_millis());
+        analysis.cardinality = def
+            .indexes
+            .iter()
+            .zip(&distinct)
+            .map(|(idx, values)| (idx.name.clone(), values.len() as u64))
+            .chain([("PRIMARY".to_string(), stats.rows)])
+            .collect();
+
+        let analysis_key = Self::analysis_key(db, table);
+        let analysis_bytes = bincode::serialize(&analysis)?;
+        let stats_key = Self::stats_key(db, table);
+        let stats_bytes = bincode::serialize(&stats)?;
+        self.ddl_transaction(|catalog, data| {
+            catalog.insert(analysis_key.as_slice(), analysis_bytes.as_slice())?;
+            data.insert(stats_key.as_slice(), stats_bytes.as_slice())?;
+            Ok(())
+        })?;
+        Ok(analysis)
+    }
+
+    /// Store (`Some`) or remove (`None`) column histograms of a table.
+    pub fn set_histograms(
+        &self,
+        db: &str,
+        table: &str,
+        histograms: Vec<(String, Option<Histogram>)>,
+    ) -> Result<(), MiniError> {
+        let _guard = self.write_lock.lock();
+        let mut analysis = self.table_analysis(db, table)?.unwrap_or_default();
+        for (column, histogram) in histograms {
+            match histogram {
+                Some(h) => analysis.histograms.insert(column, h),
+                None => analysis.histograms.remove(&column),
+            };
+        }
+        self.catalog.insert(
+            Self::analysis_key(db, table),
+            bincode::serialize(&analysis)?,
+        )?;
+        self.catalog.flush()?;
+        Ok(())
     }
 
     pub fn flush(&self) -> Result<(), MiniError> {
@@ -785,145 +2177,563 @@ impl Store {
         k
     }
 
-    // Prefix including PK but NOT TxID.
-    // Used for scanning versions of a specific row.
-    fn row_prefix_mvcc(db: &str, table: &str, pk: i64) -> Vec<u8> {
-        let mut k = Self::row_prefix(db, table); // "r\0db\0table\0"
-        k.extend_from_slice(&pk.to_be_bytes());
-        k
+    // Prefix including PK but NOT TxID.
+    // Used for scanning versions of a specific row.
+    fn row_prefix_mvcc(db: &str, table: &str, pk: i64) -> Vec<u8> {
+        let mut k = Self::row_prefix(db, table); // "r\0db\0table\0"
+        k.extend_from_slice(&pk.to_be_bytes());
+        k
+    }
+
+    fn row_key_mvcc(db: &str, table: &str, pk: i64, tx_id: TransactionId) -> Vec<u8> {
+        let mut k = Self::row_prefix_mvcc(db, table, pk);
+        // Append inverted TxID for sorting (Newest First).
+        let inverted = u64::MAX - tx_id;
+        k.extend_from_slice(&inverted.to_be_bytes());
+        k
+    }
+
+    // Lock key of a row: the prefix of its versions.
+    fn row_key(db: &str, table: &str, pk: i64) -> Vec<u8> {
+        Self::row_prefix_mvcc(db, table, pk)
+    }
+
+    // Prefix for all index entries of a table ("i\0db\0table\0"), or of a whole database when
+    // `table` is empty.
+    fn index_table_prefix(db: &str, table: &str) -> Vec<u8> {
+        let mut k = Vec::new();
+        k.extend_from_slice(b"i\0");
+        k.extend_from_slice(db.as_bytes());
+        k.push(0);
+        if !table.is_empty() {
+            k.extend_from_slice(table.as_bytes());
+            k.push(0);
+        }
+        k
+    }
+
+    // Prefix of all entries for one indexed value (without PK).
+    fn index_value_prefix(db: &str, table: &str, index_name: &str, val: &Cell) -> Vec<u8> {
+        let mut k = Self::index_table_prefix(db, table);
+        k.extend_from_slice(index_name.as_bytes());
+        k.push(0);
+        Self::encode_index_value(&mut k, val);
+        k
+    }
+
+    fn encode_index_value(k: &mut Vec<u8>, val: &Cell) {
+        match val {
+            Cell::Int(i) | Cell::Date(i) | Cell::DateTime(i) => {
+                k.extend_from_slice(&i.to_be_bytes())
+            }
+            Cell::Float(f) => k.extend_from_slice(&f.to_bits().to_be_bytes()),
+            Cell::Text(s) => {
+                k.extend_from_slice(s.as_bytes());
+                k.push(0);
+            }
+            Cell::Null => k.push(0),
+        }
+    }
+
+    // Lock key of a gap: "g" + the key of the record after it, or "G" + the index prefix for the
+    // gap after the last record.
+    fn gap_key(db: &str, table: &str, gap: &Gap) -> Vec<u8> {
+        match gap {
+            Gap::Primary(Some(pk)) => {
+                [b"g".as_slice(), &Self::row_prefix_mvcc(db, table, *pk)].concat()
+            }
+            Gap::Primary(None) => [b"G".as_slice(), &Self::row_prefix(db, table)].concat(),
+            Gap::Index(index, Some((val, pk))) => [
+                b"g".as_slice(),
+                &Self::index_key(db, table, index, val, *pk),
+            ]
+            .concat(),
+            Gap::Index(index, None) => {
+                let mut k = b"G".to_vec();
+                k.extend_from_slice(&Self::index_table_prefix(db, table));
+                k.extend_from_slice(index.as_bytes());
+                k.push(0);
+                k
+            }
+        }
+    }
+
+    fn index_key(db: &str, table: &str, index_name: &str, val: &Cell, pk: i64) -> Vec<u8> {
+        let mut k = Self::index_value_prefix(db, table, index_name, val);
+        k.extend_from_slice(&pk.to_be_bytes());
+        k
+    }
+
+    // Extract the PK from a row version key given the length of its table prefix.
+    fn parse_pk_from_row_key(key: &[u8], prefix_len: usize) -> Option<i64> {
+        // pk(8) + tx(8)
+        if key.len() < prefix_len + 16 {
+            return None;
+        }
+        let pk_bytes: [u8; 8] = key[prefix_len..prefix_len + 8].try_into().ok()?;
+        Some(i64::from_be_bytes(pk_bytes))
+    }
+
+    fn parse_tx_id_from_key(key: &[u8]) -> Result<TransactionId, MiniError> {
+        if key.len() < 8 {
+            return Err(MiniError::Invalid("corrupt mvcc key".into()));
+        }
+        let inv_bytes: [u8; 8] = key[key.len() - 8..].try_into().unwrap();
+        let inverted = u64::from_be_bytes(inv_bytes);
+        Ok(u64::MAX - inverted)
+    }
+
+    // Marker of a bulk load in progress ("m\0bulk\0" + TxID), holding its database and table.
+    fn bulk_load_key(tx_id: TransactionId) -> Vec<u8> {
+        let mut k = b"m\0bulk\0".to_vec();
+        k.extend_from_slice(&(u64::MAX - tx_id).to_be_bytes());
+        k
+    }
+
+    fn auto_inc_key(db: &str, table: &str) -> Vec<u8> {
+        let mut k = Vec::new();
+        k.extend_from_slice(b"ai\0");
+        k.extend_from_slice(db.as_bytes());
+        k.push(0);
+        k.extend_from_slice(table.as_bytes());
+        k
+    }
+
+    fn auto_inc_prefix(db: &str) -> Vec<u8> {
+        let mut k = Vec::new();
+        k.extend_from_slice(b"ai\0");
+        k.extend_from_slice(db.as_bytes());
+        k.push(0);
+        k
+    }
+
+    // TableStats live in the data tree ("s\0db\0table") so commits update them atomically with
+    // the rows; `TableAnalysis` lives in the catalog ("st\0db\0table").
+    fn stats_key(db: &str, table: &str) -> Vec<u8> {
+        let mut k = Self::stats_prefix(db);
+        k.extend_from_slice(table.as_bytes());
+        k
+    }
+
+    fn stats_prefix(db: &str) -> Vec<u8> {
+        let mut k = Vec::new();
+        k.extend_from_slice(b"s\0");
+        k.extend_from_slice(db.as_bytes());
+        k.push(0);
+        k
+    }
+
+    fn analysis_key(db: &str, table: &str) -> Vec<u8> {
+        let mut k = Self::analysis_prefix(db);
+        k.extend_from_slice(table.as_bytes());
+        k
+    }
+
+    fn analysis_prefix(db: &str) -> Vec<u8> {
+        let mut k = Vec::new();
+        k.extend_from_slice(b"st\0");
+        k.extend_from_slice(db.as_bytes());
+        k.push(0);
+        k
+    }
+}
+
+/// Lock modes. Rows take `Shared`/`Exclusive`; gaps between index records take `Gap` (from
+/// range-locking reads) or `InsertIntention` (from inserts into the gap).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+    Gap,
+    InsertIntention,
+}
+
+impl LockKind for LockMode {
+    const TIMEOUT_MESSAGE: &'static str = "row is locked by another session";
+
+    /// Gap locks only keep inserts out, and inserts into the same gap do not block each other.
+    fn compatible(self, held: LockMode) -> bool {
+        matches!(
+            (self, held),
+            (LockMode::Shared, LockMode::Shared)
+                | (LockMode::Gap, LockMode::Gap)
+                | (LockMode::InsertIntention, LockMode::InsertIntention)
+        )
+    }
+
+    fn covered_by(self, held: LockMode) -> bool {
+        self == held || (self == LockMode::Shared && held == LockMode::Exclusive)
+    }
+
+    fn replaces(self, held: LockMode) -> bool {
+        self == LockMode::Exclusive && held == LockMode::Shared
+    }
+}
+
+/// Table-level (metadata) lock modes, after MySQL's `SHARED_READ`, `SHARED_WRITE`,
+/// `SHARED_READ_ONLY`, `SHARED_NO_READ_WRITE` and `EXCLUSIVE` metadata locks. Transactions take
+/// `Read` / `Write` on the tables they read / write and keep them until they end;
+/// `LOCK TABLES ... READ` takes `ReadOnly`, `LOCK TABLES ... WRITE` takes `NoReadWrite` and DDL
+/// takes `Exclusive`. The global read lock (`FLUSH TABLES WITH READ LOCK`) is `ReadOnly` on the
+/// global key, where writers take `Write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableLockMode {
+    Read,
+    Write,
+    ReadOnly,
+    NoReadWrite,
+    Exclusive,
+}
+
+impl LockKind for TableLockMode {
+    const TIMEOUT_MESSAGE: &'static str = "table is locked by another session";
+
+    fn compatible(self, held: TableLockMode) -> bool {
+        use TableLockMode::*;
+        matches!(
+            (self, held),
+            (Read, Read | Write | ReadOnly) | (Write, Read | Write) | (ReadOnly, Read | ReadOnly)
+        )
+    }
+
+    fn covered_by(self, held: TableLockMode) -> bool {
+        use TableLockMode::*;
+        self == held || held == Exclusive || (held == NoReadWrite && self != Exclusive)
+    }
+
+    fn replaces(self, held: TableLockMode) -> bool {
+        self != held && held.covered_by(self)
+    }
+}
+
+const GLOBAL_LOCK_KEY: &[u8] = b"global";
+
+// Schemas holding temporary tables; see `Store::temp_schema`.
+const TEMP_SCHEMA_PREFIX: &str = "#sql-temp-";
+
+/// Lock modes a `LockManager` can grant.
+trait LockKind: Copy + Eq {
+    /// `LockWaitTimeout` message when a blocking request times out.
+    const TIMEOUT_MESSAGE: &'static str;
+
+    /// Whether another owner may hold `held` while this mode is granted.
+    fn compatible(self, held: Self) -> bool;
+
+    /// Whether an owner holding `held` already has this mode.
+    fn covered_by(self, held: Self) -> bool;
+
+    /// Whether granting this mode supersedes the owner's `held` lock (an upgrade).
+    fn replaces(self, held: Self) -> bool;
+}
+
+/// A gap in an index, named by the record that follows it (`None`: after the last record).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gap {
+    /// Gap in primary key order before the row with this PK.
+    Primary(Option<i64>),
+    /// Gap in a secondary index before the entry `(value, pk)`.
+    Index(String, Option<(Cell, i64)>),
+}
+
+/// What a lock request does when the row is held in a conflicting mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockWait {
+    /// Wait up to the given timeout (`innodb_lock_wait_timeout`).
+    Block(Duration),
+    /// Fail immediately (`NOWAIT`).
+    NoWait,
+    /// Give up on the row without an error (`SKIP LOCKED`).
+    SkipLocked,
+}
+
+/// Outcome of a successful lock request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockGrant {
+    /// The owner did not hold the row before.
+    Acquired,
+    /// The owner already held the row in this or a stronger mode (or upgraded it).
+    AlreadyHeld,
+    /// `SKIP LOCKED` and another owner holds a conflicting lock.
+    Skipped,
+}
+
+struct LockManager<M = LockMode> {
+    inner: Mutex<LockState<M>>,
+    // Signalled whenever locks are released or a deadlock victim is chosen.
+    changed: Condvar,
+}
+
+impl<M> Default for LockManager<M> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(LockState {
+                by_key: HashMap::new(),
+                by_owner: HashMap::new(),
+                waiting: HashMap::new(),
+                queues: HashMap::new(),
+                victims: HashSet::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+}
+
+struct LockState<M> {
+    // Modes each owner holds on a key; a gap can be held both as `Gap` and `InsertIntention`.
+    by_key: HashMap<Vec<u8>, HashMap<u32, Vec<M>>>,
+    by_owner: HashMap<u32, HashSet<Vec<u8>>>,
+    // Waits-for edges: owner -> key (and mode) it is blocked on.
+    waiting: HashMap<u32, (Vec<u8>, M)>,
+    // Owners waiting on each key, in arrival order. A request is only granted once no earlier
+    // waiter wants a conflicting mode, so a stream of shared requests cannot starve an
+    // exclusive one.
+    queues: HashMap<Vec<u8>, Vec<u32>>,
+    // Waiting owners picked as deadlock victims; they abort on wake-up.
+    victims: HashSet<u32>,
+}
+
+impl<M: LockKind> LockState<M> {
+    fn grant(&mut self, owner: u32, key: Vec<u8>, mode: M) {
+        let held = self
+            .by_key
+            .entry(key.clone())
+            .or_default()
+            .entry(owner)
+            .or_default();
+        held.retain(|m| !mode.replaces(*m));
+        held.push(mode);
+        self.by_owner.entry(owner).or_default().insert(key);
     }
 
-    fn row_key_mvcc(db: &str, table: &str, pk: i64, tx_id: TransactionId) -> Vec<u8> {
-        let mut k = Self::row_prefix_mvcc(db, table, pk);
-        // Append inverted TxID for sorting (Newest First).
-        let inverted = u64::MAX - tx_id;
-        k.extend_from_slice(&inverted.to_be_bytes());
-        k
+    /// The modes `owner` holds on `key`, if any.
+    fn held(&self, owner: u32, key: &[u8]) -> &[M] {
+        self.by_key
+            .get(key)
+            .and_then(|h| h.get(&owner))
+            .map_or(&[], |modes| modes.as_slice())
     }
 
-    // Legacy helper?
-    #[allow(dead_code)]
-    fn row_key(db: &str, table: &str, pk: i64) -> Vec<u8> {
-        Self::row_prefix_mvcc(db, table, pk)
+    /// Other owners whose locks on `key` conflict with `owner` taking it in `mode`.
+    fn blockers(&self, owner: u32, key: &[u8], mode: M) -> Vec<u32> {
+        self.by_key.get(key).map_or_else(Vec::new, |holders| {
+            holders
+                .iter()
+                .filter(|(o, held)| **o != owner && held.iter().any(|m| !mode.compatible(*m)))
+                .map(|(o, _)| *o)
+                .collect()
+        })
     }
 
-    fn index_key(db: &str, table: &str, index_name: &str, val: &Cell, pk: i64) -> Vec<u8> {
-        let mut k = Vec::new();
-        k.extend_from_slice(b"i\0");
-        k.extend_from_slice(db.as_bytes());
-        k.push(0);
-        k.extend_from_slice(table.as_bytes());
-        k.push(0);
-        k.extend_from_slice(index_name.as_bytes());
-        k.push(0);
-        match val {
-            Cell::Int(i) => k.extend_from_slice(&i.to_be_bytes()),
-            Cell::Text(s) => {
-                k.extend_from_slice(s.as_bytes());
-                k.push(0);
-            }
-            Cell::Null => k.push(0),
-            _ => {
-                // Fallback
+    /// Everyone `owner` has to wait for to take `key` in `mode`: conflicting holders, and
+    /// conflicting waiters queued before it. Owners already holding the key (upgrades) only
+    /// wait for holders.
+    fn waits_for(&self, owner: u32, key: &[u8], mode: M) -> Vec<u32> {
+        let mut out = self.blockers(owner, key, mode);
+        if !self.held(owner, key).is_empty() {
+            return out;
+        }
+        let queue = self.queues.get(key).map_or(&[][..], |q| q.as_slice());
+        for waiter in queue.iter().take_while(|o| **o != owner) {
+            let conflicts = self
+                .waiting
+                .get(waiter)
+                .is_some_and(|(_, m)| !mode.compatible(*m));
+            if conflicts && !out.contains(waiter) {
+                out.push(*waiter);
             }
         }
-        k.extend_from_slice(&pk.to_be_bytes());
-        k
+        out
     }
 
-    fn parse_tx_id_from_key(key: &[u8]) -> Result<TransactionId, MiniError> {
-        if key.len() < 8 {
-            return Err(MiniError::Invalid("corrupt mvcc key".into()));
+    /// Record that `owner` waits for `key`, joining the end of its queue on the first wait.
+    fn enqueue(&mut self, owner: u32, key: &[u8], mode: M) {
+        if self.waiting.insert(owner, (key.to_vec(), mode)).is_none() {
+            self.queues.entry(key.to_vec()).or_default().push(owner);
         }
-        let inv_bytes: [u8; 8] = key[key.len() - 8..].try_into().unwrap();
-        let inverted = u64::from_be_bytes(inv_bytes);
-        Ok(u64::MAX - inverted)
     }
 
-    fn auto_inc_key(db: &str, table: &str) -> Vec<u8> {
-        let mut k = Vec::new();
-        k.extend_from_slice(b"ai\0");
-        k.extend_from_slice(db.as_bytes());
-        k.push(0);
-        k.extend_from_slice(table.as_bytes());
-        k
+    /// Take `owner` off the queue it waits in, if any; returns whether it was queued.
+    fn dequeue(&mut self, owner: u32) -> bool {
+        let Some((key, _)) = self.waiting.remove(&owner) else {
+            return false;
+        };
+        if let Some(queue) = self.queues.get_mut(&key) {
+            queue.retain(|o| *o != owner);
+            if queue.is_empty() {
+                self.queues.remove(&key);
+            }
+        }
+        true
     }
 
-    fn auto_inc_prefix(db: &str) -> Vec<u8> {
-        let mut k = Vec::new();
-        k.extend_from_slice(b"ai\0");
-        k.extend_from_slice(db.as_bytes());
-        k.push(0);
-        k
+    /// Search the waits-for graph from `start`; returns the owners on a cycle leading back to it.
+    fn find_cycle(&self, start: u32) -> Option<Vec<u32>> {
+        let mut path = vec![start];
+        let mut visited = HashSet::from([start]);
+        self.cycle_from(start, start, &mut path, &mut visited)
+            .then_some(path)
+    }
+
+    fn cycle_from(
+        &self,
+        start: u32,
+        current: u32,
+        path: &mut Vec<u32>,
+        visited: &mut HashSet<u32>,
+    ) -> bool {
+        let Some((key, mode)) = self.waiting.get(&current) else {
+            return false;
+        };
+        for holder in self.waits_for(current, key, *mode) {
+            if holder == start {
+                return true;
+            }
+            // Cycles that do not involve us are detected by their own members.
+            if !visited.insert(holder) {
+                continue;
+            }
+            path.push(holder);
+            if self.cycle_from(start, holder, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
     }
 }
 
-// Helpers for GRANT/REVOKE
+impl<M: LockKind> LockManager<M> {
+    /// Acquire `key` for `owner` in `mode`. Conflicting holders, or conflicting requests queued
+    /// earlier, make the request wait, fail or skip the row depending on `wait`. Waiters are
+    /// granted in arrival order.
+    fn lock(
+        &self,
+        owner: u32,
+        key: Vec<u8>,
+        mode: M,
+        wait: LockWait,
+        detect_deadlocks: bool,
+    ) -> Result<LockGrant, MiniError> {
+        let deadline = match wait {
+            LockWait::Block(timeout) => Some(Instant::now() + timeout),
+            LockWait::NoWait | LockWait::SkipLocked => None,
+        };
+        let mut st = self.inner.lock();
+        loop {
+            let held = st.held(owner, &key);
+            let newly = held.is_empty();
+            if held.iter().any(|h| mode.covered_by(*h)) {
+                if st.dequeue(owner) {
+                    self.changed.notify_all();
+                }
+                st.victims.remove(&owner);
+                return Ok(LockGrant::AlreadyHeld);
+            }
+            if st.waits_for(owner, &key, mode).is_empty() {
+                // Waiters behind us may be compatible with the mode we take.
+                if st.dequeue(owner) {
+                    self.changed.notify_all();
+                }
+                st.victims.remove(&owner);
+                st.grant(owner, key, mode);
+                return Ok(if newly {
+                    LockGrant::Acquired
+                } else {
+                    LockGrant::AlreadyHeld
+                });
+            }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GrantTarget {
-    pub username: String,
-    pub host: String,
-}
+            let Some(deadline) = deadline else {
+                return match wait {
+                    LockWait::SkipLocked => Ok(LockGrant::Skipped),
+                    _ => Err(MiniError::LockNowait),
+                };
+            };
 
-#[derive(Default)]
-struct LockManager {
-    inner: Mutex<LockState>,
-}
+            if st.victims.remove(&owner) {
+                st.dequeue(owner);
+                self.changed.notify_all();
+                return Err(Self::deadlock_error());
+            }
 
-#[derive(Default)]
-struct LockState {
-    by_key: HashMap<Vec<u8>, u32>,
-    by_owner: HashMap<u32, HashSet<Vec<u8>>>,
-}
+            st.enqueue(owner, &key, mode);
+            if detect_deadlocks {
+                if let Some(cycle) = st.find_cycle(owner) {
+                    // Like InnoDB, roll back the cheapest transaction: the one holding the
+                    // fewest locks, preferring the requester on ties.
+                    let weight = |o: &u32| st.by_owner.get(o).map_or(0, |keys| keys.len());
+                    let victim = cycle
+                        .iter()
+                        .copied()
+                        .min_by_key(|o| (weight(o), *o != owner))
+                        .unwrap_or(owner);
+                    if victim == owner {
+                        st.dequeue(owner);
+                        self.changed.notify_all();
+                        return Err(Self::deadlock_error());
+                    }
+                    st.victims.insert(victim);
+                    self.changed.notify_all();
+                }
+            }
 
-impl LockManager {
-    fn lock(&self, owner: u32, key: Vec<u8>) -> Result<bool, MiniError> {
-        let mut st = self.inner.lock();
-        match st.by_key.get(&key).copied() {
-            None => {
-                st.by_key.insert(key.clone(), owner);
-                st.by_owner.entry(owner).or_default().insert(key);
-                Ok(true)
+            if self.changed.wait_until(&mut st, deadline).timed_out()
+                && !st.waits_for(owner, &key, mode).is_empty()
+            {
+                st.dequeue(owner);
+                st.victims.remove(&owner);
+                self.changed.notify_all();
+                return Err(MiniError::LockWaitTimeout(M::TIMEOUT_MESSAGE.into()));
             }
-            Some(current) if current == owner => Ok(false),
-            Some(_) => Err(MiniError::LockWaitTimeout(
-                "row is locked by another session".into(),
-            )),
         }
     }
 
+    fn deadlock_error() -> MiniError {
+        MiniError::Deadlock("lock wait would deadlock with another transaction".into())
+    }
+
     fn unlock(&self, owner: u32, key: &[u8]) {
         let mut st = self.inner.lock();
-        if st.by_key.get(key).copied() != Some(owner) {
+        let Some(holders) = st.by_key.get_mut(key) else {
+            return;
+        };
+        if holders.remove(&owner).is_none() {
             return;
         }
-        st.by_key.remove(key);
+        if holders.is_empty() {
+            st.by_key.remove(key);
+        }
         if let Some(keys) = st.by_owner.get_mut(&owner) {
             keys.remove(key);
             if keys.is_empty() {
                 st.by_owner.remove(&owner);
             }
         }
+        self.changed.notify_all();
     }
 
     fn unlock_all(&self, owner: u32) {
         let mut st = self.inner.lock();
+        st.victims.remove(&owner);
         let Some(keys) = st.by_owner.remove(&owner) else {
             return;
         };
         for key in keys {
-            if st.by_key.get(&key).copied() == Some(owner) {
-                st.by_key.remove(&key);
+            if let Some(holders) = st.by_key.get_mut(&key) {
+                holders.remove(&owner);
+                if holders.is_empty() {
+                    st.by_key.remove(&key);
+                }
             }
         }
+        self.changed.notify_all();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::{Cell, ColumnDef, Row, SqlType};
+    use crate::model::{Cell, ColumnDef, IndexDef, Row, SqlType};
     use tempfile::tempdir;
 
     #[test]
@@ -935,16 +2745,8 @@ mod tests {
         {
             let store = Store::open(path)?;
             let cols = vec![
-                ColumnDef {
-                    name: "id".into(),
-                    ty: SqlType::Int,
-                    nullable: false,
-                },
-                ColumnDef {
-                    name: "val".into(),
-                    ty: SqlType::Text,
-                    nullable: false,
-                },
+                ColumnDef::new("id", SqlType::Int, false),
+                ColumnDef::new("val", SqlType::Text, false),
             ];
             store.create_database("test_db")?;
             let table_def = TableDef {
@@ -957,18 +2759,18 @@ mod tests {
             };
             store.create_table(&table_def)?;
 
-            let (tx1, _view1) = store.txn_manager.start_txn();
+            let (tx1, view1) = store.txn_manager.start_txn();
             let row1 = Row {
                 values: vec![Cell::Int(1), Cell::Text("v1".into())],
             };
             let changes = vec![("test_db", "t1", 1i64, Some(&row1))];
-            store.apply_row_changes_mvcc(changes, tx1)?;
+            store.commit_row_changes(changes, tx1, &view1, |_, _, _| false)?;
             store.txn_manager.commit_txn(tx1);
         }
 
         // RUN 2: Restart
         {
-            let store = Store::open(path)?;
+            let store = Store::reopen(path)?;
 
             // Start new txn
             let (tx2, view2) = store.txn_manager.start_txn();
@@ -990,13 +2792,13 @@ mod tests {
                 values: vec![Cell::Int(2), Cell::Text("v2".into())],
             };
             let changes = vec![("test_db", "t1", 2i64, Some(&row2))];
-            store.apply_row_changes_mvcc(changes, tx2)?;
+            store.commit_row_changes(changes, tx2, &view2, |_, _, _| false)?;
             store.txn_manager.commit_txn(tx2);
         }
 
         // RUN 3: Restart Again
         {
-            let store = Store::open(path)?;
+            let store = Store::reopen(path)?;
             let (tx3, view3) = store.txn_manager.start_txn();
             assert!(tx3 > 2);
 
@@ -1006,4 +2808,829 @@ mod tests {
 
         Ok(())
     }
+
+    /// A table `db.t (id, age)` with an index on `age` and rows 1..=4.
+    fn ddl_fixture(path: &Path) -> Result<(), MiniError> {
+        let store = Store::open(path)?;
+        store.create_database("db")?;
+        store.create_table(&TableDef {
+            db: "db".into(),
+            name: "t".into(),
+            columns: vec![
+                ColumnDef::new("id", SqlType::Int, false),
+                ColumnDef::new("age", SqlType::Int, true),
+            ],
+            primary_key: "id".into(),
+            auto_increment: false,
+            indexes: vec![],
+        })?;
+        store.create_index(
+            "db",
+            "t",
+            IndexDef {
+                name: "idx_age".into(),
+                columns: vec!["age".into()],
+                unique: false,
+            },
+        )?;
+        let rows: Vec<Row> = (1..=4)
+            .map(|i| Row {
+                values: vec![Cell::Int(i), Cell::Int(i * 10)],
+            })
+            .collect();
+        apply_row_changes(
+            &store,
+            rows.iter()
+                .enumerate()
+                .map(|(i, r)| ("db", "t", i as i64 + 1, Some(r))),
+        )?;
+        Ok(())
+    }
+
+    /// Commit `changes` in a transaction of their own, then purge.
+    fn apply_row_changes<'a>(
+        store: &Store,
+        changes: impl IntoIterator<Item = (&'a str, &'a str, i64, Option<&'a Row>)>,
+    ) -> Result<(), MiniError> {
+        let (tx, view) = store.txn_manager.start_txn();
+        store.commit_row_changes(changes, tx, &view, |_, _, _| false)?;
+        store.txn_manager.commit_txn(tx);
+        store.purge_history()?;
+        Ok(())
+    }
+
+    fn data_keys(store: &Store, prefix: Vec<u8>) -> usize {
+        store.data.scan_prefix(prefix).count()
+    }
+
+    #[test]
+    fn test_ddl_crash_recovery() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        let path = dir.path();
+        ddl_fixture(path)?;
+
+        // Each DDL "crashes" half-way; after a restart it must be entirely undone.
+        {
+            let store = Store::reopen(path)?;
+            *store.fail_at.lock() = Some("drop_table");
+            assert!(store.drop_table("db", "t").is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.list_tables("db")?, vec!["t".to_string()]);
+            assert_eq!(store.scan_rows("db", "t")?.len(), 4);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 4);
+
+            *store.fail_at.lock() = Some("drop_database");
+            assert!(store.drop_database("db").is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.list_databases()?, vec!["db".to_string()]);
+            assert_eq!(store.scan_rows("db", "t")?.len(), 4);
+
+            *store.fail_at.lock() = Some("create_index");
+            let index = IndexDef {
+                name: "idx_id".into(),
+                columns: vec!["id".into()],
+                unique: false,
+            };
+            assert!(store.create_index("db", "t", index).is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.get_table("db", "t")?.indexes.len(), 1);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 4);
+
+            *store.fail_at.lock() = Some("alter_table");
+            let mut def = store.get_table("db", "t")?;
+            def.columns
+                .push(ColumnDef::new("extra", SqlType::Int, true));
+            let rows: Vec<(i64, Row)> = store
+                .scan_rows("db", "t")?
+                .into_iter()
+                .map(|(pk, mut row)| {
+                    row.values.push(Cell::Null);
+                    (pk, row)
+                })
+                .collect();
+            assert!(store.alter_table(&def, &rows).is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.get_table("db", "t")?.columns.len(), 2);
+            for (_, row) in store.scan_rows("db", "t")? {
+                assert_eq!(row.values.len(), 2);
+            }
+        }
+
+        {
+            let store = Store::reopen(path)?;
+            *store.fail_at.lock() = Some("create_table_with_rows");
+            let mut def = store.get_table("db", "t")?;
+            def.name = "copy".into();
+            let rows = store.scan_rows("db", "t")?;
+            assert!(store.create_table_with_rows(&def, &rows).is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.list_tables("db")?, vec!["t".to_string()]);
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "copy")), 0);
+            assert_eq!(
+                data_keys(&store, Store::index_table_prefix("db", "copy")),
+                0
+            );
+
+            *store.fail_at.lock() = Some("truncate_table");
+            assert!(store.truncate_table("db", "t").is_err());
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.scan_rows("db", "t")?.len(), 4);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 4);
+        }
+
+        // Without a crash the same DDL completes and survives a restart.
+        {
+            let store = Store::reopen(path)?;
+            store.create_index(
+                "db",
+                "t",
+                IndexDef {
+                    name: "idx_id".into(),
+                    columns: vec!["id".into()],
+                    unique: false,
+                },
+            )?;
+            let mut def = store.get_table("db", "t")?;
+            def.name = "copy".into();
+            let rows = store.scan_rows("db", "t")?;
+            store.create_table_with_rows(&def, &rows)?;
+            store.drop_table("db", "t")?;
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.list_tables("db")?, vec!["copy".to_string()]);
+            assert_eq!(store.scan_rows("db", "copy")?.len(), 4);
+            assert_eq!(
+                data_keys(&store, Store::index_table_prefix("db", "copy")),
+                8
+            );
+            store.truncate_table("db", "copy")?;
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.list_tables("db")?, vec!["copy".to_string()]);
+            assert_eq!(store.get_table("db", "copy")?.indexes.len(), 2);
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "copy")), 0);
+            assert_eq!(
+                data_keys(&store, Store::index_table_prefix("db", "copy")),
+                0
+            );
+            assert_eq!(store.table_stats("db", "copy")?.rows, 0);
+            store.drop_table("db", "copy")?;
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert!(store.list_tables("db")?.is_empty());
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "t")), 0);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 0);
+            store.drop_database("db")?;
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert!(store.list_databases()?.is_empty());
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "")), 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_load_recovery() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        let path = dir.path();
+        ddl_fixture(path)?;
+        let row = |id, age| Row {
+            values: vec![Cell::Int(id), Cell::Int(age)],
+        };
+        let (new5, same_age2) = (row(5, 50), row(2, 20));
+        let batch = || [(5, Some(&new5)), (2, Some(&same_age2)), (3, None)];
+
+        // Batches stay invisible to other snapshots until the load commits.
+        {
+            let store = Store::reopen(path)?;
+            let (tx, view) = store.txn_manager.start_txn();
+            store.bulk_write_rows("db", "t", batch(), tx, &view)?;
+            let (_, other) = store.txn_manager.start_txn();
+            assert!(store.get_row_mvcc("db", "t", 5, &other)?.is_none());
+            assert!(store.get_row_mvcc("db", "t", 5, &view)?.is_some());
+            // A crash before the load finishes.
+        }
+        {
+            let store = Store::reopen(path)?;
+            assert_eq!(store.scan_rows("db", "t")?.len(), 4);
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "t")), 4);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 4);
+            assert_eq!(store.table_stats("db", "t")?.rows, 4);
+
+            // A failed load is undone the same way.
+            let (tx, view) = store.txn_manager.start_txn();
+            store.bulk_write_rows("db", "t", batch(), tx, &view)?;
+            store.abort_bulk_load(tx)?;
+            store.txn_manager.rollback_txn(tx);
+            assert_eq!(store.scan_rows("db", "t")?.len(), 4);
+            assert_eq!(data_keys(&store, Store::row_prefix("db", "t")), 4);
+            assert_eq!(data_keys(&store, Store::index_table_prefix("db", "t")), 4);
+
+            let (tx, view) = store.txn_manager.start_txn();
+            store.bulk_write_rows("db", "t", batch(), tx, &view)?;
+            store.finish_bulk_load(tx)?;
+            store.txn_manager.commit_txn(tx);
+        }
+        {
+            let store = Store::reopen(path)?;
+            let ids: Vec<i64> = store
+                .scan_rows("db", "t")?
+                .into_iter()
+                .map(|(pk, _)| pk)
+                .collect();
+            assert_eq!(ids, vec![1, 2, 4, 5]);
+            assert_eq!(store.table_stats("db", "t")?.rows, 4);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_commit_shares_flushes() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        let store = Store::open(dir.path())?;
+        let group = store.commit_flush.clone();
+        let flushes = || group.state.lock().flushes;
+
+        // Each lone commit flushes.
+        group.commit()?;
+        group.commit()?;
+        assert_eq!(flushes(), 2);
+
+        // Committers arriving during a flush wait and are all covered by the next one.
+        group.state.lock().flushing = true;
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let group = group.clone();
+                std::thread::spawn(move || group.commit())
+            })
+            .collect();
+        while group.state.lock().requested < 6 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        {
+            let mut state = group.state.lock();
+            state.flushing = false;
+            group.flushed.notify_all();
+        }
+        for waiter in waiters {
+            waiter.join().unwrap()?;
+        }
+        assert_eq!(flushes(), 3);
+
+        // Without per-commit flushing, commits return without flushing.
+        store.set_flush_policy(FlushPolicy::OsManaged);
+        group.commit()?;
+        assert_eq!(flushes(), 3);
+        assert_eq!(store.flush_policy(), FlushPolicy::OsManaged);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_tx_id_survives_out_of_order_commits() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        let path = dir.path();
+        ddl_fixture(path)?;
+        let newest;
+        {
+            let store = Store::reopen(path)?;
+            store.set_flush_policy(FlushPolicy::PerSecond);
+            let (older, older_view) = store.txn_manager.start_txn();
+            let (newer, newer_view) = store.txn_manager.start_txn();
+            let row = |age| Row {
+                values: vec![Cell::Int(9), Cell::Int(age)],
+            };
+            let (r1, r2) = (row(1), row(2));
+            store.commit_row_changes(
+                vec![("db", "t", 9i64, Some(&r2))],
+                newer,
+                &newer_view,
+                |_, _, _| false,
+            )?;
+            store.txn_manager.commit_txn(newer);
+            store.commit_row_changes(
+                vec![("db", "t", 8i64, Some(&r1))],
+                older,
+                &older_view,
+                |_, _, _| false,
+            )?;
+            store.txn_manager.commit_txn(older);
+            newest = newer;
+        }
+        let store = Store::reopen(path)?;
+        let (tx, _) = store.txn_manager.start_txn();
+        assert!(
+            tx > newest,
+            "TxID {tx} reused after restart (newest was {newest})"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_scan_order_and_view_pins() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        ddl_fixture(dir.path())?;
+        let store = Store::reopen(dir.path())?;
+        let row = |id: i64| Row {
+            values: vec![Cell::Int(id), Cell::Int(0)],
+        };
+        let (r0, r1) = (row(-5), row(i64::MIN));
+        apply_row_changes(
+            &store,
+            vec![
+                ("db", "t", -5i64, Some(&r0)),
+                ("db", "t", i64::MIN, Some(&r1)),
+            ],
+        )?;
+
+        // Rows come out in numeric primary key order, negative keys first.
+        let (_, view) = store.txn_manager.start_txn();
+        let pks: Vec<i64> = store
+            .scan_rows_iter("db", "t", view.clone())
+            .map(|r| r.map(|(pk, _)| pk))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(pks, vec![i64::MIN, -5, 1, 2, 3, 4]);
+        let range = |lo, hi| {
+            store
+                .scan_rows_range_iter("db", "t", view.clone(), lo, hi)
+                .map(|r| r.map(|(pk, _)| pk))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        assert_eq!(range(-5, 2)?, vec![-5, 1, 2]);
+        assert_eq!(range(i64::MIN, -1)?, vec![i64::MIN, -5]);
+        assert_eq!(range(3, i64::MAX)?, vec![3, 4]);
+        assert_eq!(range(2, 2)?, vec![2]);
+        assert!(range(3, 2)?.is_empty());
+
+        // A pinned view keeps its versions after its transaction ends.
+        let pin = store.txn_manager.pin_view(&view);
+        store.txn_manager.commit_txn(view.own_tx_id.unwrap());
+        apply_row_changes(&store, vec![("db", "t", 1i64, None)])?;
+        store.purge_history()?;
+        assert_eq!(store.scan_rows_mvcc("db", "t", &view)?.len(), 6);
+        drop(pin);
+        store.purge_history()?;
+        assert_eq!(data_keys(&store, Store::row_prefix_mvcc("db", "t", 1)), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_purge_runs_in_background() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        ddl_fixture(dir.path())?;
+        let store = Store::reopen(dir.path())?;
+        let settled = || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while store.purge.lock().running {
+                assert!(Instant::now() < deadline, "purge did not finish");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let versions = || data_keys(&store, Store::row_prefix_mvcc("db", "t", 1));
+
+        let (reader, _) = store.txn_manager.start_txn();
+        let (tx, view) = store.txn_manager.start_txn();
+        let row = Row {
+            values: vec![Cell::Int(1), Cell::Int(99)],
+        };
+        store.commit_row_changes(vec![("db", "t", 1i64, Some(&row))], tx, &view, |_, _, _| {
+            false
+        })?;
+        store.txn_manager.commit_txn(tx);
+
+        // The reader still sees the old version, so the purge finds nothing to do.
+        store.request_purge();
+        settled();
+        assert_eq!(versions(), 2);
+        // Until the oldest view moves, another request does not start a purge.
+        let horizon = store.purge.lock().horizon;
+        store.request_purge();
+        assert!(!store.purge.lock().running);
+        assert_eq!(store.purge.lock().horizon, horizon);
+
+        store.txn_manager.commit_txn(reader);
+        store.request_purge();
+        settled();
+        assert_eq!(versions(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_entries_follow_snapshots() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        let store = Store::open(dir.path())?;
+        store.create_database("db")?;
+        store.create_table(&TableDef {
+            db: "db".into(),
+            name: "t".into(),
+            columns: vec![
+                ColumnDef::new("id", SqlType::Int, false),
+                ColumnDef::new("age", SqlType::Int, true),
+            ],
+            primary_key: "id".into(),
+            auto_increment: false,
+            indexes: vec![],
+        })?;
+        store.create_index(
+            "db",
+            "t",
+            IndexDef {
+                name: "idx_age".into(),
+                columns: vec!["age".into()],
+                unique: false,
+            },
+        )?;
+
+        let row = |age| Row {
+            values: vec![Cell::Int(1), Cell::Int(age)],
+        };
+        let lookup = |age, view: &ReadView| -> Result<Vec<i64>, MiniError> {
+            Ok(store
+                .index_lookup_mvcc("db", "t", "idx_age", &Cell::Int(age), view)?
+                .into_iter()
+                .map(|(pk, _)| pk)
+                .collect())
+        };
+        let index_entries = || {
+            store
+                .data
+                .scan_prefix(Store::index_table_prefix("db", "t"))
+                .count()
+        };
+
+        apply_row_changes(&store, vec![("db", "t", 1i64, Some(&row(30)))])?;
+
+        // A long-running reader keeps the age=30 version (and its index entry) alive.
+        let (reader, old_view) = store.txn_manager.start_txn();
+        apply_row_changes(&store, vec![("db", "t", 1i64, Some(&row(31)))])?;
+        assert_eq!(lookup(30, &old_view)?, vec![1]);
+        assert!(lookup(31, &old_view)?.is_empty());
+
+        let (_, new_view) = store.txn_manager.start_txn();
+        assert!(lookup(30, &new_view)?.is_empty());
+        assert_eq!(lookup(31, &new_view)?, vec![1]);
+        store.txn_manager.commit_txn(new_view.own_tx_id.unwrap());
+        assert_eq!(index_entries(), 2);
+
+        // Once no snapshot can see age=30, purge drops the old version and its entry.
+        store.txn_manager.commit_txn(reader);
+        assert_eq!(store.purge_history()?, 1);
+        assert_eq!(index_entries(), 1);
+        assert_eq!(
+            store
+                .data
+                .scan_prefix(Store::row_prefix_mvcc("db", "t", 1))
+                .count(),
+            1
+        );
+
+        // Deleting the row leaves nothing behind after purge.
+        apply_row_changes(&store, vec![("db", "t", 1i64, None)])?;
+        assert_eq!(index_entries(), 0);
+        assert_eq!(
+            store.data.scan_prefix(Store::row_prefix("db", "t")).count(),
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_index_checked_at_commit() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        ddl_fixture(dir.path())?;
+        let store = Store::reopen(dir.path())?;
+        let unique = |name: &str, column: &str| IndexDef {
+            name: name.into(),
+            columns: vec![column.into()],
+            unique: true,
+        };
+        // Existing duplicates (ignoring NULLs) keep a unique index from being built.
+        let null_age = Row {
+            values: vec![Cell::Int(5), Cell::Null],
+        };
+        let dup_age = Row {
+            values: vec![Cell::Int(6), Cell::Int(10)],
+        };
+        apply_row_changes(&store, vec![("db", "t", 5i64, Some(&null_age))])?;
+        apply_row_changes(&store, vec![("db", "t", 7i64, Some(&null_age))])?;
+        apply_row_changes(&store, vec![("db", "t", 6i64, Some(&dup_age))])?;
+        assert!(matches!(
+            store.create_index("db", "t", unique("uk_age", "age")),
+            Err(MiniError::DuplicateEntry(..))
+        ));
+        apply_row_changes(&store, vec![("db", "t", 6i64, None)])?;
+        store.create_index("db", "t", unique("uk_age", "age"))?;
+
+        // Two transactions that cannot see each other write the same value: the second
+        // commit fails. Moving the value off a row in the same commit is fine.
+        let row = |pk, age| Row {
+            values: vec![Cell::Int(pk), Cell::Int(age)],
+        };
+        let (a, a_view) = store.txn_manager.start_txn();
+        let (b, b_view) = store.txn_manager.start_txn();
+        let (r8, r9) = (row(8, 99), row(9, 99));
+        store.commit_row_changes(vec![("db", "t", 8i64, Some(&r8))], a, &a_view, |_, _, _| {
+            false
+        })?;
+        store.txn_manager.commit_txn(a);
+        let err =
+            store.commit_row_changes(vec![("db", "t", 9i64, Some(&r9))], b, &b_view, |_, _, _| {
+                false
+            });
+        assert!(matches!(err, Err(MiniError::DuplicateEntry(..))));
+        store.txn_manager.rollback_txn(b);
+
+        let (c, c_view) = store.txn_manager.start_txn();
+        let (r8, r9) = (row(8, 98), row(9, 99));
+        store.commit_row_changes(
+            vec![("db", "t", 8i64, Some(&r8)), ("db", "t", 9i64, Some(&r9))],
+            c,
+            &c_view,
+            |_, _, _| false,
+        )?;
+        store.txn_manager.commit_txn(c);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_stats_follow_commits() -> Result<(), MiniError> {
+        let dir = tempdir().map_err(|e| MiniError::Invalid(e.to_string()))?;
+        ddl_fixture(dir.path())?;
+        let store = Store::reopen(dir.path())?;
+        let def = store.get_table("db", "t")?;
+        let counters = |s: TableStats| (s.rows, s.data_length, s.index_length);
+
+        let stats = store.table_stats("db", "t")?;
+        assert_eq!(stats.rows, 4);
+        assert!(stats.index_length > 0 && stats.update_time.is_some());
+        assert_eq!(counters(stats), counters(store.measure_table(&def)?));
+
+        // Updates, deletes and re-inserts keep the counters equal to a full recount.
+        let wide = Row {
+            values: vec![Cell::Int(1), Cell::Int(1000)],
+        };
+        let back = Row {
+            values: vec![Cell::Int(2), Cell::Int(20)],
+        };
+        apply_row_changes(
+            &store,
+            vec![("db", "t", 1i64, Some(&wide)), ("db", "t", 2, None)],
+        )?;
+        assert_eq!(store.table_stats("db", "t")?.rows, 3);
+        assert_eq!(
+            counters(store.table_stats("db", "t")?),
+            counters(store.measure_table(&def)?)
+        );
+        apply_row_changes(
+            &store,
+            vec![("db", "t", 2i64, Some(&back)), ("db", "t", 9, None)],
+        )?;
+        let stats = store.table_stats("db", "t")?;
+        assert_eq!(stats.rows, 4);
+        assert_eq!(
+            counters(stats.clone()),
+            counters(store.measure_table(&def)?)
+        );
+
+        // ANALYZE records index cardinality in the catalog; it survives a restart.
+        let same_age = Row {
+            values: vec![Cell::Int(3), Cell::Int(1000)],
+        };
+        apply_row_changes(&store, vec![("db", "t", 3i64, Some(&same_age))])?;
+        let analysis = store.analyze_table("db", "t")?;
+        assert_eq!(analysis.cardinality["PRIMARY"], 4);
+        assert_eq!(analysis.cardinality["idx_age"], 3);
+        drop(store);
+        let store = Store::reopen(dir.path())?;
+        assert_eq!(
+            store
+                .table_analysis("db", "t")?
+                .map(|a| a.cardinality["idx_age"]),
+            Some(3)
+        );
+        assert_eq!(store.table_stats("db", "t")?.rows, 4);
+
+        // Dropping the table drops its statistics.
+        store.drop_table("db", "t")?;
+        assert_eq!(data_keys(&store, Store::stats_prefix("db")), 0);
+        assert!(store.table_analysis("db", "t")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_and_exclusive_row_locks() -> Result<(), MiniError> {
+        let locks = LockManager::default();
+        let key = b"r\0db\0t\0".to_vec();
+        let block = LockWait::Block(Duration::from_millis(50));
+        let lock = |owner, mode, wait| locks.lock(owner, key.clone(), mode, wait, true);
+
+        // Shared locks coexist; an exclusive request conflicts with them.
+        assert_eq!(lock(1, LockMode::Shared, block)?, LockGrant::Acquired);
+        assert_eq!(lock(2, LockMode::Shared, block)?, LockGrant::Acquired);
+        assert!(matches!(
+            lock(3, LockMode::Exclusive, LockWait::NoWait),
+            Err(MiniError::LockNowait)
+        ));
+        assert_eq!(
+            lock(3, LockMode::Exclusive, LockWait::SkipLocked)?,
+            LockGrant::Skipped
+        );
+        // Upgrading is only possible once the other shared holder is gone.
+        assert!(matches!(
+            lock(1, LockMode::Exclusive, block),
+            Err(MiniError::LockWaitTimeout(_))
+        ));
+        locks.unlock_all(2);
+        assert_eq!(lock(1, LockMode::Exclusive, block)?, LockGrant::AlreadyHeld);
+        assert_eq!(lock(1, LockMode::Shared, block)?, LockGrant::AlreadyHeld);
+        assert_eq!(
+            lock(2, LockMode::Shared, LockWait::SkipLocked)?,
+            LockGrant::Skipped
+        );
+
+        locks.unlock(1, &key);
+        assert_eq!(lock(3, LockMode::Exclusive, block)?, LockGrant::Acquired);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_waiters_are_granted_in_order() -> Result<(), MiniError> {
+        let locks = LockManager::default();
+        let key = b"r\0db\0t\0".to_vec();
+        let lock = |owner, mode, wait| locks.lock(owner, key.clone(), mode, wait, true);
+
+        assert_eq!(
+            lock(1, LockMode::Shared, LockWait::NoWait)?,
+            LockGrant::Acquired
+        );
+        std::thread::scope(|s| -> Result<(), MiniError> {
+            let writer = s.spawn(|| {
+                lock(
+                    2,
+                    LockMode::Exclusive,
+                    LockWait::Block(Duration::from_secs(5)),
+                )
+            });
+            while !locks.inner.lock().waiting.contains_key(&2) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            // A later shared request does not overtake the queued exclusive one.
+            assert!(matches!(
+                lock(3, LockMode::Shared, LockWait::NoWait),
+                Err(MiniError::LockNowait)
+            ));
+            assert!(matches!(
+                lock(
+                    3,
+                    LockMode::Shared,
+                    LockWait::Block(Duration::from_millis(50))
+                ),
+                Err(MiniError::LockWaitTimeout(_))
+            ));
+            // The shared holder itself may still take the key again.
+            assert_eq!(
+                lock(1, LockMode::Shared, LockWait::NoWait)?,
+                LockGrant::AlreadyHeld
+            );
+            locks.unlock_all(1);
+            assert_eq!(
+                writer.join().expect("writer panicked")?,
+                LockGrant::Acquired
+            );
+            Ok(())
+        })?;
+        assert!(locks.inner.lock().queues.is_empty());
+        locks.unlock_all(2);
+        assert_eq!(
+            lock(3, LockMode::Shared, LockWait::NoWait)?,
+            LockGrant::Acquired
+        );
+        locks.unlock_all(3);
+
+        // Waiting behind a queued request is a waits-for edge: 3 -> 2 (queued X) -> 1 (S holder)
+        // -> 3 is a deadlock, and 2, holding nothing, is the victim.
+        let other = b"r\0db\0u\0".to_vec();
+        let waiting = |owner| locks.inner.lock().waiting.contains_key(&owner);
+        lock(1, LockMode::Shared, LockWait::NoWait)?;
+        locks.lock(
+            3,
+            other.clone(),
+            LockMode::Exclusive,
+            LockWait::NoWait,
+            true,
+        )?;
+        std::thread::scope(|s| -> Result<(), MiniError> {
+            let writer = s.spawn(|| {
+                lock(
+                    2,
+                    LockMode::Exclusive,
+                    LockWait::Block(Duration::from_secs(5)),
+                )
+            });
+            while !waiting(2) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            let reader = s.spawn(|| {
+                let wait = LockWait::Block(Duration::from_millis(500));
+                locks.lock(1, other.clone(), LockMode::Shared, wait, true)
+            });
+            while !waiting(1) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            let wait = LockWait::Block(Duration::from_secs(5));
+            assert_eq!(lock(3, LockMode::Shared, wait)?, LockGrant::Acquired);
+            assert!(matches!(
+                writer.join().expect("writer panicked"),
+                Err(MiniError::Deadlock(_))
+            ));
+            assert!(matches!(
+                reader.join().expect("reader panicked"),
+                Err(MiniError::LockWaitTimeout(_))
+            ));
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gap_and_insert_intention_locks() -> Result<(), MiniError> {
+        let locks = LockManager::default();
+        let key = b"gr\0db\0t\0".to_vec();
+        let block = LockWait::Block(Duration::from_millis(50));
+        let lock = |owner, mode| locks.lock(owner, key.clone(), mode, block, true);
+
+        // An owner may insert into a gap it locked.
+        assert_eq!(lock(1, LockMode::Gap)?, LockGrant::Acquired);
+        assert_eq!(lock(1, LockMode::InsertIntention)?, LockGrant::AlreadyHeld);
+        locks.unlock_all(1);
+        // Gap locks coexist and keep other owners' inserts out.
+        assert_eq!(lock(1, LockMode::Gap)?, LockGrant::Acquired);
+        assert_eq!(lock(2, LockMode::Gap)?, LockGrant::Acquired);
+        assert!(matches!(
+            lock(3, LockMode::InsertIntention),
+            Err(MiniError::LockWaitTimeout(_))
+        ));
+        locks.unlock_all(1);
+        locks.unlock_all(2);
+        // Inserts into the same gap do not block each other, but block new gap locks.
+        assert_eq!(lock(3, LockMode::InsertIntention)?, LockGrant::Acquired);
+        assert_eq!(lock(4, LockMode::InsertIntention)?, LockGrant::Acquired);
+        assert!(matches!(
+            lock(1, LockMode::Gap),
+            Err(MiniError::LockWaitTimeout(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_lock_modes() -> Result<(), MiniError> {
+        use TableLockMode::*;
+        let locks = LockManager::<TableLockMode>::default();
+        let key = b"t\0db\0t".to_vec();
+        let block = LockWait::Block(Duration::from_millis(50));
+        let lock = |owner, mode| locks.lock(owner, key.clone(), mode, block, true);
+        let conflicts =
+            |owner, mode| matches!(lock(owner, mode), Err(MiniError::LockWaitTimeout(_)));
+
+        // LOCK TABLES ... READ admits readers and keeps writers out.
+        assert_eq!(lock(1, ReadOnly)?, LockGrant::Acquired);
+        assert_eq!(lock(2, Read)?, LockGrant::Acquired);
+        assert!(conflicts(3, Write));
+        assert!(conflicts(3, NoReadWrite));
+        locks.unlock_all(1);
+        // Statements reading and writing the table share it.
+        assert_eq!(lock(3, Write)?, LockGrant::Acquired);
+        assert!(conflicts(1, ReadOnly));
+        locks.unlock_all(2);
+        locks.unlock_all(3);
+        // LOCK TABLES ... WRITE excludes everyone else and covers the owner's statements.
+        assert_eq!(lock(1, NoReadWrite)?, LockGrant::Acquired);
+        assert_eq!(lock(1, Write)?, LockGrant::AlreadyHeld);
+        assert!(conflicts(2, Read));
+        locks.unlock_all(1);
+        // DDL waits for every other holder, and holding it covers everything else.
+        assert_eq!(lock(2, Read)?, LockGrant::Acquired);
+        assert!(conflicts(1, Exclusive));
+        assert_eq!(lock(2, Exclusive)?, LockGrant::AlreadyHeld);
+        assert_eq!(lock(2, NoReadWrite)?, LockGrant::AlreadyHeld);
+        assert!(conflicts(1, Read));
+        Ok(())
+    }
 }
@@ -23,6 +23,11 @@ impl Drop for ServerGuard {
 }
 
 pub fn spawn_server() -> anyhow::Result<(ServerGuard, SocketAddr)> {
+    spawn_server_with_args(&[])
+}
+
+/// Start a server with extra command-line arguments after the defaults.
+pub fn spawn_server_with_args(extra: &[&str]) -> anyhow::Result<(ServerGuard, SocketAddr)> {
     let bin = env!("CARGO_BIN_EXE_rusty-mini-mysql");
     let data_dir = tempfile::tempdir()?;
 
@@ -35,6 +40,7 @@ pub fn spawn_server() -> anyhow::Result<(ServerGuard, SocketAddr)> {
             "--root-password",
             "root",
         ])
+        .args(extra)
         .stdout(Stdio::null())
         .stderr(Stdio::piped())
         .spawn()?;
@@ -100,3 +106,12 @@ pub fn get_conn_with_retry(pool: &Pool, url: &str) -> anyhow::Result<PooledConn>
     }
     Err(anyhow::anyhow!("could not connect to server at {url}"))
 }
+
+/// The server error code of `err`; panics on client-side errors.
+#[allow(dead_code)]
+pub fn error_code(err: mysql::Error) -> u16 {
+    match err {
+        mysql::Error::MySqlError(e) => e.code,
+        other => panic!("unexpected error: {other:?}"),
+    }
+}
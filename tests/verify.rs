@@ -693,6 +693,7 @@ fn verify_row_locks() -> anyhow::Result<()> {
     conn1.query_drop("BEGIN")?;
     conn1.query_drop("UPDATE inventory SET qty = 20 WHERE id = 1")?;
 
+    conn2.query_drop("SET innodb_lock_wait_timeout = 1")?;
     conn2.query_drop("BEGIN")?;
     let err = conn2
         .query_drop("UPDATE inventory SET qty = 30 WHERE id = 1")